@@ -0,0 +1,136 @@
+use mlfq_scheduling::datastructure::admission::AdmissionQueue;
+use mlfq_scheduling::datastructure::avl::AvlTree;
+use mlfq_scheduling::datastructure::concurrent::ConcurrentAvlTree;
+use mlfq_scheduling::datastructure::Task;
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/*
+ * Contention benchmark for task admission, run with
+ *   cargo bench --bench admission
+ * Every producer thread admits TASKS_PER_PRODUCER tasks over RANKS ranks.
+ * - mutex: every producer locks a Mutex<AvlTree> and inserts (the old path)
+ * - concurrent: every producer inserts into a shared ConcurrentAvlTree
+ * - admission: producers push into the lock-free AdmissionQueue and one
+ *   scheduler thread drains it in batches into its own AvlTree
+ * Times are the best of a few rounds, from the first push until every task
+ * is in a tree. The last column is how fast the producers themselves got
+ * rid of their tasks on the admission path, which is what a submitting
+ * thread sees, the drain runs behind it on the scheduler thread.
+*/
+const TASKS_PER_PRODUCER: i32 = 20_000;
+const RANKS: i32 = 32;
+const ROUNDS: usize = 5;
+const BATCH: usize = 256;
+
+fn task(producer: i32, i: i32) -> Task {
+    Task::new(producer * TASKS_PER_PRODUCER + i, i % RANKS, 0)
+}
+
+fn run_producers(producers: i32, admit: impl Fn(Task) + Send + Sync + 'static) -> Duration {
+    let admit = Arc::new(admit);
+    let start = Arc::new(Barrier::new(producers as usize + 1));
+    let handles: Vec<_> = (0..producers)
+        .map(|p| {
+            let admit = admit.clone();
+            let start = start.clone();
+            thread::spawn(move || {
+                start.wait();
+                for i in 0..TASKS_PER_PRODUCER {
+                    admit(task(p, i));
+                }
+            })
+        })
+        .collect();
+    start.wait();
+    let began = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    began.elapsed()
+}
+
+fn mutex_path(producers: i32) -> Duration {
+    let tree = Arc::new(Mutex::new(AvlTree::new()));
+    let shared = tree.clone();
+    let elapsed = run_producers(producers, move |task| shared.lock().unwrap().insert(task));
+    assert_eq!(
+        tree.lock().unwrap().task_count(),
+        (producers * TASKS_PER_PRODUCER) as usize
+    );
+    elapsed
+}
+
+fn concurrent_path(producers: i32) -> Duration {
+    let tree = ConcurrentAvlTree::new();
+    let shared = tree.clone();
+    let elapsed = run_producers(producers, move |task| shared.insert(task));
+    assert_eq!(tree.len(), (producers * TASKS_PER_PRODUCER) as usize);
+    elapsed
+}
+
+// (producers done, every task in the tree)
+fn admission_path(producers: i32) -> (Duration, Duration) {
+    let queue = Arc::new(AdmissionQueue::new(4096));
+    let total = (producers * TASKS_PER_PRODUCER) as usize;
+
+    let scheduler = {
+        let queue = queue.clone();
+        thread::spawn(move || {
+            let mut tree = AvlTree::new();
+            let mut admitted = 0;
+            while admitted < total {
+                let drained = queue.drain_into(&mut tree, BATCH);
+                admitted += drained;
+                if drained == 0 {
+                    std::hint::spin_loop();
+                }
+            }
+            tree.task_count()
+        })
+    };
+    let shared = queue.clone();
+    let began = Instant::now();
+    let submitted = run_producers(producers, move |mut task| {
+        while let Err(back) = shared.push(task) {
+            task = back;
+            thread::yield_now();
+        }
+    });
+    let count = scheduler.join().unwrap();
+    let elapsed = began.elapsed();
+    assert_eq!(count, total);
+    (submitted, elapsed)
+}
+
+fn best<T: Ord>(run: impl Fn() -> T) -> T {
+    (0..ROUNDS).map(|_| run()).min().unwrap()
+}
+
+fn main() {
+    let cores = thread::available_parallelism().map_or(4, |n| n.get());
+    println!("{cores} cores, {TASKS_PER_PRODUCER} tasks per producer, {RANKS} ranks");
+    println!(
+        "{:>9} {:>14} {:>14} {:>14} {:>14}",
+        "producers", "mutex", "concurrent", "admission", "submit only"
+    );
+    for producers in [1, 2, 4, 8, 16] {
+        let rate = |elapsed: Duration| {
+            let tasks = f64::from(producers * TASKS_PER_PRODUCER);
+            format!("{:.2} Mtask/s", tasks / elapsed.as_secs_f64() / 1e6)
+        };
+        // ordered by the time until every task is in the tree
+        let (admitted, submitted) = best(|| {
+            let (submitted, admitted) = admission_path(producers);
+            (admitted, submitted)
+        });
+        println!(
+            "{producers:>9} {:>14} {:>14} {:>14} {:>14}",
+            rate(best(|| mutex_path(producers))),
+            rate(best(|| concurrent_path(producers))),
+            rate(admitted),
+            rate(submitted),
+        );
+    }
+}
@@ -0,0 +1,679 @@
+use crate::datastructure::Criticality;
+use crate::sim::resource::{blocking_times, Level};
+use crate::sim::workload::{gcd, TaskSpec, Workload, MAX_HORIZON};
+use serde::Serialize;
+
+/*
+ * Schedulability tests for periodic task sets on one cpu. Every task needs a
+ * period, the wcet is the burst and the deadline defaults to the period.
+ * Aperiodic tasks in the workload are ignored by the tests.
+ * `blocking` is the longest a job can wait for lower priority tasks in their
+ * critical sections (ipcp or srp, see sim::resource), every test adds it.
+ * A test that counts blocking is only sufficient, the bound is pessimistic.
+ * `wcet` is the budget at the task's own criticality level and `wcet_lo` the
+ * one in lo mode, the two only differ for hi tasks of a mixed criticality set.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeriodicTask {
+    pub id: i32,
+    pub rank: i32,
+    pub wcet: u64,
+    pub period: u64,
+    pub deadline: u64,
+    pub blocking: u64,
+    pub criticality: Criticality,
+    pub wcet_lo: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Test {
+    // Liu & Layland utilization bound for rate monotonic, sufficient only
+    LiuLayland,
+    // response time analysis with rate monotonic priorities
+    RateMonotonic,
+    // response time analysis with deadline monotonic priorities
+    DeadlineMonotonic,
+    // response time analysis with the ranks from the workload
+    FixedPriority,
+    // processor demand test for EDF
+    Edf,
+    // mixed criticality response time analysis with deadline monotonic priorities
+    Amc,
+    // mixed criticality utilization test for EDF with virtual deadlines
+    EdfVd,
+}
+
+pub const TEST_NAMES: &[&str] = &["ll", "rm", "dm", "fp", "edf", "amc", "edf-vd"];
+
+impl Test {
+    pub fn by_name(name: &str) -> Option<Test> {
+        match name {
+            "ll" => Some(Test::LiuLayland),
+            "rm" => Some(Test::RateMonotonic),
+            "dm" => Some(Test::DeadlineMonotonic),
+            "fp" => Some(Test::FixedPriority),
+            "edf" => Some(Test::Edf),
+            "amc" => Some(Test::Amc),
+            "edf-vd" => Some(Test::EdfVd),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Test::LiuLayland => "ll",
+            Test::RateMonotonic => "rm",
+            Test::DeadlineMonotonic => "dm",
+            Test::FixedPriority => "fp",
+            Test::Edf => "edf",
+            Test::Amc => "amc",
+            Test::EdfVd => "edf-vd",
+        }
+    }
+
+    // the exact test for a policy's own order, None for the policies without one
+    pub fn by_policy(policy: &str) -> Option<Test> {
+        match policy {
+            "rm" => Some(Test::RateMonotonic),
+            "priority" => Some(Test::FixedPriority),
+            "edf" => Some(Test::Edf),
+            _ => None,
+        }
+    }
+
+    // the priority order the test assumes, which decides who blocks whom
+    pub fn level(&self) -> Level {
+        match self {
+            Test::LiuLayland | Test::RateMonotonic => Level::RateMonotonic,
+            Test::DeadlineMonotonic | Test::Edf | Test::Amc | Test::EdfVd => {
+                Level::DeadlineMonotonic
+            }
+            Test::FixedPriority => Level::Rank,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskResult {
+    pub id: i32,
+    pub wcet: u64,
+    pub period: u64,
+    pub deadline: u64,
+    pub blocking: u64,
+    // worst case response time, None when it is past the deadline
+    pub response: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TestResult {
+    pub test: String,
+    pub schedulable: bool,
+    // false when failing the test does not prove the set unschedulable
+    pub exact: bool,
+    pub utilization: f64,
+    pub bound: Option<f64>,
+    // scale of the virtual deadlines of hi tasks in lo mode, edf-vd only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virtual_deadline: Option<f64>,
+    pub tasks: Vec<TaskResult>,
+}
+
+pub fn periodic_tasks(workload: &Workload) -> Vec<PeriodicTask> {
+    workload.tasks.iter().filter_map(periodic_task).collect()
+}
+
+// None for an aperiodic task
+pub fn periodic_task(spec: &TaskSpec) -> Option<PeriodicTask> {
+    let period = spec.period?;
+    let task = spec.to_task();
+    Some(PeriodicTask {
+        id: spec.id,
+        rank: spec.rank,
+        wcet: task.get_wcet(spec.criticality),
+        period,
+        deadline: spec.deadline.unwrap_or(period),
+        blocking: 0,
+        criticality: spec.criticality,
+        wcet_lo: task.get_wcet(Criticality::Lo),
+    })
+}
+
+// the tasks with the blocking times of the workload's critical sections under the test's order
+pub fn with_blocking(tasks: &[PeriodicTask], workload: &Workload, test: Test) -> Vec<PeriodicTask> {
+    let blocking = blocking_times(workload, test.level());
+    tasks
+        .iter()
+        .map(|task| PeriodicTask {
+            blocking: blocking.get(&task.id).copied().unwrap_or(0),
+            ..*task
+        })
+        .collect()
+}
+
+pub fn utilization(tasks: &[PeriodicTask]) -> f64 {
+    tasks
+        .iter()
+        .fold(0.0, |u, t| u + t.wcet as f64 / t.period as f64)
+}
+
+// sum of C / min(D, T), the set is edf schedulable when it is at most 1
+pub fn density(tasks: &[PeriodicTask]) -> f64 {
+    tasks.iter().fold(0.0, |d, t| {
+        d + t.wcet as f64 / t.deadline.min(t.period) as f64
+    })
+}
+
+// n(2^(1/n) - 1)
+pub fn liu_layland_bound(n: usize) -> f64 {
+    if n == 0 {
+        return 1.0;
+    }
+    n as f64 * (2f64.powf(1.0 / n as f64) - 1.0)
+}
+
+pub fn run(test: Test, tasks: &[PeriodicTask]) -> TestResult {
+    let u = utilization(tasks);
+    let blocked = tasks.iter().any(|t| t.blocking > 0);
+    match test {
+        Test::LiuLayland => {
+            let bound = liu_layland_bound(tasks.len());
+            TestResult {
+                test: test.name().to_string(),
+                schedulable: liu_layland_ok(tasks) && tasks.iter().all(|t| t.deadline >= t.period),
+                exact: false,
+                utilization: u,
+                bound: Some(bound),
+                virtual_deadline: None,
+                tasks: tasks.iter().map(|t| task_result(t, None)).collect(),
+            }
+        }
+        Test::RateMonotonic | Test::DeadlineMonotonic | Test::FixedPriority | Test::Amc => {
+            let priority = |t: &PeriodicTask| -> i64 {
+                match test {
+                    Test::RateMonotonic => -(t.period as i64),
+                    Test::DeadlineMonotonic | Test::Amc => -(t.deadline as i64),
+                    _ => t.rank as i64,
+                }
+            };
+            let results: Vec<TaskResult> = tasks
+                .iter()
+                .map(|task| {
+                    // tasks of equal priority are counted as interference
+                    let higher: Vec<PeriodicTask> = tasks
+                        .iter()
+                        .filter(|o| o.id != task.id && priority(o) >= priority(task))
+                        .copied()
+                        .collect();
+                    let response = match test {
+                        Test::Amc => amc_response(task, &higher),
+                        _ => response_time(task, &higher),
+                    };
+                    task_result(task, response)
+                })
+                .collect();
+            TestResult {
+                test: test.name().to_string(),
+                schedulable: results.iter().all(|r| r.response.is_some()),
+                // amc-rtb is only sufficient
+                exact: !blocked && test != Test::Amc,
+                utilization: u,
+                bound: None,
+                virtual_deadline: None,
+                tasks: results,
+            }
+        }
+        Test::Edf => TestResult {
+            test: test.name().to_string(),
+            schedulable: edf_demand_ok(tasks),
+            exact: !blocked,
+            utilization: u,
+            bound: Some(1.0),
+            virtual_deadline: None,
+            tasks: tasks.iter().map(|t| task_result(t, None)).collect(),
+        },
+        Test::EdfVd => TestResult {
+            test: test.name().to_string(),
+            schedulable: edf_vd_ok(tasks),
+            exact: false,
+            utilization: u,
+            bound: Some(1.0),
+            virtual_deadline: Some(edf_vd_factor(tasks)),
+            tasks: tasks.iter().map(|t| task_result(t, None)).collect(),
+        },
+    }
+}
+
+fn task_result(task: &PeriodicTask, response: Option<u64>) -> TaskResult {
+    TaskResult {
+        id: task.id,
+        wcet: task.wcet,
+        period: task.period,
+        deadline: task.deadline,
+        blocking: task.blocking,
+        response,
+    }
+}
+
+/*
+ * Liu & Layland with blocking (Sha, Rajkumar & Lehoczky): in rate monotonic
+ * order every task i needs U_1 + .. + U_i + B_i / T_i <= i(2^(1/i) - 1).
+ * Without blocking this is the plain bound on the whole set.
+*/
+pub fn liu_layland_ok(tasks: &[PeriodicTask]) -> bool {
+    if tasks.iter().all(|t| t.blocking == 0) {
+        return utilization(tasks) <= liu_layland_bound(tasks.len());
+    }
+    let mut sorted = tasks.to_vec();
+    sorted.sort_by_key(|t| t.period);
+    let mut u = 0.0;
+    sorted.iter().enumerate().all(|(i, t)| {
+        u += t.wcet as f64 / t.period as f64;
+        u + t.blocking as f64 / t.period as f64 <= liu_layland_bound(i + 1)
+    })
+}
+
+/*
+ * Response time analysis (Joseph & Pandya): iterate
+ * R = C + B + sum over higher priority j of ceil(R / T_j) * C_j
+ * until it settles, giving up once R is past the deadline.
+*/
+pub fn response_time(task: &PeriodicTask, higher: &[PeriodicTask]) -> Option<u64> {
+    let own = task.wcet + task.blocking;
+    let mut response = own + higher.iter().map(|h| h.wcet).sum::<u64>();
+    loop {
+        if response > task.deadline {
+            return None;
+        }
+        let next = own
+            + higher
+                .iter()
+                .map(|h| response.div_ceil(h.period) * h.wcet)
+                .sum::<u64>();
+        if next == response {
+            return Some(response);
+        }
+        response = next;
+    }
+}
+
+/*
+ * AMC-rtb (Baruah, Burns & Davis). Every task has to meet its deadline in lo
+ * mode, where every task is charged its lo budget,
+ *   R(LO) = C(LO) + B + sum over higher j of ceil(R(LO) / T_j) * C_j(LO)
+ * and a hi task also across a switch to hi mode. From then on only the hi
+ * tasks run on, at their hi budgets, but the lo tasks above it may have run
+ * for as long as R(LO),
+ *   R(HI) = C(HI) + B + sum over higher hi j of ceil(R(HI) / T_j) * C_j(HI)
+ *                     + sum over higher lo k of ceil(R(LO) / T_k) * C_k(LO)
+ * Returns R(LO) for a lo task and R(HI) for a hi one.
+*/
+pub fn amc_response(task: &PeriodicTask, higher: &[PeriodicTask]) -> Option<u64> {
+    let lo = |t: &PeriodicTask| PeriodicTask {
+        wcet: t.wcet_lo,
+        ..*t
+    };
+    let lo_higher: Vec<PeriodicTask> = higher.iter().map(lo).collect();
+    let response_lo = response_time(&lo(task), &lo_higher)?;
+    if task.criticality == Criticality::Lo {
+        return Some(response_lo);
+    }
+    let carried: u64 = higher
+        .iter()
+        .filter(|k| k.criticality == Criticality::Lo)
+        .map(|k| response_lo.div_ceil(k.period) * k.wcet_lo)
+        .sum();
+    let hi_higher: Vec<PeriodicTask> = higher
+        .iter()
+        .filter(|j| j.criticality == Criticality::Hi)
+        .copied()
+        .collect();
+    let own = PeriodicTask {
+        wcet: task.wcet + carried,
+        ..*task
+    };
+    response_time(&own, &hi_higher)
+}
+
+// lo tasks at lo budgets, hi tasks at lo budgets and hi tasks at hi budgets
+fn mixed_utilization(tasks: &[PeriodicTask]) -> (f64, f64, f64) {
+    let sum = |level: Criticality, wcet: fn(&PeriodicTask) -> u64| -> f64 {
+        tasks
+            .iter()
+            .filter(|t| t.criticality == level)
+            .map(|t| wcet(t) as f64 / t.period as f64)
+            .sum()
+    };
+    (
+        sum(Criticality::Lo, |t| t.wcet_lo),
+        sum(Criticality::Hi, |t| t.wcet_lo),
+        sum(Criticality::Hi, |t| t.wcet),
+    )
+}
+
+/*
+ * EDF-VD (Baruah, Bonifaci, D'Angelo, Li, Marchetti-Spaccamela, van der
+ * Ster & Stougie) for implicit deadlines. In lo mode a hi task is due at x
+ * times its period, which leaves it room to finish its hi budget once the
+ * system switched to hi mode and its real deadline counts again,
+ *   x = U_hi(LO) / (1 - U_lo(LO))
+ * When U_lo(LO) + U_hi(HI) <= 1 plain edf is enough and x is 1.
+*/
+pub fn edf_vd_factor(tasks: &[PeriodicTask]) -> f64 {
+    let (lo, hi_lo, hi_hi) = mixed_utilization(tasks);
+    if lo + hi_hi <= 1.0 + 1e-9 || lo >= 1.0 {
+        return 1.0;
+    }
+    (hi_lo / (1.0 - lo)).clamp(f64::EPSILON, 1.0)
+}
+
+// x * U_lo(LO) + U_hi(HI) <= 1, sufficient only
+pub fn edf_vd_ok(tasks: &[PeriodicTask]) -> bool {
+    let (lo, hi_lo, hi_hi) = mixed_utilization(tasks);
+    if tasks
+        .iter()
+        .any(|t| t.deadline != t.period || t.blocking > 0)
+    {
+        return false;
+    }
+    if lo + hi_hi <= 1.0 + 1e-9 {
+        return true;
+    }
+    lo + hi_lo <= 1.0 + 1e-9 && edf_vd_factor(tasks) * lo + hi_hi <= 1.0 + 1e-9
+}
+
+/*
+ * Periodic resource model (Shin & Lee). A partition that gets Θ ticks in
+ * every period Π is supplied at least sbf(t) ticks in any t ticks. The worst
+ * case starts right after it got its budget early in a period and then gets
+ * every budget as late as possible, a blackout of up to 2(Π - Θ),
+ *   sbf(t) = t - (k + 1)(Π - Θ)  when (k + 1)Π - 2Θ <= t <= (k + 1)Π - Θ
+ *            (k - 1)Θ            otherwise
+ * with k = max(ceil((t - (Π - Θ)) / Π), 1).
+*/
+pub fn supply_bound(budget: u64, period: u64, t: u64) -> u64 {
+    let blackout = period - budget;
+    let k = t.saturating_sub(blackout).div_ceil(period).max(1);
+    let start = ((k + 1) * period).saturating_sub(2 * budget);
+    if t >= start && t <= (k + 1) * period - budget {
+        t - (k + 1) * blackout
+    } else {
+        (k - 1) * budget
+    }
+}
+
+/*
+ * Compositional analysis. The tasks of a partition are schedulable on its
+ * reservation when under edf the work due by every deadline t fits in
+ * sbf(t), and under fixed priorities every task finds some t up to its
+ * deadline where
+ *   C + B + sum over higher j of ceil(t / T_j) * C_j <= sbf(t)
+ * Tests other than edf, rm, dm and fp can not be used.
+*/
+pub fn schedulable_on(tasks: &[PeriodicTask], test: Test, budget: u64, period: u64) -> bool {
+    let supply = |t: u64| supply_bound(budget, period, t);
+    match test {
+        Test::Edf => {
+            let reserved = budget as f64 / period as f64;
+            if utilization(tasks) > reserved + 1e-9 {
+                return false;
+            }
+            let mut hyper = period;
+            for t in tasks {
+                hyper = (hyper / gcd(hyper, t.period) * t.period).min(MAX_HORIZON);
+            }
+            let limit = hyper + tasks.iter().map(|t| t.deadline).max().unwrap_or(0);
+            tasks.iter().all(|task| {
+                let mut at = task.deadline;
+                while at <= limit {
+                    let blocking = tasks
+                        .iter()
+                        .filter(|t| t.deadline <= at)
+                        .map(|t| t.blocking)
+                        .max()
+                        .unwrap_or(0);
+                    if demand(tasks, at) + blocking > supply(at) {
+                        return false;
+                    }
+                    at += task.period;
+                }
+                true
+            })
+        }
+        Test::RateMonotonic | Test::DeadlineMonotonic | Test::FixedPriority => {
+            let priority = |t: &PeriodicTask| -> i64 {
+                match test {
+                    Test::RateMonotonic => -(t.period as i64),
+                    Test::DeadlineMonotonic => -(t.deadline as i64),
+                    _ => t.rank as i64,
+                }
+            };
+            tasks.iter().all(|task| {
+                let higher: Vec<&PeriodicTask> = tasks
+                    .iter()
+                    .filter(|o| o.id != task.id && priority(o) >= priority(task))
+                    .collect();
+                (1..=task.deadline).any(|t| {
+                    let work = task.wcet
+                        + task.blocking
+                        + higher
+                            .iter()
+                            .map(|h| t.div_ceil(h.period) * h.wcet)
+                            .sum::<u64>();
+                    work <= supply(t)
+                })
+            })
+        }
+        _ => false,
+    }
+}
+
+// smallest budget every `period` the tasks are schedulable on
+pub fn min_budget(tasks: &[PeriodicTask], test: Test, period: u64) -> Option<u64> {
+    (1..=period).find(|&budget| schedulable_on(tasks, test, budget, period))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PartitionResult {
+    pub name: String,
+    pub policy: String,
+    pub budget: u64,
+    pub period: u64,
+    pub utilization: f64,
+    // None when the partition's policy has no test
+    pub schedulable: Option<bool>,
+    // smallest budget that would do for the period
+    pub min_budget: Option<u64>,
+}
+
+// every partition of the workload against its reservation
+pub fn partitions(workload: &Workload) -> Vec<PartitionResult> {
+    workload
+        .partitions
+        .iter()
+        .map(|partition| {
+            let member: Vec<i32> = workload
+                .tasks
+                .iter()
+                .filter(|spec| spec.partition.as_ref() == Some(&partition.name))
+                .map(|spec| spec.id)
+                .collect();
+            let tasks: Vec<PeriodicTask> = periodic_tasks(workload)
+                .into_iter()
+                .filter(|t| member.contains(&t.id))
+                .collect();
+            let test = Test::by_policy(&partition.policy);
+            let tasks = match test {
+                Some(test) => with_blocking(&tasks, workload, test),
+                None => tasks,
+            };
+            PartitionResult {
+                name: partition.name.clone(),
+                policy: partition.policy.clone(),
+                budget: partition.budget,
+                period: partition.period,
+                utilization: utilization(&tasks),
+                schedulable: test
+                    .map(|test| schedulable_on(&tasks, test, partition.budget, partition.period)),
+                min_budget: test.and_then(|test| min_budget(&tasks, test, partition.period)),
+            }
+        })
+        .collect()
+}
+
+/*
+ * Processor demand criterion (Baruah): the set is EDF schedulable iff U <= 1
+ * and for every absolute deadline t up to the hyperperiod plus the longest
+ * deadline the work due by t fits in t. With srp the work due by t also
+ * has to leave room for the blocking of any task due by t.
+*/
+pub fn edf_demand_ok(tasks: &[PeriodicTask]) -> bool {
+    if utilization(tasks) > 1.0 + 1e-9 {
+        return false;
+    }
+    let blocked = tasks.iter().any(|t| t.blocking > 0);
+    if !blocked && tasks.iter().all(|t| t.deadline >= t.period) {
+        return true;
+    }
+    let mut hyper: u64 = 1;
+    for t in tasks {
+        hyper = (hyper / gcd(hyper, t.period) * t.period).min(MAX_HORIZON);
+    }
+    let limit = hyper + tasks.iter().map(|t| t.deadline).max().unwrap_or(0);
+    let mut deadlines = Vec::new();
+    for t in tasks {
+        let mut d = t.deadline;
+        while d <= limit {
+            deadlines.push(d);
+            d += t.period;
+        }
+    }
+    deadlines.sort_unstable();
+    deadlines.dedup();
+    deadlines.into_iter().all(|at| {
+        let blocking = tasks
+            .iter()
+            .filter(|t| t.deadline <= at)
+            .map(|t| t.blocking)
+            .max()
+            .unwrap_or(0);
+        demand(tasks, at) + blocking <= at
+    })
+}
+
+// work that has to be finished by `at` when every task is released at 0
+pub fn demand(tasks: &[PeriodicTask], at: u64) -> u64 {
+    tasks
+        .iter()
+        .filter(|t| at >= t.deadline)
+        .map(|t| ((at - t.deadline) / t.period + 1) * t.wcet)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: i32, wcet: u64, period: u64, deadline: u64) -> PeriodicTask {
+        PeriodicTask {
+            id,
+            rank: 0,
+            wcet,
+            period,
+            deadline,
+            blocking: 0,
+            criticality: Criticality::Lo,
+            wcet_lo: wcet,
+        }
+    }
+
+    #[test]
+    fn response_time_analysis() {
+        // classic example, R3 = 10 after interference from tasks 1 and 2
+        let tasks = [task(1, 1, 4, 4), task(2, 2, 6, 6), task(3, 3, 13, 13)];
+        let result = run(Test::RateMonotonic, &tasks);
+        let responses: Vec<Option<u64>> = result.tasks.iter().map(|t| t.response).collect();
+        assert_eq!(responses, vec![Some(1), Some(3), Some(10)]);
+        assert!(result.schedulable);
+        // U = 0.81 is above the bound for three tasks, the bound is only sufficient
+        assert!(!run(Test::LiuLayland, &tasks).schedulable);
+    }
+
+    #[test]
+    fn edf_tests() {
+        let implicit = [task(1, 2, 5, 5), task(2, 4, 7, 7)];
+        assert!(run(Test::Edf, &implicit).schedulable);
+        assert!(!run(Test::RateMonotonic, &implicit).schedulable);
+
+        // U < 1 but both jobs are due at 3
+        let constrained = [task(1, 2, 10, 3), task(2, 2, 10, 3)];
+        assert!(!run(Test::Edf, &constrained).schedulable);
+        assert_eq!(demand(&constrained, 3), 4);
+    }
+
+    #[test]
+    fn blocking_adds_to_the_response_time() {
+        let workload = Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 1, "period": 4,
+                 "sections": [{"resource": "bus", "start": 0, "length": 1}]},
+                {"id": 2, "burst": 2, "period": 6},
+                {"id": 3, "burst": 3, "period": 13,
+                 "sections": [{"resource": "bus", "start": 0, "length": 2}]}
+            ]}"#,
+        )
+        .unwrap();
+        let tasks = with_blocking(&periodic_tasks(&workload), &workload, Test::RateMonotonic);
+        let blocking: Vec<u64> = tasks.iter().map(|t| t.blocking).collect();
+        // 2 never uses the bus but 3 can run at the ceiling above it
+        assert_eq!(blocking, vec![2, 2, 0]);
+        let result = run(Test::RateMonotonic, &tasks);
+        let responses: Vec<Option<u64>> = result.tasks.iter().map(|t| t.response).collect();
+        assert_eq!(responses, vec![Some(3), Some(6), Some(10)]);
+        assert!(result.schedulable && !result.exact);
+        assert!(run(Test::Edf, &with_blocking(&tasks, &workload, Test::Edf)).schedulable);
+    }
+
+    #[test]
+    fn mixed_criticality_tests() {
+        let hi = |id: i32, lo: u64, hi: u64, period: u64| PeriodicTask {
+            criticality: Criticality::Hi,
+            wcet_lo: lo,
+            ..task(id, hi, period, period)
+        };
+        // U_lo(LO) = 0.5, U_hi(LO) = 0.2, U_hi(HI) = 0.6
+        let tasks = [task(1, 5, 10, 10), hi(2, 2, 6, 10)];
+        let result = run(Test::EdfVd, &tasks);
+        assert!(result.schedulable);
+        assert!((result.virtual_deadline.unwrap() - 0.4).abs() < 1e-9);
+        // with equal deadlines 1 interferes with 2 for up to R(LO) = 7
+        let result = run(Test::Amc, &tasks);
+        let responses: Vec<Option<u64>> = result.tasks.iter().map(|t| t.response).collect();
+        assert_eq!(responses, vec![Some(7), None]);
+
+        // 1 only runs until the switch, which it can delay by one job, not
+        // by the two it would fit into a response time of 6
+        let tasks = [task(1, 1, 4, 4), hi(2, 2, 4, 10)];
+        let responses: Vec<Option<u64>> = run(Test::Amc, &tasks)
+            .tasks
+            .iter()
+            .map(|t| t.response)
+            .collect();
+        assert_eq!(responses, vec![Some(1), Some(5)]);
+    }
+
+    #[test]
+    fn periodic_resource_model() {
+        // 2 ticks every 5, nothing for the first 6 ticks in the worst case
+        let supply: Vec<u64> = (0..=14).map(|t| supply_bound(2, 5, t)).collect();
+        assert_eq!(supply, vec![0, 0, 0, 0, 0, 0, 0, 1, 2, 2, 2, 2, 3, 4, 4]);
+        // a dedicated cpu supplies every tick
+        assert_eq!(supply_bound(5, 5, 7), 7);
+
+        let tasks = [task(1, 1, 10, 10), task(2, 2, 20, 20)];
+        // 3 ticks every 5 give at least 4 by 10, task 1 needs 1 and 2 needs 1 + 2 by 20
+        assert!(schedulable_on(&tasks, Test::RateMonotonic, 3, 5));
+        assert!(schedulable_on(&tasks, Test::Edf, 3, 5));
+        assert_eq!(min_budget(&tasks, Test::RateMonotonic, 5), Some(2));
+        assert!(!schedulable_on(&tasks, Test::RateMonotonic, 1, 5));
+    }
+}
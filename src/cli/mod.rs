@@ -0,0 +1,1355 @@
+mod output;
+
+use clap::builder::PossibleValuesParser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use mlfq_scheduling::analysis::{self, PartitionResult, Test, TEST_NAMES};
+use mlfq_scheduling::scheduler::burst::{BurstPredictor, Prediction, DEFAULT_ALPHA};
+use mlfq_scheduling::scheduler::fair::FairShare;
+use mlfq_scheduling::scheduler::hrrn::Hrrn;
+use mlfq_scheduling::scheduler::lottery::{Lottery, DEFAULT_SEED};
+use mlfq_scheduling::scheduler::mixed::{
+    Degradation, MixedCriticality, ModeSwitch, DEGRADATION_NAMES,
+};
+use mlfq_scheduling::scheduler::mlfq::config::{ConfigError, MlfqConfig, PRESET_NAMES};
+use mlfq_scheduling::scheduler::mlfq::Mlfq;
+use mlfq_scheduling::scheduler::overload::{Overload, OverloadEvent, Strategy, STRATEGY_NAMES};
+use mlfq_scheduling::scheduler::partition::{PartitionRecord, Partitioned};
+use mlfq_scheduling::scheduler::replay::{self, Recorder, Recording, Settings, TraceError};
+use mlfq_scheduling::scheduler::server::{Served, ServerRecord};
+use mlfq_scheduling::scheduler::sjf::Sjf;
+use mlfq_scheduling::scheduler::stride::Stride;
+use mlfq_scheduling::scheduler::{self, Policy, POLICY_NAMES};
+use mlfq_scheduling::sim::admission::{
+    Admission, AdmissionControl, AdmissionRecord, Check, CHECK_NAMES,
+};
+use mlfq_scheduling::sim::bandwidth::QuotaRecord;
+use mlfq_scheduling::sim::generate::{self, Kind};
+use mlfq_scheduling::sim::metrics::{GroupUsage, TaskShare, TaskVruntime};
+use mlfq_scheduling::sim::multi::{Fit, MultiReport, MultiSimulator, Placement, Priority};
+use mlfq_scheduling::sim::resource::{
+    Detection, Level, Protocol, Recovery, PROTOCOL_NAMES, RECOVERY_NAMES,
+};
+use mlfq_scheduling::sim::trace::Trace;
+use mlfq_scheduling::sim::workload::{ServerKind, Workload, WorkloadError};
+use mlfq_scheduling::sim::{SimReport, Simulator};
+use output::{float, opt, Table};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/*
+ * Exit codes
+ * 0 everything ran and every gate passed
+ * 1 a gate failed: a deadline was missed (with --fail-on-miss), an
+ *   exact schedulability test rejected the task set or a replay diverged
+ * 2 bad command line usage
+ * 3 a file could not be read, parsed or written
+*/
+pub const EXIT_OK: u8 = 0;
+pub const EXIT_FAILED: u8 = 1;
+pub const EXIT_USAGE: u8 = 2;
+pub const EXIT_INPUT: u8 = 3;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "mlfq",
+    version,
+    about = "Simulate, compare and analyze task schedulers"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run one policy over a workload and report its metrics
+    Simulate(SimulateArgs),
+    /// Run several policies over the same workload side by side
+    Compare(CompareArgs),
+    /// Run schedulability tests on the periodic tasks of a workload
+    Analyze(AnalyzeArgs),
+    /// Generate a synthetic workload
+    Generate(GenerateArgs),
+    /// Render a trace as a gantt chart
+    Gantt(GanttArgs),
+    /// Feed a trace written by `mlfq simulate --record` back into the policy
+    Replay(ReplayArgs),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
+    Csv,
+}
+
+// how tasks are spread over more than one cpu
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum PlacementArg {
+    /// One ready queue feeds every cpu, jobs may migrate
+    Global,
+    /// Pack tasks onto cpus by first fit decreasing utilization
+    FirstFit,
+    /// Pack tasks onto the fullest cpu they still fit on
+    BestFit,
+    /// Pack tasks onto the emptiest cpu
+    WorstFit,
+}
+
+impl PlacementArg {
+    fn placement(self) -> Placement {
+        match self {
+            PlacementArg::Global => Placement::Global,
+            PlacementArg::FirstFit => Placement::Partitioned(Fit::First),
+            PlacementArg::BestFit => Placement::Partitioned(Fit::Best),
+            PlacementArg::WorstFit => Placement::Partitioned(Fit::Worst),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum GenerateKind {
+    Periodic,
+    Batch,
+    /// Tasks that take resources in a ring and deadlock under the priority policy
+    Deadlock,
+    /// One shot tasks that each wait for one or two earlier tasks
+    Dag,
+}
+
+// tuning of the mlfq policy, the built in default is used without either flag
+#[derive(Debug, Args)]
+struct MlfqArgs {
+    /// Json file with the mlfq levels, boost and aging settings
+    #[arg(long, conflicts_with = "mlfq_preset")]
+    mlfq_config: Option<PathBuf>,
+    /// Named mlfq configuration
+    #[arg(long, value_parser = PossibleValuesParser::new(PRESET_NAMES))]
+    mlfq_preset: Option<String>,
+}
+
+// settings of the policies that take any, the mlfq has its own
+#[derive(Debug, Args)]
+struct PolicyArgs {
+    /// Seed of the lottery policy's draws
+    #[arg(long, default_value_t = DEFAULT_SEED)]
+    seed: u64,
+    /// Weight of the last cpu burst in the prediction of sjf, srtf and hrrn, 0 to 1
+    #[arg(long, default_value_t = DEFAULT_ALPHA)]
+    alpha: f64,
+    /// What becomes of lo criticality tasks once a hi task overran its lo budget
+    #[arg(long, default_value = "drop", value_parser = PossibleValuesParser::new(DEGRADATION_NAMES))]
+    hi_mode: String,
+    /// What gives way under overload for edf, priority and rm: firm tasks skip jobs,
+    /// elastic tasks stretch their periods or the lowest value tasks are shed
+    #[arg(long, value_parser = PossibleValuesParser::new(STRATEGY_NAMES))]
+    overload: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct SimulateArgs {
+    #[arg(long, default_value = "mlfq", value_parser = PossibleValuesParser::new(POLICY_NAMES))]
+    policy: String,
+    #[arg(long)]
+    workload: PathBuf,
+    /// Ticks to simulate, defaults to a hyperperiod or until every job is done
+    #[arg(long)]
+    horizon: Option<u64>,
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// Also write the trace as json for `mlfq gantt --trace`
+    #[arg(long)]
+    trace_out: Option<PathBuf>,
+    /// Write every call into the policy and its answer as a binary trace for `mlfq replay`
+    #[arg(long)]
+    record: Option<PathBuf>,
+    /// Exit with 1 when a job misses its deadline
+    #[arg(long)]
+    fail_on_miss: bool,
+    /// Simulated cpus, more than one needs the edf, priority or rm policy
+    #[arg(long, default_value_t = 1)]
+    cpus: usize,
+    /// Global or partitioned scheduling when there is more than one cpu
+    #[arg(long, value_enum)]
+    placement: Option<PlacementArg>,
+    /// How the resources of critical sections are shared, ipcp needs priority or rm,
+    /// srp also runs with edf
+    #[arg(long, default_value = "none", value_parser = PossibleValuesParser::new(PROTOCOL_NAMES))]
+    protocol: String,
+    /// What to do with a deadlock between critical sections: report it, abort the lowest
+    /// rank job of the cycle or take its resource away and roll it back
+    #[arg(long, default_value = "report", value_parser = PossibleValuesParser::new(RECOVERY_NAMES))]
+    deadlock_recovery: String,
+    /// Look for deadlocks every this many ticks instead of whenever a job blocks
+    #[arg(long)]
+    deadlock_check: Option<u64>,
+    /// Rank every task by the longest chain of dependent work it starts, for the priority policy
+    #[arg(long)]
+    upward_rank: bool,
+    /// Check every periodic task as it arrives and reject the ones that would overload
+    /// the cpu, needs edf, priority or rm
+    #[arg(long)]
+    admission: bool,
+    /// The admission check, defaults to bound for rm, rta for priority and density for edf
+    #[arg(long, requires = "admission", value_parser = PossibleValuesParser::new(CHECK_NAMES))]
+    admission_check: Option<String>,
+    #[command(flatten)]
+    tuning: PolicyArgs,
+    #[command(flatten)]
+    mlfq: MlfqArgs,
+}
+
+#[derive(Debug, Args)]
+struct CompareArgs {
+    #[arg(long)]
+    workload: PathBuf,
+    /// Comma separated policies, defaults to all of them
+    #[arg(long, value_delimiter = ',', value_parser = PossibleValuesParser::new(POLICY_NAMES))]
+    policies: Vec<String>,
+    #[arg(long)]
+    horizon: Option<u64>,
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// Exit with 1 when any policy misses a deadline
+    #[arg(long)]
+    fail_on_miss: bool,
+    #[command(flatten)]
+    tuning: PolicyArgs,
+    #[command(flatten)]
+    mlfq: MlfqArgs,
+}
+
+#[derive(Debug, Args)]
+struct AnalyzeArgs {
+    #[arg(long)]
+    workload: PathBuf,
+    /// Comma separated tests, defaults to all but fp which needs ranks in the workload
+    #[arg(long, value_delimiter = ',', value_parser = PossibleValuesParser::new(TEST_NAMES))]
+    tests: Vec<String>,
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+}
+
+#[derive(Debug, Args)]
+struct GenerateArgs {
+    #[arg(long, value_enum, default_value = "periodic")]
+    kind: GenerateKind,
+    #[arg(long, default_value_t = 5)]
+    tasks: usize,
+    /// Total utilization of a periodic workload
+    #[arg(long, default_value_t = 0.7)]
+    utilization: f64,
+    /// Arrivals of a batch workload are spread over this many ticks
+    #[arg(long, default_value_t = 100)]
+    span: u64,
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+    /// Write the workload here instead of stdout
+    #[arg(long)]
+    out: Option<PathBuf>,
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+}
+
+#[derive(Debug, Args)]
+struct GanttArgs {
+    /// A trace written by `mlfq simulate --trace-out`
+    #[arg(long, conflicts_with_all = ["workload", "policy"], required_unless_present = "workload")]
+    trace: Option<PathBuf>,
+    /// Simulate this workload instead of reading a trace
+    #[arg(long, requires = "policy")]
+    workload: Option<PathBuf>,
+    #[arg(long, value_parser = PossibleValuesParser::new(POLICY_NAMES))]
+    policy: Option<String>,
+    #[arg(long)]
+    horizon: Option<u64>,
+    /// Columns of the chart
+    #[arg(long, default_value_t = 80)]
+    width: usize,
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    #[command(flatten)]
+    tuning: PolicyArgs,
+    #[command(flatten)]
+    mlfq: MlfqArgs,
+}
+
+// the recorded settings are replayed unless a flag is given
+#[derive(Debug, Args)]
+struct ReplayArgs {
+    /// A trace written by `mlfq simulate --record`
+    #[arg(long)]
+    trace: PathBuf,
+    /// Replay into this policy instead of the recorded one
+    #[arg(long, value_parser = PossibleValuesParser::new(POLICY_NAMES))]
+    policy: Option<String>,
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// Seed of the lottery policy's draws
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Weight of the last cpu burst in the prediction of sjf, srtf and hrrn, 0 to 1
+    #[arg(long)]
+    alpha: Option<f64>,
+    /// What becomes of lo criticality tasks once a hi task overran its lo budget
+    #[arg(long, value_parser = PossibleValuesParser::new(DEGRADATION_NAMES))]
+    hi_mode: Option<String>,
+    /// What gives way under overload for edf, priority and rm
+    #[arg(long, value_parser = PossibleValuesParser::new(STRATEGY_NAMES), conflicts_with = "no_overload")]
+    overload: Option<String>,
+    /// Replay without the recorded overload strategy
+    #[arg(long)]
+    no_overload: bool,
+    #[command(flatten)]
+    mlfq: MlfqArgs,
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    Workload(WorkloadError),
+    Config(ConfigError),
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, serde_json::Error),
+    Trace(PathBuf, TraceError),
+    Usage(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Workload(err) => write!(f, "{err}"),
+            CliError::Config(err) => write!(f, "{err}"),
+            CliError::Io(path, err) => write!(f, "{}: {err}", path.display()),
+            CliError::Parse(path, err) => write!(f, "{}: {err}", path.display()),
+            CliError::Trace(path, err) => write!(f, "{}: {err}", path.display()),
+            CliError::Usage(msg) => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Usage(_) => EXIT_USAGE,
+            _ => EXIT_INPUT,
+        }
+    }
+}
+
+// runs a parsed command line and returns the exit code
+pub fn run(cli: Cli) -> Result<u8, CliError> {
+    match cli.command {
+        Command::Simulate(args) => simulate(args),
+        Command::Compare(args) => compare(args),
+        Command::Analyze(args) => analyze(args),
+        Command::Generate(args) => generate(args),
+        Command::Gantt(args) => gantt(args),
+        Command::Replay(args) => replay(args),
+    }
+}
+
+fn load(path: &Path) -> Result<Workload, CliError> {
+    Workload::load(path).map_err(CliError::Workload)
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), CliError> {
+    std::fs::write(path, contents).map_err(|err| CliError::Io(path.to_path_buf(), err))
+}
+
+fn json<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_string_pretty(value).expect("reports always serialize") + "\n"
+}
+
+impl PolicyArgs {
+    fn check(&self) -> Result<(), CliError> {
+        if !(0.0..=1.0).contains(&self.alpha) {
+            return Err(CliError::Usage("--alpha must be between 0 and 1".into()));
+        }
+        Ok(())
+    }
+}
+
+impl PolicyArgs {
+    // what goes into a trace's header, the mlfq config is the one in effect
+    fn settings(&self, mlfq: Option<&MlfqConfig>) -> Settings {
+        Settings {
+            mlfq: mlfq.cloned(),
+            seed: self.seed,
+            alpha: self.alpha,
+            hi_mode: self.hi_mode.clone(),
+            overload: self.overload.clone(),
+        }
+    }
+}
+
+impl ReplayArgs {
+    // the recorded settings with the flags given on top
+    fn settings(&self, recorded: &Settings) -> Result<(PolicyArgs, Option<MlfqConfig>), CliError> {
+        let tuning = PolicyArgs {
+            seed: self.seed.unwrap_or(recorded.seed),
+            alpha: self.alpha.unwrap_or(recorded.alpha),
+            hi_mode: self
+                .hi_mode
+                .clone()
+                .unwrap_or_else(|| recorded.hi_mode.clone()),
+            overload: match (&self.overload, self.no_overload) {
+                (Some(strategy), _) => Some(strategy.clone()),
+                (None, true) => None,
+                (None, false) => recorded.overload.clone(),
+            },
+        };
+        // names from a trace are not validated by clap
+        if Degradation::by_name(&tuning.hi_mode).is_none() {
+            return Err(CliError::Usage(format!(
+                "the trace was recorded with hi mode {}, pick one with --hi-mode",
+                tuning.hi_mode
+            )));
+        }
+        if let Some(strategy) = &tuning.overload {
+            if Strategy::by_name(strategy).is_none() {
+                return Err(CliError::Usage(format!(
+                    "the trace was recorded with overload strategy {strategy}, \
+                     pick one with --overload or leave it out with --no-overload"
+                )));
+            }
+        }
+        tuning.check()?;
+        let mlfq = match (self.mlfq.config()?, &recorded.mlfq) {
+            (Some(config), _) => Some(config),
+            (None, Some(config)) => {
+                config.validate().map_err(CliError::Config)?;
+                Some(config.clone())
+            }
+            (None, None) => None,
+        };
+        Ok((tuning, mlfq))
+    }
+}
+
+impl MlfqArgs {
+    fn config(&self) -> Result<Option<MlfqConfig>, CliError> {
+        let config = match (&self.mlfq_config, &self.mlfq_preset) {
+            (Some(path), _) => MlfqConfig::load(path),
+            (None, Some(name)) => MlfqConfig::preset(name),
+            (None, None) => return Ok(None),
+        };
+        config.map(Some).map_err(CliError::Config)
+    }
+}
+
+fn build_policy(
+    name: &str,
+    workload: &Workload,
+    mlfq: Option<&MlfqConfig>,
+    tuning: &PolicyArgs,
+) -> Box<dyn Policy> {
+    match (name, mlfq) {
+        ("mlfq", Some(config)) => Box::new(Mlfq::from_config(config.clone())),
+        // every leaf group orders its tasks with its own mlfq
+        ("fair", config) => Box::new(FairShare::from_workload(workload, || match config {
+            Some(config) => Box::new(Mlfq::from_config(config.clone())),
+            None => Box::new(Mlfq::new()),
+        })),
+        // tickets and currencies come from the workload
+        ("lottery", _) => Box::new(Lottery::from_workload(workload).with_seed(tuning.seed)),
+        ("stride", _) => Box::new(Stride::from_workload(workload)),
+        ("sjf", _) => Box::new(Sjf::new().with_predictor(BurstPredictor::new(tuning.alpha))),
+        ("srtf", _) => Box::new(Sjf::srtf().with_predictor(BurstPredictor::new(tuning.alpha))),
+        ("hrrn", _) => Box::new(Hrrn::new().with_predictor(BurstPredictor::new(tuning.alpha))),
+        // every partition gets its policy with the same settings
+        ("partitioned", _) => Box::new(Partitioned::from_workload(workload, |inner| {
+            build_policy(inner, workload, mlfq, tuning)
+        })),
+        _ => scheduler::by_name(name).expect("policy names are validated by clap"),
+    }
+}
+
+// the policy with the wrappers the workload and the settings ask for
+fn policy_stack(
+    name: &str,
+    workload: &Workload,
+    mlfq: Option<&MlfqConfig>,
+    tuning: &PolicyArgs,
+) -> Box<dyn Policy> {
+    let policy = build_policy(name, workload, mlfq, tuning);
+    let realtime = matches!(name, "edf" | "priority" | "rm");
+    let policy: Box<dyn Policy> = match &tuning.overload {
+        Some(strategy) if realtime => {
+            let strategy = Strategy::by_name(strategy).expect("names are validated by clap");
+            Box::new(Overload::new(policy, workload, strategy))
+        }
+        _ => policy,
+    };
+    // hi tasks are watched for overruns under the real-time policies
+    let policy: Box<dyn Policy> = if workload.is_mixed_criticality() && realtime {
+        let degradation =
+            Degradation::by_name(&tuning.hi_mode).expect("names are validated by clap");
+        Box::new(MixedCriticality::new(policy, workload).with_degradation(degradation))
+    } else {
+        policy
+    };
+    // aperiodic tasks go through their servers, see check_servers
+    if workload.servers.is_empty() {
+        policy
+    } else {
+        Box::new(Served::new(policy, workload))
+    }
+}
+
+fn run_policy(
+    policy: Box<dyn Policy>,
+    name: &str,
+    workload: &Workload,
+    horizon: Option<u64>,
+    protocol: Protocol,
+    deadlock: (Detection, Recovery),
+) -> SimReport {
+    let level = Level::by_policy(name, protocol).expect("protocol is checked against the policy");
+    Simulator::new(policy)
+        .with_horizon(horizon)
+        .with_protocol(protocol, level)
+        .with_deadlock(deadlock.0, deadlock.1)
+        .run(workload)
+}
+
+// a cbs needs edf, the other servers a fixed priority policy
+fn hosts_servers(policy: &str, workload: &Workload) -> bool {
+    workload.servers.iter().all(|server| match server.kind {
+        ServerKind::Cbs => policy == "edf",
+        _ => policy == "priority" || policy == "rm",
+    })
+}
+
+fn check_servers(policy: &str, workload: &Workload) -> Result<(), CliError> {
+    if hosts_servers(policy, workload) {
+        return Ok(());
+    }
+    Err(CliError::Usage(format!(
+        "policy {policy} cannot run the servers of the workload, \
+         cbs servers need edf, polling, deferrable and sporadic ones priority or rm"
+    )))
+}
+
+// the admission control asked for, its check has to hold for the policy
+fn admission_control(args: &SimulateArgs) -> Result<Option<AdmissionControl>, CliError> {
+    if !args.admission {
+        return Ok(None);
+    }
+    let admission = AdmissionControl::for_policy(&args.policy).ok_or_else(|| {
+        CliError::Usage(format!(
+            "policy {} has no admission check, use edf, priority or rm",
+            args.policy
+        ))
+    })?;
+    let Some(name) = &args.admission_check else {
+        return Ok(Some(admission));
+    };
+    let check = Check::by_name(name).expect("check names are validated by clap");
+    if !check.holds_for(&args.policy) {
+        return Err(CliError::Usage(format!(
+            "admission check {name} does not hold for policy {}",
+            args.policy
+        )));
+    }
+    Ok(Some(admission.with_check(check)))
+}
+
+fn simulate(args: SimulateArgs) -> Result<u8, CliError> {
+    if args.cpus == 0 {
+        return Err(CliError::Usage("--cpus must be at least 1".into()));
+    }
+    let protocol = Protocol::by_name(&args.protocol).expect("protocol names are validated by clap");
+    if Level::by_policy(&args.policy, protocol).is_none() {
+        return Err(CliError::Usage(format!(
+            "protocol {} cannot run with policy {}, use {}",
+            protocol.name(),
+            args.policy,
+            if protocol == Protocol::Srp {
+                "priority, rm or edf"
+            } else {
+                "priority or rm"
+            }
+        )));
+    }
+    if args.deadlock_check == Some(0) {
+        return Err(CliError::Usage(
+            "--deadlock-check must be at least 1".into(),
+        ));
+    }
+    let deadlock = (
+        args.deadlock_check
+            .map_or(Detection::Continuous, Detection::Every),
+        Recovery::by_name(&args.deadlock_recovery).expect("recovery names are validated by clap"),
+    );
+    if args.upward_rank && args.policy != "priority" {
+        return Err(CliError::Usage(
+            "--upward-rank sets the ranks of the priority policy".into(),
+        ));
+    }
+    let mut workload = load(&args.workload)?;
+    if args.upward_rank {
+        workload
+            .rank_by_upward_rank()
+            .expect("a loaded workload has no dependency cycle");
+    }
+    check_servers(&args.policy, &workload)?;
+    args.tuning.check()?;
+    let mlfq = args.mlfq.config()?;
+    let admission = admission_control(&args)?;
+    if args.tuning.overload.is_some() && Test::by_policy(&args.policy).is_none() {
+        return Err(CliError::Usage(format!(
+            "policy {} has no deadlines to manage overload for, use edf, priority or rm",
+            args.policy
+        )));
+    }
+    let multi = match (args.cpus, args.placement) {
+        (1, None) => None,
+        (_, _) if args.tuning.overload.is_some() => {
+            return Err(CliError::Usage(
+                "overload strategies are only simulated on one cpu".into(),
+            ))
+        }
+        (_, _) if args.record.is_some() => {
+            return Err(CliError::Usage(
+                "traces are only recorded on one cpu".into(),
+            ))
+        }
+        (_, _) if admission.is_some() => {
+            return Err(CliError::Usage(
+                "admission control is only simulated on one cpu".into(),
+            ))
+        }
+        (_, _) if protocol != Protocol::None => {
+            return Err(CliError::Usage(
+                "resource protocols are only simulated on one cpu".into(),
+            ))
+        }
+        (_, _) if workload.has_quotas() => {
+            return Err(CliError::Usage(
+                "cpu quotas are only simulated on one cpu".into(),
+            ))
+        }
+        (_, _) if !workload.servers.is_empty() => {
+            return Err(CliError::Usage(
+                "aperiodic servers are only simulated on one cpu".into(),
+            ))
+        }
+        (cpus, placement) => {
+            let priority = Priority::by_policy(&args.policy).ok_or_else(|| {
+                CliError::Usage(format!(
+                    "policy {} cannot run on more than one cpu, use edf, priority or rm",
+                    args.policy
+                ))
+            })?;
+            let placement = placement.unwrap_or(PlacementArg::Global).placement();
+            Some(
+                MultiSimulator::new(cpus, priority, placement)
+                    .with_horizon(args.horizon)
+                    .run(&workload),
+            )
+        }
+    };
+    // rejected tasks are left out, degraded ones run with their fallback
+    let admissions = match admission {
+        Some(mut admission) => {
+            let (admitted, records) = admission.admit_workload(&workload);
+            workload = admitted;
+            records
+        }
+        None => Vec::new(),
+    };
+    let mut recorded = None;
+    let report = match &multi {
+        Some(multi) => multi.report.clone(),
+        None => {
+            let mut policy = policy_stack(&args.policy, &workload, mlfq.as_ref(), &args.tuning);
+            if args.record.is_some() {
+                let settings = args.tuning.settings(mlfq.as_ref());
+                let recorder = Recorder::new(policy, &workload, &settings);
+                recorded = Some(recorder.buffer());
+                policy = Box::new(recorder);
+            }
+            SimReport {
+                admissions,
+                ..run_policy(
+                    policy,
+                    &args.policy,
+                    &workload,
+                    args.horizon,
+                    protocol,
+                    deadlock,
+                )
+            }
+        }
+    };
+    if let Some(path) = &args.trace_out {
+        write_file(path, &json(&report.trace))?;
+    }
+    if let (Some(path), Some(recorded)) = (&args.record, recorded) {
+        std::fs::write(path, recorded.bytes()).map_err(|err| CliError::Io(path.clone(), err))?;
+    }
+
+    let mut jobs = Table::new(&[
+        "task",
+        "job",
+        "release",
+        "burst",
+        "first_run",
+        "finish",
+        "turnaround",
+        "waiting",
+        "response",
+        "deadline",
+        "missed",
+    ]);
+    for job in &report.jobs {
+        jobs.push(vec![
+            job.task.to_string(),
+            job.job.to_string(),
+            job.release.to_string(),
+            job.burst.to_string(),
+            opt(job.first_run),
+            opt(job.finish),
+            opt(job.turnaround()),
+            opt(job.waiting()),
+            opt(job.response()),
+            opt(job.abs_deadline),
+            job.missed.to_string(),
+        ]);
+    }
+    match args.format.unwrap_or(Format::Text) {
+        Format::Text => {
+            let m = &report.metrics;
+            println!("policy {} over {} ticks", report.policy, report.horizon);
+            println!(
+                "jobs {}  completed {}  deadline misses {}",
+                m.jobs, m.completed, m.deadline_misses
+            );
+            println!(
+                "avg turnaround {}  avg waiting {}  avg response {}  max response {}",
+                float(m.avg_turnaround),
+                float(m.avg_waiting),
+                float(m.avg_response),
+                m.max_response
+            );
+            println!(
+                "throughput {:.4} jobs/tick  utilization {:.1}%  context switches {}  preemptions {}",
+                m.throughput,
+                m.utilization * 100.0,
+                m.context_switches,
+                m.preemptions
+            );
+            if let Some(error) = m.prediction_error {
+                println!("burst prediction error {} ticks", float(error));
+            }
+            if protocol != Protocol::None || m.contended > 0 {
+                println!(
+                    "protocol {}  resource contended {} times",
+                    protocol.name(),
+                    m.contended
+                );
+            }
+            for deadlock in &report.deadlocks {
+                let cycle: Vec<String> = deadlock
+                    .tasks
+                    .iter()
+                    .zip(&deadlock.resources)
+                    .map(|(task, resource)| format!("{task} waits for {resource}"))
+                    .collect();
+                let victim = deadlock
+                    .victim
+                    .map(|task| format!(" task {task}"))
+                    .unwrap_or_default();
+                println!(
+                    "deadlock at {}: {}, {}{victim}",
+                    deadlock.at,
+                    cycle.join(", "),
+                    deadlock.recovery
+                );
+            }
+            if let Some(multi) = &multi {
+                print_cpus(multi);
+            }
+            if !report.groups.is_empty() {
+                print_groups(&report.groups);
+            }
+            if !report.quotas.is_empty() {
+                print_quotas(&report.quotas);
+            }
+            if !report.servers.is_empty() {
+                print_servers(&report.servers);
+            }
+            if !report.mode_switches.is_empty() {
+                print_mode_switches(&report.mode_switches, m.skipped);
+            }
+            if !report.partitions.is_empty() {
+                print_partitions(&report.partitions);
+            }
+            if !report.admissions.is_empty() {
+                print_admissions(&report.admissions);
+            }
+            if !report.overloads.is_empty() {
+                print_overloads(&report.overloads, m.skipped);
+            }
+            if !report.vruntimes.is_empty() {
+                print_vruntimes(&report.vruntimes);
+            }
+            if !report.shares.is_empty() {
+                print_shares(&report.shares);
+            }
+            if !report.predictions.is_empty() {
+                print_predictions(&report.predictions);
+            }
+            println!();
+            print!("{}", jobs.text());
+        }
+        Format::Json => match &multi {
+            Some(multi) => print!("{}", json(multi)),
+            None => print!("{}", json(&report)),
+        },
+        Format::Csv => print!("{}", jobs.csv()),
+    }
+    Ok(gate(
+        args.fail_on_miss && report.metrics.deadline_misses > 0,
+    ))
+}
+
+fn print_cpus(multi: &MultiReport) {
+    println!(
+        "cpus {}  migrations {}",
+        multi.cpus.len(),
+        multi.report.metrics.migrations
+    );
+    if let Some(partition) = multi.partition.as_ref().filter(|p| !p.is_feasible()) {
+        println!(
+            "tasks {:?} fit on no cpu and were put on the emptiest one",
+            partition.unplaced
+        );
+    }
+    let mut table = Table::new(&["cpu", "busy", "utilization", "tasks", "assigned"]);
+    for cpu in &multi.cpus {
+        let tasks: Vec<String> = cpu.tasks.iter().map(i32::to_string).collect();
+        table.push(vec![
+            cpu.cpu.to_string(),
+            cpu.busy.to_string(),
+            float(cpu.utilization),
+            tasks.join(" "),
+            cpu.assigned_utilization.map(float).unwrap_or_default(),
+        ]);
+    }
+    println!();
+    print!("{}", table.text());
+}
+
+fn print_groups(groups: &[GroupUsage]) {
+    let mut table = Table::new(&["group", "parent", "weight", "busy", "share"]);
+    for group in groups {
+        table.push(vec![
+            group.group.clone(),
+            group.parent.clone().unwrap_or_default(),
+            group.weight.to_string(),
+            group.busy.to_string(),
+            float(group.share),
+        ]);
+    }
+    println!();
+    print!("{}", table.text());
+}
+
+fn print_quotas(quotas: &[QuotaRecord]) {
+    let mut table = Table::new(&["quota", "budget", "period", "throttles", "throttled"]);
+    for quota in quotas {
+        table.push(vec![
+            quota.owner.clone(),
+            quota.budget.to_string(),
+            quota.period.to_string(),
+            quota.throttles.to_string(),
+            quota.throttled.to_string(),
+        ]);
+    }
+    println!();
+    print!("{}", table.text());
+}
+
+fn print_servers(servers: &[ServerRecord]) {
+    let mut table = Table::new(&["server", "kind", "budget", "period", "served", "exhausted"]);
+    for server in servers {
+        table.push(vec![
+            server.name.clone(),
+            server.kind.name().to_string(),
+            server.budget.to_string(),
+            server.period.to_string(),
+            server.served.to_string(),
+            server.exhausted.to_string(),
+        ]);
+    }
+    println!();
+    print!("{}", table.text());
+}
+
+fn print_partition_results(partitions: &[PartitionResult]) {
+    let mut table = Table::new(&[
+        "partition",
+        "policy",
+        "budget",
+        "period",
+        "utilization",
+        "min_budget",
+        "schedulable",
+    ]);
+    for partition in partitions {
+        table.push(vec![
+            partition.name.clone(),
+            partition.policy.clone(),
+            partition.budget.to_string(),
+            partition.period.to_string(),
+            format!("{:.3}", partition.utilization),
+            opt(partition.min_budget),
+            opt(partition.schedulable),
+        ]);
+    }
+    println!();
+    print!("{}", table.text());
+}
+
+fn print_partitions(partitions: &[PartitionRecord]) {
+    let mut table = Table::new(&["partition", "policy", "budget", "period", "used"]);
+    for partition in partitions {
+        table.push(vec![
+            partition.name.clone(),
+            partition.policy.clone(),
+            partition.budget.to_string(),
+            partition.period.to_string(),
+            partition.used.to_string(),
+        ]);
+    }
+    println!();
+    print!("{}", table.text());
+}
+
+fn print_admissions(admissions: &[AdmissionRecord]) {
+    let mut table = Table::new(&["task", "at", "outcome", "period", "rank", "reason"]);
+    for record in admissions {
+        let (outcome, period, rank, reason) = match &record.admission {
+            Admission::Admitted => ("admitted", String::new(), String::new(), ""),
+            Admission::Degraded {
+                period,
+                rank,
+                reason,
+            } => (
+                "degraded",
+                period.to_string(),
+                rank.to_string(),
+                reason.as_str(),
+            ),
+            Admission::Rejected { reason } => {
+                ("rejected", String::new(), String::new(), reason.as_str())
+            }
+        };
+        table.push(vec![
+            record.task.to_string(),
+            record.at.to_string(),
+            outcome.to_string(),
+            period,
+            rank,
+            reason.to_string(),
+        ]);
+    }
+    println!();
+    print!("{}", table.text());
+}
+
+fn print_overloads(events: &[OverloadEvent], skipped: usize) {
+    println!();
+    println!(
+        "overload decisions {}  skipped jobs {skipped}",
+        events.len()
+    );
+    let mut table = Table::new(&["at", "task", "job", "action", "period", "reason"]);
+    for event in events {
+        table.push(vec![
+            event.at.to_string(),
+            event.task.to_string(),
+            opt(event.job),
+            event.action.name().to_string(),
+            opt(event.period),
+            event.reason.clone(),
+        ]);
+    }
+    print!("{}", table.text());
+}
+
+fn print_mode_switches(switches: &[ModeSwitch], skipped: usize) {
+    println!();
+    println!("mode switches {}  skipped jobs {skipped}", switches.len());
+    let mut table = Table::new(&["at", "mode", "overrun by"]);
+    for switch in switches {
+        table.push(vec![
+            switch.at.to_string(),
+            switch.to.name().to_string(),
+            opt(switch.task),
+        ]);
+    }
+    print!("{}", table.text());
+}
+
+fn print_vruntimes(vruntimes: &[TaskVruntime]) {
+    let mut table = Table::new(&["task", "nice", "vruntime"]);
+    for task in vruntimes {
+        table.push(vec![
+            task.task.to_string(),
+            task.nice.to_string(),
+            float(task.vruntime),
+        ]);
+    }
+    println!();
+    print!("{}", table.text());
+}
+
+fn print_shares(shares: &[TaskShare]) {
+    let mut table = Table::new(&["task", "tickets", "requested", "measured"]);
+    for share in shares {
+        table.push(vec![
+            share.task.to_string(),
+            float(share.tickets),
+            float(share.requested),
+            float(share.measured),
+        ]);
+    }
+    println!();
+    print!("{}", table.text());
+}
+
+fn print_predictions(predictions: &[Prediction]) {
+    let mut table = Table::new(&["task", "bursts", "mean_error", "next"]);
+    for prediction in predictions {
+        table.push(vec![
+            prediction.task.to_string(),
+            prediction.bursts.to_string(),
+            float(prediction.mean_error),
+            float(prediction.next),
+        ]);
+    }
+    println!();
+    print!("{}", table.text());
+}
+
+fn compare(args: CompareArgs) -> Result<u8, CliError> {
+    let workload = load(&args.workload)?;
+    args.tuning.check()?;
+    let mlfq = args.mlfq.config()?;
+    // by default only the policies that can run the workload's servers
+    let policies: Vec<&str> = if args.policies.is_empty() {
+        POLICY_NAMES
+            .iter()
+            .copied()
+            .filter(|name| hosts_servers(name, &workload))
+            .collect()
+    } else {
+        args.policies.iter().map(String::as_str).collect()
+    };
+    for name in &policies {
+        check_servers(name, &workload)?;
+    }
+    let reports: Vec<SimReport> = policies
+        .iter()
+        .map(|name| {
+            run_policy(
+                policy_stack(name, &workload, mlfq.as_ref(), &args.tuning),
+                name,
+                &workload,
+                args.horizon,
+                Protocol::None,
+                Default::default(),
+            )
+        })
+        .collect();
+
+    let mut table = Table::new(&[
+        "policy",
+        "completed",
+        "avg_turnaround",
+        "avg_waiting",
+        "avg_response",
+        "max_response",
+        "throughput",
+        "utilization",
+        "switches",
+        "preemptions",
+        "misses",
+    ]);
+    for report in &reports {
+        let m = &report.metrics;
+        table.push(vec![
+            report.policy.clone(),
+            format!("{}/{}", m.completed, m.jobs),
+            float(m.avg_turnaround),
+            float(m.avg_waiting),
+            float(m.avg_response),
+            m.max_response.to_string(),
+            format!("{:.4}", m.throughput),
+            float(m.utilization),
+            m.context_switches.to_string(),
+            m.preemptions.to_string(),
+            m.deadline_misses.to_string(),
+        ]);
+    }
+    match args.format.unwrap_or(Format::Text) {
+        Format::Text => print!("{}", table.text()),
+        Format::Json => {
+            let metrics: Vec<_> = reports
+                .iter()
+                .map(|r| serde_json::json!({ "policy": r.policy, "metrics": r.metrics }))
+                .collect();
+            print!("{}", json(&metrics));
+        }
+        Format::Csv => print!("{}", table.csv()),
+    }
+    let missed = reports.iter().any(|r| r.metrics.deadline_misses > 0);
+    Ok(gate(args.fail_on_miss && missed))
+}
+
+fn analyze(args: AnalyzeArgs) -> Result<u8, CliError> {
+    let workload = load(&args.workload)?;
+    let tasks = analysis::periodic_tasks(&workload);
+    if tasks.is_empty() {
+        return Err(CliError::Usage(format!(
+            "{}: no periodic tasks to analyze",
+            args.workload.display()
+        )));
+    }
+    // the mixed criticality tests only by default when there are hi tasks
+    let mixed = workload.is_mixed_criticality();
+    let names: Vec<&str> = if args.tests.is_empty() {
+        TEST_NAMES
+            .iter()
+            .copied()
+            .filter(|name| *name != "fp" && (mixed || !matches!(*name, "amc" | "edf-vd")))
+            .collect()
+    } else {
+        args.tests.iter().map(String::as_str).collect()
+    };
+    // critical sections in the workload add their blocking under ipcp or srp
+    let results: Vec<_> = names
+        .iter()
+        .map(|name| {
+            let test = Test::by_name(name).unwrap();
+            analysis::run(test, &analysis::with_blocking(&tasks, &workload, test))
+        })
+        .collect();
+
+    // every partition against its reservation, see analysis::schedulable_on
+    let partitions = analysis::partitions(&workload);
+
+    let mut table = Table::new(&[
+        "test",
+        "task",
+        "wcet",
+        "period",
+        "deadline",
+        "blocking",
+        "response",
+        "schedulable",
+    ]);
+    for result in &results {
+        for task in &result.tasks {
+            table.push(vec![
+                result.test.clone(),
+                task.id.to_string(),
+                task.wcet.to_string(),
+                task.period.to_string(),
+                task.deadline.to_string(),
+                task.blocking.to_string(),
+                opt(task.response),
+                result.schedulable.to_string(),
+            ]);
+        }
+    }
+    match args.format.unwrap_or(Format::Text) {
+        Format::Text => {
+            println!(
+                "{} periodic tasks, utilization {:.3}",
+                tasks.len(),
+                analysis::utilization(&tasks)
+            );
+            for result in &results {
+                let bound = result
+                    .bound
+                    .map(|b| format!(" (bound {b:.3})"))
+                    .unwrap_or_default();
+                let bound = match result.virtual_deadline {
+                    Some(x) => format!("{bound} (virtual deadlines at {x:.3})"),
+                    None => bound,
+                };
+                let verdict = match (result.schedulable, result.exact) {
+                    (true, _) => "schedulable",
+                    (false, true) => "not schedulable",
+                    (false, false) => "inconclusive",
+                };
+                println!("{:>4}: {verdict}{bound}", result.test);
+            }
+            println!();
+            print!("{}", table.text());
+            if !partitions.is_empty() {
+                print_partition_results(&partitions);
+            }
+        }
+        Format::Json if partitions.is_empty() => print!("{}", json(&results)),
+        Format::Json => print!(
+            "{}",
+            json(&serde_json::json!({ "tests": results, "partitions": partitions }))
+        ),
+        Format::Csv => print!("{}", table.csv()),
+    }
+    // a sufficient test that fails proves nothing so it does not fail the gate
+    let unschedulable = results.iter().any(|r| r.exact && !r.schedulable)
+        || partitions.iter().any(|p| p.schedulable == Some(false));
+    Ok(gate(unschedulable))
+}
+
+fn generate(args: GenerateArgs) -> Result<u8, CliError> {
+    if args.utilization.is_nan() || args.utilization <= 0.0 {
+        return Err(CliError::Usage("--utilization must be above 0".into()));
+    }
+    let options = generate::Options {
+        kind: match args.kind {
+            GenerateKind::Periodic => Kind::Periodic,
+            GenerateKind::Batch => Kind::Batch,
+            GenerateKind::Deadlock => Kind::Deadlock,
+            GenerateKind::Dag => Kind::Dag,
+        },
+        tasks: args.tasks,
+        utilization: args.utilization,
+        span: args.span,
+        seed: args.seed,
+    };
+    let workload = generate::generate(&options);
+
+    let mut table = Table::new(&[
+        "id", "rank", "arrival", "burst", "period", "deadline", "after",
+    ]);
+    for spec in &workload.tasks {
+        let after: Vec<String> = spec.after.iter().map(i32::to_string).collect();
+        table.push(vec![
+            spec.id.to_string(),
+            spec.rank.to_string(),
+            spec.arrival.to_string(),
+            spec.burst.to_string(),
+            opt(spec.period),
+            opt(spec.deadline),
+            after.join(" "),
+        ]);
+    }
+    let rendered = match args.format.unwrap_or(Format::Json) {
+        Format::Text => table.text(),
+        Format::Json => json(&workload),
+        Format::Csv => table.csv(),
+    };
+    match &args.out {
+        Some(path) => write_file(path, &rendered)?,
+        None => print!("{rendered}"),
+    }
+    Ok(EXIT_OK)
+}
+
+fn gantt(args: GanttArgs) -> Result<u8, CliError> {
+    let trace: Trace = match (&args.trace, &args.workload, &args.policy) {
+        (Some(path), _, _) => {
+            let text =
+                std::fs::read_to_string(path).map_err(|err| CliError::Io(path.clone(), err))?;
+            serde_json::from_str(&text).map_err(|err| CliError::Parse(path.clone(), err))?
+        }
+        (None, Some(path), Some(policy)) => {
+            args.tuning.check()?;
+            let mlfq = args.mlfq.config()?;
+            let workload = load(path)?;
+            check_servers(policy, &workload)?;
+            run_policy(
+                policy_stack(policy, &workload, mlfq.as_ref(), &args.tuning),
+                policy,
+                &workload,
+                args.horizon,
+                Protocol::None,
+                Default::default(),
+            )
+            .trace
+        }
+        _ => {
+            return Err(CliError::Usage(
+                "gantt needs --trace or --workload and --policy".into(),
+            ))
+        }
+    };
+
+    let mut table = Table::new(&["cpu", "start", "end", "task"]);
+    for slice in &trace.slices {
+        table.push(vec![
+            slice.cpu.to_string(),
+            slice.start.to_string(),
+            slice.end.to_string(),
+            opt(slice.task),
+        ]);
+    }
+    match args.format.unwrap_or(Format::Text) {
+        Format::Text => print!("{}", trace.gantt(args.width)),
+        Format::Json => print!("{}", json(&trace)),
+        Format::Csv => print!("{}", table.csv()),
+    }
+    Ok(EXIT_OK)
+}
+
+fn replay(args: ReplayArgs) -> Result<u8, CliError> {
+    let bytes = std::fs::read(&args.trace).map_err(|err| CliError::Io(args.trace.clone(), err))?;
+    let recording =
+        Recording::decode(&bytes).map_err(|err| CliError::Trace(args.trace.clone(), err))?;
+    let name = args.policy.as_deref().unwrap_or(&recording.policy);
+    if !POLICY_NAMES.contains(&name) {
+        return Err(CliError::Usage(format!(
+            "the trace was recorded with policy {name}, pick one with --policy"
+        )));
+    }
+    check_servers(name, &recording.workload)?;
+    let (tuning, mlfq) = args.settings(&recording.settings)?;
+    let mut policy = policy_stack(name, &recording.workload, mlfq.as_ref(), &tuning);
+    let report = replay::replay(&recording, policy.as_mut());
+
+    let mut table = Table::new(&["step", "at", "call", "recorded", "replayed"]);
+    if let Some(divergence) = &report.divergence {
+        table.push(vec![
+            divergence.step.to_string(),
+            divergence.at.to_string(),
+            divergence.call.to_string(),
+            divergence.recorded.clone(),
+            divergence.replayed.clone(),
+        ]);
+    }
+    match args.format.unwrap_or(Format::Text) {
+        Format::Text => {
+            println!(
+                "replayed {} of {} steps recorded with {} into {name}",
+                report.replayed, report.steps, report.policy
+            );
+            if let Some(divergence) = &report.divergence {
+                println!(
+                    "diverged at step {} (tick {}) on {}: recorded {}, replayed {}",
+                    divergence.step,
+                    divergence.at,
+                    divergence.call,
+                    divergence.recorded,
+                    divergence.replayed
+                );
+            }
+        }
+        Format::Json => print!("{}", json(&report)),
+        Format::Csv => print!("{}", table.csv()),
+    }
+    Ok(gate(report.divergence.is_some()))
+}
+
+fn gate(failed: bool) -> u8 {
+    if failed {
+        EXIT_FAILED
+    } else {
+        EXIT_OK
+    }
+}
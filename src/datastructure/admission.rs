@@ -0,0 +1,249 @@
+use super::avl::AvlTree;
+use super::Task;
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/*
+ * Bounded lock-free multi producer, multi consumer queue (Vyukov ring).
+ *
+ * Producers admit tasks from any thread without taking a lock, the scheduler
+ * thread drains them in batches into the rank buckets of its tree. Every slot
+ * carries a sequence number:
+ * - seq == pos      the slot is free for the producer that claims `pos`
+ * - seq == pos + 1  the slot holds a value for the consumer that claims `pos`
+ * A producer claims a position with a compare exchange on `tail`, writes the
+ * value and then publishes it by storing pos + 1 into the sequence. The
+ * consumer does the same on `head` and hands the slot back to the producers
+ * one lap later by storing pos + capacity. Nobody ever waits on somebody
+ * else, a full queue hands the value back to the caller instead.
+*/
+pub struct AdmissionQueue<T> {
+    slots: Box<[Slot<T>]>,
+    mask: usize,
+    head: Padded<AtomicUsize>,
+    tail: Padded<AtomicUsize>,
+}
+
+struct Slot<T> {
+    seq: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+// keeps head and tail on their own cache lines so producers and the consumer
+// do not invalidate each other
+#[repr(align(64))]
+struct Padded<T>(T);
+
+// values only move between threads through the sequence handshake
+unsafe impl<T: Send> Send for AdmissionQueue<T> {}
+unsafe impl<T: Send> Sync for AdmissionQueue<T> {}
+
+impl<T> AdmissionQueue<T> {
+    // capacity is rounded up to a power of two
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(2).next_power_of_two();
+        let slots = (0..capacity)
+            .map(|seq| Slot {
+                seq: AtomicUsize::new(seq),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            })
+            .collect();
+        AdmissionQueue {
+            slots,
+            mask: capacity - 1,
+            head: Padded(AtomicUsize::new(0)),
+            tail: Padded(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    // hands the value back when the queue is full
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut pos = self.tail.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq as isize - pos as isize;
+            if diff == 0 {
+                match self.tail.0.compare_exchange_weak(
+                    pos,
+                    pos + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.seq.store(pos + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                // the consumer has not freed this slot from the last lap yet
+                return Err(value);
+            } else {
+                pos = self.tail.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut pos = self.head.0.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let seq = slot.seq.load(Ordering::Acquire);
+            let diff = seq as isize - (pos + 1) as isize;
+            if diff == 0 {
+                match self.head.0.compare_exchange_weak(
+                    pos,
+                    pos + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        slot.seq.store(pos + self.mask + 1, Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                return None;
+            } else {
+                pos = self.head.0.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    // snapshot, producers may be adding while it is read
+    pub fn len(&self) -> usize {
+        let tail = self.tail.0.load(Ordering::Acquire);
+        let head = self.head.0.load(Ordering::Acquire);
+        tail.saturating_sub(head).min(self.capacity())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // pops up to `max` values, returns how many were handed to `sink`
+    pub fn drain(&self, max: usize, mut sink: impl FnMut(T)) -> usize {
+        let mut count = 0;
+        while count < max {
+            let Some(value) = self.pop() else {
+                break;
+            };
+            sink(value);
+            count += 1;
+        }
+        count
+    }
+}
+
+impl AdmissionQueue<Task> {
+    // moves up to `max` admitted tasks into the rank buckets of the tree
+    pub fn drain_into(&self, tree: &mut AvlTree, max: usize) -> usize {
+        self.drain(max, |task| tree.insert(task))
+    }
+}
+
+impl<T> Drop for AdmissionQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T> std::fmt::Debug for AdmissionQueue<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AdmissionQueue")
+            .field("capacity", &self.capacity())
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn fifo_and_full() {
+        let queue = AdmissionQueue::new(3);
+        assert_eq!(queue.capacity(), 4);
+        for i in 0..4 {
+            queue.push(i).unwrap();
+        }
+        assert_eq!(queue.push(9), Err(9));
+        assert_eq!(queue.pop(), Some(0));
+        queue.push(4).unwrap();
+        let drained: Vec<i32> = std::iter::from_fn(|| queue.pop()).collect();
+        assert_eq!(drained, vec![1, 2, 3, 4]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn drains_into_rank_buckets() {
+        let queue = AdmissionQueue::new(8);
+        for (id, rank) in [(1, 1), (2, 5), (3, 1), (4, 3)] {
+            queue.push(Task::new(id, rank, 0)).unwrap();
+        }
+        let mut tree = AvlTree::new();
+        assert_eq!(queue.drain_into(&mut tree, 3), 3);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.drain_into(&mut tree, usize::MAX), 1);
+        let order: Vec<i32> = std::iter::from_fn(|| tree.pop_highest())
+            .map(|t| t.get_id())
+            .collect();
+        assert_eq!(order, vec![2, 4, 1, 3]);
+    }
+
+    #[test]
+    fn many_producers_and_consumers() {
+        let queue = Arc::new(AdmissionQueue::new(64));
+        let producers: Vec<_> = (0..4)
+            .map(|t| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    for i in 0..10_000u64 {
+                        let mut value = t * 100_000 + i;
+                        while let Err(back) = queue.push(value) {
+                            value = back;
+                            thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    let mut seen = Vec::new();
+                    while seen.len() < 20_000 {
+                        match queue.pop() {
+                            Some(value) => seen.push(value),
+                            None => thread::yield_now(),
+                        }
+                    }
+                    seen
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut seen: Vec<u64> = consumers
+            .into_iter()
+            .flat_map(|c| c.join().unwrap())
+            .collect();
+        seen.sort_unstable();
+        seen.dedup();
+        assert_eq!(seen.len(), 40_000);
+    }
+}
@@ -1,7 +1,6 @@
 use super::linklist::*;
-use super::Task;
+use super::{Affinity, Task};
 use std::cmp::Ordering;
-use std::ptr::write;
 use std::sync::{Arc, Mutex};
 
 // An AVL tree is a self-balancing binary search tree. It ensures that the height
@@ -12,19 +11,45 @@ use std::sync::{Arc, Mutex};
 
 // enum to allow either a Task type or LinkList type
 
+// Every node is a bucket for one rank. A bucket that runs out of tasks is
+// removed from the tree unless its rank was reserved up front (pinned).
+// The rank of a bucket is the task's rank unless the tree was built with
+// another key, e.g. the virtual runtime of a task, see `AvlTree::keyed_by`.
+// Keys are i64 so such keys do not run out over a long run.
+// `affinity` sums up the cpus the tasks of the whole subtree may run on so a
+// search for one cpu skips the subtrees that hold nothing for it.
 #[derive(Debug, Clone)]
 pub struct AvlTree {
     val: Option<LinkList>,
+    rank: i64,
+    key: fn(&Task) -> i64,
+    pinned: bool,
     height: i32,
+    affinity: Affinity,
     left: Option<Arc<Mutex<AvlTree>>>,
     right: Option<Arc<Mutex<AvlTree>>>,
 }
 
+impl Default for AvlTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl AvlTree {
     pub fn new() -> Self {
+        AvlTree::keyed_by(|task| task.get_rank().into())
+    }
+
+    // a tree that orders the tasks by `key` instead of their rank
+    pub fn keyed_by(key: fn(&Task) -> i64) -> Self {
         AvlTree {
             val: None,
+            rank: 0,
+            key,
+            pinned: false,
             height: 1,
+            affinity: Affinity::NONE,
             left: None,
             right: None,
         }
@@ -34,6 +59,20 @@ impl AvlTree {
         self.val.is_none() && self.height == 1
     }
 
+    // number of tasks stored over all the buckets
+    pub fn task_count(&self) -> usize {
+        let own = self.val.as_ref().map_or(0, |ll| ll.len() as usize);
+        let left = self
+            .left
+            .as_ref()
+            .map_or(0, |node| node.lock().unwrap().task_count());
+        let right = self
+            .right
+            .as_ref()
+            .map_or(0, |node| node.lock().unwrap().task_count());
+        own + left + right
+    }
+
     // searches by rank to easily find the node that a task belongs to
     // this function will return the root node that holds the rank
     pub fn search_by_task(&self, target: &Task) -> Option<AvlTree> {
@@ -41,203 +80,482 @@ impl AvlTree {
             return None;
         }
         if let Some(ll) = &self.val {
-            if ll.is_empty() {
-                return None;
-            }
-            //base case if there is a single value
-            let cur_node = ll.get_head().unwrap().borrow().clone();
-            let cur_node_rank = cur_node.get_rank();
-            if target.get_rank() == cur_node_rank {
-                return Some(self.clone());
-            } else if target.get_rank() < cur_node_rank {
-                if let Some(left) = &self.left {
-                    return left.lock().unwrap().search_by_task(target);
+            match (self.key)(target).cmp(&self.rank) {
+                Ordering::Equal if ll.is_empty() => return None,
+                Ordering::Equal => return Some(self.clone()),
+                Ordering::Less => {
+                    if let Some(left) = &self.left {
+                        return left.lock().unwrap().search_by_task(target);
+                    }
                 }
-            } else if target.get_rank() > cur_node_rank {
-                if let Some(right) = &self.right {
-                    return right.lock().unwrap().search_by_task(target);
+                Ordering::Greater => {
+                    if let Some(right) = &self.right {
+                        return right.lock().unwrap().search_by_task(target);
+                    }
                 }
             }
         }
         None
     }
 
-    // TODO create the real public search task
-    // issue when adding the new value it seemed to delete the previous one
+    // creates an empty bucket for `rank` that stays in the tree when it runs empty
+    pub fn reserve_rank(&mut self, rank: i32) {
+        self.reserve_key(rank.into());
+    }
+
+    fn reserve_key(&mut self, rank: i64) {
+        if self.val.is_none() {
+            self.val = Some(LinkList::new());
+            self.rank = rank;
+            self.pinned = true;
+            self.height = 1;
+            return;
+        }
+        let key = self.key;
+        match rank.cmp(&self.rank) {
+            Ordering::Equal => self.pinned = true,
+            Ordering::Greater => self
+                .right
+                .get_or_insert_with(|| Arc::new(Mutex::new(AvlTree::keyed_by(key))))
+                .lock()
+                .unwrap()
+                .reserve_key(rank),
+            Ordering::Less => self
+                .left
+                .get_or_insert_with(|| Arc::new(Mutex::new(AvlTree::keyed_by(key))))
+                .lock()
+                .unwrap()
+                .reserve_key(rank),
+        }
+        self.balance();
+    }
 
     //traverse through and display the path to value
     pub fn insert(&mut self, new_task: Task) {
         if self.is_empty() {
             let mut ll = LinkList::new();
+            self.rank = (self.key)(&new_task);
             ll.push_back(new_task);
             self.val = Some(ll);
         } else {
             self.r_insert(new_task);
         }
-        self.update_height();
+        self.balance();
     }
     // Searches node to insert into
     fn r_insert(&mut self, new_val: Task) {
+        let key = self.key;
         match self.val {
-            Some(ref mut ll) => {
-                let cur_node = ll.get_head().unwrap().borrow().clone();
-                match new_val.get_rank().cmp(&cur_node.get_rank()) {
-                    Ordering::Equal => {
-                        ll.push_back(new_val);
-                    }
-                    Ordering::Greater => {
-                        if let Some(right) = &mut self.right {
-                            right.lock().unwrap().r_insert(new_val);
-                        } else {
-                            let new_tree = AvlTree::new();
-                            self.right = Some(Arc::new(Mutex::new(new_tree)));
-                            self.right
-                                .as_ref()
-                                .unwrap()
-                                .lock()
-                                .unwrap()
-                                .r_insert(new_val);
-                        }
-                    }
-                    Ordering::Less => {
-                        if let Some(left) = &mut self.left {
-                            left.lock().unwrap().r_insert(new_val);
-                        } else {
-                            let new_tree = AvlTree::new();
-                            self.left = Some(Arc::new(Mutex::new(new_tree)));
-                            self.left
-                                .as_ref()
-                                .unwrap()
-                                .lock()
-                                .unwrap()
-                                .r_insert(new_val);
-                        }
-                    }
+            Some(ref mut ll) => match key(&new_val).cmp(&self.rank) {
+                Ordering::Equal => {
+                    ll.push_back(new_val);
                 }
-            }
+                Ordering::Greater => {
+                    self.right
+                        .get_or_insert_with(|| Arc::new(Mutex::new(AvlTree::keyed_by(key))))
+                        .lock()
+                        .unwrap()
+                        .r_insert(new_val);
+                }
+                Ordering::Less => {
+                    self.left
+                        .get_or_insert_with(|| Arc::new(Mutex::new(AvlTree::keyed_by(key))))
+                        .lock()
+                        .unwrap()
+                        .r_insert(new_val);
+                }
+            },
             None => {
                 let mut new_ll = LinkList::new();
+                self.rank = key(&new_val);
                 new_ll.push_back(new_val);
                 self.val = Some(new_ll);
                 self.height = 1;
             }
         }
-        self.update_height();
+        self.balance();
     }
 
     // will delete a task within the tree
-    fn delete_by_task(&mut self, target: &Task) -> Option<Task> {
-        // search task
-        if let Some(leaf) = self.search_by_task(target) {
-            if let Some(mut ll) = leaf.val {
+    pub fn delete_by_task(&mut self, target: &Task) -> Option<Task> {
+        let ll = self.val.as_mut()?;
+        let retrieved = match (self.key)(target).cmp(&self.rank) {
+            Ordering::Equal => {
                 let retrieved = ll.delete_task(target);
-                self.val = Some(ll);
+                if ll.is_empty() && !self.pinned {
+                    self.remove_node(self.rank);
+                }
                 return retrieved;
             }
+            Ordering::Less => Self::delete_in(&mut self.left, target),
+            Ordering::Greater => Self::delete_in(&mut self.right, target),
+        };
+        self.balance();
+        retrieved
+    }
+
+    fn delete_in(child: &mut Option<Arc<Mutex<AvlTree>>>, target: &Task) -> Option<Task> {
+        let node = child.as_ref()?;
+        let (retrieved, emptied) = {
+            let mut node = node.lock().unwrap();
+            (node.delete_by_task(target), node.val.is_none())
+        };
+        if emptied {
+            *child = None;
         }
-        None
+        retrieved
     }
 
-    //update height
-    fn update_height(&mut self) {
-        let left_height = self
-            .left
+    // deletes every queued job of the task, whatever rank each one sits at
+    pub fn delete_by_id(&mut self, id: i32) -> Vec<Task> {
+        self.tasks()
+            .into_iter()
+            .filter(|task| task.get_id() == id)
+            .filter_map(|task| self.delete_by_task(&task))
+            .collect()
+    }
+
+    // highest rank that currently holds a task, empty reserved buckets are skipped,
+    // in a tree with another key the rank of the task that would be popped
+    pub fn highest_rank(&self) -> Option<i32> {
+        if let Some(rank) = self
+            .right
+            .as_ref()
+            .and_then(|right| right.lock().unwrap().highest_rank())
+        {
+            return Some(rank);
+        }
+        if let Some(ll) = self.val.as_ref().filter(|ll| !ll.is_empty()) {
+            return Some(ll.get_head_rank());
+        }
+        self.left
             .as_ref()
-            .map(|node| node.lock().unwrap().height)
-            .unwrap_or(0);
-        let right_height = self
+            .and_then(|left| left.lock().unwrap().highest_rank())
+    }
+
+    // key of the highest bucket that holds a task, the rank in a rank tree
+    pub fn highest_key(&self) -> Option<i64> {
+        if let Some(key) = self
             .right
             .as_ref()
-            .map(|node| node.lock().unwrap().height)
-            .unwrap_or(0);
+            .and_then(|right| right.lock().unwrap().highest_key())
+        {
+            return Some(key);
+        }
+        if self.val.as_ref().is_some_and(|ll| !ll.is_empty()) {
+            return Some(self.rank);
+        }
+        self.left
+            .as_ref()
+            .and_then(|left| left.lock().unwrap().highest_key())
+    }
 
-        self.height = 1 + std::cmp::max(left_height, right_height);
+    // every cpu some task in the tree may run on
+    pub fn affinity(&self) -> Affinity {
+        self.affinity
+    }
 
-        // Update height after rotations
+    // highest rank holding a task that may run on `cpu`
+    pub fn highest_rank_for_cpu(&self, cpu: usize) -> Option<i32> {
+        if !self.affinity.allows(cpu) {
+            return None;
+        }
+        if let Some(rank) = self
+            .right
+            .as_ref()
+            .and_then(|right| right.lock().unwrap().highest_rank_for_cpu(cpu))
+        {
+            return Some(rank);
+        }
+        if let Some(task) = self.val.as_ref().and_then(|ll| ll.first_for_cpu(cpu)) {
+            return Some(task.get_rank());
+        }
         self.left
             .as_ref()
-            .map(|node| node.lock().unwrap().update_height());
-        self.right
+            .and_then(|left| left.lock().unwrap().highest_rank_for_cpu(cpu))
+    }
+
+    // removes the first task of the highest rank that may run on `cpu`,
+    // subtrees whose affinity leaves out `cpu` are not visited
+    pub fn pick_next_for_cpu(&mut self, cpu: usize) -> Option<Task> {
+        if !self.affinity.allows(cpu) {
+            return None;
+        }
+        let mut picked = Self::pick_in(&mut self.right, cpu);
+        if picked.is_none() {
+            if let Some(ll) = self.val.as_mut() {
+                picked = ll.take_first_for_cpu(cpu);
+                if picked.is_some() && ll.is_empty() && !self.pinned {
+                    self.remove_node(self.rank);
+                }
+            }
+        }
+        if picked.is_none() {
+            picked = Self::pick_in(&mut self.left, cpu);
+        }
+        // also narrows the summary when the search came up empty
+        self.balance();
+        picked
+    }
+
+    fn pick_in(child: &mut Option<Arc<Mutex<AvlTree>>>, cpu: usize) -> Option<Task> {
+        let node = child.as_ref()?;
+        let (picked, emptied) = {
+            let mut node = node.lock().unwrap();
+            (node.pick_next_for_cpu(cpu), node.val.is_none())
+        };
+        if emptied {
+            *child = None;
+        }
+        picked
+    }
+
+    // copy of the task that would be popped next
+    pub fn peek_highest(&self) -> Option<Task> {
+        if let Some(task) = self
+            .right
+            .as_ref()
+            .and_then(|right| right.lock().unwrap().peek_highest())
+        {
+            return Some(task);
+        }
+        if let Some(head) = self.val.as_ref().and_then(|ll| ll.get_head()) {
+            return Some(head.read().clone());
+        }
+        self.left
             .as_ref()
-            .map(|node| node.lock().unwrap().update_height());
+            .and_then(|left| left.lock().unwrap().peek_highest())
     }
 
-    // left rotation left imbalance
-    /*          root -> right-> right      root-> right -> left
-     *           6         7                   6            8
-     *             \      / \                    \        /  \
-     *              7 -> 6   8                   8  ->  6    7
-     *               \                           /
-     *                8                         7
-     *
-     * */
-    //fn left_rotation(&mut self)
-    //root -> right
-    // root-> right -> left
-    // right grandchild val
-    // left child val
-    // root -> right -> right
-    // update_height()
-
-    // balance()
-
-    // right rotation left imbalance
-    /*          root -> left-> left      root-> left -> Right
-     *           5     4                6         4
-     *          /     / \              /        /  \
-     *         4 ->  3   5            4    ->  5    6
-     *        /                        \
-     *      3                           5
-     *
-     * */
-    //fn right_rotation(&mut self)
-    //root -> left
-    // root-> left -> right
-    // right grandchild val
-    // left child val
-    // root -> left -> left
-    // update_height()
-    //balance()
-
-    //blance factor function is the difference between the height
-    //fn balance_factor(&self) -> i32
-
-    // balance the tree after inserting
-    //fn balance(&mut self) {
-    //left
-    //LR
-    //left tree is lower then the right tree left rotation on left child
-    //LL
-    //left tree higher then the right subtee right_rotation
-    //right
-    //RL
-    //right tree higher then the left subtee left_rotation
-    //RR
-    //right tree is lower then the left tree right rotation on right child
-
-    // Function to check if the AVL tree is balanced
-    /*fn is_avl_balanced(&self) -> bool {
-        let left_height = self
+    // removes the first task of the highest rank, tasks of the same rank
+    // come out in the order they were inserted
+    pub fn pop_highest(&mut self) -> Option<Task> {
+        let mut popped = Self::pop_in(&mut self.right);
+        if popped.is_none() {
+            if let Some(ll) = self.val.as_mut() {
+                popped = ll.pop();
+                if popped.is_some() && ll.is_empty() && !self.pinned {
+                    self.remove_node(self.rank);
+                }
+            }
+        }
+        if popped.is_none() {
+            popped = Self::pop_in(&mut self.left);
+        }
+        self.balance();
+        popped
+    }
+
+    fn pop_in(child: &mut Option<Arc<Mutex<AvlTree>>>) -> Option<Task> {
+        let node = child.as_ref()?;
+        let (popped, emptied) = {
+            let mut node = node.lock().unwrap();
+            (node.pop_highest(), node.val.is_none())
+        };
+        if emptied {
+            *child = None;
+        }
+        popped
+    }
+
+    // all the tasks from the highest rank down, in queue order within a rank
+    pub fn tasks(&self) -> Vec<Task> {
+        let mut tasks = Vec::new();
+        if let Some(right) = &self.right {
+            tasks.extend(right.lock().unwrap().tasks());
+        }
+        if let Some(ll) = &self.val {
+            tasks.extend(ll.tasks());
+        }
+        if let Some(left) = &self.left {
+            tasks.extend(left.lock().unwrap().tasks());
+        }
+        tasks
+    }
+
+    // Delete
+    // removes the node for `rank`
+    // No subtree case: the node is emptied and the parent drops it
+    // One Subtree case: the child takes the place of the node
+    // Two subtree case: the smallest rank of the right subtree moves up
+    fn remove_node(&mut self, rank: i64) {
+        if self.val.is_none() {
+            return;
+        }
+        match rank.cmp(&self.rank) {
+            Ordering::Less => Self::remove_in(&mut self.left, rank),
+            Ordering::Greater => Self::remove_in(&mut self.right, rank),
+            Ordering::Equal => match (self.left.take(), self.right.take()) {
+                (None, None) => {
+                    self.val = None;
+                }
+                (Some(child), None) | (None, Some(child)) => {
+                    let mut child = child.lock().unwrap();
+                    self.val = child.val.take();
+                    self.rank = child.rank;
+                    self.pinned = child.pinned;
+                    self.left = child.left.take();
+                    self.right = child.right.take();
+                }
+                (Some(left), Some(right)) => {
+                    self.left = Some(left);
+                    let ((min_rank, min_val, min_pinned), emptied) = {
+                        let mut right = right.lock().unwrap();
+                        (right.take_min(), right.val.is_none())
+                    };
+                    self.rank = min_rank;
+                    self.val = min_val;
+                    self.pinned = min_pinned;
+                    if !emptied {
+                        self.right = Some(right);
+                    }
+                }
+            },
+        }
+        self.balance();
+    }
+
+    fn remove_in(child: &mut Option<Arc<Mutex<AvlTree>>>, rank: i64) {
+        if let Some(node) = child {
+            let emptied = {
+                let mut node = node.lock().unwrap();
+                node.remove_node(rank);
+                node.val.is_none()
+            };
+            if emptied {
+                *child = None;
+            }
+        }
+    }
+
+    // takes the bucket with the smallest rank out of this subtree
+    fn take_min(&mut self) -> (i64, Option<LinkList>, bool) {
+        if let Some(left) = &self.left {
+            let (min, emptied) = {
+                let mut left = left.lock().unwrap();
+                (left.take_min(), left.val.is_none())
+            };
+            if emptied {
+                self.left = None;
+            }
+            self.balance();
+            return min;
+        }
+        let min = (self.rank, self.val.take(), self.pinned);
+        if let Some(right) = self.right.take() {
+            let mut right = right.lock().unwrap();
+            self.val = right.val.take();
+            self.rank = right.rank;
+            self.pinned = right.pinned;
+            self.left = right.left.take();
+            self.right = right.right.take();
+        }
+        self.balance();
+        min
+    }
+
+    //update height, together with the affinity summary of the subtree
+    fn update_height(&mut self) {
+        let (left_height, left_affinity) = self
             .left
             .as_ref()
-            .map_or(0, |node| node.lock().unwrap().height);
-        let right_height = self
+            .map(|node| {
+                let node = node.lock().unwrap();
+                (node.height, node.affinity)
+            })
+            .unwrap_or((0, Affinity::NONE));
+        let (right_height, right_affinity) = self
             .right
             .as_ref()
-            .map_or(0, |node| node.lock().unwrap().height);
-
-        (left_height - right_height).abs() <= 1
-    }*/
-
-    //balance
-    //Delete
-    //No subtree case
-    //One Subtree case
-    //Two subtree case
-    //traverse
-    //update priority
-    //concurrency
-    //look into preemption */
+            .map(|node| {
+                let node = node.lock().unwrap();
+                (node.height, node.affinity)
+            })
+            .unwrap_or((0, Affinity::NONE));
+
+        self.height = 1 + std::cmp::max(left_height, right_height);
+        let own = self.val.as_ref().map_or(Affinity::NONE, LinkList::affinity);
+        self.affinity = own.union(left_affinity).union(right_affinity);
+    }
+
+    fn balance_factor(&self) -> i32 {
+        let height = |node: &Option<Arc<Mutex<AvlTree>>>| {
+            node.as_ref().map_or(0, |node| node.lock().unwrap().height)
+        };
+        height(&self.left) - height(&self.right)
+    }
+
+    // right rotation, the root of a subtree is this node itself so the left
+    // child's bucket moves up into it and its own bucket moves down
+    /*          root -> left-> left
+     *           5     4
+     *          /     / \
+     *         4 ->  3   5
+     *        /
+     *      3
+     * */
+    fn rotate_right(&mut self) {
+        let Some(left) = self.left.take() else {
+            return;
+        };
+        {
+            let mut node = left.lock().unwrap();
+            std::mem::swap(&mut *node, self);
+            node.left = self.right.take();
+            node.update_height();
+        }
+        self.right = Some(left);
+        self.update_height();
+    }
+
+    // left rotation, the mirror of rotate_right
+    /*          root -> right-> right
+     *           6         7
+     *             \      / \
+     *              7 -> 6   8
+     *               \
+     *                8
+     * */
+    fn rotate_left(&mut self) {
+        let Some(right) = self.right.take() else {
+            return;
+        };
+        {
+            let mut node = right.lock().unwrap();
+            std::mem::swap(&mut *node, self);
+            node.right = self.left.take();
+            node.update_height();
+        }
+        self.left = Some(right);
+        self.update_height();
+    }
+
+    // balance the subtree after a change below it, LL, LR, RR and RL cases
+    fn balance(&mut self) {
+        self.update_height();
+        let factor = self.balance_factor();
+        if factor > 1 {
+            if let Some(left) = &self.left {
+                let mut left = left.lock().unwrap();
+                if left.balance_factor() < 0 {
+                    left.rotate_left();
+                }
+            }
+            self.rotate_right();
+        } else if factor < -1 {
+            if let Some(right) = &self.right {
+                let mut right = right.lock().unwrap();
+                if right.balance_factor() > 0 {
+                    right.rotate_right();
+                }
+            }
+            self.rotate_left();
+        }
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
 }
 
 #[cfg(test)]
@@ -284,7 +602,8 @@ mod tests {
             avl_tree.insert(task.clone());
         }
 
-        assert_eq!(avl_tree.height, 5);
+        // eight ranks fit in four levels
+        assert_eq!(avl_tree.height, 4);
         match avl_tree.val {
             Some(ref ll) => assert_eq!(ll.len(), 4),
             None => unreachable!(),
@@ -323,7 +642,7 @@ mod tests {
                 Some(ll) => {
                     // Check the rank of the retrieved task
                     let retrieved_task = ll.get_head().unwrap();
-                    assert_eq!(retrieved_task.borrow().get_rank(), tasks[2].get_rank());
+                    assert_eq!(retrieved_task.read().get_rank(), tasks[2].get_rank());
                 }
                 None => unreachable!(),
             }
@@ -380,7 +699,7 @@ mod tests {
         match avl_tree.val {
             Some(ref ll) => {
                 assert_eq!(ll.len(), 1);
-                assert_eq!(ll.get_head().unwrap().borrow().get_id(), tasks[4].get_id());
+                assert_eq!(ll.get_head().unwrap().read().get_id(), tasks[4].get_id());
             }
             None => unreachable!(),
         }
@@ -402,4 +721,205 @@ mod tests {
         assert_eq!(empty_tree.height, 1); // Height should remain 0
         assert!(empty_tree.is_empty()); // Tree should remain empty
     }
+
+    #[test]
+    fn test_pop_highest() {
+        let mut avl_tree = AvlTree::new();
+        let tasks = vec![
+            Task::new(1, 5, 0),
+            Task::new(2, 6, 0),
+            Task::new(3, 3, 0),
+            Task::new(4, 4, 0),
+            Task::new(5, 5, 0),
+            Task::new(6, 8, 0),
+            Task::new(7, 7, 0),
+        ];
+        for task in &tasks {
+            avl_tree.insert(task.clone());
+        }
+        assert_eq!(avl_tree.task_count(), 7);
+        assert_eq!(avl_tree.highest_rank(), Some(8));
+
+        // highest rank first, same rank in insertion order
+        let order: Vec<i32> = std::iter::from_fn(|| avl_tree.pop_highest())
+            .map(|t| t.get_id())
+            .collect();
+        assert_eq!(order, vec![6, 7, 2, 1, 5, 4, 3]);
+        assert!(avl_tree.is_empty());
+        assert_eq!(avl_tree.highest_rank(), None);
+
+        // the emptied tree is usable again
+        avl_tree.insert(Task::new(8, 2, 0));
+        assert_eq!(avl_tree.peek_highest().unwrap().get_id(), 8);
+    }
+
+    #[test]
+    fn test_delete_removes_empty_nodes() {
+        let mut avl_tree = AvlTree::new();
+        for (id, rank) in [(1, 5), (2, 3), (3, 8), (4, 7), (5, 9), (6, 6)] {
+            avl_tree.insert(Task::new(id, rank, 0));
+        }
+
+        // inserting 6 rotated 7 up to the root, 5 has 3 and 6 below it
+        assert_eq!(avl_tree.rank, 7);
+        // node with two subtrees, its successor (rank 6) moves up
+        assert_eq!(
+            avl_tree
+                .delete_by_task(&Task::new(1, 5, 0))
+                .unwrap()
+                .get_id(),
+            1
+        );
+        assert_eq!(avl_tree.left.as_ref().unwrap().lock().unwrap().rank, 6);
+        assert!(avl_tree.search_by_task(&Task::new(1, 5, 0)).is_none());
+
+        // leaf and single subtree cases
+        assert!(avl_tree.delete_by_task(&Task::new(5, 9, 0)).is_some());
+        assert!(avl_tree.delete_by_task(&Task::new(3, 8, 0)).is_some());
+        assert_eq!(avl_tree.highest_rank(), Some(7));
+
+        let ranks: Vec<i32> = avl_tree.tasks().iter().map(|t| t.get_rank()).collect();
+        assert_eq!(ranks, vec![7, 6, 3]);
+        // 7 with only 6 and 3 below was rotated
+        assert_eq!(avl_tree.rank, 6);
+        assert_eq!(avl_tree.height, 2);
+    }
+
+    #[test]
+    fn test_reserved_ranks_stay() {
+        let mut avl_tree = AvlTree::new();
+        for rank in [2, 1, 0] {
+            avl_tree.reserve_rank(rank);
+        }
+        assert_eq!(avl_tree.highest_rank(), None);
+        // the three buckets are balanced around 1
+        assert_eq!(avl_tree.height, 2);
+
+        avl_tree.insert(Task::new(1, 1, 0));
+        avl_tree.insert(Task::new(2, 0, 0));
+        assert_eq!(avl_tree.highest_rank(), Some(1));
+        assert_eq!(avl_tree.pop_highest().unwrap().get_id(), 1);
+        assert_eq!(avl_tree.pop_highest().unwrap().get_id(), 2);
+        assert!(avl_tree.pop_highest().is_none());
+
+        // the buckets are still there after running empty
+        assert_eq!(avl_tree.height, 2);
+        avl_tree.insert(Task::new(3, 0, 0));
+        assert!(avl_tree.search_by_task(&Task::new(3, 0, 0)).is_some());
+        assert_eq!(
+            avl_tree
+                .delete_by_task(&Task::new(3, 0, 0))
+                .unwrap()
+                .get_id(),
+            3
+        );
+        assert_eq!(avl_tree.height, 2);
+    }
+
+    // height of the subtree, checking every node on the way
+    fn assert_balanced(tree: &AvlTree) -> i32 {
+        let height = |node: &Option<Arc<Mutex<AvlTree>>>| {
+            node.as_ref()
+                .map_or(0, |node| assert_balanced(&node.lock().unwrap()))
+        };
+        let (left, right) = (height(&tree.left), height(&tree.right));
+        assert!(
+            (left - right).abs() <= 1,
+            "key {} out of balance",
+            tree.rank
+        );
+        assert_eq!(tree.height, 1 + left.max(right));
+        tree.height
+    }
+
+    #[test]
+    fn ascending_keys_stay_balanced() {
+        // vruntimes only grow, a plain BST would turn into a list
+        let mut avl_tree = AvlTree::keyed_by(|task| task.get_vruntime() as i64);
+        let count = 10_000;
+        for id in 0..count {
+            let mut task = Task::new(id, 0, 0);
+            task.set_vruntime(id as u64 * 3);
+            avl_tree.insert(task);
+        }
+        assert_balanced(&avl_tree);
+        // an AVL tree is at most 1.44 log2(n) high
+        let bound = (1.44 * (count as f64).log2()).ceil() as i32;
+        assert!(avl_tree.height() <= bound, "{}", avl_tree.height());
+        assert_eq!(avl_tree.highest_key(), Some((count as i64 - 1) * 3));
+
+        // pops from the top and deletes from the middle keep it balanced
+        for _ in 0..count / 4 {
+            avl_tree.pop_highest();
+        }
+        for id in (0..count / 2).step_by(3) {
+            let mut task = Task::new(id, 0, 0);
+            task.set_vruntime(id as u64 * 3);
+            assert!(avl_tree.delete_by_task(&task).is_some());
+        }
+        assert_balanced(&avl_tree);
+        assert!(avl_tree.height() <= bound);
+        let left = avl_tree.task_count();
+        let order: Vec<i32> = std::iter::from_fn(|| avl_tree.pop_highest())
+            .map(|task| task.get_id())
+            .collect();
+        assert_eq!(order.len(), left);
+        assert!(order.windows(2).all(|pair| pair[0] > pair[1]));
+        assert!(avl_tree.is_empty());
+    }
+
+    #[test]
+    fn test_keyed_by() {
+        // lowest id first, the rank does not matter
+        let mut avl_tree = AvlTree::keyed_by(|task| (-task.get_id()).into());
+        for (id, rank) in [(3, 1), (1, 9), (4, 5), (2, 0), (5, 3)] {
+            avl_tree.insert(Task::new(id, rank, 0));
+        }
+        assert_eq!(avl_tree.highest_key(), Some(-1));
+        // the rank of task 1, which comes out first
+        assert_eq!(avl_tree.highest_rank(), Some(9));
+        assert!(avl_tree.delete_by_task(&Task::new(4, 5, 0)).is_some());
+        assert!(avl_tree.search_by_task(&Task::new(2, 0, 0)).is_some());
+        let order: Vec<i32> = std::iter::from_fn(|| avl_tree.pop_highest())
+            .map(|t| t.get_id())
+            .collect();
+        assert_eq!(order, vec![1, 2, 3, 5]);
+    }
+
+    #[test]
+    fn test_pick_next_for_cpu() {
+        let mut avl_tree = AvlTree::new();
+        avl_tree.insert(Task::new(1, 5, 0));
+        avl_tree.insert(Task::new(2, 9, 0).with_affinity(Affinity::only(1)));
+        avl_tree.insert(Task::new(3, 7, 0).with_affinity(Affinity::cpus(&[0, 1])));
+        avl_tree.insert(Task::new(4, 9, 0).with_affinity(Affinity::only(2)));
+
+        // the highest rank is never handed to a cpu outside its mask
+        assert_eq!(avl_tree.highest_rank(), Some(9));
+        assert_eq!(avl_tree.highest_rank_for_cpu(0), Some(7));
+        assert_eq!(avl_tree.pick_next_for_cpu(0).unwrap().get_id(), 3);
+        assert_eq!(avl_tree.pick_next_for_cpu(0).unwrap().get_id(), 1);
+        assert!(avl_tree.pick_next_for_cpu(0).is_none());
+        // what is left is pinned to cpus 1 and 2
+        assert_eq!(avl_tree.affinity(), Affinity::cpus(&[1, 2]));
+        assert!(avl_tree.pick_next_for_cpu(5).is_none());
+        assert_eq!(avl_tree.pick_next_for_cpu(2).unwrap().get_id(), 4);
+        assert_eq!(avl_tree.pick_next_for_cpu(1).unwrap().get_id(), 2);
+        assert!(avl_tree.is_empty());
+    }
+
+    #[test]
+    fn keys_do_not_run_out_past_i32() {
+        let mut avl_tree = AvlTree::keyed_by(|task| -(task.get_vruntime() as i64));
+        for (id, vruntime) in [(1, 5_000_000_000), (2, 3_000_000_000), (3, 4_000_000_000)] {
+            let mut task = Task::new(id, 0, 0);
+            task.set_vruntime(vruntime);
+            avl_tree.insert(task);
+        }
+        assert_eq!(avl_tree.highest_key(), Some(-3_000_000_000));
+        let order: Vec<i32> = std::iter::from_fn(|| avl_tree.pop_highest())
+            .map(|t| t.get_id())
+            .collect();
+        assert_eq!(order, vec![2, 3, 1]);
+    }
 }
@@ -0,0 +1,438 @@
+use super::linklist::LinkList;
+use super::Task;
+use std::cmp::Ordering;
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::{Arc, Mutex, RwLock};
+
+/*
+ * Thread safe AVL tree of rank buckets that can be cloned and shared.
+ *
+ * Locking is split in two levels:
+ * - the shape of the tree (which ranks exist, the rotations) sits behind one
+ *   RwLock
+ * - every bucket is a LinkList behind its own Mutex
+ * Adding to or taking from a rank that already has a bucket only takes the
+ * shape lock for reading, so threads working on different ranks run in
+ * parallel and only contend on their own bucket. The shape lock is taken for
+ * writing only to add a bucket for a new rank or drop a bucket that ran
+ * empty, which is also the only time the tree rotates.
+ *
+ * Deadlock freedom: a thread always takes the shape lock before a bucket lock
+ * and holds at most one bucket lock at a time. Rotations run with the shape
+ * lock held for writing and never touch a bucket lock, they only move the
+ * Arc of the bucket, so nothing else can be waiting on a lock they need.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct ConcurrentAvlTree {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    root: RwLock<Option<Box<Node>>>,
+    len: AtomicUsize,
+}
+
+type Bucket = Arc<Mutex<LinkList>>;
+
+#[derive(Debug)]
+struct Node {
+    rank: i32,
+    bucket: Bucket,
+    height: i32,
+    left: Option<Box<Node>>,
+    right: Option<Box<Node>>,
+}
+
+impl ConcurrentAvlTree {
+    pub fn new() -> Self {
+        ConcurrentAvlTree::default()
+    }
+
+    // number of tasks over all buckets
+    pub fn len(&self) -> usize {
+        self.inner.len.load(atomic::Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&self, task: Task) {
+        let rank = task.get_rank();
+        {
+            let root = self.inner.root.read().unwrap();
+            if let Some(bucket) = find(&root, rank) {
+                // counted under the bucket lock, before anyone can pop the task
+                let mut bucket = bucket.lock().unwrap();
+                bucket.push_back(task);
+                self.inner.len.fetch_add(1, atomic::Ordering::AcqRel);
+                return;
+            }
+        }
+        // the rank is new, another thread may have added it since we looked
+        let mut root = self.inner.root.write().unwrap();
+        let bucket = match find(&root, rank) {
+            Some(bucket) => bucket,
+            None => {
+                let bucket: Bucket = Arc::new(Mutex::new(LinkList::new()));
+                *root = Some(insert_node(root.take(), rank, bucket.clone()));
+                bucket
+            }
+        };
+        let mut bucket = bucket.lock().unwrap();
+        bucket.push_back(task);
+        self.inner.len.fetch_add(1, atomic::Ordering::AcqRel);
+    }
+
+    // highest rank that holds a task
+    pub fn highest_rank(&self) -> Option<i32> {
+        let root = self.inner.root.read().unwrap();
+        let mut found = None;
+        descending(&root, &mut |node| {
+            if node.bucket.lock().unwrap().is_empty() {
+                return false;
+            }
+            found = Some(node.rank);
+            true
+        });
+        found
+    }
+
+    pub fn peek_highest(&self) -> Option<Task> {
+        let root = self.inner.root.read().unwrap();
+        let mut found = None;
+        descending(&root, &mut |node| {
+            found = node
+                .bucket
+                .lock()
+                .unwrap()
+                .get_head()
+                .map(|task| task.read().clone());
+            found.is_some()
+        });
+        found
+    }
+
+    // removes the oldest task of the highest rank
+    pub fn pop_highest(&self) -> Option<Task> {
+        let (task, emptied) = {
+            let root = self.inner.root.read().unwrap();
+            let mut popped = None;
+            descending(&root, &mut |node| {
+                let mut bucket = node.bucket.lock().unwrap();
+                popped = bucket.pop().map(|task| (task, bucket.is_empty()));
+                if popped.is_some() {
+                    self.inner.len.fetch_sub(1, atomic::Ordering::AcqRel);
+                }
+                popped.is_some()
+            });
+            popped?
+        };
+        if emptied {
+            self.prune(task.get_rank());
+        }
+        Some(task)
+    }
+
+    // removes a task by id from the bucket of its rank
+    pub fn delete_by_task(&self, target: &Task) -> Option<Task> {
+        let (task, emptied) = {
+            let root = self.inner.root.read().unwrap();
+            let bucket = find(&root, target.get_rank())?;
+            let mut bucket = bucket.lock().unwrap();
+            let task = bucket.delete_task(target)?;
+            self.inner.len.fetch_sub(1, atomic::Ordering::AcqRel);
+            (task, bucket.is_empty())
+        };
+        if emptied {
+            self.prune(target.get_rank());
+        }
+        Some(task)
+    }
+
+    // copy of every task from the highest rank down
+    pub fn tasks(&self) -> Vec<Task> {
+        let root = self.inner.root.read().unwrap();
+        let mut tasks = Vec::new();
+        descending(&root, &mut |node| {
+            tasks.extend(node.bucket.lock().unwrap().tasks());
+            false
+        });
+        tasks
+    }
+
+    // ranks that currently have a bucket, highest first
+    pub fn ranks(&self) -> Vec<i32> {
+        let root = self.inner.root.read().unwrap();
+        let mut ranks = Vec::new();
+        descending(&root, &mut |node| {
+            ranks.push(node.rank);
+            false
+        });
+        ranks
+    }
+
+    pub fn height(&self) -> i32 {
+        height(&self.inner.root.read().unwrap())
+    }
+
+    // drops the bucket of `rank` if it is still empty once we hold the write lock
+    fn prune(&self, rank: i32) {
+        let mut root = self.inner.root.write().unwrap();
+        let still_empty = find(&root, rank).is_some_and(|bucket| bucket.lock().unwrap().is_empty());
+        if still_empty {
+            *root = remove_node(root.take(), rank);
+        }
+    }
+}
+
+fn find(mut node: &Option<Box<Node>>, rank: i32) -> Option<Bucket> {
+    while let Some(current) = node {
+        node = match rank.cmp(&current.rank) {
+            Ordering::Equal => return Some(current.bucket.clone()),
+            Ordering::Less => &current.left,
+            Ordering::Greater => &current.right,
+        };
+    }
+    None
+}
+
+// visits the nodes from the highest rank down until `visit` returns true
+fn descending(node: &Option<Box<Node>>, visit: &mut dyn FnMut(&Node) -> bool) -> bool {
+    match node {
+        Some(node) => {
+            descending(&node.right, visit) || visit(node) || descending(&node.left, visit)
+        }
+        None => false,
+    }
+}
+
+fn height(node: &Option<Box<Node>>) -> i32 {
+    node.as_ref().map_or(0, |node| node.height)
+}
+
+fn update_height(node: &mut Node) {
+    node.height = 1 + height(&node.left).max(height(&node.right));
+}
+
+fn balance_factor(node: &Node) -> i32 {
+    height(&node.left) - height(&node.right)
+}
+
+/*          root -> left-> left
+ *           5     4
+ *          /     / \
+ *         4 ->  3   5
+ *        /
+ *      3
+ */
+fn rotate_right(mut root: Box<Node>) -> Box<Node> {
+    let mut left = root.left.take().expect("rotate right needs a left child");
+    root.left = left.right.take();
+    update_height(&mut root);
+    left.right = Some(root);
+    update_height(&mut left);
+    left
+}
+
+/*          root -> right-> right
+ *           6         7
+ *             \      / \
+ *              7 -> 6   8
+ *               \
+ *                8
+ */
+fn rotate_left(mut root: Box<Node>) -> Box<Node> {
+    let mut right = root.right.take().expect("rotate left needs a right child");
+    root.right = right.left.take();
+    update_height(&mut root);
+    right.left = Some(root);
+    update_height(&mut right);
+    right
+}
+
+// LL, LR, RR and RL cases
+fn balance(mut node: Box<Node>) -> Box<Node> {
+    update_height(&mut node);
+    let factor = balance_factor(&node);
+    if factor > 1 {
+        if node
+            .left
+            .as_ref()
+            .is_some_and(|left| balance_factor(left) < 0)
+        {
+            node.left = node.left.take().map(rotate_left);
+        }
+        return rotate_right(node);
+    }
+    if factor < -1 {
+        if node
+            .right
+            .as_ref()
+            .is_some_and(|right| balance_factor(right) > 0)
+        {
+            node.right = node.right.take().map(rotate_right);
+        }
+        return rotate_left(node);
+    }
+    node
+}
+
+fn insert_node(node: Option<Box<Node>>, rank: i32, bucket: Bucket) -> Box<Node> {
+    let Some(mut node) = node else {
+        return Box::new(Node {
+            rank,
+            bucket,
+            height: 1,
+            left: None,
+            right: None,
+        });
+    };
+    match rank.cmp(&node.rank) {
+        Ordering::Less => node.left = Some(insert_node(node.left.take(), rank, bucket)),
+        Ordering::Greater => node.right = Some(insert_node(node.right.take(), rank, bucket)),
+        Ordering::Equal => node.bucket = bucket,
+    }
+    balance(node)
+}
+
+fn remove_node(node: Option<Box<Node>>, rank: i32) -> Option<Box<Node>> {
+    let mut node = node?;
+    match rank.cmp(&node.rank) {
+        Ordering::Less => node.left = remove_node(node.left.take(), rank),
+        Ordering::Greater => node.right = remove_node(node.right.take(), rank),
+        Ordering::Equal => match (node.left.take(), node.right.take()) {
+            (None, None) => return None,
+            (Some(child), None) | (None, Some(child)) => return Some(child),
+            (Some(left), Some(right)) => {
+                // the smallest rank of the right subtree takes the place of the node
+                let (right, mut successor) = take_min(right);
+                successor.left = Some(left);
+                successor.right = right;
+                return Some(balance(successor));
+            }
+        },
+    }
+    Some(balance(node))
+}
+
+fn take_min(mut node: Box<Node>) -> (Option<Box<Node>>, Box<Node>) {
+    match node.left.take() {
+        Some(left) => {
+            let (left, min) = take_min(left);
+            node.left = left;
+            (Some(balance(node)), min)
+        }
+        None => (node.right.take(), node),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn assert_balanced(node: &Option<Box<Node>>) -> i32 {
+        match node {
+            Some(node) => {
+                let left = assert_balanced(&node.left);
+                let right = assert_balanced(&node.right);
+                assert!(
+                    (left - right).abs() <= 1,
+                    "rank {} out of balance",
+                    node.rank
+                );
+                assert_eq!(node.height, 1 + left.max(right));
+                node.height
+            }
+            None => 0,
+        }
+    }
+
+    #[test]
+    fn stays_balanced() {
+        let tree = ConcurrentAvlTree::new();
+        // ascending ranks would make a plain BST a linked list
+        for rank in 0..1000 {
+            tree.insert(Task::new(rank, rank, 0));
+        }
+        assert_balanced(&tree.inner.root.read().unwrap());
+        assert!(tree.height() <= 11);
+
+        for rank in (0..1000).step_by(3) {
+            assert!(tree.delete_by_task(&Task::new(rank, rank, 0)).is_some());
+        }
+        assert_balanced(&tree.inner.root.read().unwrap());
+        assert_eq!(tree.len(), 666);
+        assert_eq!(tree.highest_rank(), Some(998));
+        assert_eq!(tree.ranks().len(), 666);
+    }
+
+    #[test]
+    fn pops_by_rank_then_insertion_order() {
+        let tree = ConcurrentAvlTree::new();
+        for (id, rank) in [(1, 2), (2, 7), (3, 2), (4, 5), (5, 7)] {
+            tree.insert(Task::new(id, rank, 0));
+        }
+        assert_eq!(tree.peek_highest().unwrap().get_id(), 2);
+        let order: Vec<i32> = std::iter::from_fn(|| tree.pop_highest())
+            .map(|t| t.get_id())
+            .collect();
+        assert_eq!(order, vec![2, 5, 4, 1, 3]);
+        assert!(tree.is_empty());
+        assert!(tree.ranks().is_empty());
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let tree = ConcurrentAvlTree::new();
+        // a pop never gets to count a task before its insert did
+        let stop = Arc::new(atomic::AtomicBool::new(false));
+        let watcher = {
+            let (tree, stop) = (tree.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(atomic::Ordering::Acquire) {
+                    assert!(tree.len() <= 4000, "len wrapped to {}", tree.len());
+                }
+            })
+        };
+        let producers: Vec<_> = (0..8)
+            .map(|t| {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    for i in 0..500 {
+                        // a mix of shared ranks and new ones forces rotations
+                        let rank = if i % 2 == 0 { t } else { t * 1000 + i };
+                        tree.insert(Task::new(t * 10_000 + i, rank, 0));
+                    }
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let tree = tree.clone();
+                thread::spawn(move || {
+                    let mut popped = 0;
+                    while popped < 500 {
+                        if tree.pop_highest().is_some() {
+                            popped += 1;
+                        } else {
+                            thread::yield_now();
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let popped: usize = consumers.into_iter().map(|c| c.join().unwrap()).sum();
+        stop.store(true, atomic::Ordering::Release);
+        watcher.join().unwrap();
+        assert_eq!(popped + tree.len(), 4000);
+        assert_eq!(tree.tasks().len(), tree.len());
+        assert_balanced(&tree.inner.root.read().unwrap());
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/*
+ * Dependencies between tasks, an edge from a to b means b may only become
+ * ready once a completed. Every task has a cost (its burst), edges are
+ * checked when they are added so the graph never holds a cycle.
+ *
+ * The upward rank of a task (HEFT) is its cost plus the largest upward rank
+ * of its successors, the length of the longest chain it starts. Running the
+ * highest upward rank first keeps the critical path moving.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Dag {
+    costs: BTreeMap<i32, u64>,
+    successors: HashMap<i32, Vec<i32>>,
+    predecessors: HashMap<i32, Vec<i32>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DagError {
+    UnknownTask(i32),
+    // the tasks of the cycle the edge would close, starting and ending at its source
+    Cycle(Vec<i32>),
+}
+
+impl fmt::Display for DagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DagError::UnknownTask(id) => write!(f, "no task {id} to depend on"),
+            DagError::Cycle(cycle) => {
+                let path: Vec<String> = cycle.iter().map(i32::to_string).collect();
+                write!(f, "dependency cycle {}", path.join(" -> "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for DagError {}
+
+impl Dag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_task(&mut self, id: i32, cost: u64) {
+        self.costs.insert(id, cost);
+    }
+
+    pub fn contains(&self, id: i32) -> bool {
+        self.costs.contains_key(&id)
+    }
+
+    // `to` waits for `from`, refused when `from` already waits for `to`
+    pub fn add_edge(&mut self, from: i32, to: i32) -> Result<(), DagError> {
+        for id in [from, to] {
+            if !self.contains(id) {
+                return Err(DagError::UnknownTask(id));
+            }
+        }
+        if self.successors(from).contains(&to) {
+            return Ok(());
+        }
+        if let Some(mut path) = self.path(to, from) {
+            path.insert(0, from);
+            return Err(DagError::Cycle(path));
+        }
+        self.successors.entry(from).or_default().push(to);
+        self.predecessors.entry(to).or_default().push(from);
+        Ok(())
+    }
+
+    pub fn successors(&self, id: i32) -> &[i32] {
+        self.successors.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn predecessors(&self, id: i32) -> &[i32] {
+        self.predecessors.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    // a path of successor edges from `from` to `to`, depth first
+    fn path(&self, from: i32, to: i32) -> Option<Vec<i32>> {
+        let mut stack = vec![(from, vec![from])];
+        let mut seen = std::collections::HashSet::new();
+        while let Some((at, path)) = stack.pop() {
+            if at == to {
+                return Some(path);
+            }
+            if !seen.insert(at) {
+                continue;
+            }
+            for &next in self.successors(at) {
+                let mut next_path = path.clone();
+                next_path.push(next);
+                stack.push((next, next_path));
+            }
+        }
+        None
+    }
+
+    // every task after all of its predecessors, ties by id
+    pub fn topological_order(&self) -> Vec<i32> {
+        let mut missing: HashMap<i32, usize> = self
+            .costs
+            .keys()
+            .map(|&id| (id, self.predecessors(id).len()))
+            .collect();
+        let mut ready: std::collections::BTreeSet<i32> = missing
+            .iter()
+            .filter(|(_, count)| **count == 0)
+            .map(|(id, _)| *id)
+            .collect();
+        let mut order = Vec::with_capacity(self.costs.len());
+        while let Some(id) = ready.pop_first() {
+            order.push(id);
+            for &next in self.successors(id) {
+                let count = missing.get_mut(&next).expect("edges join known tasks");
+                *count -= 1;
+                if *count == 0 {
+                    ready.insert(next);
+                }
+            }
+        }
+        order
+    }
+
+    pub fn upward_ranks(&self) -> HashMap<i32, u64> {
+        let mut ranks = HashMap::with_capacity(self.costs.len());
+        for id in self.topological_order().into_iter().rev() {
+            let below = self
+                .successors(id)
+                .iter()
+                .map(|next| ranks[next])
+                .max()
+                .unwrap_or(0);
+            ranks.insert(id, self.costs[&id] + below);
+        }
+        ranks
+    }
+
+    // the longest chain by cost, from an entry task to an exit task
+    pub fn critical_path(&self) -> Vec<i32> {
+        let ranks = self.upward_ranks();
+        let highest = |ids: &mut dyn Iterator<Item = i32>| ids.max_by_key(|id| (ranks[id], -id));
+        let mut path = Vec::new();
+        let mut at = highest(
+            &mut self
+                .costs
+                .keys()
+                .copied()
+                .filter(|&id| self.predecessors(id).is_empty()),
+        );
+        while let Some(id) = at {
+            path.push(id);
+            at = highest(&mut self.successors(id).iter().copied());
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diamond() -> Dag {
+        // 1 -> 2 -> 4, 1 -> 3 -> 4
+        let mut dag = Dag::new();
+        for (id, cost) in [(1, 2), (2, 5), (3, 1), (4, 3)] {
+            dag.add_task(id, cost);
+        }
+        for (from, to) in [(1, 2), (1, 3), (2, 4), (3, 4)] {
+            dag.add_edge(from, to).unwrap();
+        }
+        dag
+    }
+
+    #[test]
+    fn refuses_edges_that_close_a_cycle() {
+        let mut dag = diamond();
+        assert_eq!(dag.add_edge(4, 1), Err(DagError::Cycle(vec![4, 1, 3, 4])));
+        assert_eq!(dag.add_edge(3, 3), Err(DagError::Cycle(vec![3, 3])));
+        assert_eq!(dag.add_edge(1, 9), Err(DagError::UnknownTask(9)));
+        assert_eq!(dag.topological_order(), vec![1, 2, 3, 4]);
+        assert_eq!(
+            DagError::Cycle(vec![4, 1, 2, 4]).to_string(),
+            "dependency cycle 4 -> 1 -> 2 -> 4"
+        );
+    }
+
+    #[test]
+    fn upward_rank_follows_the_longest_chain() {
+        let dag = diamond();
+        let ranks = dag.upward_ranks();
+        assert_eq!(ranks[&4], 3);
+        assert_eq!(ranks[&2], 8);
+        assert_eq!(ranks[&3], 4);
+        assert_eq!(ranks[&1], 10);
+        assert_eq!(dag.critical_path(), vec![1, 2, 4]);
+    }
+}
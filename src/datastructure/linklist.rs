@@ -1,7 +1,6 @@
-use super::Task;
-use std::cell::RefCell;
+use super::{Affinity, Task};
 use std::fmt::Debug;
-use std::rc::{Rc, Weak};
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, Weak};
 
 /// [None] <=> [Node1:Task1] <=> [Node2:Task2] <=> [...] [NodeN:taskN]<=> [None]
 ///         ^                                                          ^
@@ -13,18 +12,39 @@ use std::rc::{Rc, Weak};
 /// that will help manage tasks
 ///
 
+// RwLock around every node and task so the list can be sent between threads.
+// The list only changes through &mut self, so these locks are never contended,
+// they are only there for the shared prev and next links. A write while the
+// same thread still holds a read is a bug, it blocks.
+#[derive(Debug, Default)]
+pub struct Lock<T>(RwLock<T>);
+
+impl<T> Lock<T> {
+    pub fn new(value: T) -> Self {
+        Lock(RwLock::new(value))
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.0.read().unwrap()
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.0.write().unwrap()
+    }
+}
+
 // struct for the node in linklist
 #[derive(Debug, Clone)]
 pub struct Node {
-    node: Rc<RefCell<Task>>,
-    next: Option<Rc<RefCell<Node>>>,
-    prev: Option<Weak<RefCell<Node>>>,
+    node: Arc<Lock<Task>>,
+    next: Option<Arc<Lock<Node>>>,
+    prev: Option<Weak<Lock<Node>>>,
 }
 
 impl Node {
-    fn new(task: Task) -> Rc<RefCell<Self>> {
-        Rc::new(RefCell::new(Node {
-            node: Rc::new(RefCell::new(task)),
+    fn new(task: Task) -> Arc<Lock<Self>> {
+        Arc::new(Lock::new(Node {
+            node: Arc::new(Lock::new(task)),
             next: None,
             prev: None,
         }))
@@ -34,31 +54,42 @@ impl Node {
 // the structure of a doubly linked list
 #[derive(Debug, Clone)]
 pub struct LinkList {
-    head: Option<Rc<RefCell<Node>>>,
-    tail: Option<Weak<RefCell<Node>>>,
+    head: Option<Arc<Lock<Node>>>,
+    tail: Option<Weak<Lock<Node>>>,
     size: i32,
+    // union of the affinity of the tasks, may still name cpus of tasks that
+    // left until a search for such a cpu comes up empty and narrows it
+    affinity: Affinity,
+}
+impl Default for LinkList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
+
 impl LinkList {
     pub fn new() -> Self {
         LinkList {
             head: None,
             tail: None,
             size: 0,
+            affinity: Affinity::NONE,
         }
     }
 
     //Inserts a task at the back of the doubly linked list
     //Takes a single task as a param
     pub fn push_back(&mut self, task: Task) {
+        self.affinity = self.affinity().union(task.get_affinity());
         let new_node = Node::new(task);
         match self.tail.take() {
             //Changes value of tail with none while taking ownership
             Some(prev_tail) => {
-                let new_tail = Rc::downgrade(&new_node); // creates a weak reference
-                                                         //upgrade the weak reference then borrow_mut so that the next feild can be updated
-                                                         //to the new tail.
-                prev_tail.upgrade().unwrap().borrow_mut().next = Some(new_node.clone()); //weak
-                new_node.borrow_mut().prev = Some(prev_tail);
+                let new_tail = Arc::downgrade(&new_node); // creates a weak reference
+                                                          //upgrade the weak reference then lock it for writing so that the next feild can be updated
+                                                          //to the new tail.
+                prev_tail.upgrade().unwrap().write().next = Some(new_node.clone()); //weak
+                new_node.write().prev = Some(prev_tail);
                 self.tail = Some(new_tail);
                 self.size += 1;
             }
@@ -70,7 +101,7 @@ impl LinkList {
                 */
                 self.head = Some(new_node.clone()); //make the head be the new_node
                                                     // make the tail be a weak reference to the same node
-                self.tail = Some(Rc::downgrade(&new_node));
+                self.tail = Some(Arc::downgrade(&new_node));
                 self.size += 1;
             }
         }
@@ -84,43 +115,90 @@ impl LinkList {
         self.size == 0
     }
 
-    pub fn get_head(&self) -> Option<Rc<RefCell<Task>>> {
-        Some(self.head.clone().unwrap().borrow().node.clone())
+    // every cpu some task in the list may run on
+    pub fn affinity(&self) -> Affinity {
+        if self.is_empty() {
+            Affinity::NONE
+        } else {
+            self.affinity
+        }
+    }
+
+    // copy of the first task (in queue order) that may run on `cpu`
+    pub fn first_for_cpu(&self, cpu: usize) -> Option<Task> {
+        if !self.affinity().allows(cpu) {
+            return None;
+        }
+        let mut cur = self.head.clone();
+        while let Some(node) = cur {
+            let task = node.read().node.read().clone();
+            if task.can_run_on(cpu) {
+                return Some(task);
+            }
+            cur = node.read().next.clone();
+        }
+        None
+    }
+
+    // removes the first task (in queue order) that may run on `cpu`
+    pub fn take_first_for_cpu(&mut self, cpu: usize) -> Option<Task> {
+        if !self.affinity().allows(cpu) {
+            return None;
+        }
+        let mut exact = Affinity::NONE;
+        let mut cur = self.head.clone();
+        while let Some(node) = cur {
+            let task = node.read().node.read().clone();
+            if task.can_run_on(cpu) {
+                return self.delete_task(&task);
+            }
+            exact = exact.union(task.get_affinity());
+            cur = node.read().next.clone();
+        }
+        // nothing here for `cpu`, the next search can skip the list
+        self.affinity = exact;
+        None
+    }
+
+    pub fn get_head(&self) -> Option<Arc<Lock<Task>>> {
+        self.head.as_ref().map(|node| node.read().node.clone())
     }
 
     pub fn get_head_rank(&self) -> i32 {
         self.head
             .as_ref()
-            .map_or(-1, |node| node.borrow().node.borrow().rank)
+            .map_or(-1, |node| node.read().node.read().rank)
     }
 
-    pub fn get_tail(&self) -> Option<Rc<RefCell<Task>>> {
-        Some(
-            self.tail
-                .clone()
-                .unwrap()
-                .upgrade()
-                .unwrap()
-                .borrow()
-                .node
-                .clone(),
-        )
+    pub fn get_tail(&self) -> Option<Arc<Lock<Task>>> {
+        self.tail
+            .as_ref()
+            .and_then(|tail| tail.upgrade())
+            .map(|node| node.read().node.clone())
     }
 
-    //TODO Complete Traversal over doubly linked list forewards an backwards
+    // Traverses the list from head to tail and returns a copy of every task
+    pub fn tasks(&self) -> Vec<Task> {
+        let mut tasks = Vec::with_capacity(self.size as usize);
+        let mut cur = self.head.clone();
+        while let Some(node) = cur {
+            tasks.push(node.read().node.read().clone());
+            cur = node.read().next.clone();
+        }
+        tasks
+    }
 
     // Given a task check if the tasks exists and if so return the Node it belongs to
-    pub fn search_by_task(&self, value: Task) -> Option<Rc<RefCell<Node>>> {
+    pub fn search_by_task(&self, value: Task) -> Option<Arc<Lock<Node>>> {
         if self.is_empty() {
-            println!("empty list");
             return None;
         }
         let mut cur = self.head.clone();
         while let Some(node) = cur {
-            let cur_node = node.borrow();
-            let task = cur_node.node.borrow();
+            let cur_node = node.read();
+            let task = cur_node.node.read();
             if value.id == task.id {
-                return Some(Rc::clone(&node));
+                return Some(Arc::clone(&node));
             }
             //move to next node
             cur = cur_node.next.clone();
@@ -139,39 +217,45 @@ impl LinkList {
         }
 
         if let Some(h_val) = self.get_head() {
-            if t_task.id == h_val.borrow().id {
+            if t_task.id == h_val.read().id {
                 return self.pop();
             }
         }
 
         if let Some(t_val) = self.get_tail() {
-            if t_task.id == t_val.borrow().id {
+            if t_task.id == t_val.read().id {
                 return self.pop_back();
             }
         }
         self.pop_mid(t_task)
     }
 
+    // Deletes every task with the id, for a task with several queued jobs
+    pub fn delete_by_id(&mut self, id: i32) -> Vec<Task> {
+        let target = Task::new(id, 0, 0);
+        std::iter::from_fn(|| self.delete_task(&target)).collect()
+    }
+
     // deletes a task within the middle of the linked list
     fn pop_mid(&mut self, t_task: &Task) -> Option<Task> {
         if let Some(t_node) = self.search_by_task(t_task.clone()) {
-            let p_node = t_node.borrow().prev.clone();
-            let n_node = t_node.borrow().next.clone();
+            let p_node = t_node.read().prev.clone();
+            let n_node = t_node.read().next.clone();
             let p_node_c = p_node.clone();
             // Update the next and prev pointers of the neighboring nodes
             if let Some(p) = p_node_c {
-                p.upgrade().unwrap().borrow_mut().next = n_node.clone();
+                p.upgrade().unwrap().write().next = n_node.clone();
             }
 
             if let Some(n) = n_node {
-                n.borrow_mut().prev = p_node;
+                n.write().prev = p_node;
             }
 
             // Optional: Clear the next and prev pointers of the deleted node
-            t_node.borrow_mut().next = None;
-            t_node.borrow_mut().prev = None;
+            t_node.write().next = None;
+            t_node.write().prev = None;
             self.size -= 1;
-            return Some(t_node.borrow().node.borrow().clone());
+            return Some(t_node.read().node.read().clone());
         }
 
         None
@@ -179,46 +263,32 @@ impl LinkList {
 
     // pops the tail task
     fn pop_back(&mut self) -> Option<Task> {
-        if let Some(prev_tail) = self.tail.take() {
-            if let Some(new_tail) = prev_tail.upgrade() {
-                // Update self.tail to the previous tail's prev
-                self.tail = new_tail.borrow().prev.clone();
-
-                println!("pop, new tail: {:?}", self.tail);
-                // If there's a new tail, update its next reference
-                if let Some(new_tail_ref) = &self.tail {
-                    if let Some(new_tail) = new_tail_ref.upgrade() {
-                        new_tail.borrow_mut().next = None;
-                    };
-                }
-
-                let task = prev_tail.upgrade().unwrap().borrow().node.borrow().clone();
-                self.size -= 1;
-                Some(task)
-            } else {
-                // If the previous tail cannot be upgraded to a strong reference, treat it as the last node in the list
-                self.head = None;
-                self.tail = None;
-
-                let task = prev_tail.upgrade().unwrap().borrow().node.borrow().clone();
-
-                self.size -= 1;
-                Some(task)
-            }
-        } else {
-            None
+        let prev_tail = self.tail.take()?.upgrade()?;
+        // Update self.tail to the previous tail's prev
+        self.tail = prev_tail.read().prev.clone();
+        match self.tail.as_ref().and_then(|tail| tail.upgrade()) {
+            // If there's a new tail, update its next reference
+            Some(new_tail) => new_tail.write().next = None,
+            // otherwise the tail was the only node left
+            None => self.head = None,
         }
+
+        let task = prev_tail.read().node.read().clone();
+        self.size -= 1;
+        Some(task)
     }
 
     // pops the head task
-    fn pop(&mut self) -> Option<Task> {
+    pub fn pop(&mut self) -> Option<Task> {
         self.head.take().map(|prev_head| {
-            self.head = prev_head.borrow().next.clone();
-            if let Some(ref new_head) = self.head {
-                new_head.borrow_mut().prev = None;
+            self.head = prev_head.read().next.clone();
+            match self.head {
+                Some(ref new_head) => new_head.write().prev = None,
+                // the list is empty so the tail must not point at the old head
+                None => self.tail = None,
             }
 
-            let task = prev_head.borrow().node.borrow().clone();
+            let task = prev_head.read().node.read().clone();
 
             self.size -= 1;
             task
@@ -228,9 +298,7 @@ impl LinkList {
 
 #[cfg(test)]
 mod tests {
-    use super::{LinkList, Task};
-    use std::cell::RefCell;
-    use std::rc::Rc;
+    use super::{Affinity, LinkList, Task};
 
     #[test]
     fn basic_functions() {
@@ -246,8 +314,8 @@ mod tests {
         ll.push_back(tasks[2].clone());
         ll.push_back(tasks[3].clone());
         ll.push_back(tasks[4].clone());
-        assert_eq!(ll.get_head().unwrap().borrow().rank, 1);
-        assert_eq!(ll.get_head().unwrap().borrow().id, 1);
+        assert_eq!(ll.get_head().unwrap().read().rank, 1);
+        assert_eq!(ll.get_head().unwrap().read().id, 1);
         // Check the size of the linked list
         assert_eq!(ll.size, 5);
     }
@@ -297,7 +365,7 @@ mod tests {
         // Search for nodes that exist in the linked list
         let found_node = ll.search_by_task(tasks[0].clone());
         assert!(found_node.is_some());
-        assert_eq!(found_node.unwrap().borrow().node.borrow().id, tasks[0].id);
+        assert_eq!(found_node.unwrap().read().node.read().id, tasks[0].id);
 
         // Push more tasks
         ll.push_back(tasks[3].clone());
@@ -348,4 +416,40 @@ mod tests {
         // Attempt to delete from an empty list
         assert!(ll.delete_task(&Task::new(42, 42, 0)).is_none());
     }
+
+    #[test]
+    fn reuse_after_emptying() {
+        let mut ll = LinkList::new();
+        ll.push_back(Task::new(1, 1, 0));
+        assert_eq!(ll.pop().unwrap().id, 1);
+        assert!(ll.get_head().is_none());
+        assert!(ll.get_tail().is_none());
+
+        // a drained list has to accept new tasks again
+        ll.push_back(Task::new(2, 1, 0));
+        ll.push_back(Task::new(3, 1, 0));
+        assert_eq!(ll.delete_task(&Task::new(3, 1, 0)).unwrap().id, 3);
+        assert_eq!(ll.delete_task(&Task::new(2, 1, 0)).unwrap().id, 2);
+        ll.push_back(Task::new(4, 1, 0));
+        let ids: Vec<i32> = ll.tasks().iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![4]);
+    }
+
+    #[test]
+    fn take_first_for_cpu() {
+        let mut ll = LinkList::new();
+        ll.push_back(Task::new(1, 1, 0).with_affinity(Affinity::only(0)));
+        ll.push_back(Task::new(2, 1, 0).with_affinity(Affinity::cpus(&[1, 2])));
+        ll.push_back(Task::new(3, 1, 0));
+        assert_eq!(ll.affinity(), Affinity::ALL);
+
+        assert_eq!(ll.first_for_cpu(2).unwrap().id, 2);
+        assert_eq!(ll.take_first_for_cpu(2).unwrap().id, 2);
+        assert_eq!(ll.take_first_for_cpu(2).unwrap().id, 3);
+        // only the task pinned to cpu 0 is left, the summary narrows to it
+        assert!(ll.take_first_for_cpu(1).is_none());
+        assert_eq!(ll.affinity(), Affinity::only(0));
+        assert_eq!(ll.take_first_for_cpu(0).unwrap().id, 1);
+        assert_eq!(ll.affinity(), Affinity::NONE);
+    }
 }
@@ -1,32 +1,343 @@
+pub mod admission;
 pub mod avl;
+pub mod concurrent;
+pub mod dag;
 pub mod linklist;
+pub mod waitfor;
+
+use serde::{Deserialize, Serialize};
 
 /*
  * id to determine a task
  * rank to determine priority
     ll.push_back(task);
  * state will need to change to a different struct
+ *
+ * The timing fields describe the task to the simulator. A task arrives at
+ * `arrival` and needs `burst` ticks of cpu, `remaining` counts down as it runs.
+ * A periodic task releases a new job every `period` ticks and each job has to
+ * finish `deadline` ticks after its release (defaults to the period).
+ * `affinity` is the set of cpus the task may run on, every cpu by default.
+ * `nice` weighs the task for the cfs policy, which keeps the weighted time
+ * the task ran so far in `vruntime`. `pass` is where the stride policy has
+ * got the task to, the smallest pass runs next.
+ * `estimate` is what the burst predictor expects of the task's next cpu burst.
+ * `criticality` says how much the task matters, `wcet` holds the budget it is
+ * granted at each criticality level, lo first. Without budgets every level
+ * grants the burst.
 */
 #[derive(Debug, Clone, PartialEq)]
 pub struct Task {
     id: i32,
     rank: i32,
     state: i32, // will change to a task struct
+    job: u32,
+    arrival: u64,
+    burst: u64,
+    remaining: u64,
+    period: Option<u64>,
+    deadline: Option<u64>,
+    abs_deadline: Option<u64>,
+    io: Option<IoPattern>,
+    affinity: Affinity,
+    nice: i32,
+    vruntime: u64,
+    pass: u64,
+    estimate: BurstEstimate,
+    criticality: Criticality,
+    wcet: Option<[u64; 2]>,
+}
+
+// After every `every` ticks on the cpu the task blocks for `duration` ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoPattern {
+    pub every: u64,
+    pub duration: u64,
+}
+
+// The predicted length of the current cpu burst and the ticks it ran of it so far
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BurstEstimate {
+    pub predicted: f64,
+    pub ran: u64,
+}
+
+impl BurstEstimate {
+    // what is left of the prediction, a burst that ran past it is expected
+    // to run about as long again as it already did
+    pub fn remaining(&self) -> f64 {
+        let ran = self.ran as f64;
+        if ran < self.predicted {
+            self.predicted - ran
+        } else {
+            ran
+        }
+    }
+}
+
+// Criticality levels, a hi task is certified to a larger wcet than a lo one
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Criticality {
+    #[default]
+    Lo,
+    Hi,
+}
+
+impl Criticality {
+    pub fn name(self) -> &'static str {
+        match self {
+            Criticality::Lo => "lo",
+            Criticality::Hi => "hi",
+        }
+    }
+}
+
+// Set of cpus as a bit mask, bit n is cpu n so cpus 0 to 63 can be named
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Affinity(u64);
+
+impl Default for Affinity {
+    fn default() -> Self {
+        Affinity::ALL
+    }
+}
+
+impl Affinity {
+    pub const ALL: Affinity = Affinity(u64::MAX);
+    pub const NONE: Affinity = Affinity(0);
+    pub const MAX_CPUS: usize = 64;
+
+    pub fn from_bits(bits: u64) -> Self {
+        Affinity(bits)
+    }
+
+    // cpus past MAX_CPUS are ignored
+    pub fn cpus(cpus: &[usize]) -> Self {
+        Affinity(
+            cpus.iter()
+                .filter(|&&cpu| cpu < Self::MAX_CPUS)
+                .fold(0, |bits, &cpu| bits | 1 << cpu),
+        )
+    }
+
+    // pinned to a single cpu
+    pub fn only(cpu: usize) -> Self {
+        Affinity::cpus(&[cpu])
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn allows(self, cpu: usize) -> bool {
+        cpu < Self::MAX_CPUS && self.0 & (1 << cpu) != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn union(self, other: Affinity) -> Affinity {
+        Affinity(self.0 | other.0)
+    }
 }
 
 impl Task {
     pub fn new(id: i32, rank: i32, state: i32) -> Task {
-        Task { id, rank, state }
+        Task {
+            id,
+            rank,
+            state,
+            job: 0,
+            arrival: 0,
+            burst: 1,
+            remaining: 1,
+            period: None,
+            deadline: None,
+            abs_deadline: None,
+            io: None,
+            affinity: Affinity::ALL,
+            nice: 0,
+            vruntime: 0,
+            pass: 0,
+            estimate: BurstEstimate::default(),
+            criticality: Criticality::Lo,
+            wcet: None,
+        }
+    }
+
+    pub fn with_arrival(mut self, arrival: u64) -> Task {
+        self.arrival = arrival;
+        self
+    }
+
+    pub fn with_burst(mut self, burst: u64) -> Task {
+        self.burst = burst;
+        self.remaining = burst;
+        self
+    }
+
+    pub fn with_period(mut self, period: u64) -> Task {
+        self.period = Some(period);
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: u64) -> Task {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_io(mut self, every: u64, duration: u64) -> Task {
+        self.io = Some(IoPattern { every, duration });
+        self
+    }
+
+    pub fn with_affinity(mut self, affinity: Affinity) -> Task {
+        self.affinity = affinity;
+        self
+    }
+
+    pub fn with_nice(mut self, nice: i32) -> Task {
+        self.nice = nice;
+        self
+    }
+
+    // the budgets at the lo and the hi level, hi is at least lo
+    pub fn with_criticality(mut self, criticality: Criticality, lo: u64, hi: u64) -> Task {
+        self.criticality = criticality;
+        self.wcet = Some([lo, hi.max(lo)]);
+        self
     }
 
     pub fn get_rank(&self) -> i32 {
         self.rank
     }
 
+    pub fn set_rank(&mut self, rank: i32) {
+        self.rank = rank;
+    }
+
     pub fn get_id(&self) -> i32 {
         self.id
     }
     pub fn get_state(&self) -> i32 {
         self.state
     }
+
+    // which release of a periodic task this is, 0 for a one shot task
+    pub fn get_job(&self) -> u32 {
+        self.job
+    }
+
+    pub fn get_arrival(&self) -> u64 {
+        self.arrival
+    }
+
+    pub fn get_burst(&self) -> u64 {
+        self.burst
+    }
+
+    pub fn get_remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    pub fn get_period(&self) -> Option<u64> {
+        self.period
+    }
+
+    // relative deadline, an explicit deadline wins over the implicit period one
+    pub fn get_deadline(&self) -> Option<u64> {
+        self.deadline.or(self.period)
+    }
+
+    pub fn get_abs_deadline(&self) -> Option<u64> {
+        self.abs_deadline
+    }
+
+    pub fn get_io(&self) -> Option<IoPattern> {
+        self.io
+    }
+
+    pub fn get_affinity(&self) -> Affinity {
+        self.affinity
+    }
+
+    pub fn set_affinity(&mut self, affinity: Affinity) {
+        self.affinity = affinity;
+    }
+
+    pub fn get_nice(&self) -> i32 {
+        self.nice
+    }
+
+    pub fn get_vruntime(&self) -> u64 {
+        self.vruntime
+    }
+
+    pub fn set_vruntime(&mut self, vruntime: u64) {
+        self.vruntime = vruntime;
+    }
+
+    pub fn get_pass(&self) -> u64 {
+        self.pass
+    }
+
+    pub fn set_pass(&mut self, pass: u64) {
+        self.pass = pass;
+    }
+
+    pub fn get_estimate(&self) -> BurstEstimate {
+        self.estimate
+    }
+
+    pub fn set_estimate(&mut self, estimate: BurstEstimate) {
+        self.estimate = estimate;
+    }
+
+    pub fn get_criticality(&self) -> Criticality {
+        self.criticality
+    }
+
+    // the budget granted at `level`
+    pub fn get_wcet(&self, level: Criticality) -> u64 {
+        match self.wcet {
+            Some(wcet) => wcet[level as usize],
+            None => self.burst,
+        }
+    }
+
+    // ticks the current job ran so far
+    pub fn get_executed(&self) -> u64 {
+        self.burst - self.remaining
+    }
+
+    pub fn can_run_on(&self, cpu: usize) -> bool {
+        self.affinity.allows(cpu)
+    }
+
+    // creates job number `job` released at `release`, the deadline is relative to it
+    pub fn release(&self, job: u32, release: u64) -> Task {
+        let mut job = Task {
+            job,
+            ..self.clone()
+        };
+        job.arrival = release;
+        job.remaining = self.burst;
+        job.abs_deadline = self.get_deadline().map(|d| release + d);
+        job
+    }
+
+    // rolls a job back, the simulator redoes work it took a resource away from
+    pub fn set_remaining(&mut self, remaining: u64) {
+        self.remaining = remaining.min(self.burst);
+    }
+
+    // runs the task for `ticks`, returns true once there is no work left
+    pub fn run_for(&mut self, ticks: u64) -> bool {
+        self.remaining = self.remaining.saturating_sub(ticks);
+        self.remaining == 0
+    }
 }
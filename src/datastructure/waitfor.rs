@@ -0,0 +1,159 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/*
+ * Wait-for graph between tasks and resources.
+ * A resource points at the task holding it and a blocked task points at the
+ * resource it waits for. A task waits for one resource at a time and a
+ * resource has one holder, so following the edges from a task is a single
+ * path, the tasks deadlock when that path comes back to where it started.
+ *
+ * `wait` checks the path of the new edge only, which is enough when it is
+ * called on every block (continuous detection), `cycles` walks the whole
+ * graph for checks on demand.
+*/
+#[derive(Debug, Clone)]
+pub struct WaitForGraph<T, R> {
+    holders: HashMap<R, T>,
+    waits: HashMap<T, R>,
+}
+
+// tasks[i] waits for resources[i], which is held by tasks[i + 1] (wrapping around)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deadlock<T, R> {
+    pub tasks: Vec<T>,
+    pub resources: Vec<R>,
+}
+
+impl<T, R> Default for WaitForGraph<T, R> {
+    fn default() -> Self {
+        WaitForGraph {
+            holders: HashMap::new(),
+            waits: HashMap::new(),
+        }
+    }
+}
+
+impl<T, R> WaitForGraph<T, R>
+where
+    T: Copy + Eq + Hash + Ord,
+    R: Clone + Eq + Hash,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn holder(&self, resource: &R) -> Option<T> {
+        self.holders.get(resource).copied()
+    }
+
+    pub fn waits_for(&self, task: T) -> Option<&R> {
+        self.waits.get(&task)
+    }
+
+    pub fn held_by(&self, task: T) -> Vec<R> {
+        self.holders
+            .iter()
+            .filter(|(_, holder)| **holder == task)
+            .map(|(resource, _)| resource.clone())
+            .collect()
+    }
+
+    // the task holds the resource and no longer waits for anything
+    pub fn acquire(&mut self, resource: R, task: T) {
+        self.waits.remove(&task);
+        self.holders.insert(resource, task);
+    }
+
+    pub fn release(&mut self, resource: &R) -> Option<T> {
+        self.holders.remove(resource)
+    }
+
+    // records that the task waits for the resource, with the cycle it closes if any
+    pub fn wait(&mut self, task: T, resource: R) -> Option<Deadlock<T, R>> {
+        self.waits.insert(task, resource);
+        self.cycle_from(task)
+    }
+
+    pub fn stop_waiting(&mut self, task: T) {
+        self.waits.remove(&task);
+    }
+
+    // drops every edge of the task, the resources it held are free afterwards
+    pub fn remove(&mut self, task: T) -> Vec<R> {
+        self.waits.remove(&task);
+        let held = self.held_by(task);
+        for resource in &held {
+            self.holders.remove(resource);
+        }
+        held
+    }
+
+    // follows the path from the task, Some when it comes back to it
+    pub fn cycle_from(&self, task: T) -> Option<Deadlock<T, R>> {
+        let mut tasks = vec![task];
+        let mut resources = Vec::new();
+        let mut at = task;
+        loop {
+            let resource = self.waits.get(&at)?;
+            let holder = *self.holders.get(resource)?;
+            resources.push(resource.clone());
+            if holder == task {
+                return Some(Deadlock { tasks, resources });
+            }
+            // a path into someone else's cycle is blocked but not part of it
+            if tasks.contains(&holder) {
+                return None;
+            }
+            tasks.push(holder);
+            at = holder;
+        }
+    }
+
+    // every cycle once, each starting at its smallest task
+    pub fn cycles(&self) -> Vec<Deadlock<T, R>> {
+        let mut seen = HashSet::new();
+        let mut starts: Vec<T> = self.waits.keys().copied().collect();
+        starts.sort_unstable();
+        let mut found = Vec::new();
+        for task in starts {
+            if seen.contains(&task) {
+                continue;
+            }
+            if let Some(cycle) = self.cycle_from(task) {
+                seen.extend(cycle.tasks.iter().copied());
+                found.push(cycle);
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_cycle_through_a_new_edge() {
+        let mut graph: WaitForGraph<i32, &str> = WaitForGraph::new();
+        graph.acquire("a", 1);
+        graph.acquire("b", 2);
+        graph.acquire("c", 3);
+        assert_eq!(graph.wait(1, "b"), None);
+        // 4 waits on the cycle but is not part of it
+        assert_eq!(graph.wait(4, "c"), None);
+        assert_eq!(graph.wait(3, "a"), None);
+        let cycle = graph.wait(2, "c").unwrap();
+        assert_eq!(cycle.tasks, vec![2, 3, 1]);
+        assert_eq!(cycle.resources, vec!["c", "a", "b"]);
+        assert_eq!(graph.cycle_from(4), None);
+
+        let all = graph.cycles();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].tasks, vec![1, 2, 3]);
+
+        assert_eq!(graph.remove(2), vec!["b"]);
+        assert!(graph.cycles().is_empty());
+        assert_eq!(graph.holder(&"b"), None);
+    }
+}
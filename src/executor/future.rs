@@ -0,0 +1,742 @@
+use crate::datastructure::avl::AvlTree;
+use crate::datastructure::Task;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+
+/*
+ * Executor for futures with a rank.
+ * The loop in `run` owns the AvlTree of ready futures and always polls the
+ * highest rank first, futures of the same rank take turns in the order they
+ * became ready. Wakers and spawners only send task ids to that loop so they
+ * can be used from any thread.
+ * Single threaded, the futures are polled on the thread calling `run`.
+ * Multi threaded, `run` hands the futures out to worker threads.
+ * With aging, every `aging` polls the futures that are not being polled move
+ * up one rank, the ones in the tree as well as the ones waiting for a waker.
+ * A future keeps its aged rank until it is polled again, so a woken future
+ * is queued at its aged rank, after a poll it drops back to the rank it was
+ * spawned with.
+ * `run` returns once every future completed, or once nothing is queued and
+ * no waker of a pending future is left, those futures can never be woken.
+ * A future that panics is caught where it is polled, on the worker thread
+ * too, it counts as completed and its JoinHandle reports JoinError::Panicked.
+*/
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type Cell = Arc<Mutex<Option<BoxFuture>>>;
+
+enum Event {
+    Spawn { id: i32, rank: i32, cell: Cell },
+    Wake(i32),
+    Polled { worker: usize, id: i32, done: bool },
+}
+
+// spawns futures onto an executor, can be cloned into other futures and threads
+#[derive(Clone)]
+pub struct Spawner {
+    sender: Sender<Event>,
+    next_id: Arc<AtomicI32>,
+}
+
+impl Spawner {
+    pub fn spawn<F>(&self, rank: i32, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let state = Arc::new(JoinState::default());
+        let result = state.clone();
+        let wrapped: BoxFuture = Box::pin(async move {
+            let output = CatchUnwind(Box::pin(future)).await;
+            result.complete(output);
+        });
+        let cell = Arc::new(Mutex::new(Some(wrapped)));
+        // the executor lives as long as a spawner could be used from its tasks
+        let _ = self.sender.send(Event::Spawn { id, rank, cell });
+        JoinHandle { id, state }
+    }
+}
+
+// why a JoinHandle has no result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    // the future panicked while it was polled
+    Panicked,
+    // `run` returned with the future still pending, nothing was left to wake it
+    Stalled,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Panicked => write!(f, "the future panicked"),
+            JoinError::Stalled => write!(f, "the future stalled, nothing can wake it"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+// polls the future inside catch_unwind, a panic ends it with JoinError::Panicked
+struct CatchUnwind<F>(Pin<Box<F>>);
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = self.0.as_mut();
+        match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(_) => Poll::Ready(Err(JoinError::Panicked)),
+        }
+    }
+}
+
+struct JoinState<T> {
+    result: Mutex<Option<Result<T, JoinError>>>,
+    finished: Mutex<bool>,
+    cond: Condvar,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl<T> Default for JoinState<T> {
+    fn default() -> Self {
+        JoinState {
+            result: Mutex::new(None),
+            finished: Mutex::new(false),
+            cond: Condvar::new(),
+            waker: Mutex::new(None),
+        }
+    }
+}
+
+impl<T> JoinState<T> {
+    fn complete(&self, output: Result<T, JoinError>) {
+        *self.result.lock().unwrap() = Some(output);
+        *self.finished.lock().unwrap() = true;
+        self.cond.notify_all();
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+// the result of a spawned future, await it from another future or `join` it from a thread
+pub struct JoinHandle<T> {
+    id: i32,
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        *self.state.finished.lock().unwrap()
+    }
+
+    // the result if the future finished and it was not taken yet
+    pub fn try_take(&self) -> Option<Result<T, JoinError>> {
+        self.state.result.lock().unwrap().take()
+    }
+
+    // blocks the calling thread until the future finished
+    pub fn join(self) -> Result<T, JoinError> {
+        let mut finished = self.state.finished.lock().unwrap();
+        while !*finished {
+            finished = self.state.cond.wait(finished).unwrap();
+        }
+        drop(finished);
+        self.try_take().expect("result already taken")
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(output) = self.try_take() {
+            return Poll::Ready(output);
+        }
+        *self.state.waker.lock().unwrap() = Some(cx.waker().clone());
+        // it may have finished while the waker was stored
+        match self.try_take() {
+            Some(output) => Poll::Ready(output),
+            None => Poll::Pending,
+        }
+    }
+}
+
+// gives the other futures of the same rank a turn before continuing
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+pub struct YieldNow {
+    yielded: bool,
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+struct TaskWaker {
+    id: i32,
+    sender: Sender<Event>,
+    // wakers alive over all futures of the executor
+    live: Arc<AtomicUsize>,
+}
+
+impl Drop for TaskWaker {
+    fn drop(&mut self) {
+        self.live.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let _ = self.sender.send(Event::Wake(self.id));
+    }
+}
+
+// polls a future once, returns true when it completed
+fn poll_task(id: i32, cell: &Cell, sender: &Sender<Event>, live: &Arc<AtomicUsize>) -> bool {
+    live.fetch_add(1, Ordering::AcqRel);
+    let waker = Waker::from(Arc::new(TaskWaker {
+        id,
+        sender: sender.clone(),
+        live: live.clone(),
+    }));
+    let mut cx = Context::from_waker(&waker);
+    let mut slot = cell.lock().unwrap();
+    match slot.as_mut() {
+        Some(future) => {
+            let done = future.as_mut().poll(&mut cx).is_ready();
+            if done {
+                *slot = None;
+            }
+            done
+        }
+        None => true,
+    }
+}
+
+pub struct Executor {
+    spawner: Spawner,
+    inbox: Receiver<Event>,
+    workers: usize,
+    aging: Option<u64>,
+    polls: u64,
+    wakers: Arc<AtomicUsize>,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Executor {
+    // polls every future on the thread that calls `run`
+    pub fn new() -> Self {
+        Executor::multi_threaded(0)
+    }
+
+    // polls the futures on `workers` threads, 0 is the same as `new`
+    pub fn multi_threaded(workers: usize) -> Self {
+        let (sender, inbox) = channel();
+        Executor {
+            spawner: Spawner {
+                sender,
+                next_id: Arc::new(AtomicI32::new(1)),
+            },
+            inbox,
+            workers,
+            aging: None,
+            polls: 0,
+            wakers: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn with_aging(mut self, polls: u64) -> Self {
+        self.aging = Some(polls.max(1));
+        self
+    }
+
+    pub fn spawner(&self) -> Spawner {
+        self.spawner.clone()
+    }
+
+    pub fn spawn<F>(&self, rank: i32, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawner.spawn(rank, future)
+    }
+
+    // runs `future` and every other spawned future to completion
+    pub fn block_on<F>(&mut self, rank: i32, future: F) -> Result<F::Output, JoinError>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let handle = self.spawn(rank, future);
+        self.run();
+        // run also returns when nothing is left that could wake the future
+        handle.try_take().unwrap_or(Err(JoinError::Stalled))
+    }
+
+    // polls until every spawned future completed
+    pub fn run(&mut self) {
+        let mut scheduler = Scheduler::new(self.aging, self.polls);
+        if self.workers == 0 {
+            self.run_local(&mut scheduler);
+        } else {
+            self.run_threads(&mut scheduler);
+        }
+        self.polls = scheduler.polls;
+    }
+
+    // true when the pending futures can never be woken, a waker that was
+    // used before it was dropped left its event in the inbox
+    fn stuck(&self, scheduler: &mut Scheduler) -> bool {
+        if self.wakers.load(Ordering::Acquire) > 0 {
+            return false;
+        }
+        let mut woken = false;
+        while let Ok(event) = self.inbox.try_recv() {
+            scheduler.handle(event);
+            woken = true;
+        }
+        !woken
+    }
+
+    fn run_local(&self, scheduler: &mut Scheduler) {
+        let sender = &self.spawner.sender;
+        loop {
+            while let Ok(event) = self.inbox.try_recv() {
+                scheduler.handle(event);
+            }
+            match scheduler.next() {
+                Some((id, cell)) => {
+                    let done = poll_task(id, &cell, sender, &self.wakers);
+                    scheduler.polled(id, done);
+                }
+                None if scheduler.is_done() => return,
+                None if self.stuck(scheduler) => return,
+                // everything is waiting on a waker from another thread
+                None => match self.inbox.recv() {
+                    Ok(event) => scheduler.handle(event),
+                    Err(_) => return,
+                },
+            }
+        }
+    }
+
+    fn run_threads(&self, scheduler: &mut Scheduler) {
+        thread::scope(|scope| {
+            let mut work = Vec::with_capacity(self.workers);
+            for worker in 0..self.workers {
+                let (work_sender, jobs) = channel::<(i32, Cell)>();
+                work.push(work_sender);
+                let sender = self.spawner.sender.clone();
+                let live = self.wakers.clone();
+                scope.spawn(move || {
+                    while let Ok((id, cell)) = jobs.recv() {
+                        let done = poll_task(id, &cell, &sender, &live);
+                        let _ = sender.send(Event::Polled { worker, id, done });
+                    }
+                });
+            }
+
+            let mut idle: Vec<usize> = (0..self.workers).rev().collect();
+            loop {
+                while let Ok(event) = self.inbox.try_recv() {
+                    if let Event::Polled { worker, .. } = event {
+                        idle.push(worker);
+                    }
+                    scheduler.handle(event);
+                }
+                while !idle.is_empty() {
+                    let Some(next) = scheduler.next() else {
+                        break;
+                    };
+                    let worker = idle.pop().unwrap();
+                    let _ = work[worker].send(next);
+                }
+                if scheduler.is_done() {
+                    break;
+                }
+                if idle.len() == self.workers && scheduler.ready.task_count() == 0 {
+                    if self.stuck(scheduler) {
+                        break;
+                    }
+                    continue;
+                }
+                match self.inbox.recv() {
+                    Ok(event) => {
+                        if let Event::Polled { worker, .. } = event {
+                            idle.push(worker);
+                        }
+                        scheduler.handle(event);
+                    }
+                    Err(_) => break,
+                }
+            }
+            // dropping the senders stops the workers
+            drop(work);
+        });
+    }
+}
+
+// bookkeeping for a spawned future, only touched by the loop in `run`
+struct Slot {
+    cell: Cell,
+    base_rank: i32,
+    rank: i32,
+    queued: bool,
+    running: bool,
+    // woken while it was being polled
+    notified: bool,
+}
+
+struct Scheduler {
+    ready: AvlTree,
+    slots: HashMap<i32, Slot>,
+    aging: Option<u64>,
+    polls: u64,
+}
+
+impl Scheduler {
+    fn new(aging: Option<u64>, polls: u64) -> Self {
+        Scheduler {
+            ready: AvlTree::new(),
+            slots: HashMap::new(),
+            aging,
+            polls,
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Spawn { id, rank, cell } => {
+                self.slots.insert(
+                    id,
+                    Slot {
+                        cell,
+                        base_rank: rank,
+                        rank,
+                        queued: false,
+                        running: false,
+                        notified: false,
+                    },
+                );
+                self.enqueue(id);
+            }
+            Event::Wake(id) => self.enqueue(id),
+            Event::Polled { id, done, .. } => self.polled(id, done),
+        }
+    }
+
+    fn enqueue(&mut self, id: i32) {
+        let Some(slot) = self.slots.get_mut(&id) else {
+            return;
+        };
+        if slot.running {
+            slot.notified = true;
+        } else if !slot.queued {
+            slot.queued = true;
+            self.ready.insert(Task::new(id, slot.rank, 0));
+        }
+    }
+
+    fn next(&mut self) -> Option<(i32, Cell)> {
+        let task = self.ready.pop_highest()?;
+        let slot = self.slots.get_mut(&task.get_id())?;
+        slot.queued = false;
+        slot.running = true;
+        slot.rank = slot.base_rank;
+        Some((task.get_id(), slot.cell.clone()))
+    }
+
+    fn polled(&mut self, id: i32, done: bool) {
+        self.polls += 1;
+        if done {
+            self.slots.remove(&id);
+        } else if let Some(slot) = self.slots.get_mut(&id) {
+            slot.running = false;
+            if slot.notified {
+                slot.notified = false;
+                self.enqueue(id);
+            }
+        }
+        if let Some(aging) = self.aging {
+            if self.polls.is_multiple_of(aging) {
+                self.age();
+            }
+        }
+    }
+
+    fn age(&mut self) {
+        // pending futures age in their slot and are queued at that rank once woken
+        for slot in self.slots.values_mut() {
+            if !slot.queued && !slot.running {
+                slot.rank = slot.rank.saturating_add(1);
+            }
+        }
+        for task in self.ready.tasks() {
+            if let Some(mut task) = self.ready.delete_by_task(&task) {
+                task.set_rank(task.get_rank().saturating_add(1));
+                if let Some(slot) = self.slots.get_mut(&task.get_id()) {
+                    slot.rank = task.get_rank();
+                }
+                self.ready.insert(task);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_order(
+        executor: &Executor,
+        log: &Arc<Mutex<Vec<i32>>>,
+        tag: i32,
+        rank: i32,
+        turns: usize,
+    ) {
+        let log = log.clone();
+        executor.spawn(rank, async move {
+            for _ in 0..turns {
+                log.lock().unwrap().push(tag);
+                yield_now().await;
+            }
+        });
+    }
+
+    #[test]
+    fn highest_rank_first_and_round_robin_within_rank() {
+        let mut executor = Executor::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        log_order(&executor, &log, 1, 1, 1);
+        log_order(&executor, &log, 2, 5, 3);
+        log_order(&executor, &log, 3, 5, 3);
+        log_order(&executor, &log, 4, 3, 1);
+        executor.run();
+        assert_eq!(*log.lock().unwrap(), vec![2, 3, 2, 3, 2, 3, 4, 1]);
+    }
+
+    #[test]
+    fn join_handles_and_nested_spawns() {
+        let mut executor = Executor::new();
+        let spawner = executor.spawner();
+        let total = executor.block_on(0, async move {
+            let handles: Vec<JoinHandle<u64>> = (1..=10)
+                .map(|i| spawner.spawn(i as i32, async move { i * i }))
+                .collect();
+            let mut total = 0;
+            for handle in handles {
+                total += handle.await.unwrap();
+            }
+            total
+        });
+        assert_eq!(total, Ok(385));
+    }
+
+    #[test]
+    fn aging_lets_low_rank_through() {
+        let mut executor = Executor::new().with_aging(2);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        log_order(&executor, &log, 1, 4, 10);
+        log_order(&executor, &log, 2, 0, 1);
+        executor.run();
+        let log = log.lock().unwrap();
+        let low = log.iter().position(|tag| *tag == 2).unwrap();
+        assert!(low < log.len() - 1, "{log:?}");
+    }
+
+    #[test]
+    fn multi_threaded_runs_everything() {
+        let mut executor = Executor::multi_threaded(4);
+        let handles: Vec<JoinHandle<u64>> = (0..64)
+            .map(|i| {
+                executor.spawn(i % 5, async move {
+                    let mut sum = 0;
+                    for step in 0..4 {
+                        sum += i as u64 * step;
+                        yield_now().await;
+                    }
+                    sum
+                })
+            })
+            .collect();
+        executor.run();
+        let total: u64 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(total, (0..64u64).map(|i| i * 6).sum::<u64>());
+    }
+
+    #[test]
+    fn woken_from_another_thread() {
+        let mut executor = Executor::multi_threaded(2);
+        let handle = executor.spawn(1, async {
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let timer = Timer::default();
+            let shared = timer.clone();
+            thread::spawn(move || {
+                thread::sleep(std::time::Duration::from_millis(5));
+                shared.fire();
+                sender.send(7).unwrap();
+            });
+            timer.await;
+            receiver.recv().unwrap()
+        });
+        executor.run();
+        assert_eq!(handle.join(), Ok(7));
+    }
+
+    #[test]
+    fn a_woken_future_is_queued_at_its_aged_rank() {
+        let mut executor = Executor::new().with_aging(1);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let timer = Timer::default();
+        let (high_log, high_timer) = (log.clone(), timer.clone());
+        executor.spawn(3, async move {
+            for step in 0..10 {
+                high_log.lock().unwrap().push(("high", step));
+                if step == 5 {
+                    high_timer.fire();
+                }
+                yield_now().await;
+            }
+        });
+        let low_log = log.clone();
+        executor.spawn(0, async move {
+            timer.await;
+            low_log.lock().unwrap().push(("low", 0));
+        });
+        executor.run();
+        // it aged while it waited, so it runs within a step of the wake
+        // instead of starting over from rank 0 behind the rest of high
+        let log = log.lock().unwrap();
+        let low = log.iter().position(|entry| entry.0 == "low").unwrap();
+        assert!(low <= 7, "{log:?}");
+    }
+
+    #[test]
+    fn run_returns_when_no_waker_is_left() {
+        // pending for good, the waker is dropped right away
+        struct Forever;
+        impl Future for Forever {
+            type Output = ();
+            fn poll(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+                Poll::Pending
+            }
+        }
+        for workers in [0, 2] {
+            let mut executor = Executor::multi_threaded(workers);
+            let stuck = executor.spawn(1, Forever);
+            let done = executor.spawn(0, async { 5 });
+            executor.run();
+            assert!(!stuck.is_finished());
+            assert_eq!(done.join(), Ok(5));
+        }
+        // block_on says so instead of panicking
+        let mut executor = Executor::new();
+        assert_eq!(
+            executor.block_on(0, std::future::pending::<u32>()),
+            Err(JoinError::Stalled)
+        );
+    }
+
+    #[test]
+    fn a_panicking_future_poisons_its_handle() {
+        for workers in [0, 2] {
+            let mut executor = Executor::multi_threaded(workers);
+            let bad = executor.spawn(3, async {
+                yield_now().await;
+                panic!("the second poll fails");
+            });
+            let good: Vec<JoinHandle<i32>> = (0..4)
+                .map(|i| {
+                    executor.spawn(i, async move {
+                        yield_now().await;
+                        i
+                    })
+                })
+                .collect();
+            // the worker survived, so run comes back
+            executor.run();
+            assert!(bad.is_finished());
+            assert_eq!(bad.join(), Err(JoinError::Panicked));
+            let total: i32 = good.into_iter().map(|h| h.join().unwrap()).sum();
+            assert_eq!(total, 6);
+        }
+        // a future awaiting it sees the failure too
+        let mut executor = Executor::multi_threaded(2);
+        let spawner = executor.spawner();
+        let seen = executor.block_on(0, async move {
+            spawner.spawn(1, async { panic!("fails") }).await
+        });
+        assert_eq!(seen, Ok(Err::<(), _>(JoinError::Panicked)));
+    }
+
+    // becomes ready once `fire` was called on any clone
+    #[derive(Clone, Default)]
+    struct Timer {
+        state: Arc<Mutex<(bool, Option<Waker>)>>,
+    }
+
+    impl Timer {
+        fn fire(&self) {
+            let mut state = self.state.lock().unwrap();
+            state.0 = true;
+            if let Some(waker) = state.1.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    impl Future for Timer {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let mut state = self.state.lock().unwrap();
+            if state.0 {
+                Poll::Ready(())
+            } else {
+                state.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,4 @@
+pub mod future;
+pub mod mutex;
+pub mod pool;
+pub mod stealing;
@@ -0,0 +1,399 @@
+use super::pool::{Message, TaskContext};
+use crate::datastructure::waitfor::WaitForGraph;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/*
+ * Priority inheritance mutex for the ThreadPool.
+ * A PiMutex is held by a task, not by a thread: the task keeps it over steps
+ * and preemptions until it calls `unlock` or finishes. A task that finds it
+ * locked is queued as a waiter and its step has to return
+ * StepResult::Blocked, it leaves the ready tree until the mutex is handed to
+ * it. While a higher rank task waits, the holder runs at the waiter's rank:
+ * the dispatcher moves it to that rank's bucket, or treats it as having that
+ * rank when it is running. If the holder is itself waiting for another mutex
+ * the raised rank carries on down the chain.
+ * On unlock the mutex goes straight to the highest rank waiter and the
+ * holder drops back to its own rank, or to the highest rank still waiting on
+ * another mutex it holds.
+ * A task whose closure panics ends and hands on its mutexes like any
+ * finished task. The data is not poisoned for the next holder, it sees
+ * whatever the panicking task left behind, half done updates included.
+*/
+
+// what the dispatcher has to do after the lock table changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LockEvent {
+    // the task's effective rank changed, move it
+    Rerank(i32),
+    // the task was handed the mutex it waited for
+    Wake(i32),
+}
+
+// an unlock by a task that does not hold the mutex
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnlockError {
+    pub mutex: usize,
+    pub task: i32,
+    pub holder: Option<i32>,
+}
+
+impl fmt::Display for UnlockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.holder {
+            Some(holder) => write!(
+                f,
+                "task {} unlocks mutex {} held by task {holder}",
+                self.task, self.mutex
+            ),
+            None => write!(
+                f,
+                "task {} unlocks mutex {} that nobody holds",
+                self.task, self.mutex
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UnlockError {}
+
+// mutexes of one pool, shared between the mutexes and the dispatcher
+#[derive(Debug, Default)]
+pub(crate) struct Locks {
+    next_mutex: usize,
+    // mutexes that do not pass their waiters' ranks on
+    plain: HashSet<usize>,
+    holders: HashMap<usize, i32>,
+    // waiting tasks of every mutex, in arrival order
+    waiters: HashMap<usize, Vec<i32>>,
+    // the mutex every blocked task waits for
+    waiting_on: HashMap<i32, usize>,
+    // own rank of every task that used a mutex, taken at its first lock
+    base: HashMap<i32, i32>,
+    // rank inherited from waiters, only kept while above the own rank
+    inherited: HashMap<i32, i32>,
+}
+
+impl Locks {
+    fn register(&mut self) -> usize {
+        self.next_mutex += 1;
+        self.next_mutex
+    }
+
+    // effective rank, None for a task that never locked anything
+    pub(crate) fn rank(&self, task: i32) -> Option<i32> {
+        let base = *self.base.get(&task)?;
+        Some(
+            self.inherited
+                .get(&task)
+                .map_or(base, |&rank| rank.max(base)),
+        )
+    }
+
+    pub(crate) fn is_waiting(&self, task: i32) -> bool {
+        self.waiting_on.contains_key(&task)
+    }
+
+    // true when the task holds the mutex, otherwise it is queued as a waiter
+    fn acquire(&mut self, mutex: usize, task: i32, rank: i32, events: &mut Vec<LockEvent>) -> bool {
+        self.base.entry(task).or_insert(rank);
+        match self.holders.get(&mutex) {
+            None => {
+                self.holders.insert(mutex, task);
+                return true;
+            }
+            Some(&holder) if holder == task => return true,
+            Some(_) => {}
+        }
+        self.waiters.entry(mutex).or_default().push(task);
+        self.waiting_on.insert(task, mutex);
+
+        // walk the chain of holders, every one runs at least at the waiter's rank
+        let rank = self.rank(task).unwrap_or(rank);
+        let mut mutex = mutex;
+        while let Some(&holder) = self.holders.get(&mutex) {
+            if self.plain.contains(&mutex) || self.rank(holder).is_some_and(|own| own >= rank) {
+                break;
+            }
+            self.inherited.insert(holder, rank);
+            events.push(LockEvent::Rerank(holder));
+            match self.waiting_on.get(&holder) {
+                Some(&next) => mutex = next,
+                None => break,
+            }
+        }
+        false
+    }
+
+    // leaves the table untouched when the task does not hold the mutex
+    fn release(
+        &mut self,
+        mutex: usize,
+        task: i32,
+        events: &mut Vec<LockEvent>,
+    ) -> Result<(), UnlockError> {
+        let holder = self.holders.get(&mutex).copied();
+        if holder != Some(task) {
+            return Err(UnlockError {
+                mutex,
+                task,
+                holder,
+            });
+        }
+        let mut queue = self.waiters.remove(&mutex).unwrap_or_default();
+        // highest rank first, the earliest arrival among equal ranks
+        let next = queue
+            .iter()
+            .enumerate()
+            .max_by_key(|(at, waiter)| (self.rank(**waiter), std::cmp::Reverse(*at)))
+            .map(|(at, _)| at);
+        match next {
+            Some(at) => {
+                let next = queue.remove(at);
+                self.holders.insert(mutex, next);
+                self.waiting_on.remove(&next);
+                if !queue.is_empty() {
+                    self.waiters.insert(mutex, queue);
+                }
+                events.push(LockEvent::Wake(next));
+                self.settle(next, events);
+            }
+            None => {
+                self.holders.remove(&mutex);
+            }
+        }
+        self.settle(task, events);
+        Ok(())
+    }
+
+    // recomputes the inherited rank from the waiters of the mutexes the task holds
+    fn settle(&mut self, task: i32, events: &mut Vec<LockEvent>) {
+        let before = self.rank(task);
+        let inherited = self
+            .holders
+            .iter()
+            .filter(|(mutex, holder)| **holder == task && !self.plain.contains(mutex))
+            .filter_map(|(mutex, _)| self.waiters.get(mutex))
+            .flatten()
+            .filter_map(|waiter| self.rank(*waiter))
+            .max();
+        match inherited {
+            Some(rank) => self.inherited.insert(task, rank),
+            None => self.inherited.remove(&task),
+        };
+        if self.rank(task) != before {
+            events.push(LockEvent::Rerank(task));
+        }
+    }
+
+    pub(crate) fn graph(&self) -> WaitForGraph<i32, usize> {
+        let mut graph = WaitForGraph::new();
+        for (&mutex, &holder) in &self.holders {
+            graph.acquire(mutex, holder);
+        }
+        for (&task, &mutex) in &self.waiting_on {
+            graph.wait(task, mutex);
+        }
+        graph
+    }
+
+    // a finished task hands on whatever it still holds
+    pub(crate) fn finish(&mut self, task: i32, events: &mut Vec<LockEvent>) {
+        let held: Vec<usize> = self
+            .holders
+            .iter()
+            .filter(|(_, holder)| **holder == task)
+            .map(|(mutex, _)| *mutex)
+            .collect();
+        for mutex in held {
+            // held by the task, the release cannot fail
+            let _ = self.release(mutex, task, events);
+        }
+        self.base.remove(&task);
+        self.inherited.remove(&task);
+    }
+}
+
+pub struct PiMutex<T> {
+    id: usize,
+    locks: Arc<Mutex<Locks>>,
+    sender: Sender<Message>,
+    data: Mutex<T>,
+}
+
+impl<T> PiMutex<T> {
+    pub(crate) fn new(locks: Arc<Mutex<Locks>>, sender: Sender<Message>, value: T) -> Self {
+        let id = locks.lock().unwrap().register();
+        PiMutex {
+            id,
+            locks,
+            sender,
+            data: Mutex::new(value),
+        }
+    }
+
+    // without inheritance a waiter never raises the holder, only for comparison
+    pub fn with_inheritance(self, inherit: bool) -> Self {
+        let mut locks = self.locks.lock().unwrap();
+        if inherit {
+            locks.plain.remove(&self.id);
+        } else {
+            locks.plain.insert(self.id);
+        }
+        drop(locks);
+        self
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn holder(&self) -> Option<i32> {
+        self.locks.lock().unwrap().holders.get(&self.id).copied()
+    }
+
+    /*
+     * Locks the mutex for the calling task, or gives access again when the
+     * task already holds it. None means the task is now waiting and the step
+     * has to return StepResult::Blocked, the task runs again once it holds
+     * the mutex. Dropping the guard keeps the mutex locked.
+     * A holder that panicked does not poison the data, see above.
+     */
+    pub fn lock(&self, ctx: &TaskContext) -> Option<PiGuard<'_, T>> {
+        let mut events = Vec::new();
+        let held = self
+            .locks
+            .lock()
+            .unwrap()
+            .acquire(self.id, ctx.id(), ctx.rank(), &mut events);
+        self.notify(events);
+        held.then(|| PiGuard {
+            data: self.data.lock().unwrap_or_else(PoisonError::into_inner),
+        })
+    }
+
+    // hands the mutex to the highest rank waiter, fails if the task does not hold it
+    pub fn unlock(&self, ctx: &TaskContext) -> Result<(), UnlockError> {
+        let mut events = Vec::new();
+        let released = self
+            .locks
+            .lock()
+            .unwrap()
+            .release(self.id, ctx.id(), &mut events);
+        self.notify(events);
+        released
+    }
+
+    fn notify(&self, events: Vec<LockEvent>) {
+        for event in events {
+            // the dispatcher only stops once every task finished
+            let _ = self.sender.send(Message::Lock(event));
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for PiMutex<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PiMutex")
+            .field("id", &self.id)
+            .field("holder", &self.holder())
+            .finish()
+    }
+}
+
+// access to the data for the rest of the step
+pub struct PiGuard<'a, T> {
+    data: MutexGuard<'a, T>,
+}
+
+impl<T> Deref for PiGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.data
+    }
+}
+
+impl<T> DerefMut for PiGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inheritance_follows_the_chain() {
+        let mut locks = Locks::default();
+        let (m1, m2) = (locks.register(), locks.register());
+        let mut events = Vec::new();
+        assert!(locks.acquire(m1, 1, 1, &mut events));
+        assert!(locks.acquire(m2, 2, 3, &mut events));
+        // 2 waits for 1, 3 waits for 2 so 1 runs at rank 9
+        assert!(!locks.acquire(m1, 2, 3, &mut events));
+        assert!(!locks.acquire(m2, 3, 9, &mut events));
+        assert_eq!(locks.rank(1), Some(9));
+        assert_eq!(locks.rank(2), Some(9));
+
+        events.clear();
+        locks.release(m1, 1, &mut events).unwrap();
+        assert_eq!(events, vec![LockEvent::Wake(2), LockEvent::Rerank(1)]);
+        assert_eq!(locks.rank(1), Some(1));
+        assert_eq!(locks.rank(2), Some(9));
+        assert!(!locks.is_waiting(2));
+
+        events.clear();
+        locks.finish(2, &mut events);
+        assert_eq!(events, vec![LockEvent::Wake(3), LockEvent::Rerank(2)]);
+        assert_eq!(locks.holders.get(&m2), Some(&3));
+    }
+
+    #[test]
+    fn highest_waiter_gets_the_mutex() {
+        let mut locks = Locks::default();
+        let m = locks.register();
+        let mut events = Vec::new();
+        locks.acquire(m, 1, 1, &mut events);
+        for (task, rank) in [(2, 4), (3, 7), (4, 7)] {
+            locks.acquire(m, task, rank, &mut events);
+        }
+        events.clear();
+        locks.release(m, 1, &mut events).unwrap();
+        assert_eq!(events[0], LockEvent::Wake(3));
+        // 4 still waits so 3 inherits nothing above its own 7
+        assert_eq!(locks.rank(3), Some(7));
+    }
+
+    #[test]
+    fn unlocking_a_foreign_mutex_fails_and_changes_nothing() {
+        let mut locks = Locks::default();
+        let m = locks.register();
+        let mut events = Vec::new();
+        assert_eq!(
+            locks.release(m, 1, &mut events),
+            Err(UnlockError {
+                mutex: m,
+                task: 1,
+                holder: None
+            })
+        );
+        locks.acquire(m, 1, 1, &mut events);
+        locks.acquire(m, 2, 5, &mut events);
+        events.clear();
+        let err = locks.release(m, 2, &mut events).unwrap_err();
+        assert_eq!(err.holder, Some(1));
+        assert_eq!(
+            err.to_string(),
+            format!("task 2 unlocks mutex {m} held by task 1")
+        );
+        assert!(events.is_empty());
+        assert_eq!(locks.holders.get(&m), Some(&1));
+        assert!(locks.is_waiting(2));
+        assert_eq!(locks.rank(1), Some(5));
+    }
+}
@@ -0,0 +1,1228 @@
+use super::mutex::{LockEvent, Locks, PiMutex};
+use crate::datastructure::admission::AdmissionQueue;
+use crate::datastructure::avl::AvlTree;
+use crate::datastructure::waitfor::Deadlock;
+use crate::datastructure::{Affinity, Task};
+use crate::scheduler::observer::{EventKind, Observers, SchedulerObserver};
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+/*
+ * Thread pool that runs task closures by rank.
+ * A task is a closure that is called once per step, every return from the
+ * closure is a yield point. A dispatcher thread owns the AvlTree ready queue
+ * and hands the highest rank task to an idle worker. When a task arrives with
+ * a higher rank than something that is running and no worker is idle, the
+ * worker running the lowest rank is asked to give up its task at its next
+ * yield point, the task goes back into the tree and the higher rank runs.
+ * Preemption is cooperative, a closure that never returns is never preempted.
+ * A closure that panics is caught on its worker, the task ends there and its
+ * handle reports Outcome::Panicked, the worker goes on with the next task.
+ * A task spawned with an affinity only runs on the workers in its mask, an
+ * idle worker takes the highest rank it may run, not the highest overall.
+ *
+ * New tasks do not go through the channel one by one. `spawn` pushes them into
+ * a lock-free admission queue and only sends a nudge when the dispatcher is not
+ * already due to look at it, the dispatcher then drains the whole batch into
+ * the tree. If the queue is full the task falls back to the channel.
+ *
+ * Tasks share data through `PiMutex`es made by `mutex`. A task that waits
+ * for one leaves the tree and the holder inherits its rank, see mutex.rs.
+ *
+ * Registered observers are told by the dispatcher when a task is spawned,
+ * dispatched, preempted or yields, blocks on a mutex and is handed it, when
+ * its inherited rank changes and when it ends. A task's job is always 0 and
+ * `at` is the number of microseconds since the pool was made.
+*/
+// tasks admitted between two drains before `spawn` falls back to the channel
+pub const ADMISSION_CAPACITY: usize = 1024;
+
+pub type Job = Box<dyn FnMut(&mut TaskContext) -> StepResult + Send>;
+
+// what a task closure returns after a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    // call me again, unless something with a higher rank is waiting
+    Continue,
+    // let the other tasks of my rank run first (round robin)
+    Yield,
+    // a PiMutex::lock returned None, run me again once I hold the mutex
+    Blocked,
+    Done,
+}
+
+// handed to the closure on every step
+#[derive(Debug)]
+pub struct TaskContext {
+    id: i32,
+    // written by the dispatcher when the task's effective rank changes
+    rank: Arc<AtomicI32>,
+    pub(crate) steps: u64,
+    preempt: Arc<AtomicBool>,
+}
+
+impl TaskContext {
+    pub(crate) fn new(id: i32, rank: Arc<AtomicI32>, steps: u64, preempt: Arc<AtomicBool>) -> Self {
+        TaskContext {
+            id,
+            rank,
+            steps,
+            preempt,
+        }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    // effective rank, raised while a waiter on a PiMutex it holds outranks it
+    pub fn rank(&self) -> i32 {
+        self.rank.load(Ordering::Acquire)
+    }
+
+    // steps this task ran so far, over all its dispatches
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // true when the task is about to be preempted at its next yield point,
+    // long running steps can check it to return early
+    pub fn should_yield(&self) -> bool {
+        self.preempt.load(Ordering::Acquire)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub spawned: u64,
+    pub completed: u64,
+    pub dispatches: u64,
+    pub preemptions: u64,
+    pub yields: u64,
+    pub blocks: u64,
+    // times a mutex holder was raised to a waiter's rank
+    pub boosts: u64,
+    // tasks whose closure panicked, they do not count as completed
+    pub panicked: u64,
+    // tasks still waiting on a PiMutex at shutdown
+    pub cancelled: u64,
+}
+
+// how a task ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    // the closure panicked
+    Panicked,
+    // the pool shut down while the task waited on a PiMutex nobody unlocks
+    Cancelled,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Completion {
+    done: Mutex<Option<Outcome>>,
+    cond: Condvar,
+}
+
+impl Completion {
+    pub(crate) fn finish(&self, outcome: Outcome) {
+        let mut done = self.done.lock().unwrap();
+        *done = Some(outcome);
+        self.cond.notify_all();
+    }
+}
+
+// waits for a spawned task
+#[derive(Debug, Clone)]
+pub struct TaskHandle {
+    id: i32,
+    completion: Arc<Completion>,
+}
+
+impl TaskHandle {
+    pub(crate) fn new(id: i32, completion: Arc<Completion>) -> Self {
+        TaskHandle { id, completion }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    // true once the task ended, whatever the outcome
+    pub fn is_finished(&self) -> bool {
+        self.outcome().is_some()
+    }
+
+    pub fn outcome(&self) -> Option<Outcome> {
+        *self.completion.done.lock().unwrap()
+    }
+
+    pub fn join(&self) -> Outcome {
+        let mut done = self.completion.done.lock().unwrap();
+        loop {
+            if let Some(outcome) = *done {
+                return outcome;
+            }
+            done = self.completion.cond.wait(done).unwrap();
+        }
+    }
+}
+
+// a task together with its closure while it is away from the tree
+pub(crate) struct Running {
+    task: Task,
+    job: Job,
+    steps: u64,
+    completion: Arc<Completion>,
+}
+
+pub(crate) enum Message {
+    Submit(Running),
+    // the admission queue has tasks waiting
+    Admit,
+    // the worker handed its task back, preempted or yielding
+    Returned {
+        worker: usize,
+        running: Running,
+        yielded: bool,
+    },
+    // the task waits for a PiMutex
+    Blocked {
+        worker: usize,
+        running: Running,
+    },
+    Lock(LockEvent),
+    Finished {
+        worker: usize,
+        outcome: Outcome,
+    },
+    Shutdown,
+}
+
+struct Admission {
+    queue: AdmissionQueue<Running>,
+    // set while an Admit message is on its way to the dispatcher
+    nudged: AtomicBool,
+}
+
+impl Default for Admission {
+    fn default() -> Self {
+        Admission {
+            queue: AdmissionQueue::new(ADMISSION_CAPACITY),
+            nudged: AtomicBool::new(false),
+        }
+    }
+}
+
+enum Work {
+    Run(Box<Running>),
+    Stop,
+}
+
+pub struct ThreadPool {
+    sender: Sender<Message>,
+    admission: Arc<Admission>,
+    dispatcher: Option<JoinHandle<()>>,
+    workers: Vec<JoinHandle<()>>,
+    next_id: AtomicI32,
+    stats: Arc<Mutex<PoolStats>>,
+    locks: Arc<Mutex<Locks>>,
+    observers: Arc<Mutex<Observers>>,
+}
+
+impl ThreadPool {
+    pub fn new(workers: usize) -> Self {
+        let workers = workers.max(1);
+        let (sender, inbox) = channel();
+        let stats = Arc::new(Mutex::new(PoolStats::default()));
+        let flags: Vec<Arc<AtomicBool>> = (0..workers)
+            .map(|_| Arc::new(AtomicBool::new(false)))
+            .collect();
+        let ranks: Vec<Arc<AtomicI32>> = (0..workers).map(|_| Arc::default()).collect();
+
+        let mut work_senders = Vec::with_capacity(workers);
+        let mut handles = Vec::with_capacity(workers);
+        for (index, (flag, rank)) in flags.iter().zip(&ranks).enumerate() {
+            let (work_sender, work) = channel();
+            work_senders.push(work_sender);
+            let sender = sender.clone();
+            let (flag, rank) = (flag.clone(), rank.clone());
+            let handle = thread::Builder::new()
+                .name(format!("pool-worker-{index}"))
+                .spawn(move || worker(index, work, sender, flag, rank))
+                .expect("failed to spawn worker thread");
+            handles.push(handle);
+        }
+
+        let admission = Arc::new(Admission::default());
+        let locks = Arc::new(Mutex::new(Locks::default()));
+        let observers = Arc::new(Mutex::new(Observers::default()));
+        let dispatcher_admission = admission.clone();
+        let dispatcher_stats = stats.clone();
+        let dispatcher_locks = locks.clone();
+        let dispatcher_observers = observers.clone();
+        let dispatcher = thread::Builder::new()
+            .name("pool-dispatcher".into())
+            .spawn(move || {
+                Dispatcher::new(
+                    work_senders,
+                    flags,
+                    ranks,
+                    dispatcher_admission,
+                    dispatcher_stats,
+                    dispatcher_locks,
+                    dispatcher_observers,
+                )
+                .run(inbox);
+            })
+            .expect("failed to spawn dispatcher thread");
+
+        ThreadPool {
+            sender,
+            admission,
+            dispatcher: Some(dispatcher),
+            workers: handles,
+            next_id: AtomicI32::new(1),
+            stats,
+            locks,
+            observers,
+        }
+    }
+
+    // may be called more than once, every observer sees the events from the
+    // next spawn on
+    pub fn with_observer(self, observer: Box<dyn SchedulerObserver>) -> Self {
+        self.observers.lock().unwrap().register(observer);
+        self
+    }
+
+    // a priority inheritance mutex for the tasks of this pool
+    pub fn mutex<T>(&self, value: T) -> PiMutex<T> {
+        PiMutex::new(self.locks.clone(), self.sender.clone(), value)
+    }
+
+    pub fn spawn<F>(&self, rank: i32, job: F) -> TaskHandle
+    where
+        F: FnMut(&mut TaskContext) -> StepResult + Send + 'static,
+    {
+        self.spawn_pinned(rank, Affinity::ALL, job)
+    }
+
+    // the task only ever runs on the workers in `affinity` (bit n is worker n)
+    pub fn spawn_pinned<F>(&self, rank: i32, affinity: Affinity, job: F) -> TaskHandle
+    where
+        F: FnMut(&mut TaskContext) -> StepResult + Send + 'static,
+    {
+        assert!(
+            (0..self.workers.len()).any(|worker| affinity.allows(worker)),
+            "affinity {:#x} names none of the {} workers",
+            affinity.bits(),
+            self.workers.len()
+        );
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let completion = Arc::new(Completion::default());
+        let running = Running {
+            task: Task::new(id, rank, 0).with_affinity(affinity),
+            job: Box::new(job),
+            steps: 0,
+            completion: completion.clone(),
+        };
+        self.stats.lock().unwrap().spawned += 1;
+        let message = match self.admission.queue.push(running) {
+            Ok(()) if self.admission.nudged.swap(true, Ordering::AcqRel) => None,
+            Ok(()) => Some(Message::Admit),
+            Err(running) => Some(Message::Submit(running)),
+        };
+        if let Some(message) = message {
+            self.sender.send(message).expect("dispatcher stopped");
+        }
+        TaskHandle { id, completion }
+    }
+
+    // cycles of tasks waiting for each other's mutexes, by task and PiMutex::id
+    pub fn deadlocks(&self) -> Vec<Deadlock<i32, usize>> {
+        self.locks.lock().unwrap().graph().cycles()
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        *self.stats.lock().unwrap()
+    }
+
+    // waits for every spawned task to finish and stops the threads, tasks
+    // left deadlocked on a PiMutex end as Outcome::Cancelled
+    pub fn shutdown(mut self) -> PoolStats {
+        self.stop();
+        self.stats()
+    }
+
+    fn stop(&mut self) {
+        if let Some(dispatcher) = self.dispatcher.take() {
+            let _ = self.sender.send(Message::Shutdown);
+            let _ = dispatcher.join();
+            for worker in self.workers.drain(..) {
+                let _ = worker.join();
+            }
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// `rank` is set by the dispatcher before it hands over a task
+fn worker(
+    index: usize,
+    work: Receiver<Work>,
+    sender: Sender<Message>,
+    preempt: Arc<AtomicBool>,
+    rank: Arc<AtomicI32>,
+) {
+    while let Ok(Work::Run(running)) = work.recv() {
+        let mut running = *running;
+        let mut ctx = TaskContext::new(
+            running.task.get_id(),
+            rank.clone(),
+            running.steps,
+            preempt.clone(),
+        );
+        let message = loop {
+            let step = panic::catch_unwind(AssertUnwindSafe(|| (running.job)(&mut ctx)));
+            let Ok(result) = step else {
+                running.completion.finish(Outcome::Panicked);
+                break Message::Finished {
+                    worker: index,
+                    outcome: Outcome::Panicked,
+                };
+            };
+            ctx.steps += 1;
+            running.steps = ctx.steps;
+            // the task leaves with the rank it inherited while running
+            running.task.set_rank(ctx.rank());
+            match result {
+                StepResult::Done => {
+                    running.completion.finish(Outcome::Done);
+                    break Message::Finished {
+                        worker: index,
+                        outcome: Outcome::Done,
+                    };
+                }
+                StepResult::Yield => {
+                    break Message::Returned {
+                        worker: index,
+                        running,
+                        yielded: true,
+                    }
+                }
+                StepResult::Blocked => {
+                    break Message::Blocked {
+                        worker: index,
+                        running,
+                    }
+                }
+                StepResult::Continue => {
+                    if preempt.swap(false, Ordering::AcqRel) {
+                        break Message::Returned {
+                            worker: index,
+                            running,
+                            yielded: false,
+                        };
+                    }
+                }
+            }
+        };
+        if sender.send(message).is_err() {
+            return;
+        }
+    }
+}
+
+// why observers see a rank change, the pool only changes ranks through its mutexes
+const INHERITED: &str = "the rank it inherits through a mutex changed";
+
+struct Dispatcher {
+    ready: AvlTree,
+    // closures of the tasks in the tree, by task id
+    parked: HashMap<i32, Running>,
+    // tasks waiting for a mutex, out of the tree
+    blocked: HashMap<i32, Running>,
+    workers: Vec<Sender<Work>>,
+    flags: Vec<Arc<AtomicBool>>,
+    // effective rank of the task each worker runs, read by its TaskContext
+    ranks: Vec<Arc<AtomicI32>>,
+    // id and rank of the task each worker runs, None when idle
+    running: Vec<Option<(i32, i32)>>,
+    admission: Arc<Admission>,
+    stats: Arc<Mutex<PoolStats>>,
+    locks: Arc<Mutex<Locks>>,
+    observers: Arc<Mutex<Observers>>,
+    // events are timed from here
+    start: Instant,
+    stopping: bool,
+}
+
+impl Dispatcher {
+    fn new(
+        workers: Vec<Sender<Work>>,
+        flags: Vec<Arc<AtomicBool>>,
+        ranks: Vec<Arc<AtomicI32>>,
+        admission: Arc<Admission>,
+        stats: Arc<Mutex<PoolStats>>,
+        locks: Arc<Mutex<Locks>>,
+        observers: Arc<Mutex<Observers>>,
+    ) -> Self {
+        let count = workers.len();
+        Dispatcher {
+            ready: AvlTree::new(),
+            parked: HashMap::new(),
+            blocked: HashMap::new(),
+            workers,
+            flags,
+            ranks,
+            running: vec![None; count],
+            admission,
+            stats,
+            locks,
+            observers,
+            start: Instant::now(),
+            stopping: false,
+        }
+    }
+
+    fn run(mut self, inbox: Receiver<Message>) {
+        while let Ok(message) = inbox.recv() {
+            match message {
+                Message::Submit(running) => self.spawned(running),
+                Message::Admit => self.admit(),
+                Message::Returned {
+                    worker,
+                    running,
+                    yielded,
+                } => {
+                    self.running[worker] = None;
+                    let mut stats = self.stats.lock().unwrap();
+                    if yielded {
+                        stats.yields += 1;
+                    } else {
+                        stats.preemptions += 1;
+                    }
+                    drop(stats);
+                    let reason = if yielded {
+                        "it yielded"
+                    } else {
+                        "a higher rank needs its worker"
+                    };
+                    self.emit(EventKind::Preempted, &running.task, reason);
+                    self.park(running);
+                }
+                Message::Blocked { worker, running } => {
+                    self.running[worker] = None;
+                    self.stats.lock().unwrap().blocks += 1;
+                    // the mutex may have been handed over before this arrived
+                    let id = running.task.get_id();
+                    if self.locks.lock().unwrap().is_waiting(id) {
+                        self.emit(EventKind::Blocked, &running.task, "it waits for a mutex");
+                        self.blocked.insert(id, running);
+                    } else {
+                        self.park(running);
+                    }
+                }
+                Message::Lock(event) => self.apply(event),
+                Message::Finished { worker, outcome } => {
+                    if let Some((id, rank)) = self.running[worker].take() {
+                        let task = Task::new(id, rank, 0);
+                        let reason = match outcome {
+                            Outcome::Panicked => "its closure panicked",
+                            _ => "its closure returned Done",
+                        };
+                        self.emit(EventKind::Completed, &task, reason);
+                        self.observers.lock().unwrap().forget(&task);
+                        let mut events = Vec::new();
+                        self.locks.lock().unwrap().finish(id, &mut events);
+                        for event in events {
+                            self.apply(event);
+                        }
+                    }
+                    let mut stats = self.stats.lock().unwrap();
+                    match outcome {
+                        Outcome::Done => stats.completed += 1,
+                        Outcome::Panicked => stats.panicked += 1,
+                        Outcome::Cancelled => stats.cancelled += 1,
+                    }
+                }
+                Message::Shutdown => self.stopping = true,
+            }
+            self.dispatch();
+            let idle = self.running.iter().all(Option::is_none);
+            // with nothing running no one is left to unlock for the blocked tasks,
+            // they are deadlocked and cancelled
+            let drained = self.ready.task_count() == 0 && self.admission.queue.is_empty();
+            if self.stopping && idle && drained {
+                break;
+            }
+        }
+        for (_, running) in std::mem::take(&mut self.blocked) {
+            let reason = "it still waited on a mutex at shutdown";
+            self.emit(EventKind::Completed, &running.task, reason);
+            running.completion.finish(Outcome::Cancelled);
+            self.stats.lock().unwrap().cancelled += 1;
+        }
+        for worker in &self.workers {
+            let _ = worker.send(Work::Stop);
+        }
+    }
+
+    // clears the nudge before draining, a spawn that lands after the drain
+    // started sends a new one so nothing is left behind in the queue
+    fn admit(&mut self) {
+        self.admission.nudged.store(false, Ordering::Release);
+        while let Some(running) = self.admission.queue.pop() {
+            self.spawned(running);
+        }
+    }
+
+    // a new task, its rank is watched from here on
+    fn spawned(&mut self, running: Running) {
+        let mut observers = self.observers.lock().unwrap();
+        let at = self.now();
+        observers.emit(EventKind::Admitted, &running.task, at, "spawned");
+        observers.watch_rank(&running.task, at, INHERITED);
+        drop(observers);
+        self.park(running);
+    }
+
+    // microseconds since the pool was made
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn emit(&self, kind: EventKind, task: &Task, reason: &'static str) {
+        let at = self.now();
+        self.observers.lock().unwrap().emit(kind, task, at, reason);
+    }
+
+    // a task that holds a mutex goes in at its effective rank
+    fn park(&mut self, mut running: Running) {
+        if let Some(rank) = self.locks.lock().unwrap().rank(running.task.get_id()) {
+            running.task.set_rank(rank);
+        }
+        let at = self.now();
+        self.observers
+            .lock()
+            .unwrap()
+            .watch_rank(&running.task, at, INHERITED);
+        self.ready.insert(running.task.clone());
+        self.parked.insert(running.task.get_id(), running);
+    }
+
+    // moves a task to the rank the lock table gives it, wherever it is
+    fn apply(&mut self, event: LockEvent) {
+        match event {
+            LockEvent::Wake(id) => {
+                if let Some(running) = self.blocked.remove(&id) {
+                    self.emit(EventKind::Woken, &running.task, "it was handed its mutex");
+                    self.park(running);
+                }
+            }
+            LockEvent::Rerank(id) => {
+                let Some(rank) = self.locks.lock().unwrap().rank(id) else {
+                    return;
+                };
+                let old = if let Some(running) = self.parked.get_mut(&id) {
+                    let old = running.task.get_rank();
+                    self.ready.delete_by_task(&running.task);
+                    running.task.set_rank(rank);
+                    self.ready.insert(running.task.clone());
+                    Some(old)
+                } else if let Some(running) = self.blocked.get_mut(&id) {
+                    let old = running.task.get_rank();
+                    running.task.set_rank(rank);
+                    Some(old)
+                } else {
+                    // running, the next preemption check and the task's context
+                    // see the new rank
+                    let worker = self
+                        .running
+                        .iter()
+                        .position(|running| running.is_some_and(|(task, _)| task == id));
+                    worker.and_then(|worker| {
+                        self.ranks[worker].store(rank, Ordering::Release);
+                        let (_, old) = self.running[worker].as_mut()?;
+                        Some(std::mem::replace(old, rank))
+                    })
+                };
+                if old.is_some_and(|old| old < rank) {
+                    self.stats.lock().unwrap().boosts += 1;
+                }
+                if old.is_some() {
+                    let at = self.now();
+                    let task = Task::new(id, rank, 0);
+                    self.observers
+                        .lock()
+                        .unwrap()
+                        .watch_rank(&task, at, INHERITED);
+                }
+            }
+        }
+    }
+
+    // fills idle workers from the top of the tree, then asks for a preemption
+    // if the tree still holds a higher rank than something that is running
+    fn dispatch(&mut self) {
+        for worker in 0..self.workers.len() {
+            if self.running[worker].is_some() {
+                continue;
+            }
+            let Some(task) = self.ready.pick_next_for_cpu(worker) else {
+                continue;
+            };
+            let running = self
+                .parked
+                .remove(&task.get_id())
+                .expect("every queued task has a closure");
+            self.running[worker] = Some((task.get_id(), task.get_rank()));
+            self.ranks[worker].store(task.get_rank(), Ordering::Release);
+            self.flags[worker].store(false, Ordering::Release);
+            self.stats.lock().unwrap().dispatches += 1;
+            self.emit(EventKind::Dispatched, &task, "an idle worker picked it");
+            let _ = self.workers[worker].send(Work::Run(Box::new(running)));
+        }
+
+        // only a worker that the waiting task may run on can be asked
+        let victim = self
+            .running
+            .iter()
+            .enumerate()
+            .filter(|(worker, _)| !self.flags[*worker].load(Ordering::Acquire))
+            .filter_map(|(worker, running)| {
+                let (_, rank) = (*running)?;
+                let top = self.ready.highest_rank_for_cpu(worker)?;
+                (top > rank).then_some((worker, rank))
+            })
+            .min_by_key(|(_, rank)| *rank);
+        if let Some((worker, _)) = victim {
+            self.flags[worker].store(true, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::observer::EventLog;
+    use std::sync::mpsc::sync_channel;
+    use std::time::Duration;
+
+    #[test]
+    fn runs_every_task_to_completion() {
+        let pool = ThreadPool::new(3);
+        let total = Arc::new(Mutex::new(0));
+        let handles: Vec<TaskHandle> = (0..20)
+            .map(|i| {
+                let total = total.clone();
+                let mut left = 5;
+                pool.spawn(i % 4, move |_ctx| {
+                    *total.lock().unwrap() += 1;
+                    left -= 1;
+                    if left == 0 {
+                        StepResult::Done
+                    } else {
+                        StepResult::Yield
+                    }
+                })
+            })
+            .collect();
+        for handle in &handles {
+            handle.join();
+            assert!(handle.is_finished());
+        }
+        let stats = pool.shutdown();
+        assert_eq!(*total.lock().unwrap(), 100);
+        assert_eq!(stats.completed, 20);
+        assert_eq!(stats.yields, 80);
+    }
+
+    #[test]
+    fn a_panicking_task_still_completes_its_handle() {
+        let pool = ThreadPool::new(1);
+        let bad = pool.spawn(5, |ctx| {
+            if ctx.steps() == 2 {
+                panic!("step 2 fails");
+            }
+            StepResult::Continue
+        });
+        let good = pool.spawn(1, |ctx| {
+            if ctx.steps() == 3 {
+                StepResult::Done
+            } else {
+                StepResult::Yield
+            }
+        });
+        assert_eq!(bad.join(), Outcome::Panicked);
+        // the worker survived and runs the rest
+        assert_eq!(good.join(), Outcome::Done);
+        let stats = pool.shutdown();
+        assert_eq!((stats.completed, stats.panicked), (1, 1));
+    }
+
+    #[test]
+    fn spawns_from_many_threads() {
+        let pool = Arc::new(ThreadPool::new(2));
+        let spawners: Vec<_> = (0..4)
+            .map(|t| {
+                let pool = pool.clone();
+                thread::spawn(move || {
+                    // more than the admission queue holds, some take the channel
+                    (0..ADMISSION_CAPACITY as i32)
+                        .map(|i| pool.spawn((t + i) % 8, |_ctx| StepResult::Done))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for spawner in spawners {
+            for handle in spawner.join().unwrap() {
+                handle.join();
+            }
+        }
+        let pool = Arc::try_unwrap(pool).ok().unwrap();
+        let stats = pool.shutdown();
+        assert_eq!(stats.spawned, 4 * ADMISSION_CAPACITY as u64);
+        assert_eq!(stats.completed, stats.spawned);
+    }
+
+    #[test]
+    fn pinned_tasks_run_on_their_worker() {
+        let pool = ThreadPool::new(3);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handles: Vec<TaskHandle> = (0..12)
+            .map(|i| {
+                let seen = seen.clone();
+                // the highest ranks are pinned to worker 1, the rest may run anywhere
+                let (rank, affinity) = if i % 3 == 0 {
+                    (9, Affinity::only(1))
+                } else {
+                    (1, Affinity::ALL)
+                };
+                pool.spawn_pinned(rank, affinity, move |ctx| {
+                    let name = thread::current().name().unwrap().to_string();
+                    seen.lock().unwrap().push((ctx.rank(), name));
+                    thread::sleep(Duration::from_micros(200));
+                    if ctx.steps() == 2 {
+                        StepResult::Done
+                    } else {
+                        StepResult::Yield
+                    }
+                })
+            })
+            .collect();
+        for handle in &handles {
+            handle.join();
+        }
+        let seen = seen.lock().unwrap();
+        assert!(seen
+            .iter()
+            .filter(|(rank, _)| *rank == 9)
+            .all(|(_, name)| name == "pool-worker-1"));
+        assert_eq!(pool.shutdown().completed, 12);
+    }
+
+    // finishing order of a low rank task holding `bus` for 30 steps, a high rank task
+    // that needs the bus and a medium rank task that does not, all on one worker
+    fn pathfinder(inherit: bool) -> (Vec<&'static str>, PoolStats) {
+        let pool = ThreadPool::new(1);
+        let bus = Arc::new(pool.mutex(Vec::new()).with_inheritance(inherit));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (locked_tx, locked) = sync_channel(1);
+
+        let (meteo_bus, meteo_log) = (bus.clone(), log.clone());
+        let meteo = pool.spawn(1, move |ctx| {
+            meteo_bus.lock(ctx).unwrap().push("meteo");
+            if ctx.steps() == 0 {
+                let _ = locked_tx.send(());
+            }
+            thread::sleep(Duration::from_millis(1));
+            if ctx.steps() < 30 {
+                return StepResult::Continue;
+            }
+            meteo_bus.unlock(ctx).unwrap();
+            meteo_log.lock().unwrap().push("meteo");
+            StepResult::Done
+        });
+        locked.recv().unwrap();
+
+        let (bus_bus, bus_log) = (bus.clone(), log.clone());
+        let management = pool.spawn(9, move |ctx| {
+            let Some(mut data) = bus_bus.lock(ctx) else {
+                return StepResult::Blocked;
+            };
+            data.push("management");
+            drop(data);
+            bus_bus.unlock(ctx).unwrap();
+            bus_log.lock().unwrap().push("management");
+            StepResult::Done
+        });
+        let comms_log = log.clone();
+        let comms = pool.spawn(5, move |ctx| {
+            thread::sleep(Duration::from_millis(1));
+            if ctx.steps() < 20 {
+                return StepResult::Continue;
+            }
+            comms_log.lock().unwrap().push("comms");
+            StepResult::Done
+        });
+        for handle in [meteo, management, comms] {
+            handle.join();
+        }
+        assert_eq!(bus.holder(), None);
+        let stats = pool.shutdown();
+        let log = log.lock().unwrap().clone();
+        (log, stats)
+    }
+
+    #[test]
+    fn inheritance_prevents_the_pathfinder_inversion() {
+        // without inheritance the medium task runs while the high one waits for the bus
+        let (order, stats) = pathfinder(false);
+        assert_eq!(order, vec!["comms", "meteo", "management"]);
+        assert_eq!(stats.boosts, 0);
+
+        let (order, stats) = pathfinder(true);
+        assert_eq!(order, vec!["meteo", "management", "comms"]);
+        assert_eq!(stats.blocks, 1);
+        assert_eq!(stats.boosts, 1);
+    }
+
+    #[test]
+    fn inheritance_is_transitive() {
+        let pool = ThreadPool::new(1);
+        let (first, second) = (Arc::new(pool.mutex(())), Arc::new(pool.mutex(())));
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (locked_tx, locked) = sync_channel(2);
+
+        // low holds first, mid holds second and waits for first
+        let (low_first, low_log, low_tx) = (first.clone(), log.clone(), locked_tx.clone());
+        let low = pool.spawn(1, move |ctx| {
+            low_first.lock(ctx).unwrap();
+            if ctx.steps() == 0 {
+                let _ = low_tx.send(());
+            }
+            thread::sleep(Duration::from_millis(1));
+            if ctx.steps() < 30 {
+                return StepResult::Continue;
+            }
+            low_first.unlock(ctx).unwrap();
+            low_log.lock().unwrap().push("low");
+            StepResult::Done
+        });
+        locked.recv().unwrap();
+
+        let (mid_first, mid_second, mid_log) = (first.clone(), second.clone(), log.clone());
+        let mut announced = false;
+        let mid = pool.spawn(3, move |ctx| {
+            mid_second.lock(ctx).unwrap();
+            if !announced {
+                announced = true;
+                let _ = locked_tx.send(());
+                return StepResult::Continue;
+            }
+            if mid_first.lock(ctx).is_none() {
+                return StepResult::Blocked;
+            }
+            mid_first.unlock(ctx).unwrap();
+            mid_second.unlock(ctx).unwrap();
+            mid_log.lock().unwrap().push("mid");
+            StepResult::Done
+        });
+        locked.recv().unwrap();
+
+        // high waits for second, which lifts mid and through it low
+        let (high_second, high_log) = (second.clone(), log.clone());
+        let high = pool.spawn(9, move |ctx| {
+            if high_second.lock(ctx).is_none() {
+                return StepResult::Blocked;
+            }
+            high_second.unlock(ctx).unwrap();
+            high_log.lock().unwrap().push("high");
+            StepResult::Done
+        });
+        let other_log = log.clone();
+        let other = pool.spawn(5, move |ctx| {
+            thread::sleep(Duration::from_millis(1));
+            if ctx.steps() < 20 {
+                return StepResult::Continue;
+            }
+            other_log.lock().unwrap().push("other");
+            StepResult::Done
+        });
+        for handle in [low, mid, high, other] {
+            handle.join();
+        }
+        assert_eq!(*log.lock().unwrap(), vec!["low", "mid", "high", "other"]);
+        assert!(pool.shutdown().boosts >= 2);
+    }
+
+    #[test]
+    fn a_running_holder_sees_and_keeps_the_inherited_rank() {
+        let pool = ThreadPool::new(2);
+        let bus = Arc::new(pool.mutex(()));
+        let (locked_tx, locked) = sync_channel(1);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+
+        let (low_bus, low_seen) = (bus.clone(), seen.clone());
+        let mut yielded = false;
+        let low = pool.spawn(1, move |ctx| {
+            low_bus.lock(ctx).unwrap();
+            if ctx.steps() == 0 {
+                let _ = locked_tx.send(());
+            }
+            // raised while it runs, then handed back and dispatched again
+            if !yielded && ctx.rank() < 9 && ctx.steps() < 500 {
+                thread::sleep(Duration::from_millis(1));
+                return StepResult::Continue;
+            }
+            if !yielded {
+                yielded = true;
+                low_seen.lock().unwrap().push(ctx.rank());
+                return StepResult::Yield;
+            }
+            low_seen.lock().unwrap().push(ctx.rank());
+            low_bus.unlock(ctx).unwrap();
+            StepResult::Done
+        });
+        locked.recv().unwrap();
+
+        let high_bus = bus.clone();
+        let high = pool.spawn(9, move |ctx| match high_bus.lock(ctx) {
+            Some(_) => {
+                high_bus.unlock(ctx).unwrap();
+                StepResult::Done
+            }
+            None => StepResult::Blocked,
+        });
+        low.join();
+        high.join();
+        assert_eq!(*seen.lock().unwrap(), vec![9, 9]);
+        assert_eq!(pool.shutdown().completed, 2);
+    }
+
+    #[test]
+    fn observers_see_the_dispatcher() {
+        let log = EventLog::new();
+        let pool = ThreadPool::new(2).with_observer(Box::new(log.clone()));
+        let bus = Arc::new(pool.mutex(()));
+        let (locked_tx, locked) = sync_channel(1);
+
+        let low_bus = bus.clone();
+        let low = pool.spawn(1, move |ctx| {
+            low_bus.lock(ctx).unwrap();
+            if ctx.steps() == 0 {
+                let _ = locked_tx.send(());
+            }
+            if ctx.rank() < 9 && ctx.steps() < 500 {
+                thread::sleep(Duration::from_millis(1));
+                return StepResult::Continue;
+            }
+            // gives the waiter's Blocked message time to reach the dispatcher
+            thread::sleep(Duration::from_millis(20));
+            low_bus.unlock(ctx).unwrap();
+            StepResult::Done
+        });
+        locked.recv().unwrap();
+        let high_bus = bus.clone();
+        let high = pool.spawn(9, move |ctx| match high_bus.lock(ctx) {
+            Some(_) => {
+                high_bus.unlock(ctx).unwrap();
+                StepResult::Done
+            }
+            None => StepResult::Blocked,
+        });
+        low.join();
+        high.join();
+        pool.shutdown();
+
+        let events = log.events();
+        let of = |id: i32| -> Vec<&str> {
+            events
+                .iter()
+                .filter(|(_, event)| event.task == id)
+                .map(|(kind, _)| kind.name())
+                .collect()
+        };
+        // low inherits the waiter's rank and gives it back on unlock
+        assert_eq!(
+            of(low.id()),
+            vec!["admitted", "dispatched", "boosted", "demoted", "completed"]
+        );
+        assert_eq!(
+            of(high.id()),
+            vec![
+                "admitted",
+                "dispatched",
+                "blocked",
+                "woken",
+                "dispatched",
+                "completed"
+            ]
+        );
+        let boost = events
+            .iter()
+            .find(|(kind, _)| *kind == EventKind::Boosted)
+            .unwrap();
+        assert_eq!((boost.1.old_rank, boost.1.new_rank), (1, 9));
+        // every event comes from the dispatcher thread, in order
+        assert!(events.windows(2).all(|pair| pair[0].1.at <= pair[1].1.at));
+    }
+
+    #[test]
+    fn a_panicking_holder_hands_on_its_mutex() {
+        let pool = ThreadPool::new(1);
+        let count = Arc::new(pool.mutex(0));
+        let holder_count = count.clone();
+        let holder = pool.spawn(1, move |ctx| {
+            let mut count = holder_count.lock(ctx).unwrap();
+            *count += 1;
+            panic!("the guard is dropped while unwinding");
+        });
+        assert_eq!(holder.join(), Outcome::Panicked);
+
+        let (seen_tx, seen) = sync_channel(1);
+        let reader_count = count.clone();
+        let reader = pool.spawn(1, move |ctx| {
+            let Some(mut count) = reader_count.lock(ctx) else {
+                return StepResult::Blocked;
+            };
+            // the update made before the panic is kept
+            *count += 1;
+            let _ = seen_tx.send(*count);
+            drop(count);
+            reader_count.unlock(ctx).unwrap();
+            StepResult::Done
+        });
+        assert_eq!(reader.join(), Outcome::Done);
+        assert_eq!(seen.recv().unwrap(), 2);
+        let stats = pool.shutdown();
+        assert_eq!((stats.completed, stats.panicked), (1, 1));
+    }
+
+    #[test]
+    fn unlocking_a_mutex_held_by_another_task_fails() {
+        let pool = ThreadPool::new(1);
+        let bus = Arc::new(pool.mutex(0));
+        let holder_bus = bus.clone();
+        let holder = pool.spawn(1, move |ctx| {
+            *holder_bus.lock(ctx).unwrap() += 1;
+            StepResult::Done
+        });
+        holder.join();
+
+        let (thief_bus, errors) = (bus.clone(), Arc::new(Mutex::new(Vec::new())));
+        let thief_errors = errors.clone();
+        let thief = pool.spawn(1, move |ctx| {
+            thief_errors
+                .lock()
+                .unwrap()
+                .push(thief_bus.unlock(ctx).unwrap_err());
+            // the lock table is still usable afterwards
+            *thief_bus.lock(ctx).unwrap() += 1;
+            thief_bus.unlock(ctx).unwrap();
+            StepResult::Done
+        });
+        assert_eq!(thief.join(), Outcome::Done);
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].holder, None);
+        assert_eq!(pool.shutdown().completed, 2);
+    }
+
+    #[test]
+    fn finds_tasks_locking_in_opposite_order() {
+        let pool = ThreadPool::new(2);
+        let (left, right) = (Arc::new(pool.mutex(())), Arc::new(pool.mutex(())));
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let handles: Vec<TaskHandle> = [(&left, &right), (&right, &left)]
+            .into_iter()
+            .map(|(first, second)| {
+                let (first, second, barrier) = (first.clone(), second.clone(), barrier.clone());
+                pool.spawn(1, move |ctx| {
+                    first.lock(ctx).unwrap();
+                    barrier.wait();
+                    match second.lock(ctx) {
+                        Some(_) => StepResult::Done,
+                        None => StepResult::Blocked,
+                    }
+                })
+            })
+            .collect();
+        let mut deadlocks = pool.deadlocks();
+        for _ in 0..500 {
+            if !deadlocks.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(2));
+            deadlocks = pool.deadlocks();
+        }
+        assert_eq!(deadlocks.len(), 1);
+        assert_eq!(deadlocks[0].tasks, vec![handles[0].id(), handles[1].id()]);
+        assert_eq!(deadlocks[0].resources, vec![right.id(), left.id()]);
+        let stats = pool.shutdown();
+        assert_eq!((stats.completed, stats.cancelled), (0, 2));
+        // their handles are completed, a join does not hang
+        for handle in &handles {
+            assert_eq!(handle.join(), Outcome::Cancelled);
+        }
+    }
+
+    #[test]
+    fn higher_rank_preempts_at_yield_point() {
+        let pool = ThreadPool::new(1);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (started_tx, started) = sync_channel(1);
+
+        let low_log = log.clone();
+        let low = pool.spawn(1, move |ctx| {
+            if ctx.steps() == 0 {
+                let _ = started_tx.send(());
+            }
+            thread::sleep(Duration::from_millis(2));
+            low_log.lock().unwrap().push(("low", ctx.steps()));
+            if ctx.steps() == 49 {
+                StepResult::Done
+            } else {
+                StepResult::Continue
+            }
+        });
+        started.recv().unwrap();
+
+        let high_log = log.clone();
+        let high = pool.spawn(9, move |ctx| {
+            high_log.lock().unwrap().push(("high", ctx.steps()));
+            if ctx.steps() == 2 {
+                StepResult::Done
+            } else {
+                StepResult::Continue
+            }
+        });
+        high.join();
+        low.join();
+
+        let log = log.lock().unwrap();
+        let high_done = log.iter().position(|entry| *entry == ("high", 2)).unwrap();
+        let low_done = log.iter().position(|entry| *entry == ("low", 49)).unwrap();
+        assert!(high_done < low_done);
+        // the low task resumed where it left off
+        let low_steps: Vec<u64> = log
+            .iter()
+            .filter(|(name, _)| *name == "low")
+            .map(|(_, step)| *step)
+            .collect();
+        assert_eq!(low_steps, (0..50).collect::<Vec<_>>());
+        assert_eq!(pool.shutdown().preemptions, 1);
+    }
+}
@@ -0,0 +1,892 @@
+use super::pool::{Completion, Job, Outcome, StepResult, TaskContext, TaskHandle};
+use crate::datastructure::avl::AvlTree;
+use crate::datastructure::{Affinity, Task};
+use crate::scheduler::observer::{EventKind, Observers, SchedulerObserver};
+use crate::sim::rng::Rng;
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/*
+ * Thread pool with one run queue per worker and work stealing.
+ * Every worker owns an AvlTree of rank buckets and always runs the highest
+ * rank of its own tree, so rank order holds within a worker. New tasks are
+ * spread round robin over the workers (or placed with `spawn_on`). A worker
+ * whose tree ran empty becomes a thief: it walks the other workers in the
+ * order given by the steal policy and takes the task at the head of the
+ * highest rank bucket of the first victim that has one.
+ *
+ * A worker only ever holds one run queue lock at a time, the thief drops the
+ * victim's lock before it touches its own tree.
+ * Affinity is respected everywhere: a task spawned with `spawn_pinned` is only
+ * placed on, run by and stolen by the workers in its mask, a worker and a
+ * thief take the highest rank they may run.
+ * Preemption is cooperative like in the ThreadPool, a task that lands in a
+ * worker's tree with a higher rank than the task it runs asks it to hand the
+ * task back at its next yield point.
+ * There are no PiMutexes here, a task whose step returns Blocked waits for
+ * something another task does. It is parked out of the tree, not stolen, and
+ * goes back into its worker's tree once some task finished or handed its
+ * worker back, or once the worker ran out of work and waited for the steal
+ * timeout.
+ * A closure that panics ends its task with Outcome::Panicked like in the
+ * ThreadPool, the worker goes on with the next task.
+ * Registered observers are told from the workers when a task is spawned,
+ * dispatched, preempted or yields, blocks, is woken and ends, timed like in
+ * the ThreadPool. Ranks never change here.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StealPolicy {
+    // a random victim first, then the others in turn
+    Random,
+    // the victim with the most ready tasks first
+    MostLoaded,
+    // victims on the thief's NUMA node first (in random order), then the rest
+    SameNode,
+}
+
+pub const STEAL_POLICY_NAMES: &[&str] = &["random", "most-loaded", "same-node"];
+
+impl StealPolicy {
+    pub fn by_name(name: &str) -> Option<StealPolicy> {
+        match name {
+            "random" => Some(StealPolicy::Random),
+            "most-loaded" => Some(StealPolicy::MostLoaded),
+            "same-node" => Some(StealPolicy::SameNode),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StealConfig {
+    pub workers: usize,
+    pub policy: StealPolicy,
+    // NUMA node of every worker, workers missing from the list are on node 0
+    pub nodes: Vec<usize>,
+    pub seed: u64,
+}
+
+impl StealConfig {
+    pub fn new(workers: usize) -> Self {
+        StealConfig {
+            workers: workers.max(1),
+            policy: StealPolicy::Random,
+            nodes: Vec::new(),
+            seed: 1,
+        }
+    }
+
+    pub fn with_policy(mut self, policy: StealPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn with_nodes(mut self, nodes: Vec<usize>) -> Self {
+        self.nodes = nodes;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    fn node(&self, worker: usize) -> usize {
+        self.nodes.get(worker).copied().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkerStats {
+    pub node: usize,
+    // tasks taken off a run queue to run, own or stolen
+    pub dispatches: u64,
+    pub steps: u64,
+    // tasks this worker stole
+    pub steals: u64,
+    // of those, taken from a worker on the same node
+    pub local_steals: u64,
+    // tasks other workers stole from this one
+    pub stolen_from: u64,
+    // rounds over every victim that found nothing
+    pub failed_steals: u64,
+    pub max_queue: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StealStats {
+    pub spawned: u64,
+    pub completed: u64,
+    // tasks whose closure panicked, they do not count as completed
+    pub panicked: u64,
+    pub preemptions: u64,
+    pub yields: u64,
+    // steps that returned Blocked and parked their task
+    pub blocks: u64,
+    pub workers: Vec<WorkerStats>,
+}
+
+impl StealStats {
+    pub fn steals(&self) -> u64 {
+        self.workers.iter().map(|w| w.steals).sum()
+    }
+
+    pub fn failed_steals(&self) -> u64 {
+        self.workers.iter().map(|w| w.failed_steals).sum()
+    }
+
+    // busiest worker over the mean in dispatches, 1.0 is perfectly balanced
+    pub fn imbalance(&self) -> f64 {
+        let total: u64 = self.workers.iter().map(|w| w.dispatches).sum();
+        if total == 0 {
+            return 1.0;
+        }
+        let max = self.workers.iter().map(|w| w.dispatches).max().unwrap_or(0);
+        max as f64 * self.workers.len() as f64 / total as f64
+    }
+}
+
+// a task together with its closure
+struct Entry {
+    task: Task,
+    job: Job,
+    steps: u64,
+    completion: Arc<Completion>,
+}
+
+#[derive(Default)]
+struct RunQueue {
+    ready: AvlTree,
+    // closures of the tasks in the tree, by task id
+    parked: HashMap<i32, Entry>,
+    // tasks whose step returned Blocked, with the pool's progress at that time
+    blocked: Vec<(u64, Entry)>,
+    // rank of the task the worker runs
+    running: Option<i32>,
+}
+
+impl RunQueue {
+    fn push(&mut self, entry: Entry) {
+        self.ready.insert(entry.task.clone());
+        self.parked.insert(entry.task.get_id(), entry);
+    }
+
+    // highest rank that `cpu` may run
+    fn pop(&mut self, cpu: usize) -> Option<Entry> {
+        let task = self.ready.pick_next_for_cpu(cpu)?;
+        let entry = self
+            .parked
+            .remove(&task.get_id())
+            .expect("every queued task has a closure");
+        Some(entry)
+    }
+
+    // puts back the blocked tasks that parked before `progress`
+    fn wake(&mut self, progress: u64, shared: &Shared) {
+        let (waking, parked) = std::mem::take(&mut self.blocked)
+            .into_iter()
+            .partition(|(since, _)| *since < progress);
+        self.blocked = parked;
+        for (_, entry) in waking {
+            shared.emit(EventKind::Woken, &entry.task, "another task made progress");
+            self.push(entry);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    dispatches: AtomicU64,
+    steps: AtomicU64,
+    steals: AtomicU64,
+    local_steals: AtomicU64,
+    stolen_from: AtomicU64,
+    failed_steals: AtomicU64,
+    max_queue: AtomicUsize,
+}
+
+struct Worker {
+    node: usize,
+    queue: Mutex<RunQueue>,
+    // ready tasks in the queue, read by thieves without taking the lock
+    load: AtomicUsize,
+    preempt: Arc<AtomicBool>,
+    counters: Counters,
+}
+
+impl Worker {
+    // pushes under the queue lock and asks for a preemption if needed
+    fn push(&self, entry: Entry) {
+        let rank = entry.task.get_rank();
+        let mut queue = self.queue.lock().unwrap();
+        queue.push(entry);
+        if queue.running.is_some_and(|running| running < rank) {
+            self.preempt.store(true, Ordering::Release);
+        }
+        self.update_load(&queue);
+    }
+
+    fn wake(&self, progress: u64, shared: &Shared) {
+        let mut queue = self.queue.lock().unwrap();
+        if !queue.blocked.is_empty() {
+            queue.wake(progress, shared);
+            self.update_load(&queue);
+        }
+    }
+
+    fn update_load(&self, queue: &RunQueue) {
+        let len = queue.ready.task_count();
+        self.load.store(len, Ordering::Release);
+        self.counters.max_queue.fetch_max(len, Ordering::Relaxed);
+    }
+}
+
+struct Shared {
+    workers: Vec<Worker>,
+    policy: StealPolicy,
+    // bumped on every push so idle workers do not miss new work
+    signal: Mutex<u64>,
+    wake: Condvar,
+    // spawned and not finished
+    outstanding: AtomicUsize,
+    stop: AtomicBool,
+    spawned: AtomicU64,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    preemptions: AtomicU64,
+    yields: AtomicU64,
+    blocks: AtomicU64,
+    // bumped whenever a task finished or handed its worker back
+    progress: AtomicU64,
+    observers: Mutex<Observers>,
+    // set once an observer is registered, until then no event takes the lock
+    observed: AtomicBool,
+    // events are timed from here
+    start: Instant,
+}
+
+impl Shared {
+    fn notify(&self) {
+        *self.signal.lock().unwrap() += 1;
+        self.wake.notify_one();
+    }
+
+    fn emit(&self, kind: EventKind, task: &Task, reason: &'static str) {
+        if !self.observed.load(Ordering::Acquire) {
+            return;
+        }
+        let at = self.start.elapsed().as_micros() as u64;
+        self.observers.lock().unwrap().emit(kind, task, at, reason);
+    }
+}
+
+pub struct WorkStealingPool {
+    shared: Arc<Shared>,
+    handles: Vec<JoinHandle<()>>,
+    next_id: AtomicI32,
+    next_worker: AtomicUsize,
+}
+
+impl WorkStealingPool {
+    pub fn new(workers: usize) -> Self {
+        WorkStealingPool::with_config(StealConfig::new(workers))
+    }
+
+    pub fn with_config(config: StealConfig) -> Self {
+        let workers = config.workers.max(1);
+        let shared = Arc::new(Shared {
+            workers: (0..workers)
+                .map(|index| Worker {
+                    node: config.node(index),
+                    queue: Mutex::new(RunQueue::default()),
+                    load: AtomicUsize::new(0),
+                    preempt: Arc::new(AtomicBool::new(false)),
+                    counters: Counters::default(),
+                })
+                .collect(),
+            policy: config.policy,
+            signal: Mutex::new(0),
+            wake: Condvar::new(),
+            outstanding: AtomicUsize::new(0),
+            stop: AtomicBool::new(false),
+            spawned: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            preemptions: AtomicU64::new(0),
+            yields: AtomicU64::new(0),
+            blocks: AtomicU64::new(0),
+            progress: AtomicU64::new(0),
+            observers: Mutex::new(Observers::default()),
+            observed: AtomicBool::new(false),
+            start: Instant::now(),
+        });
+        let handles = (0..workers)
+            .map(|index| {
+                let shared = shared.clone();
+                let rng = Rng::new(config.seed.wrapping_add(index as u64));
+                thread::Builder::new()
+                    .name(format!("steal-worker-{index}"))
+                    .spawn(move || run_worker(index, shared, rng))
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        WorkStealingPool {
+            shared,
+            handles,
+            next_id: AtomicI32::new(1),
+            next_worker: AtomicUsize::new(0),
+        }
+    }
+
+    // may be called more than once, every observer sees the events from the
+    // next spawn on
+    pub fn with_observer(self, observer: Box<dyn SchedulerObserver>) -> Self {
+        self.shared.observers.lock().unwrap().register(observer);
+        self.shared.observed.store(true, Ordering::Release);
+        self
+    }
+
+    pub fn workers(&self) -> usize {
+        self.shared.workers.len()
+    }
+
+    // places the task round robin over the workers
+    pub fn spawn<F>(&self, rank: i32, job: F) -> TaskHandle
+    where
+        F: FnMut(&mut TaskContext) -> StepResult + Send + 'static,
+    {
+        let worker = self.next_worker.fetch_add(1, Ordering::Relaxed) % self.workers();
+        self.spawn_on(worker, rank, job)
+    }
+
+    pub fn spawn_on<F>(&self, worker: usize, rank: i32, job: F) -> TaskHandle
+    where
+        F: FnMut(&mut TaskContext) -> StepResult + Send + 'static,
+    {
+        self.place(worker % self.workers(), rank, Affinity::ALL, job)
+    }
+
+    // round robin over the workers in `affinity` (bit n is worker n)
+    pub fn spawn_pinned<F>(&self, rank: i32, affinity: Affinity, job: F) -> TaskHandle
+    where
+        F: FnMut(&mut TaskContext) -> StepResult + Send + 'static,
+    {
+        let allowed: Vec<usize> = (0..self.workers())
+            .filter(|&worker| affinity.allows(worker))
+            .collect();
+        assert!(
+            !allowed.is_empty(),
+            "affinity {:#x} names none of the {} workers",
+            affinity.bits(),
+            self.workers()
+        );
+        let next = self.next_worker.fetch_add(1, Ordering::Relaxed);
+        self.place(allowed[next % allowed.len()], rank, affinity, job)
+    }
+
+    fn place<F>(&self, worker: usize, rank: i32, affinity: Affinity, job: F) -> TaskHandle
+    where
+        F: FnMut(&mut TaskContext) -> StepResult + Send + 'static,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let completion = Arc::new(Completion::default());
+        let entry = Entry {
+            task: Task::new(id, rank, 0).with_affinity(affinity),
+            job: Box::new(job),
+            steps: 0,
+            completion: completion.clone(),
+        };
+        self.shared.spawned.fetch_add(1, Ordering::Relaxed);
+        self.shared.outstanding.fetch_add(1, Ordering::AcqRel);
+        self.shared
+            .emit(EventKind::Admitted, &entry.task, "spawned");
+        self.shared.workers[worker].push(entry);
+        self.shared.notify();
+        TaskHandle::new(id, completion)
+    }
+
+    pub fn stats(&self) -> StealStats {
+        let shared = &self.shared;
+        StealStats {
+            spawned: shared.spawned.load(Ordering::Acquire),
+            completed: shared.completed.load(Ordering::Acquire),
+            panicked: shared.panicked.load(Ordering::Acquire),
+            preemptions: shared.preemptions.load(Ordering::Acquire),
+            yields: shared.yields.load(Ordering::Acquire),
+            blocks: shared.blocks.load(Ordering::Acquire),
+            workers: shared
+                .workers
+                .iter()
+                .map(|w| WorkerStats {
+                    node: w.node,
+                    dispatches: w.counters.dispatches.load(Ordering::Acquire),
+                    steps: w.counters.steps.load(Ordering::Acquire),
+                    steals: w.counters.steals.load(Ordering::Acquire),
+                    local_steals: w.counters.local_steals.load(Ordering::Acquire),
+                    stolen_from: w.counters.stolen_from.load(Ordering::Acquire),
+                    failed_steals: w.counters.failed_steals.load(Ordering::Acquire),
+                    max_queue: w.counters.max_queue.load(Ordering::Acquire),
+                })
+                .collect(),
+        }
+    }
+
+    // waits for every spawned task to finish and stops the threads
+    pub fn shutdown(mut self) -> StealStats {
+        self.stop();
+        self.stats()
+    }
+
+    fn stop(&mut self) {
+        if self.handles.is_empty() {
+            return;
+        }
+        self.shared.stop.store(true, Ordering::Release);
+        *self.shared.signal.lock().unwrap() += 1;
+        self.shared.wake.notify_all();
+        for handle in self.handles.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for WorkStealingPool {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// order in which `thief` visits the other workers
+fn victims(thief: usize, policy: StealPolicy, workers: &[Worker], rng: &mut Rng) -> Vec<usize> {
+    let count = workers.len();
+    let start = rng.next_u64() as usize % count;
+    // every other worker, starting at a random one
+    let mut order: Vec<usize> = (0..count)
+        .map(|i| (start + i) % count)
+        .filter(|&victim| victim != thief)
+        .collect();
+    match policy {
+        StealPolicy::Random => {}
+        StealPolicy::MostLoaded => order
+            .sort_by_key(|&victim| std::cmp::Reverse(workers[victim].load.load(Ordering::Acquire))),
+        StealPolicy::SameNode => {
+            let node = workers[thief].node;
+            order.sort_by_key(|&victim| workers[victim].node != node)
+        }
+    }
+    order
+}
+
+// takes the head of the highest rank bucket of the first victim that has one
+fn steal(thief: usize, shared: &Shared, rng: &mut Rng) -> Option<Entry> {
+    let workers = &shared.workers;
+    for victim in victims(thief, shared.policy, workers, rng) {
+        if workers[victim].load.load(Ordering::Acquire) == 0 {
+            continue;
+        }
+        let entry = {
+            let mut queue = workers[victim].queue.lock().unwrap();
+            let entry = queue.pop(thief);
+            workers[victim].update_load(&queue);
+            entry
+        };
+        if let Some(entry) = entry {
+            let counters = &workers[thief].counters;
+            counters.steals.fetch_add(1, Ordering::Relaxed);
+            if workers[victim].node == workers[thief].node {
+                counters.local_steals.fetch_add(1, Ordering::Relaxed);
+            }
+            workers[victim]
+                .counters
+                .stolen_from
+                .fetch_add(1, Ordering::Relaxed);
+            return Some(entry);
+        }
+    }
+    workers[thief]
+        .counters
+        .failed_steals
+        .fetch_add(1, Ordering::Relaxed);
+    None
+}
+
+fn run_worker(index: usize, shared: Arc<Shared>, mut rng: Rng) {
+    let me = &shared.workers[index];
+    loop {
+        let seen = *shared.signal.lock().unwrap();
+        me.wake(shared.progress.load(Ordering::Acquire), &shared);
+        let own = {
+            let mut queue = me.queue.lock().unwrap();
+            let entry = queue.pop(index);
+            me.update_load(&queue);
+            entry
+        };
+        let Some(entry) = own.or_else(|| steal(index, &shared, &mut rng)) else {
+            if shared.stop.load(Ordering::Acquire)
+                && shared.outstanding.load(Ordering::Acquire) == 0
+            {
+                return;
+            }
+            let signal = shared.signal.lock().unwrap();
+            if *signal == seen {
+                // the timeout retries steals of work that was requeued quietly
+                let _ = shared
+                    .wake
+                    .wait_timeout(signal, Duration::from_millis(1))
+                    .unwrap();
+            }
+            // nothing else to run, the blocked tasks get another try
+            me.wake(u64::MAX, &shared);
+            continue;
+        };
+        run_entry(index, me, &shared, entry);
+    }
+}
+
+fn run_entry(index: usize, me: &Worker, shared: &Shared, mut entry: Entry) {
+    let rank = entry.task.get_rank();
+    {
+        let mut queue = me.queue.lock().unwrap();
+        queue.running = Some(rank);
+        // a stolen task may have landed while something higher arrived here
+        let higher_waiting = queue
+            .ready
+            .highest_rank_for_cpu(index)
+            .is_some_and(|top| top > rank);
+        me.preempt.store(higher_waiting, Ordering::Release);
+    }
+    me.counters.dispatches.fetch_add(1, Ordering::Relaxed);
+    shared.emit(EventKind::Dispatched, &entry.task, "its worker picked it");
+
+    let mut ctx = TaskContext::new(
+        entry.task.get_id(),
+        Arc::new(AtomicI32::new(rank)),
+        entry.steps,
+        me.preempt.clone(),
+    );
+    let next = loop {
+        let step = panic::catch_unwind(AssertUnwindSafe(|| (entry.job)(&mut ctx)));
+        let Ok(result) = step else {
+            break None;
+        };
+        ctx.steps += 1;
+        entry.steps = ctx.steps;
+        me.counters.steps.fetch_add(1, Ordering::Relaxed);
+        match result {
+            StepResult::Done | StepResult::Blocked => break Some(result),
+            StepResult::Yield => {
+                shared.yields.fetch_add(1, Ordering::Relaxed);
+                break Some(result);
+            }
+            StepResult::Continue => {
+                if me.preempt.swap(false, Ordering::AcqRel) {
+                    shared.preemptions.fetch_add(1, Ordering::Relaxed);
+                    break Some(result);
+                }
+            }
+        }
+    };
+
+    let mut queue = me.queue.lock().unwrap();
+    queue.running = None;
+    match next {
+        None => {
+            drop(queue);
+            shared.progress.fetch_add(1, Ordering::AcqRel);
+            shared.emit(EventKind::Completed, &entry.task, "its closure panicked");
+            entry.completion.finish(Outcome::Panicked);
+            shared.panicked.fetch_add(1, Ordering::Relaxed);
+            shared.outstanding.fetch_sub(1, Ordering::AcqRel);
+        }
+        Some(StepResult::Blocked) => {
+            shared.blocks.fetch_add(1, Ordering::Relaxed);
+            shared.emit(EventKind::Blocked, &entry.task, "its step returned Blocked");
+            let progress = shared.progress.load(Ordering::Acquire);
+            queue.blocked.push((progress, entry));
+        }
+        Some(StepResult::Done) => {
+            drop(queue);
+            shared.progress.fetch_add(1, Ordering::AcqRel);
+            shared.emit(
+                EventKind::Completed,
+                &entry.task,
+                "its closure returned Done",
+            );
+            entry.completion.finish(Outcome::Done);
+            shared.completed.fetch_add(1, Ordering::Relaxed);
+            shared.outstanding.fetch_sub(1, Ordering::AcqRel);
+        }
+        Some(result @ (StepResult::Yield | StepResult::Continue)) => {
+            let reason = if result == StepResult::Yield {
+                "it yielded"
+            } else {
+                "a higher rank landed in its queue"
+            };
+            shared.emit(EventKind::Preempted, &entry.task, reason);
+            queue.push(entry);
+            me.update_load(&queue);
+            drop(queue);
+            shared.progress.fetch_add(1, Ordering::AcqRel);
+            shared.notify();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::observer::EventLog;
+    use std::sync::mpsc::sync_channel;
+
+    fn sleepy(steps: u64) -> impl FnMut(&mut TaskContext) -> StepResult + Send + 'static {
+        move |ctx| {
+            thread::sleep(Duration::from_micros(200));
+            if ctx.steps() + 1 == steps {
+                StepResult::Done
+            } else {
+                StepResult::Continue
+            }
+        }
+    }
+
+    #[test]
+    fn idle_workers_steal_with_every_policy() {
+        for name in STEAL_POLICY_NAMES {
+            let config = StealConfig::new(4)
+                .with_policy(StealPolicy::by_name(name).unwrap())
+                .with_nodes(vec![0, 0, 1, 1]);
+            let pool = WorkStealingPool::with_config(config);
+            // everything lands on worker 0, the others only get work by stealing
+            let handles: Vec<TaskHandle> = (0..40)
+                .map(|i| pool.spawn_on(0, i % 5, sleepy(5)))
+                .collect();
+            for handle in &handles {
+                handle.join();
+            }
+            let stats = pool.shutdown();
+            assert_eq!(stats.completed, 40, "{name}");
+            assert!(stats.steals() > 0, "{name}");
+            assert_eq!(stats.workers[0].stolen_from, stats.steals(), "{name}");
+            let dispatched: u64 = stats.workers.iter().map(|w| w.dispatches).sum();
+            assert!(dispatched >= 40, "{name}");
+            assert!(stats.imbalance() >= 1.0);
+        }
+    }
+
+    #[test]
+    fn pinned_tasks_are_never_stolen() {
+        let pool = WorkStealingPool::new(3);
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let handles: Vec<TaskHandle> = (0..30)
+            .map(|i| {
+                let seen = seen.clone();
+                let mut step = sleepy(3);
+                let job = move |ctx: &mut TaskContext| {
+                    if i % 2 == 0 {
+                        let name = thread::current().name().unwrap().to_string();
+                        seen.lock().unwrap().push(name);
+                    }
+                    step(ctx)
+                };
+                // pinned tasks outrank the others so they would be stolen first
+                if i % 2 == 0 {
+                    pool.spawn_pinned(5, Affinity::only(2), job)
+                } else {
+                    pool.spawn_on(2, 1, job)
+                }
+            })
+            .collect();
+        for handle in &handles {
+            handle.join();
+        }
+        assert!(seen
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|name| name == "steal-worker-2"));
+        let stats = pool.shutdown();
+        assert_eq!(stats.completed, 30);
+        assert!(stats.workers[2].dispatches >= 15);
+    }
+
+    #[test]
+    fn runs_own_queue_by_rank() {
+        let pool = WorkStealingPool::new(1);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let (gate_tx, gate) = sync_channel::<()>(0);
+        let (started_tx, started) = sync_channel::<()>(0);
+        let first = pool.spawn(0, move |_ctx| {
+            started_tx.send(()).unwrap();
+            gate.recv().unwrap();
+            StepResult::Done
+        });
+        // the worker has to be busy before the others are queued
+        started.recv().unwrap();
+        let handles: Vec<TaskHandle> = [3, 7, 1, 7, 5]
+            .into_iter()
+            .map(|rank| {
+                let log = log.clone();
+                pool.spawn(rank, move |ctx| {
+                    log.lock().unwrap().push((ctx.rank(), ctx.id()));
+                    StepResult::Done
+                })
+            })
+            .collect();
+        gate_tx.send(()).unwrap();
+        first.join();
+        for handle in &handles {
+            handle.join();
+        }
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![(7, 3), (7, 5), (5, 6), (3, 2), (1, 4)]
+        );
+        assert_eq!(pool.shutdown().steals(), 0);
+    }
+
+    #[test]
+    fn a_panicking_task_does_not_hang_shutdown() {
+        let pool = WorkStealingPool::new(2);
+        let bad = pool.spawn_on(0, 5, |ctx| {
+            if ctx.steps() == 1 {
+                panic!("step 1 fails");
+            }
+            StepResult::Continue
+        });
+        let good: Vec<TaskHandle> = (0..4).map(|i| pool.spawn_on(0, i, sleepy(3))).collect();
+        let stats = pool.shutdown();
+        assert_eq!(bad.join(), Outcome::Panicked);
+        for handle in &good {
+            assert_eq!(handle.join(), Outcome::Done);
+        }
+        assert_eq!((stats.completed, stats.panicked), (4, 1));
+    }
+
+    #[test]
+    fn observers_see_every_worker() {
+        let log = EventLog::new();
+        let pool = WorkStealingPool::new(2).with_observer(Box::new(log.clone()));
+        let yielder = pool.spawn(1, |ctx| match ctx.steps() {
+            0 => StepResult::Yield,
+            _ => StepResult::Done,
+        });
+        let blocker = pool.spawn(5, |ctx| match ctx.steps() {
+            0 => StepResult::Blocked,
+            _ => StepResult::Done,
+        });
+        let panicker = pool.spawn(3, |_ctx| panic!("fails"));
+        pool.shutdown();
+
+        let events = log.events();
+        let of = |id: i32| -> Vec<&str> {
+            events
+                .iter()
+                .filter(|(_, event)| event.task == id)
+                .map(|(kind, _)| kind.name())
+                .collect()
+        };
+        assert_eq!(
+            of(yielder.id()),
+            vec![
+                "admitted",
+                "dispatched",
+                "preempted",
+                "dispatched",
+                "completed"
+            ]
+        );
+        assert_eq!(
+            of(blocker.id()),
+            vec![
+                "admitted",
+                "dispatched",
+                "blocked",
+                "woken",
+                "dispatched",
+                "completed"
+            ]
+        );
+        assert_eq!(
+            of(panicker.id()),
+            vec!["admitted", "dispatched", "completed"]
+        );
+        let ended = events
+            .iter()
+            .rfind(|(_, event)| event.task == panicker.id())
+            .unwrap();
+        assert_eq!(ended.1.reason, "its closure panicked");
+    }
+
+    #[test]
+    fn a_blocked_task_is_parked_instead_of_spinning() {
+        let pool = WorkStealingPool::new(1);
+        let ready = Arc::new(AtomicBool::new(false));
+        let (started_tx, started) = sync_channel::<()>(0);
+        let (gate_tx, gate) = sync_channel::<()>(0);
+        // keeps the worker busy until the waiter is queued behind it
+        let first = pool.spawn(0, move |_ctx| {
+            started_tx.send(()).unwrap();
+            gate.recv().unwrap();
+            StepResult::Done
+        });
+        started.recv().unwrap();
+
+        let setter_ready = ready.clone();
+        let setter = pool.spawn(1, move |ctx| {
+            thread::sleep(Duration::from_millis(1));
+            if ctx.steps() < 10 {
+                return StepResult::Continue;
+            }
+            setter_ready.store(true, Ordering::Release);
+            StepResult::Done
+        });
+        // outranks the setter, a requeued waiter would keep it from ever running
+        let waiter = pool.spawn(9, move |_ctx| {
+            if ready.load(Ordering::Acquire) {
+                StepResult::Done
+            } else {
+                StepResult::Blocked
+            }
+        });
+        gate_tx.send(()).unwrap();
+        for handle in [first, setter, waiter] {
+            assert_eq!(handle.join(), Outcome::Done);
+        }
+        let stats = pool.shutdown();
+        assert_eq!(stats.completed, 3);
+        // parked on its first step, woken when the first task finished and
+        // parked again, then woken by the setter
+        assert!(stats.blocks <= 2, "{}", stats.blocks);
+        assert_eq!(stats.yields, 0);
+    }
+
+    #[test]
+    fn victim_order_follows_policy() {
+        // no running pool, its workers would overwrite the loads
+        let workers: Vec<Worker> = [(0, 0), (1, 2), (0, 9), (1, 4)]
+            .into_iter()
+            .map(|(node, load)| Worker {
+                node,
+                queue: Mutex::new(RunQueue::default()),
+                load: AtomicUsize::new(load),
+                preempt: Arc::new(AtomicBool::new(false)),
+                counters: Counters::default(),
+            })
+            .collect();
+        let workers = &workers;
+        let mut rng = Rng::new(3);
+        assert_eq!(
+            victims(0, StealPolicy::MostLoaded, workers, &mut rng),
+            vec![2, 3, 1]
+        );
+        let same_node = victims(1, StealPolicy::SameNode, workers, &mut rng);
+        assert_eq!(same_node[0], 3);
+        let mut random = victims(2, StealPolicy::Random, workers, &mut rng);
+        random.sort_unstable();
+        assert_eq!(random, vec![0, 1, 3]);
+    }
+}
@@ -0,0 +1,5 @@
+pub mod analysis;
+pub mod datastructure;
+pub mod executor;
+pub mod scheduler;
+pub mod sim;
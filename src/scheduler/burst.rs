@@ -0,0 +1,140 @@
+use crate::datastructure::{BurstEstimate, Task};
+use serde::Serialize;
+use std::collections::HashMap;
+
+pub const DEFAULT_ALPHA: f64 = 0.5;
+// ticks expected of a task that has not finished a burst yet
+pub const DEFAULT_INITIAL: f64 = 4.0;
+
+/*
+ * Predicts the next cpu burst of a task with an exponential average,
+ *   next = alpha * actual + (1 - alpha) * predicted
+ * A burst ends when the task blocks or its job completes, ticks on either
+ * side of a preemption count to the same burst. The estimate travels with
+ * the task, the predictor keeps the last one of every task so the next job
+ * of a periodic task starts from it, and how far off every burst was.
+*/
+#[derive(Debug, Clone)]
+pub struct BurstPredictor {
+    alpha: f64,
+    initial: f64,
+    tasks: HashMap<i32, Prediction>,
+}
+
+// how the predictions for one task did
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Prediction {
+    pub task: i32,
+    pub bursts: u64,
+    // mean of |predicted - actual| over the bursts
+    pub mean_error: f64,
+    // what the next burst is expected to be
+    pub next: f64,
+}
+
+impl Default for BurstPredictor {
+    fn default() -> Self {
+        Self::new(DEFAULT_ALPHA)
+    }
+}
+
+impl BurstPredictor {
+    // alpha is clamped to 0..=1, 1 only trusts the last burst
+    pub fn new(alpha: f64) -> Self {
+        BurstPredictor {
+            alpha: alpha.clamp(0.0, 1.0),
+            initial: DEFAULT_INITIAL,
+            tasks: HashMap::new(),
+        }
+    }
+
+    pub fn with_initial(mut self, ticks: f64) -> Self {
+        self.initial = ticks.max(0.0);
+        self
+    }
+
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    pub fn next(&self, id: i32) -> f64 {
+        self.tasks.get(&id).map_or(self.initial, |p| p.next)
+    }
+
+    // a new job starts a burst from the last prediction of its task
+    pub fn start(&self, task: &mut Task) {
+        task.set_estimate(BurstEstimate {
+            predicted: self.next(task.get_id()),
+            ran: 0,
+        });
+    }
+
+    // the task ran one more tick of its burst
+    pub fn charge(task: &mut Task) {
+        let mut estimate = task.get_estimate();
+        estimate.ran += 1;
+        task.set_estimate(estimate);
+    }
+
+    // the burst of the task ended, returns the estimate of its next one
+    pub fn observe(&mut self, task: &Task) -> BurstEstimate {
+        let estimate = task.get_estimate();
+        let actual = estimate.ran as f64;
+        let next = self.alpha * actual + (1.0 - self.alpha) * estimate.predicted;
+        let error = (estimate.predicted - actual).abs();
+        let prediction = self.tasks.entry(task.get_id()).or_insert(Prediction {
+            task: task.get_id(),
+            ..Prediction::default()
+        });
+        prediction.mean_error = (prediction.mean_error * prediction.bursts as f64 + error)
+            / (prediction.bursts + 1) as f64;
+        prediction.bursts += 1;
+        prediction.next = next;
+        BurstEstimate {
+            predicted: next,
+            ran: 0,
+        }
+    }
+
+    // by task id
+    pub fn predictions(&self) -> Vec<Prediction> {
+        let mut predictions: Vec<Prediction> = self.tasks.values().copied().collect();
+        predictions.sort_by_key(|p| p.task);
+        predictions
+    }
+
+    // mean absolute error over every burst of every task
+    pub fn mean_error(&self) -> Option<f64> {
+        let bursts: u64 = self.tasks.values().map(|p| p.bursts).sum();
+        let error: f64 = self
+            .tasks
+            .values()
+            .map(|p| p.mean_error * p.bursts as f64)
+            .sum();
+        (bursts > 0).then(|| error / bursts as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn averages_towards_the_bursts_seen() {
+        let mut predictor = BurstPredictor::new(0.5).with_initial(10.0);
+        let mut task = Task::new(1, 0, 0);
+        predictor.start(&mut task);
+        let mut next = Vec::new();
+        for burst in [6, 4, 6, 4, 13] {
+            for _ in 0..burst {
+                BurstPredictor::charge(&mut task);
+            }
+            task.set_estimate(predictor.observe(&task));
+            next.push(task.get_estimate().predicted);
+        }
+        assert_eq!(next, vec![8.0, 6.0, 6.0, 5.0, 9.0]);
+        // |10-6| + |8-4| + |6-6| + |6-4| + |5-13| over five bursts
+        assert_eq!(predictor.mean_error(), Some(18.0 / 5.0));
+        assert_eq!(predictor.predictions()[0].bursts, 5);
+    }
+}
@@ -0,0 +1,280 @@
+use super::{Enqueue, Policy, Tick};
+use crate::datastructure::avl::AvlTree;
+use crate::datastructure::Task;
+use std::collections::HashMap;
+
+// every ready task runs once in this many ticks, unless there are too many of them
+pub const DEFAULT_TARGET_LATENCY: u64 = 12;
+// shortest slice a task gets however many others are ready
+pub const DEFAULT_MIN_GRANULARITY: u64 = 2;
+// weight of a nice 0 task, a tick it runs adds this much to its vruntime
+pub const NICE_0_WEIGHT: u64 = 1024;
+
+// the kernel's table from nice -20 to 19, one nice level is about 10% of cpu
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+pub fn weight(nice: i32) -> u64 {
+    WEIGHTS[(nice.clamp(-20, 19) + 20) as usize]
+}
+
+// smallest vruntime first, a nice 19 task takes some 10^14 ticks to run past i64::MAX
+pub fn vruntime_key(task: &Task) -> i64 {
+    -(task.get_vruntime().min(i64::MAX as u64) as i64)
+}
+
+/*
+ * Completely fair scheduler. Every tick a task runs adds NICE_0_WEIGHT /
+ * weight ticks to its virtual runtime, scaled by NICE_0_WEIGHT so that a
+ * nice 0 tick counts 1024. The ready tree is keyed by vruntime and the task
+ * that is furthest behind runs next.
+ * A picked task gets its weight's share of the target latency, at least the
+ * minimum granularity, and then goes back to the tree. Once more tasks are
+ * ready than fit in the latency the period grows to a granularity each.
+ * `min_vruntime` follows the smallest vruntime and never goes back. A new
+ * task starts there, a task that slept comes back at most half a latency
+ * behind it so it can not hold the cpu for as long as it was away.
+ * A task that arrives or wakes preempts the running one when it is more than
+ * a granularity behind it.
+*/
+#[derive(Debug)]
+pub struct Cfs {
+    ready: AvlTree,
+    // summed weight of the ready tasks
+    load: u64,
+    min_vruntime: u64,
+    target_latency: u64,
+    min_granularity: u64,
+    // last vruntime of every task seen, kept while it sleeps
+    vruntimes: HashMap<i32, u64>,
+    // smallest vruntime that arrived or woke up since the last tick
+    waking: Option<u64>,
+    // ticks the running task may run and has run since it was picked
+    slice: u64,
+    ran: u64,
+}
+
+impl Default for Cfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cfs {
+    pub fn new() -> Self {
+        Cfs {
+            ready: AvlTree::keyed_by(vruntime_key),
+            load: 0,
+            min_vruntime: 0,
+            target_latency: DEFAULT_TARGET_LATENCY,
+            min_granularity: DEFAULT_MIN_GRANULARITY,
+            vruntimes: HashMap::new(),
+            waking: None,
+            slice: 0,
+            ran: 0,
+        }
+    }
+
+    pub fn with_target_latency(mut self, ticks: u64) -> Self {
+        self.target_latency = ticks.max(1);
+        self
+    }
+
+    pub fn with_min_granularity(mut self, ticks: u64) -> Self {
+        self.min_granularity = ticks.max(1);
+        self
+    }
+
+    pub fn min_vruntime(&self) -> u64 {
+        self.min_vruntime
+    }
+
+    // ticks a task of `weight` may run when it is picked now
+    fn slice_for(&self, weight: u64) -> u64 {
+        let running = self.ready.task_count() as u64 + 1;
+        let period = self.target_latency.max(running * self.min_granularity);
+        (period * weight / (self.load + weight)).max(self.min_granularity)
+    }
+
+    fn update_min_vruntime(&mut self, running: Option<u64>) {
+        let leftmost = self.ready.peek_highest().map(|task| task.get_vruntime());
+        let smallest = match (running, leftmost) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        if let Some(smallest) = smallest {
+            self.min_vruntime = self.min_vruntime.max(smallest);
+        }
+    }
+}
+
+impl Policy for Cfs {
+    fn name(&self) -> &str {
+        "cfs"
+    }
+
+    fn enqueue(&mut self, mut task: Task, _now: u64, why: Enqueue) {
+        let id = task.get_id();
+        let vruntime = match why {
+            Enqueue::Preempted | Enqueue::Expired => task.get_vruntime(),
+            Enqueue::Arrival if !self.vruntimes.contains_key(&id) => self.min_vruntime,
+            // later jobs of a periodic task slept in between just like a waking task
+            _ => {
+                let credit = self.target_latency * NICE_0_WEIGHT / 2;
+                let slept = self.vruntimes.get(&id).copied();
+                slept
+                    .unwrap_or(task.get_vruntime())
+                    .max(self.min_vruntime.saturating_sub(credit))
+            }
+        };
+        if matches!(why, Enqueue::Arrival | Enqueue::Wake) {
+            self.waking = Some(self.waking.map_or(vruntime, |v| v.min(vruntime)));
+        }
+        task.set_vruntime(vruntime);
+        self.vruntimes.insert(id, vruntime);
+        self.load += weight(task.get_nice());
+        self.ready.insert(task);
+    }
+
+    fn pick_next(&mut self, _now: u64) -> Option<Task> {
+        let task = self.ready.pop_highest()?;
+        let weight = weight(task.get_nice());
+        self.load -= weight;
+        self.slice = self.slice_for(weight);
+        self.ran = 0;
+        self.update_min_vruntime(Some(task.get_vruntime()));
+        Some(task)
+    }
+
+    fn tick(&mut self, running: &mut Task, _now: u64) -> Tick {
+        let vruntime =
+            running.get_vruntime() + NICE_0_WEIGHT * NICE_0_WEIGHT / weight(running.get_nice());
+        running.set_vruntime(vruntime);
+        self.vruntimes.insert(running.get_id(), vruntime);
+        self.waking = None;
+        self.ran += 1;
+        self.update_min_vruntime(Some(vruntime));
+        if self.ran >= self.slice && self.ready.task_count() > 0 {
+            return Tick::Expired;
+        }
+        Tick::Continue
+    }
+
+    fn should_preempt(&self, running: &Task, _now: u64) -> bool {
+        self.waking
+            .is_some_and(|v| v + self.min_granularity * NICE_0_WEIGHT < running.get_vruntime())
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        let removed = self.ready.delete_by_id(id);
+        for task in &removed {
+            self.load -= weight(task.get_nice());
+        }
+        removed
+    }
+
+    fn ready_count(&self) -> usize {
+        self.ready.task_count()
+    }
+
+    fn vruntime(&self, id: i32) -> Option<u64> {
+        self.vruntimes.get(&id).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::workload::Workload;
+    use crate::sim::Simulator;
+
+    #[test]
+    fn nice_levels_split_the_cpu_by_weight() {
+        let workload = Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 1000},
+                {"id": 2, "burst": 1000, "nice": 5}
+            ]}"#,
+        )
+        .unwrap();
+        let report = Simulator::new(Box::new(Cfs::new()))
+            .with_horizon(Some(400))
+            .run(&workload);
+        let busy = |id: i32| -> u64 {
+            report
+                .trace
+                .slices
+                .iter()
+                .filter(|slice| slice.task == Some(id))
+                .map(|slice| slice.end - slice.start)
+                .sum()
+        };
+        // 1024 against 335, about three quarters of the cpu for nice 0
+        assert!((295..=310).contains(&busy(1)), "{}", busy(1));
+        assert_eq!(busy(1) + busy(2), 400);
+        // both ran the same weighted time, give or take a slice
+        let (a, b) = (report.vruntimes[0].vruntime, report.vruntimes[1].vruntime);
+        assert!((a - b).abs() <= 12.0, "{a} {b}");
+    }
+
+    #[test]
+    fn nice_weighting_holds_past_i32_vruntimes() {
+        // alone for 40000 ticks the nice 19 task runs up a vruntime of 2.8 * 10^9
+        let workload = Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 100000, "nice": 19},
+                {"id": 2, "arrival": 40000, "burst": 10000},
+                {"id": 3, "arrival": 40000, "burst": 10000, "nice": 5}
+            ]}"#,
+        )
+        .unwrap();
+        let report = Simulator::new(Box::new(Cfs::new()))
+            .with_horizon(Some(44000))
+            .run(&workload);
+        assert!(report.vruntimes[0].vruntime > i32::MAX as f64 / NICE_0_WEIGHT as f64);
+        let busy = |id: i32| -> u64 {
+            report
+                .trace
+                .slices
+                .iter()
+                .filter(|slice| slice.task == Some(id))
+                .map(|slice| slice.end.saturating_sub(slice.start.max(40000)))
+                .sum()
+        };
+        // 1024, 335 and 15 of 1374, the nice 19 task gets about 44 of the 4000 ticks
+        assert!(busy(1) < 100, "{}", busy(1));
+        assert!((2900..=3050).contains(&busy(2)), "{}", busy(2));
+        assert_eq!(busy(1) + busy(2) + busy(3), 4000);
+    }
+
+    #[test]
+    fn sleepers_are_clamped_when_they_wake() {
+        let mut cfs = Cfs::new();
+        cfs.enqueue(Task::new(1, 0, 0).with_burst(500), 0, Enqueue::Arrival);
+        let mut hog = cfs.pick_next(0).unwrap();
+        for now in 0..100 {
+            cfs.tick(&mut hog, now);
+        }
+        assert_eq!(cfs.vruntime(1), Some(100 * NICE_0_WEIGHT));
+        assert_eq!(cfs.min_vruntime(), 100 * NICE_0_WEIGHT);
+
+        // a new task starts level with the hog, one that slept since 0 only
+        // gets half a latency of credit
+        cfs.enqueue(Task::new(2, 0, 0), 100, Enqueue::Arrival);
+        let mut sleeper = Task::new(3, 0, 0);
+        sleeper.set_vruntime(0);
+        cfs.vruntimes.insert(3, 0);
+        cfs.enqueue(sleeper, 100, Enqueue::Wake);
+        assert_eq!(cfs.vruntime(2), Some(100 * NICE_0_WEIGHT));
+        assert_eq!(cfs.vruntime(3), Some(94 * NICE_0_WEIGHT));
+        // the sleeper is far enough behind to preempt the hog right away
+        assert!(cfs.should_preempt(&hog, 100));
+        cfs.enqueue(hog, 100, Enqueue::Preempted);
+        assert_eq!(cfs.pick_next(100).unwrap().get_id(), 3);
+        // three ready, 12 ticks of latency split three ways
+        assert_eq!(cfs.slice, 4);
+    }
+}
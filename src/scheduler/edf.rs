@@ -0,0 +1,64 @@
+use super::{Enqueue, Policy};
+use crate::datastructure::avl::AvlTree;
+use crate::datastructure::Task;
+
+/*
+ * Earliest deadline first. The rank of a job is derived from its absolute
+ * deadline when it is released so the tree hands out the earliest deadline
+ * first, jobs without a deadline only run when nothing else is ready.
+*/
+#[derive(Debug, Default)]
+pub struct Edf {
+    ready: AvlTree,
+}
+
+impl Edf {
+    pub fn new() -> Self {
+        Edf {
+            ready: AvlTree::new(),
+        }
+    }
+}
+
+pub fn deadline_rank(task: &Task) -> i32 {
+    match task.get_abs_deadline() {
+        Some(deadline) => rank_of_deadline(deadline),
+        None => i32::MIN,
+    }
+}
+
+// the earlier the deadline the higher the rank
+pub fn rank_of_deadline(deadline: u64) -> i32 {
+    i32::MAX - deadline.min(i32::MAX as u64) as i32
+}
+
+impl Policy for Edf {
+    fn name(&self) -> &str {
+        "edf"
+    }
+
+    fn enqueue(&mut self, mut task: Task, _now: u64, why: Enqueue) {
+        if why == Enqueue::Arrival {
+            task.set_rank(deadline_rank(&task));
+        }
+        self.ready.insert(task);
+    }
+
+    fn pick_next(&mut self, _now: u64) -> Option<Task> {
+        self.ready.pop_highest()
+    }
+
+    fn should_preempt(&self, running: &Task, _now: u64) -> bool {
+        self.ready
+            .highest_rank()
+            .is_some_and(|rank| rank > running.get_rank())
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        self.ready.delete_by_id(id)
+    }
+
+    fn ready_count(&self) -> usize {
+        self.ready.task_count()
+    }
+}
@@ -0,0 +1,422 @@
+use super::{Enqueue, Policy};
+use crate::datastructure::Task;
+use crate::sim::workload::Workload;
+use std::collections::HashMap;
+
+// ticks a group keeps the cpu before a group further behind may take it
+pub const DEFAULT_SLICE: u64 = 4;
+
+/*
+ * Hierarchical fair share. Groups form a tree under an unnamed root and only
+ * leaf groups hold tasks, every leaf orders its own tasks with an inner
+ * policy (mlfq by default). To pick a task the policy walks down from the
+ * root, at every level taking the child with ready work that is furthest
+ * behind, then asks that leaf's policy.
+ * A group's virtual time grows by 1 / weight for every tick its tasks run,
+ * so siblings get the cpu in proportion to their weights. A group that had
+ * nothing to run starts again level with its busiest sibling instead of
+ * cashing in the time it was idle.
+ * The running task keeps the cpu for at least a slice, after that it is
+ * preempted when another group is further behind. Within a group the inner
+ * policy preempts as it always does.
+*/
+pub struct FairShare {
+    // groups[0] is the root
+    groups: Vec<Group>,
+    // leaf group of every task, unknown tasks go to the first leaf
+    member: HashMap<i32, usize>,
+    // weight changes by tick, latest last
+    changes: Vec<(u64, usize, u32)>,
+    slice: u64,
+    // id and leaf of the running task and the ticks it ran since it was picked
+    running: Option<(i32, usize, u64)>,
+}
+
+struct Group {
+    name: String,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    weight: u32,
+    vtime: f64,
+    // ticks run by the tasks in and below the group
+    usage: u64,
+    // ready tasks in and below the group
+    ready: usize,
+    policy: Option<Box<dyn Policy>>,
+}
+
+impl Group {
+    fn new(name: &str, parent: Option<usize>, weight: u32) -> Self {
+        Group {
+            name: name.to_string(),
+            parent,
+            children: Vec::new(),
+            weight,
+            vtime: 0.0,
+            usage: 0,
+            ready: 0,
+            policy: None,
+        }
+    }
+}
+
+impl FairShare {
+    // a single group, the inner policy alone decides
+    pub fn new(inner: impl Fn() -> Box<dyn Policy>) -> Self {
+        Self::from_workload(&Workload::default(), inner)
+    }
+
+    // the groups of a validated workload, every leaf gets its own inner policy
+    pub fn from_workload(workload: &Workload, inner: impl Fn() -> Box<dyn Policy>) -> Self {
+        let mut groups = vec![Group::new("", None, 1)];
+        let mut changes = Vec::new();
+        for spec in &workload.groups {
+            let parent = match &spec.parent {
+                Some(name) => groups.iter().position(|g| &g.name == name).unwrap_or(0),
+                None => 0,
+            };
+            let at = groups.len();
+            groups[parent].children.push(at);
+            groups.push(Group::new(&spec.name, Some(parent), spec.weight));
+            changes.extend(spec.changes.iter().map(|c| (c.at, at, c.weight)));
+        }
+        if groups.len() == 1 {
+            groups[0].children.push(1);
+            groups.push(Group::new("default", Some(0), 1));
+        }
+        for group in groups.iter_mut().skip(1) {
+            if group.children.is_empty() {
+                group.policy = Some(inner());
+            }
+        }
+        let member = workload
+            .tasks
+            .iter()
+            .filter_map(|spec| {
+                let name = spec.group.as_ref()?;
+                Some((spec.id, groups.iter().position(|g| &g.name == name)?))
+            })
+            .collect();
+        changes.sort_by_key(|&(at, _, _)| std::cmp::Reverse(at));
+        FairShare {
+            groups,
+            member,
+            changes,
+            slice: DEFAULT_SLICE,
+            running: None,
+        }
+    }
+
+    pub fn with_slice(mut self, slice: u64) -> Self {
+        self.slice = slice.max(1);
+        self
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.groups
+            .iter()
+            .skip(1)
+            .position(|g| g.name == name)
+            .map(|at| at + 1)
+    }
+
+    // false when there is no such group, a weight of 0 counts as 1
+    pub fn set_weight(&mut self, name: &str, weight: u32) -> bool {
+        match self.index(name) {
+            Some(at) => {
+                self.groups[at].weight = weight.max(1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get_weight(&self, name: &str) -> Option<u32> {
+        self.index(name).map(|at| self.groups[at].weight)
+    }
+
+    // ticks the tasks in and below the group ran
+    pub fn usage(&self, name: &str) -> Option<u64> {
+        self.index(name).map(|at| self.groups[at].usage)
+    }
+
+    fn leaf_of(&self, task: &Task) -> usize {
+        match self.member.get(&task.get_id()) {
+            Some(&leaf) => leaf,
+            None => self
+                .groups
+                .iter()
+                .position(|g| g.policy.is_some())
+                .expect("there is always a leaf group"),
+        }
+    }
+
+    // the group and its ancestors up to, not including, the root
+    fn path(&self, mut at: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        while let Some(parent) = self.groups[at].parent {
+            path.push(at);
+            at = parent;
+        }
+        path
+    }
+
+    fn is_active(&self, at: usize, running: &[usize]) -> bool {
+        self.groups[at].ready > 0 || running.contains(&at)
+    }
+
+    // the leaf a pick would go to, the running task's groups count as having work
+    fn descend(&self, running: &[usize]) -> Option<usize> {
+        let mut at = 0;
+        while self.groups[at].policy.is_none() {
+            at = self.groups[at]
+                .children
+                .iter()
+                .copied()
+                .filter(|&child| self.is_active(child, running))
+                .min_by(|&a, &b| self.groups[a].vtime.total_cmp(&self.groups[b].vtime))?;
+        }
+        Some(at)
+    }
+
+    fn running_path(&self) -> Vec<usize> {
+        self.running
+            .map_or_else(Vec::new, |(_, leaf, _)| self.path(leaf))
+    }
+}
+
+impl Policy for FairShare {
+    fn name(&self) -> &str {
+        "fair"
+    }
+
+    fn enqueue(&mut self, task: Task, now: u64, why: Enqueue) {
+        let leaf = self.leaf_of(&task);
+        let running = self.running_path();
+        for at in self.path(leaf) {
+            if !self.is_active(at, &running) {
+                let parent = self.groups[at].parent.unwrap_or(0);
+                let behind = self.groups[parent]
+                    .children
+                    .iter()
+                    .filter(|&&sibling| self.is_active(sibling, &running))
+                    .map(|&sibling| self.groups[sibling].vtime)
+                    .min_by(f64::total_cmp);
+                if let Some(behind) = behind {
+                    let group = &mut self.groups[at];
+                    group.vtime = group.vtime.max(behind);
+                }
+            }
+            self.groups[at].ready += 1;
+        }
+        self.groups[0].ready += 1;
+        let policy = self.groups[leaf]
+            .policy
+            .as_mut()
+            .expect("tasks are in leaf groups");
+        // the running task handed back is off the cpu, its groups only stay
+        // active through the ready count
+        if self.running.is_some_and(|(id, _, _)| id == task.get_id()) {
+            self.running = None;
+        }
+        policy.enqueue(task, now, why);
+    }
+
+    fn pick_next(&mut self, now: u64) -> Option<Task> {
+        let leaf = self.descend(&[])?;
+        let task = self.groups[leaf].policy.as_mut()?.pick_next(now)?;
+        for at in self.path(leaf) {
+            self.groups[at].ready -= 1;
+        }
+        self.groups[0].ready -= 1;
+        self.running = Some((task.get_id(), leaf, 0));
+        Some(task)
+    }
+
+    fn tick(&mut self, running: &mut Task, now: u64) -> super::Tick {
+        let leaf = self.leaf_of(running);
+        for at in self.path(leaf) {
+            let group = &mut self.groups[at];
+            group.usage += 1;
+            group.vtime += 1.0 / group.weight as f64;
+        }
+        self.groups[0].usage += 1;
+        if let Some((_, _, ran)) = &mut self.running {
+            *ran += 1;
+        }
+        let policy = self.groups[leaf]
+            .policy
+            .as_mut()
+            .expect("tasks are in leaf groups");
+        policy.tick(running, now)
+    }
+
+    fn should_preempt(&self, running: &Task, now: u64) -> bool {
+        let leaf = self.leaf_of(running);
+        if let Some((_, _, ran)) = self.running {
+            if ran >= self.slice && self.descend(&self.path(leaf)) != Some(leaf) {
+                return true;
+            }
+        }
+        self.groups[leaf]
+            .policy
+            .as_ref()
+            .is_some_and(|policy| policy.should_preempt(running, now))
+    }
+
+    fn on_block(&mut self, task: &mut Task, now: u64) {
+        let leaf = self.leaf_of(task);
+        self.running = None;
+        if let Some(policy) = self.groups[leaf].policy.as_mut() {
+            policy.on_block(task, now);
+        }
+    }
+
+    fn on_complete(&mut self, task: &Task, now: u64) {
+        let leaf = self.leaf_of(task);
+        self.running = None;
+        if let Some(policy) = self.groups[leaf].policy.as_mut() {
+            policy.on_complete(task, now);
+        }
+    }
+
+    fn on_clock(&mut self, now: u64) {
+        while self.changes.last().is_some_and(|&(at, _, _)| at <= now) {
+            let (_, group, weight) = self.changes.pop().unwrap();
+            self.groups[group].weight = weight;
+        }
+        for group in &mut self.groups {
+            if let Some(policy) = group.policy.as_mut() {
+                policy.on_clock(now);
+            }
+        }
+    }
+
+    fn rank_changes(&mut self) -> Vec<Task> {
+        self.groups
+            .iter_mut()
+            .filter_map(|group| group.policy.as_mut())
+            .flat_map(|policy| policy.rank_changes())
+            .collect()
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        let leaf = self.leaf_of(&Task::new(id, 0, 0));
+        let removed = match self.groups[leaf].policy.as_mut() {
+            Some(policy) => policy.remove(id),
+            None => Vec::new(),
+        };
+        for at in self.path(leaf) {
+            self.groups[at].ready -= removed.len();
+        }
+        self.groups[0].ready -= removed.len();
+        removed
+    }
+
+    fn ready_count(&self) -> usize {
+        self.groups[0].ready
+    }
+
+    fn vruntime(&self, id: i32) -> Option<u64> {
+        let leaf = self.leaf_of(&Task::new(id, 0, 0));
+        self.groups[leaf].policy.as_ref()?.vruntime(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::fifo::Fifo;
+    use crate::sim::Simulator;
+
+    fn tenants(weights: [u32; 2]) -> Workload {
+        Workload::parse(&format!(
+            r#"{{"groups": [{{"name": "a", "weight": {}}}, {{"name": "b", "weight": {}}}],
+                "tasks": [
+                {{"id": 1, "burst": 400, "group": "a"}},
+                {{"id": 2, "burst": 400, "group": "a"}},
+                {{"id": 3, "burst": 400, "group": "a"}},
+                {{"id": 4, "burst": 400, "group": "b"}}
+            ]}}"#,
+            weights[0], weights[1]
+        ))
+        .unwrap()
+    }
+
+    fn usage(workload: &Workload, ticks: u64) -> Vec<u64> {
+        let mut policy = FairShare::from_workload(workload, || Box::new(Fifo::new()));
+        let mut running: Option<Task> = None;
+        for task in workload.tasks() {
+            policy.enqueue(task, 0, Enqueue::Arrival);
+        }
+        for now in 0..ticks {
+            if let Some(task) = running.take() {
+                if policy.should_preempt(&task, now) {
+                    policy.enqueue(task, now, Enqueue::Preempted);
+                } else {
+                    running = Some(task);
+                }
+            }
+            let mut task = running.take().or_else(|| policy.pick_next(now)).unwrap();
+            policy.tick(&mut task, now);
+            running = Some(task);
+        }
+        vec![policy.usage("a").unwrap(), policy.usage("b").unwrap()]
+    }
+
+    #[test]
+    fn groups_share_by_weight_not_by_task_count() {
+        // three tasks in a do not get three times the cpu of b
+        assert_eq!(usage(&tenants([1, 1]), 400), vec![200, 200]);
+        assert_eq!(usage(&tenants([3, 1]), 400), vec![300, 100]);
+    }
+
+    #[test]
+    fn nested_groups_split_their_parents_share() {
+        let workload = Workload::parse(
+            r#"{"groups": [
+                {"name": "a"}, {"name": "b"},
+                {"name": "a/web", "parent": "a", "weight": 3},
+                {"name": "a/batch", "parent": "a", "changes": [{"at": 40, "weight": 3}]}
+            ], "tasks": [
+                {"id": 1, "burst": 100, "group": "a/web"},
+                {"id": 2, "burst": 100, "group": "a/batch"},
+                {"id": 3, "burst": 100, "group": "b"}
+            ]}"#,
+        )
+        .unwrap();
+        let report = Simulator::new(Box::new(FairShare::from_workload(&workload, || {
+            Box::new(Fifo::new())
+        })))
+        .with_horizon(Some(80))
+        .run(&workload);
+        let busy: HashMap<&str, u64> = report
+            .groups
+            .iter()
+            .map(|group| (group.group.as_str(), group.busy))
+            .collect();
+        assert_eq!(busy["a"], 40);
+        assert_eq!(busy["b"], 40);
+        // about 15 and 5 of the first 40 ticks, then 10 and 10 once batch
+        // weighs as much as web, the slices round it off
+        assert_eq!(busy["a/web"], 24);
+        assert_eq!(busy["a/batch"], 16);
+    }
+
+    #[test]
+    fn a_task_handed_back_is_no_longer_running() {
+        let workload = tenants([1, 1]);
+        let mut policy = FairShare::from_workload(&workload, || Box::new(Fifo::new()));
+        for task in workload.tasks() {
+            policy.enqueue(task, 0, Enqueue::Arrival);
+        }
+        let mut task = policy.pick_next(0).unwrap();
+        policy.tick(&mut task, 0);
+        // throttled: handed back and taken out, as the simulator does
+        let id = task.get_id();
+        policy.enqueue(task, 1, Enqueue::Preempted);
+        assert_eq!(policy.remove(id).len(), 1);
+        assert!(policy.running.is_none());
+        assert_eq!(policy.ready_count(), 3);
+    }
+}
@@ -0,0 +1,39 @@
+use super::{Enqueue, Policy};
+use crate::datastructure::linklist::LinkList;
+use crate::datastructure::Task;
+
+// First come first served, a task keeps the cpu until it completes or blocks
+#[derive(Debug, Default)]
+pub struct Fifo {
+    ready: LinkList,
+}
+
+impl Fifo {
+    pub fn new() -> Self {
+        Fifo {
+            ready: LinkList::new(),
+        }
+    }
+}
+
+impl Policy for Fifo {
+    fn name(&self) -> &str {
+        "fifo"
+    }
+
+    fn enqueue(&mut self, task: Task, _now: u64, _why: Enqueue) {
+        self.ready.push_back(task);
+    }
+
+    fn pick_next(&mut self, _now: u64) -> Option<Task> {
+        self.ready.pop()
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        self.ready.delete_by_id(id)
+    }
+
+    fn ready_count(&self) -> usize {
+        self.ready.len() as usize
+    }
+}
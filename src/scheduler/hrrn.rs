@@ -0,0 +1,123 @@
+use super::burst::BurstPredictor;
+use super::{Enqueue, Policy, PolicyRecords, Tick};
+use crate::datastructure::Task;
+
+/*
+ * Highest response ratio next. Whenever the cpu is free the ready task with
+ * the highest (waiting + predicted burst) / predicted burst runs until it
+ * blocks or completes. Short bursts go first like under sjf, but the ratio
+ * of a long waiting task keeps growing so it can not starve. The ratio
+ * changes with every tick, so the ready tasks are scanned at every pick
+ * instead of being kept in order.
+*/
+#[derive(Debug, Default)]
+pub struct Hrrn {
+    // ready tasks and when they were queued
+    ready: Vec<(Task, u64)>,
+    predictor: BurstPredictor,
+}
+
+impl Hrrn {
+    pub fn new() -> Self {
+        Hrrn::default()
+    }
+
+    pub fn with_predictor(mut self, predictor: BurstPredictor) -> Self {
+        self.predictor = predictor;
+        self
+    }
+}
+
+pub fn response_ratio(task: &Task, queued: u64, now: u64) -> f64 {
+    let predicted = task.get_estimate().remaining().max(f64::EPSILON);
+    (now.saturating_sub(queued) as f64 + predicted) / predicted
+}
+
+impl Policy for Hrrn {
+    fn name(&self) -> &str {
+        "hrrn"
+    }
+
+    fn enqueue(&mut self, mut task: Task, now: u64, why: Enqueue) {
+        if why == Enqueue::Arrival {
+            self.predictor.start(&mut task);
+        }
+        self.ready.push((task, now));
+    }
+
+    // the first one queued wins a tie
+    fn pick_next(&mut self, now: u64) -> Option<Task> {
+        let mut best: Option<(usize, f64)> = None;
+        for (at, (task, queued)) in self.ready.iter().enumerate() {
+            let ratio = response_ratio(task, *queued, now);
+            if best.is_none_or(|(_, highest)| ratio > highest) {
+                best = Some((at, ratio));
+            }
+        }
+        let (at, _) = best?;
+        Some(self.ready.remove(at).0)
+    }
+
+    fn tick(&mut self, running: &mut Task, _now: u64) -> Tick {
+        BurstPredictor::charge(running);
+        Tick::Continue
+    }
+
+    fn on_block(&mut self, task: &mut Task, _now: u64) {
+        let estimate = self.predictor.observe(task);
+        task.set_estimate(estimate);
+    }
+
+    fn on_complete(&mut self, task: &Task, _now: u64) {
+        self.predictor.observe(task);
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        let (removed, ready): (Vec<_>, Vec<_>) = std::mem::take(&mut self.ready)
+            .into_iter()
+            .partition(|(task, _)| task.get_id() == id);
+        self.ready = ready;
+        removed.into_iter().map(|(task, _)| task).collect()
+    }
+
+    fn ready_count(&self) -> usize {
+        self.ready.len()
+    }
+
+    fn records(&self) -> PolicyRecords {
+        PolicyRecords {
+            predictor: Some(self.predictor.clone()),
+            ..PolicyRecords::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructure::BurstEstimate;
+
+    #[test]
+    fn waiting_raises_a_long_burst() {
+        let mut hrrn = Hrrn::new();
+        let mut long = Task::new(1, 0, 0);
+        long.set_estimate(BurstEstimate {
+            predicted: 10.0,
+            ran: 0,
+        });
+        hrrn.enqueue(long, 0, Enqueue::Wake);
+        hrrn.enqueue(Task::new(2, 0, 0), 8, Enqueue::Arrival);
+        // (10 + 10) / 10 = 2 against (2 + 4) / 4 = 1.5
+        assert_eq!(response_ratio(&hrrn.ready[0].0, 0, 10), 2.0);
+        assert_eq!(hrrn.pick_next(10).unwrap().get_id(), 1);
+
+        // a fresh short burst beats a long one that just got queued
+        let mut long = Task::new(3, 0, 0);
+        long.set_estimate(BurstEstimate {
+            predicted: 10.0,
+            ran: 0,
+        });
+        hrrn.enqueue(long, 10, Enqueue::Wake);
+        assert_eq!(hrrn.pick_next(10).unwrap().get_id(), 2);
+    }
+}
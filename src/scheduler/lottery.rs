@@ -0,0 +1,205 @@
+use super::tickets::{Tickets, DEFAULT_QUANTUM};
+use super::{Enqueue, Policy, PolicyRecords, Tick};
+use crate::datastructure::Task;
+use crate::sim::rng::Rng;
+use crate::sim::workload::Workload;
+
+pub const DEFAULT_SEED: u64 = 1;
+
+/*
+ * Lottery scheduling. Every quantum a ticket is drawn out of all the tickets
+ * held by the ready tasks and its holder runs, so over time a task gets the
+ * cpu in proportion to the base value of its tickets. The draws come from a
+ * seeded generator and a run can be repeated exactly.
+*/
+#[derive(Debug)]
+pub struct Lottery {
+    // ready tasks in the order they were queued
+    ready: Vec<Task>,
+    tickets: Tickets,
+    rng: Rng,
+    quantum: u64,
+    ran: u64,
+}
+
+impl Default for Lottery {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lottery {
+    // every task holds the default tickets
+    pub fn new() -> Self {
+        Self::from_workload(&Workload::default())
+    }
+
+    pub fn from_workload(workload: &Workload) -> Self {
+        Lottery {
+            ready: Vec::new(),
+            tickets: Tickets::from_workload(workload),
+            rng: Rng::new(DEFAULT_SEED),
+            quantum: DEFAULT_QUANTUM,
+            ran: 0,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    pub fn with_quantum(mut self, quantum: u64) -> Self {
+        self.quantum = quantum.max(1);
+        self
+    }
+
+    pub fn tickets(&self) -> &Tickets {
+        &self.tickets
+    }
+
+    pub fn tickets_mut(&mut self) -> &mut Tickets {
+        &mut self.tickets
+    }
+
+    // see Tickets::transfer, counts from the next draw on
+    pub fn transfer(&mut self, from: i32, to: i32, tickets: u64) -> bool {
+        self.tickets.transfer(from, to, tickets)
+    }
+}
+
+impl Policy for Lottery {
+    fn name(&self) -> &str {
+        "lottery"
+    }
+
+    fn enqueue(&mut self, task: Task, _now: u64, _why: Enqueue) {
+        self.ready.push(task);
+    }
+
+    fn pick_next(&mut self, _now: u64) -> Option<Task> {
+        let values: Vec<f64> = self
+            .ready
+            .iter()
+            .map(|task| self.tickets.value(task.get_id()))
+            .collect();
+        let total: f64 = values.iter().sum();
+        let mut winner = self.rng.next_f64() * total;
+        let mut at = self.ready.len().checked_sub(1)?;
+        for (i, value) in values.iter().enumerate() {
+            if winner < *value {
+                at = i;
+                break;
+            }
+            winner -= value;
+        }
+        self.ran = 0;
+        Some(self.ready.remove(at))
+    }
+
+    fn tick(&mut self, _running: &mut Task, _now: u64) -> Tick {
+        self.ran += 1;
+        if self.ran >= self.quantum && !self.ready.is_empty() {
+            return Tick::Expired;
+        }
+        Tick::Continue
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        let (removed, ready) = std::mem::take(&mut self.ready)
+            .into_iter()
+            .partition(|task| task.get_id() == id);
+        self.ready = ready;
+        removed
+    }
+
+    fn ready_count(&self) -> usize {
+        self.ready.len()
+    }
+
+    fn records(&self) -> PolicyRecords {
+        PolicyRecords {
+            tickets: Some(self.tickets.clone()),
+            ..PolicyRecords::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Simulator;
+
+    #[test]
+    fn same_seed_same_draws() {
+        let draws = |seed: u64| -> Vec<i32> {
+            let mut lottery = Lottery::new().with_seed(seed);
+            for id in 1..=4 {
+                lottery.enqueue(Task::new(id, 0, 0), 0, Enqueue::Arrival);
+            }
+            (0..20)
+                .map(|now| {
+                    let task = lottery.pick_next(now).unwrap();
+                    let id = task.get_id();
+                    lottery.enqueue(task, now, Enqueue::Expired);
+                    id
+                })
+                .collect()
+        };
+        assert_eq!(draws(7), draws(7));
+        assert_ne!(draws(7), draws(8));
+    }
+
+    #[test]
+    fn currencies_cap_what_their_tasks_get() {
+        // alice hands out 300 tickets, bob 100, both are funded equally
+        let workload = Workload::parse(
+            r#"{"currencies": [{"name": "alice", "funding": 100}, {"name": "bob", "funding": 100}],
+                "tasks": [
+                {"id": 1, "burst": 2000, "tickets": 200, "currency": "alice"},
+                {"id": 2, "burst": 2000, "tickets": 100, "currency": "alice"},
+                {"id": 3, "burst": 2000, "tickets": 100, "currency": "bob"}
+            ]}"#,
+        )
+        .unwrap();
+        let lottery = Lottery::from_workload(&workload);
+        assert_eq!(lottery.tickets.value(1), 200.0 / 3.0);
+        assert_eq!(lottery.tickets.value(3), 100.0);
+
+        let report = Simulator::new(Box::new(lottery.with_seed(3)))
+            .with_horizon(Some(3000))
+            .run(&workload);
+        let measured: Vec<f64> = report.shares.iter().map(|s| s.measured).collect();
+        let requested: Vec<f64> = report.shares.iter().map(|s| s.requested).collect();
+        // bob's single task gets as much as all of alice's
+        assert!((requested[2] - 0.5).abs() < 1e-9);
+        for (measured, requested) in measured.iter().zip(&requested) {
+            assert!(
+                (measured - requested).abs() < 0.03,
+                "{measured} {requested}"
+            );
+        }
+    }
+
+    #[test]
+    fn transfer_moves_tickets_within_a_currency() {
+        let mut lottery = Lottery::new();
+        assert!(lottery.transfer(1, 2, 60));
+        assert_eq!(lottery.tickets().get(1), 40);
+        assert_eq!(lottery.tickets().get(2), 160);
+        // the giver keeps at least one ticket
+        assert!(!lottery.transfer(1, 2, 40));
+
+        lottery.enqueue(Task::new(1, 0, 0), 0, Enqueue::Arrival);
+        lottery.enqueue(Task::new(2, 0, 0), 0, Enqueue::Arrival);
+        let wins = (0..1000)
+            .filter(|&now| {
+                let task = lottery.pick_next(now).unwrap();
+                let won = task.get_id() == 2;
+                lottery.enqueue(task, now, Enqueue::Expired);
+                won
+            })
+            .count();
+        assert!((760..=840).contains(&wins), "{wins}");
+    }
+}
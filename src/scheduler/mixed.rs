@@ -0,0 +1,365 @@
+use super::edf::{deadline_rank, rank_of_deadline};
+use super::{Enqueue, Policy, PolicyRecords, Tick};
+use crate::analysis;
+use crate::datastructure::{Criticality, Task};
+use crate::sim::workload::Workload;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// what becomes of the lo tasks in hi mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Degradation {
+    // their jobs are dropped, new ones are not released
+    #[default]
+    Drop,
+    // they only run when no hi task is ready
+    Degrade,
+}
+
+pub const DEGRADATION_NAMES: &[&str] = &["drop", "degrade"];
+
+impl Degradation {
+    pub fn by_name(name: &str) -> Option<Degradation> {
+        match name {
+            "drop" => Some(Degradation::Drop),
+            "degrade" => Some(Degradation::Degrade),
+            _ => None,
+        }
+    }
+}
+
+// the system changed criticality mode at `at`, `task` overran its lo budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ModeSwitch {
+    pub at: u64,
+    pub to: Criticality,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task: Option<i32>,
+}
+
+/*
+ * Mixed criticality on top of edf or a fixed priority policy. The system
+ * starts in lo mode where every job is held to its lo budget. A lo job that
+ * runs out of it is dropped, a hi job that runs out of it switches the
+ * system to hi mode, from then on the lo tasks are dropped or degraded to
+ * the lowest rank. The first time nothing is left to run the system goes
+ * back to lo mode.
+ * Under fixed priorities this is AMC, the ranks never change. Under edf it is
+ * EDF-VD, in lo mode a hi job is due at x times its relative deadline (see
+ * analysis::edf_vd_factor) and gets its real deadline back at the switch.
+*/
+pub struct MixedCriticality {
+    inner: Box<dyn Policy>,
+    mode: Criticality,
+    degradation: Degradation,
+    // x of edf-vd, None under fixed priorities
+    virtual_deadlines: Option<f64>,
+    lo: Vec<i32>,
+    hi: Vec<i32>,
+    // rank a degraded job had before, by (task, job)
+    degraded: HashMap<(i32, u32), i32>,
+    // dropped lo jobs by (task, job)
+    dropped: Vec<(i32, u32)>,
+    switches: Vec<ModeSwitch>,
+}
+
+impl MixedCriticality {
+    // virtual deadlines are used when `inner` is edf
+    pub fn new(inner: Box<dyn Policy>, workload: &Workload) -> Self {
+        let virtual_deadlines = (inner.name() == "edf")
+            .then(|| analysis::edf_vd_factor(&analysis::periodic_tasks(workload)));
+        let (hi, lo): (Vec<_>, Vec<_>) = workload
+            .tasks
+            .iter()
+            .partition(|spec| spec.criticality == Criticality::Hi);
+        MixedCriticality {
+            inner,
+            mode: Criticality::Lo,
+            degradation: Degradation::Drop,
+            virtual_deadlines,
+            lo: lo.iter().map(|spec| spec.id).collect(),
+            hi: hi.iter().map(|spec| spec.id).collect(),
+            degraded: HashMap::new(),
+            dropped: Vec::new(),
+            switches: Vec::new(),
+        }
+    }
+
+    pub fn with_degradation(mut self, degradation: Degradation) -> Self {
+        self.degradation = degradation;
+        self
+    }
+
+    pub fn mode(&self) -> Criticality {
+        self.mode
+    }
+
+    // lo jobs dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped.len() as u64
+    }
+
+    fn drop_job(&mut self, task: &Task) {
+        self.dropped.push((task.get_id(), task.get_job()));
+    }
+
+    fn virtual_rank(&self, task: &Task) -> Option<i32> {
+        let x = self.virtual_deadlines?;
+        let deadline = task.get_deadline()?;
+        let scaled = (x * deadline as f64).ceil() as u64;
+        Some(rank_of_deadline(task.get_arrival() + scaled.max(1)))
+    }
+
+    fn degrade(&mut self, mut task: Task, now: u64) {
+        match self.degradation {
+            Degradation::Drop => self.drop_job(&task),
+            Degradation::Degrade => {
+                self.degraded
+                    .entry((task.get_id(), task.get_job()))
+                    .or_insert(task.get_rank());
+                task.set_rank(i32::MIN);
+                self.inner.enqueue(task, now, Enqueue::Preempted);
+            }
+        }
+    }
+
+    fn switch_to_hi(&mut self, now: u64, task: i32) {
+        self.mode = Criticality::Hi;
+        self.switches.push(ModeSwitch {
+            at: now,
+            to: Criticality::Hi,
+            task: Some(task),
+        });
+        for id in self.lo.clone() {
+            for job in self.inner.remove(id) {
+                self.degrade(job, now);
+            }
+        }
+        if self.virtual_deadlines.is_some() {
+            for id in self.hi.clone() {
+                for mut job in self.inner.remove(id) {
+                    job.set_rank(deadline_rank(&job));
+                    self.inner.enqueue(job, now, Enqueue::Preempted);
+                }
+            }
+        }
+    }
+}
+
+impl Policy for MixedCriticality {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn enqueue(&mut self, mut task: Task, now: u64, why: Enqueue) {
+        let lo_budget = task.get_wcet(Criticality::Lo);
+        match (task.get_criticality(), self.mode) {
+            // ran out of its budget, see tick
+            (Criticality::Lo, _) if task.get_executed() >= lo_budget => self.drop_job(&task),
+            (Criticality::Lo, Criticality::Hi) => self.degrade(task, now),
+            (Criticality::Lo, Criticality::Lo) => {
+                match self.degraded.remove(&(task.get_id(), task.get_job())) {
+                    Some(rank) => {
+                        task.set_rank(rank);
+                        self.inner.enqueue(task, now, Enqueue::Preempted);
+                    }
+                    None => self.inner.enqueue(task, now, why),
+                }
+            }
+            (Criticality::Hi, Criticality::Lo)
+                if matches!(why, Enqueue::Arrival | Enqueue::Wake) =>
+            {
+                match self.virtual_rank(&task) {
+                    Some(rank) => {
+                        task.set_rank(rank);
+                        self.inner.enqueue(task, now, Enqueue::Wake);
+                    }
+                    None => self.inner.enqueue(task, now, why),
+                }
+            }
+            (Criticality::Hi, _) => {
+                if self.virtual_deadlines.is_some() && why == Enqueue::Wake {
+                    task.set_rank(deadline_rank(&task));
+                }
+                self.inner.enqueue(task, now, why);
+            }
+        }
+    }
+
+    // nothing left to run is an idle instant, back to lo mode
+    fn pick_next(&mut self, now: u64) -> Option<Task> {
+        let task = self.inner.pick_next(now);
+        if task.is_none() && self.mode == Criticality::Hi {
+            self.mode = Criticality::Lo;
+            self.switches.push(ModeSwitch {
+                at: now,
+                to: Criticality::Lo,
+                task: None,
+            });
+        }
+        task
+    }
+
+    fn tick(&mut self, running: &mut Task, now: u64) -> Tick {
+        let verdict = self.inner.tick(running, now);
+        let lo_budget = running.get_wcet(Criticality::Lo);
+        if running.get_remaining() == 0 || running.get_executed() < lo_budget {
+            return verdict;
+        }
+        match (running.get_criticality(), self.mode) {
+            // budgets are enforced, the job is dropped when it comes back
+            (Criticality::Lo, _) => Tick::Expired,
+            (Criticality::Hi, Criticality::Lo) => {
+                self.switch_to_hi(now + 1, running.get_id());
+                if self.virtual_deadlines.is_some() {
+                    running.set_rank(deadline_rank(running));
+                }
+                verdict
+            }
+            (Criticality::Hi, Criticality::Hi) => verdict,
+        }
+    }
+
+    fn should_preempt(&self, running: &Task, now: u64) -> bool {
+        self.inner.should_preempt(running, now)
+    }
+
+    fn on_block(&mut self, task: &mut Task, now: u64) {
+        self.inner.on_block(task, now);
+    }
+
+    fn on_complete(&mut self, task: &Task, now: u64) {
+        self.degraded.remove(&(task.get_id(), task.get_job()));
+        self.inner.on_complete(task, now);
+    }
+
+    fn on_clock(&mut self, now: u64) {
+        self.inner.on_clock(now);
+    }
+
+    fn rank_changes(&mut self) -> Vec<Task> {
+        self.inner.rank_changes()
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        self.inner.remove(id)
+    }
+
+    fn ready_count(&self) -> usize {
+        self.inner.ready_count()
+    }
+
+    fn records(&self) -> PolicyRecords {
+        PolicyRecords {
+            mode_switches: self.switches.clone(),
+            dropped_jobs: self.dropped.clone(),
+            ..self.inner.records()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::edf::Edf;
+    use crate::scheduler::priority::FixedPriority;
+    use crate::sim::{SimReport, Simulator};
+
+    // every job of the hi task 1 runs past its lo budget of 2
+    fn workload() -> Workload {
+        Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 4, "period": 6, "criticality": "hi", "wcet": {"lo": 2, "hi": 4}},
+                {"id": 2, "burst": 3, "period": 8}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn run(inner: Box<dyn Policy>, degradation: Degradation) -> SimReport {
+        let workload = workload();
+        let policy = MixedCriticality::new(inner, &workload).with_degradation(degradation);
+        Simulator::new(Box::new(policy))
+            .with_horizon(Some(24))
+            .run(&workload)
+    }
+
+    fn switches(report: &SimReport) -> Vec<(u64, Criticality)> {
+        report.mode_switches.iter().map(|s| (s.at, s.to)).collect()
+    }
+
+    #[test]
+    fn amc_drops_lo_tasks_until_an_idle_instant() {
+        let report = run(Box::new(FixedPriority::rate_monotonic()), Degradation::Drop);
+        assert_eq!(
+            &switches(&report)[..4],
+            &[
+                (2, Criticality::Hi),
+                (4, Criticality::Lo),
+                (8, Criticality::Hi),
+                (10, Criticality::Lo)
+            ]
+        );
+        assert_eq!(report.mode_switches[0].task, Some(1));
+        let (hi, lo): (Vec<_>, Vec<_>) = report.jobs.iter().partition(|job| job.task == 1);
+        assert!(hi.iter().all(|job| !job.missed));
+        // the job of 2 released at 0 is dropped at the switch, the one at 8
+        // is not released in hi mode
+        assert_eq!(lo[0].finish, None);
+        assert_eq!(lo[1].finish, None);
+        // and shows up as skipped, not as a deadline miss
+        assert!(lo[..2].iter().all(|job| job.skipped && !job.missed));
+        assert_eq!(
+            report.metrics.skipped,
+            lo.iter().filter(|job| job.skipped).count()
+        );
+
+        // degraded instead, 2 only runs while 1 has nothing to do, together
+        // they need more than the cpu and the system never gets back to lo mode
+        let report = run(
+            Box::new(FixedPriority::rate_monotonic()),
+            Degradation::Degrade,
+        );
+        let (hi, lo): (Vec<_>, Vec<_>) = report.jobs.iter().partition(|job| job.task == 1);
+        assert!(hi.iter().all(|job| !job.missed));
+        assert_eq!(lo[0].finish, Some(11));
+        assert_eq!(switches(&report), vec![(2, Criticality::Hi)]);
+    }
+
+    #[test]
+    fn a_lo_job_over_its_budget_is_reported_skipped() {
+        // 2 claims 1 tick but needs 3, 1 stays within its lo budget
+        let workload = Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 2, "period": 6, "criticality": "hi", "wcet": {"lo": 2, "hi": 4}},
+                {"id": 2, "burst": 3, "period": 8, "wcet": {"lo": 1, "hi": 1}}
+            ]}"#,
+        )
+        .unwrap();
+        let policy = MixedCriticality::new(Box::new(FixedPriority::rate_monotonic()), &workload);
+        let report = Simulator::new(Box::new(policy))
+            .with_horizon(Some(24))
+            .run(&workload);
+        assert!(report.mode_switches.is_empty());
+        let lo: Vec<_> = report.jobs.iter().filter(|job| job.task == 2).collect();
+        assert_eq!(lo.len(), 3);
+        assert!(lo.iter().all(|job| job.skipped && job.finish.is_none()));
+        assert_eq!(report.metrics.skipped, 3);
+        assert_eq!(report.metrics.deadline_misses, 0);
+    }
+
+    #[test]
+    fn edf_vd_gives_hi_jobs_their_deadline_back_at_the_switch() {
+        let workload = workload();
+        let policy = MixedCriticality::new(Box::new(Edf::new()), &workload);
+        // U_lo(LO) + U_hi(HI) = 0.375 + 0.667 > 1, x = (2 / 6) / (1 - 0.375)
+        assert!((policy.virtual_deadlines.unwrap() - 8.0 / 15.0).abs() < 1e-9);
+        let report = run(Box::new(Edf::new()), Degradation::Drop);
+        assert_eq!(report.mode_switches[0].at, 2);
+        assert!(report
+            .jobs
+            .iter()
+            .filter(|job| job.task == 1)
+            .all(|job| !job.missed));
+    }
+}
@@ -0,0 +1,245 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/*
+ * Tuning of the MLFQ, stored as json
+ * {
+ *   "levels": [{"quantum": 2, "allotment": 4}, {"quantum": 4, "allotment": 8}],
+ *   "boost_interval": 100,
+ *   "aging_rate": 50,
+ *   "yield_keeps_priority": false
+ * }
+ * Levels are listed from the top (highest rank) down. `aging_rate` is how
+ * long a ready task may wait before it moves up one level, `yield_keeps_priority`
+ * forgets the allotment a task used when it blocks before using it all up.
+ * Quanta may not shrink toward the higher ranks: going up a level the
+ * quantum stays or grows, so no level gets a longer quantum than the level
+ * above it. The usual MLFQ layout, short quanta at the top, shrinks toward
+ * the top and needs `allow_shrinking_quanta`, the default and the presets
+ * set it.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MlfqConfig {
+    pub levels: Vec<Level>,
+    #[serde(default)]
+    pub boost_interval: Option<u64>,
+    #[serde(default)]
+    pub aging_rate: Option<u64>,
+    #[serde(default)]
+    pub yield_keeps_priority: bool,
+    // lets a level get a longer quantum than the level above it
+    #[serde(default)]
+    pub allow_shrinking_quanta: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Level {
+    pub quantum: u64,
+    pub allotment: u64,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+    UnknownPreset(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "cannot read mlfq config: {err}"),
+            ConfigError::Parse(err) => write!(f, "cannot parse mlfq config: {err}"),
+            ConfigError::Invalid(msg) => write!(f, "invalid mlfq config: {msg}"),
+            ConfigError::UnknownPreset(name) => write!(
+                f,
+                "unknown mlfq preset {name:?}, expected one of {}",
+                PRESET_NAMES.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub const PRESET_NAMES: &[&str] = &["default", "solaris-like", "interactive", "batch"];
+
+fn levels(table: &[(u64, u64)]) -> Vec<Level> {
+    table
+        .iter()
+        .map(|&(quantum, allotment)| Level { quantum, allotment })
+        .collect()
+}
+
+impl Default for MlfqConfig {
+    fn default() -> Self {
+        MlfqConfig {
+            // short quanta at the top, which shrinks toward the higher ranks
+            levels: levels(&[(2, 4), (4, 8), (8, 8)]),
+            boost_interval: Some(100),
+            aging_rate: None,
+            yield_keeps_priority: false,
+            allow_shrinking_quanta: true,
+        }
+    }
+}
+
+impl MlfqConfig {
+    /*
+     * Named presets
+     * solaris-like: many levels, a task drops a level every time it uses a
+     *   full quantum and tasks that wait too long are aged upwards instead of
+     *   a global boost, like the Solaris time sharing dispatch table
+     * interactive: short quanta at the top and a frequent boost so io bound
+     *   tasks stay responsive
+     * batch: few levels with long quanta to cut down on context switches
+     */
+    pub fn preset(name: &str) -> Result<MlfqConfig, ConfigError> {
+        let config = match name {
+            "default" => MlfqConfig::default(),
+            "solaris-like" => MlfqConfig {
+                levels: levels(&[(4, 4), (8, 8), (12, 12), (16, 16), (20, 20), (40, 40)]),
+                boost_interval: None,
+                aging_rate: Some(50),
+                yield_keeps_priority: true,
+                allow_shrinking_quanta: true,
+            },
+            "interactive" => MlfqConfig {
+                levels: levels(&[(1, 2), (2, 4), (4, 8), (8, 16)]),
+                boost_interval: Some(50),
+                aging_rate: None,
+                yield_keeps_priority: true,
+                allow_shrinking_quanta: true,
+            },
+            "batch" => MlfqConfig {
+                levels: levels(&[(10, 20), (20, 40), (40, 80)]),
+                boost_interval: Some(500),
+                aging_rate: None,
+                yield_keeps_priority: false,
+                allow_shrinking_quanta: true,
+            },
+            _ => return Err(ConfigError::UnknownPreset(name.to_string())),
+        };
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<MlfqConfig, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        MlfqConfig::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<MlfqConfig, ConfigError> {
+        let config: MlfqConfig = serde_json::from_str(text).map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: String| Err(ConfigError::Invalid(msg));
+        if self.levels.is_empty() {
+            return invalid("at least one level is needed".into());
+        }
+        for (i, level) in self.levels.iter().enumerate() {
+            if level.quantum == 0 {
+                return invalid(format!("level {i}: quantum must be at least 1"));
+            }
+            if level.allotment < level.quantum {
+                return invalid(format!(
+                    "level {i}: allotment {} is shorter than the quantum {}",
+                    level.allotment, level.quantum
+                ));
+            }
+        }
+        if !self.allow_shrinking_quanta {
+            for (i, pair) in self.levels.windows(2).enumerate() {
+                if pair[1].quantum > pair[0].quantum {
+                    return invalid(format!(
+                        "level {} quantum {} is longer than level {i} above it ({}), \
+                         quanta may not shrink toward the higher ranks \
+                         unless allow_shrinking_quanta is set",
+                        i + 1,
+                        pair[1].quantum,
+                        pair[0].quantum
+                    ));
+                }
+            }
+        }
+        if self.boost_interval == Some(0) {
+            return invalid("boost_interval must be at least 1".into());
+        }
+        if self.aging_rate == Some(0) {
+            return invalid("aging_rate must be at least 1".into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_valid() {
+        for name in PRESET_NAMES {
+            assert!(
+                MlfqConfig::preset(name).unwrap().validate().is_ok(),
+                "{name}"
+            );
+        }
+        assert!(matches!(
+            MlfqConfig::preset("nope"),
+            Err(ConfigError::UnknownPreset(_))
+        ));
+    }
+
+    #[test]
+    fn parse_and_validate() {
+        let config = MlfqConfig::parse(
+            r#"{"levels": [{"quantum": 6, "allotment": 12}, {"quantum": 2, "allotment": 2}],
+                "boost_interval": 40, "yield_keeps_priority": true}"#,
+        )
+        .unwrap();
+        assert_eq!(config.levels[0].allotment, 12);
+        assert_eq!(config.aging_rate, None);
+
+        let empty = r#"{"levels": []}"#;
+        assert!(matches!(
+            MlfqConfig::parse(empty),
+            Err(ConfigError::Invalid(_))
+        ));
+
+        // 8 at the bottom shrinks to 2 toward the top
+        let shrinking =
+            r#"{"levels": [{"quantum": 2, "allotment": 2}, {"quantum": 8, "allotment": 8}]}"#;
+        assert!(matches!(
+            MlfqConfig::parse(shrinking),
+            Err(ConfigError::Invalid(_))
+        ));
+        let allowed = shrinking.replace("]}", r#"], "allow_shrinking_quanta": true}"#);
+        assert!(MlfqConfig::parse(&allowed).is_ok());
+    }
+
+    #[test]
+    fn quanta_do_not_shrink_toward_higher_ranks() {
+        let quanta = |quanta: &[u64]| {
+            let levels: Vec<String> = quanta
+                .iter()
+                .map(|q| format!(r#"{{"quantum": {q}, "allotment": {q}}}"#))
+                .collect();
+            MlfqConfig::parse(&format!(r#"{{"levels": [{}]}}"#, levels.join(", ")))
+        };
+        // top down: equal or shorter quanta going down are fine
+        assert!(quanta(&[8, 2, 2]).is_ok());
+        assert!(quanta(&[16, 4, 1]).is_ok());
+        // level 1 is longer than level 0 above it, going up the quantum shrinks
+        match quanta(&[4, 8, 2]) {
+            Err(ConfigError::Invalid(msg)) => {
+                assert!(msg.starts_with("level 1 quantum 8"), "{msg}")
+            }
+            other => panic!("{other:?}"),
+        }
+        assert!(quanta(&[1, 4, 16]).is_err());
+    }
+}
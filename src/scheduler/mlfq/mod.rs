@@ -0,0 +1,298 @@
+pub mod config;
+
+use super::{Enqueue, Policy, Tick};
+use crate::datastructure::avl::AvlTree;
+use crate::datastructure::Task;
+pub use config::{Level, MlfqConfig};
+use std::collections::{BTreeMap, HashMap};
+
+/*
+ * Multi-Level Feedback Queue
+ * Every level is a rank bucket in the tree, the top level has the highest rank.
+ * 1. a higher level always runs before a lower one
+ * 2. tasks on the same level take turns, each getting the quantum of the level
+ * 3. a new task enters at the top level
+ * 4. once a task used up the allotment of its level it moves down one level
+ * 5. every `boost_interval` ticks all tasks go back to the top level
+ * A bucket is reserved for every level when the queue is built from its config.
+*/
+#[derive(Debug)]
+pub struct Mlfq {
+    config: MlfqConfig,
+    ready: AvlTree,
+    // allotment used by a task on its current level
+    used: HashMap<i32, u64>,
+    // last boost a task has seen, a stale task is moved to the top level
+    epoch: HashMap<i32, u64>,
+    // when a ready task was last queued, used for aging
+    queued_at: HashMap<i32, u64>,
+    // queued jobs a boost or aging moved, until rank_changes hands them out
+    changed: BTreeMap<(i32, u32), Task>,
+    boosts: u64,
+    slice: u64,
+}
+
+impl Default for Mlfq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mlfq {
+    pub fn new() -> Self {
+        Mlfq::from_config(MlfqConfig::default())
+    }
+
+    // the config is expected to be validated already
+    pub fn from_config(config: MlfqConfig) -> Self {
+        assert!(!config.levels.is_empty(), "mlfq needs at least one level");
+        let mut ready = AvlTree::new();
+        for rank in 0..config.levels.len() as i32 {
+            ready.reserve_rank(rank);
+        }
+        Mlfq {
+            config,
+            ready,
+            used: HashMap::new(),
+            epoch: HashMap::new(),
+            queued_at: HashMap::new(),
+            changed: BTreeMap::new(),
+            boosts: 0,
+            slice: 0,
+        }
+    }
+
+    pub fn config(&self) -> &MlfqConfig {
+        &self.config
+    }
+
+    fn top_rank(&self) -> i32 {
+        self.config.levels.len() as i32 - 1
+    }
+
+    fn level(&self, rank: i32) -> Level {
+        let index = (self.top_rank() - rank).clamp(0, self.top_rank()) as usize;
+        self.config.levels[index]
+    }
+
+    // puts a task that missed the last boost back on the top level
+    fn catch_up(&mut self, task: &mut Task) {
+        let top = self.top_rank();
+        let seen = self.epoch.entry(task.get_id()).or_insert(0);
+        if *seen < self.boosts {
+            *seen = self.boosts;
+            task.set_rank(top);
+            self.used.insert(task.get_id(), 0);
+        }
+    }
+
+    // catch_up for a task that is queued or about to be, the change is reported
+    fn catch_up_queued(&mut self, task: &mut Task) {
+        let rank = task.get_rank();
+        self.catch_up(task);
+        if task.get_rank() != rank {
+            self.changed
+                .insert((task.get_id(), task.get_job()), task.clone());
+        }
+    }
+
+    fn boost(&mut self) {
+        self.boosts += 1;
+        let mut tasks = Vec::new();
+        while let Some(task) = self.ready.pop_highest() {
+            tasks.push(task);
+        }
+        for mut task in tasks {
+            self.catch_up_queued(&mut task);
+            self.ready.insert(task);
+        }
+    }
+
+    // moves every task that waited `aging_rate` ticks below the top up one level
+    fn age(&mut self, now: u64, rate: u64) {
+        let top = self.top_rank();
+        let starving: Vec<Task> = self
+            .ready
+            .tasks()
+            .into_iter()
+            .filter(|task| task.get_rank() < top)
+            .filter(|task| {
+                let since = self.queued_at.get(&task.get_id()).copied().unwrap_or(now);
+                // a task may be handed in with a later time than the clock
+                now.saturating_sub(since) >= rate
+            })
+            .collect();
+        for task in starving {
+            if let Some(mut task) = self.ready.delete_by_task(&task) {
+                task.set_rank(task.get_rank() + 1);
+                self.used.insert(task.get_id(), 0);
+                self.queued_at.insert(task.get_id(), now);
+                self.changed
+                    .insert((task.get_id(), task.get_job()), task.clone());
+                self.ready.insert(task);
+            }
+        }
+    }
+}
+
+impl Policy for Mlfq {
+    fn name(&self) -> &str {
+        "mlfq"
+    }
+
+    fn enqueue(&mut self, mut task: Task, now: u64, why: Enqueue) {
+        match why {
+            Enqueue::Arrival => {
+                task.set_rank(self.top_rank());
+                self.used.insert(task.get_id(), 0);
+                self.epoch.insert(task.get_id(), self.boosts);
+            }
+            _ => self.catch_up_queued(&mut task),
+        }
+        self.queued_at.insert(task.get_id(), now);
+        self.ready.insert(task);
+    }
+
+    fn pick_next(&mut self, _now: u64) -> Option<Task> {
+        self.slice = 0;
+        let task = self.ready.pop_highest()?;
+        self.queued_at.remove(&task.get_id());
+        Some(task)
+    }
+
+    fn tick(&mut self, running: &mut Task, _now: u64) -> Tick {
+        self.catch_up(running);
+        let level = self.level(running.get_rank());
+        self.slice += 1;
+        let used = self.used.entry(running.get_id()).or_insert(0);
+        *used += 1;
+        if *used >= level.allotment && running.get_rank() > 0 {
+            *used = 0;
+            running.set_rank(running.get_rank() - 1);
+            return Tick::Expired;
+        }
+        if self.slice >= level.quantum {
+            return Tick::Expired;
+        }
+        Tick::Continue
+    }
+
+    fn should_preempt(&self, running: &Task, _now: u64) -> bool {
+        self.ready
+            .highest_rank()
+            .is_some_and(|rank| rank > running.get_rank())
+    }
+
+    fn on_block(&mut self, task: &mut Task, _now: u64) {
+        if self.config.yield_keeps_priority {
+            self.used.insert(task.get_id(), 0);
+        }
+    }
+
+    fn on_complete(&mut self, task: &Task, _now: u64) {
+        self.used.remove(&task.get_id());
+        self.epoch.remove(&task.get_id());
+        self.changed.remove(&(task.get_id(), task.get_job()));
+    }
+
+    fn on_clock(&mut self, now: u64) {
+        if let Some(interval) = self.config.boost_interval {
+            if now > 0 && now.is_multiple_of(interval) {
+                self.boost();
+            }
+        }
+        if let Some(rate) = self.config.aging_rate {
+            self.age(now, rate);
+        }
+    }
+
+    fn rank_changes(&mut self) -> Vec<Task> {
+        std::mem::take(&mut self.changed).into_values().collect()
+    }
+
+    // the allotment used so far is kept for when the task comes back
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        self.queued_at.remove(&id);
+        self.ready.delete_by_id(id)
+    }
+
+    fn ready_count(&self) -> usize {
+        self.ready.task_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demotes_after_allotment_and_boosts_back() {
+        let mut mlfq = Mlfq::from_config(MlfqConfig {
+            levels: vec![
+                Level {
+                    quantum: 2,
+                    allotment: 2,
+                },
+                Level {
+                    quantum: 4,
+                    allotment: 4,
+                },
+            ],
+            boost_interval: Some(10),
+            ..MlfqConfig::default()
+        });
+        mlfq.enqueue(Task::new(1, 0, 0).with_burst(20), 0, Enqueue::Arrival);
+        let mut task = mlfq.pick_next(0).unwrap();
+        assert_eq!(task.get_rank(), 1);
+
+        assert_eq!(mlfq.tick(&mut task, 0), Tick::Continue);
+        assert_eq!(mlfq.tick(&mut task, 1), Tick::Expired);
+        assert_eq!(task.get_rank(), 0);
+
+        // a fresh arrival on the top level preempts the demoted task
+        mlfq.enqueue(Task::new(2, 0, 0), 2, Enqueue::Arrival);
+        assert!(mlfq.should_preempt(&task, 2));
+        mlfq.enqueue(task, 2, Enqueue::Preempted);
+
+        mlfq.on_clock(10);
+        let order: Vec<(i32, i32)> = std::iter::from_fn(|| mlfq.pick_next(10))
+            .map(|t| (t.get_id(), t.get_rank()))
+            .collect();
+        assert_eq!(order, vec![(2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn aging_and_yield_keep_priority() {
+        let mut mlfq = Mlfq::from_config(MlfqConfig {
+            boost_interval: None,
+            aging_rate: Some(5),
+            yield_keeps_priority: true,
+            ..MlfqConfig::default()
+        });
+        let mut task = Task::new(1, 0, 0).with_burst(50);
+        mlfq.enqueue(task.clone(), 0, Enqueue::Arrival);
+        task = mlfq.pick_next(0).unwrap();
+        for now in 0..3 {
+            mlfq.tick(&mut task, now);
+        }
+        // blocking before the allotment (4) is used up forgets the 3 ticks
+        mlfq.on_block(&mut task, 3);
+        mlfq.enqueue(task, 5, Enqueue::Wake);
+        let mut task = mlfq.pick_next(5).unwrap();
+        assert_eq!(mlfq.tick(&mut task, 5), Tick::Continue);
+        assert_eq!(mlfq.tick(&mut task, 6), Tick::Expired);
+        assert_eq!(task.get_rank(), 2);
+
+        // demoted to the bottom, then waits long enough to be aged up
+        task.set_rank(0);
+        mlfq.enqueue(task, 10, Enqueue::Expired);
+        mlfq.on_clock(14);
+        assert_eq!(mlfq.ready.highest_rank(), Some(0));
+        mlfq.on_clock(15);
+        assert_eq!(mlfq.ready.highest_rank(), Some(1));
+
+        // a clock behind the time the task was queued at ages nothing
+        mlfq.on_clock(9);
+        assert_eq!(mlfq.ready.highest_rank(), Some(1));
+    }
+}
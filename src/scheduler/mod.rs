@@ -0,0 +1,158 @@
+pub mod burst;
+pub mod cfs;
+pub mod edf;
+pub mod fair;
+pub mod fifo;
+pub mod hrrn;
+pub mod lottery;
+pub mod mixed;
+pub mod mlfq;
+pub mod observer;
+pub mod overload;
+pub mod partition;
+pub mod priority;
+pub mod replay;
+pub mod round_robin;
+pub mod server;
+pub mod sjf;
+pub mod stride;
+pub mod tickets;
+
+use crate::datastructure::Task;
+
+/*
+ * A policy owns the ready tasks and decides which one runs next.
+ * The simulator drives it: tasks are handed in with `enqueue`, the policy
+ * hands one out with `pick_next`, and the running task is charged one tick
+ * at a time with `tick` until it completes, blocks or the policy takes the
+ * cpu away by expiring its slice or asking for preemption.
+*/
+pub trait Policy {
+    fn name(&self) -> &str;
+
+    // a task became ready, `why` tells the policy where it is coming from
+    fn enqueue(&mut self, task: Task, now: u64, why: Enqueue);
+
+    // removes the task that should run next from the ready set
+    fn pick_next(&mut self, now: u64) -> Option<Task>;
+
+    // the running task used the tick starting at `now`
+    fn tick(&mut self, _running: &mut Task, _now: u64) -> Tick {
+        Tick::Continue
+    }
+
+    // asked before every tick, true hands the running task back with Enqueue::Preempted
+    fn should_preempt(&self, _running: &Task, _now: u64) -> bool {
+        false
+    }
+
+    // the running task gave up the cpu to wait on io
+    fn on_block(&mut self, _task: &mut Task, _now: u64) {}
+
+    fn on_complete(&mut self, _task: &Task, _now: u64) {}
+
+    // called once per tick before anything is dispatched
+    fn on_clock(&mut self, _now: u64) {}
+
+    // queued jobs whose rank the policy changed on its own, e.g. an mlfq boost,
+    // at their new rank, each handed out once. Other rank changes are only
+    // seen when the job is next picked or ticked, see observer::Observers
+    fn rank_changes(&mut self) -> Vec<Task> {
+        Vec::new()
+    }
+
+    // takes every ready job of the task out of the ready set, e.g. to throttle it
+    fn remove(&mut self, id: i32) -> Vec<Task>;
+
+    fn ready_count(&self) -> usize;
+
+    // weighted runtime of the task so far, for the policies that keep one
+    fn vruntime(&self, _id: i32) -> Option<u64> {
+        None
+    }
+
+    // what the policy kept track of for the report, asked once after the run
+    fn records(&self) -> PolicyRecords {
+        PolicyRecords::default()
+    }
+}
+
+/*
+ * What a policy kept track of over a run. A policy fills in the parts it
+ * has, a wrapper starts from the records of the policy it wraps and adds its
+ * own.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct PolicyRecords {
+    // for the proportional share policies
+    pub tickets: Option<tickets::Tickets>,
+    // for the policies that order by predicted bursts
+    pub predictor: Option<burst::BurstPredictor>,
+    // what the aperiodic servers handed out, see server::Served
+    pub servers: Vec<server::ServerRecord>,
+    // changes of the criticality mode, see mixed::MixedCriticality
+    pub mode_switches: Vec<mixed::ModeSwitch>,
+    // lo jobs mixed criticality dropped, by task and job
+    pub dropped_jobs: Vec<(i32, u32)>,
+    // what the partitions got out of their reservations, see partition::Partitioned
+    pub partitions: Vec<partition::PartitionRecord>,
+    // what the overload strategy decided, see overload::Overload
+    pub overloads: Vec<overload::OverloadEvent>,
+}
+
+// why a task is handed to the policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enqueue {
+    Arrival,
+    Wake,
+    Preempted,
+    Expired,
+}
+
+// what should happen to the running task after a tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tick {
+    Continue,
+    Expired,
+}
+
+pub const POLICY_NAMES: &[&str] = &[
+    "fifo",
+    "rr",
+    "priority",
+    "rm",
+    "edf",
+    "mlfq",
+    "fair",
+    "cfs",
+    "lottery",
+    "stride",
+    "sjf",
+    "srtf",
+    "hrrn",
+    "partitioned",
+];
+
+// builds a policy with its default settings
+pub fn by_name(name: &str) -> Option<Box<dyn Policy>> {
+    let policy: Box<dyn Policy> = match name {
+        "fifo" => Box::new(fifo::Fifo::new()),
+        "rr" => Box::new(round_robin::RoundRobin::new(round_robin::DEFAULT_QUANTUM)),
+        "priority" => Box::new(priority::FixedPriority::new()),
+        "rm" => Box::new(priority::FixedPriority::rate_monotonic()),
+        "edf" => Box::new(edf::Edf::new()),
+        "mlfq" => Box::new(mlfq::Mlfq::new()),
+        // without a workload there is one group, see fair::FairShare::from_workload
+        "fair" => Box::new(fair::FairShare::new(|| Box::new(mlfq::Mlfq::new()))),
+        "cfs" => Box::new(cfs::Cfs::new()),
+        "lottery" => Box::new(lottery::Lottery::new()),
+        "stride" => Box::new(stride::Stride::new()),
+        "sjf" => Box::new(sjf::Sjf::new()),
+        "srtf" => Box::new(sjf::Sjf::srtf()),
+        "hrrn" => Box::new(hrrn::Hrrn::new()),
+        // one partition with the whole cpu, see partition::Partitioned::from_workload
+        "partitioned" => Box::new(partition::Partitioned::new(Box::new(mlfq::Mlfq::new()))),
+        _ => return None,
+    };
+    Some(policy)
+}
@@ -0,0 +1,336 @@
+use crate::datastructure::Task;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    // a job was handed to the policy for the first time
+    Admitted,
+    // the policy picked the job to run
+    Dispatched,
+    // the job was taken off the cpu while it could still run
+    Preempted,
+    // the policy lowered the job's rank
+    Demoted,
+    // the policy raised the job's rank
+    Boosted,
+    // the job cannot run until something happens, io, a resource or a quota
+    Blocked,
+    // a blocked job is ready again
+    Woken,
+    Completed,
+    // the job's deadline passed before it completed
+    DeadlineMissed,
+}
+
+impl EventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::Admitted => "admitted",
+            EventKind::Dispatched => "dispatched",
+            EventKind::Preempted => "preempted",
+            EventKind::Demoted => "demoted",
+            EventKind::Boosted => "boosted",
+            EventKind::Blocked => "blocked",
+            EventKind::Woken => "woken",
+            EventKind::Completed => "completed",
+            EventKind::DeadlineMissed => "deadline_missed",
+        }
+    }
+}
+
+// what an observer is told, the ranks only differ for demotions and boosts
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchedEvent {
+    pub task: i32,
+    pub job: u32,
+    pub old_rank: i32,
+    pub new_rank: i32,
+    pub at: u64,
+    pub reason: &'static str,
+}
+
+impl SchedEvent {
+    pub fn of(task: &Task, at: u64, reason: &'static str) -> Self {
+        SchedEvent {
+            task: task.get_id(),
+            job: task.get_job(),
+            old_rank: task.get_rank(),
+            new_rank: task.get_rank(),
+            at,
+            reason,
+        }
+    }
+}
+
+/*
+ * Watches what the scheduler does. Every event goes through `on_event`,
+ * which by default calls the callback of its kind, so an observer either
+ * overrides the callbacks it cares about or takes everything in `on_event`.
+ * Demotions and boosts of a waiting job are only on time for the changes
+ * the policy reports, see Observers. Observers are Send, the pools tell
+ * them from their own threads.
+*/
+pub trait SchedulerObserver: Send {
+    fn on_event(&mut self, kind: EventKind, event: &SchedEvent) {
+        match kind {
+            EventKind::Admitted => self.on_admitted(event),
+            EventKind::Dispatched => self.on_dispatched(event),
+            EventKind::Preempted => self.on_preempted(event),
+            EventKind::Demoted => self.on_demoted(event),
+            EventKind::Boosted => self.on_boosted(event),
+            EventKind::Blocked => self.on_blocked(event),
+            EventKind::Woken => self.on_woken(event),
+            EventKind::Completed => self.on_completed(event),
+            EventKind::DeadlineMissed => self.on_deadline_missed(event),
+        }
+    }
+
+    fn on_admitted(&mut self, _event: &SchedEvent) {}
+
+    fn on_dispatched(&mut self, _event: &SchedEvent) {}
+
+    fn on_preempted(&mut self, _event: &SchedEvent) {}
+
+    fn on_demoted(&mut self, _event: &SchedEvent) {}
+
+    fn on_boosted(&mut self, _event: &SchedEvent) {}
+
+    fn on_blocked(&mut self, _event: &SchedEvent) {}
+
+    fn on_woken(&mut self, _event: &SchedEvent) {}
+
+    fn on_completed(&mut self, _event: &SchedEvent) {}
+
+    fn on_deadline_missed(&mut self, _event: &SchedEvent) {}
+}
+
+/*
+ * The observers registered on a scheduler. Events are only built when there
+ * is somebody to tell, without observers every call returns right away.
+ * Ranks are changed inside the policies, so the rank every job was last seen
+ * at is kept and a change shows up as a demotion or a boost the next time
+ * the job is seen. Queued jobs a policy hands out through
+ * Policy::rank_changes are seen on the tick they changed, any other change to
+ * a waiting job is reported late, when it is dispatched, and a change that
+ * was undone before that is not reported at all.
+*/
+#[derive(Default)]
+pub struct Observers {
+    observers: Vec<Box<dyn SchedulerObserver>>,
+    seen: HashMap<(i32, u32), i32>,
+}
+
+impl Observers {
+    pub fn register(&mut self, observer: Box<dyn SchedulerObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+
+    #[inline]
+    pub fn emit(&mut self, kind: EventKind, task: &Task, at: u64, reason: &'static str) {
+        if self.observers.is_empty() {
+            return;
+        }
+        self.notify(kind, &SchedEvent::of(task, at, reason));
+    }
+
+    fn notify(&mut self, kind: EventKind, event: &SchedEvent) {
+        for observer in &mut self.observers {
+            observer.on_event(kind, event);
+        }
+    }
+
+    // a demotion or a boost when the job's rank moved since it was last seen
+    #[inline]
+    pub fn watch_rank(&mut self, task: &Task, at: u64, reason: &'static str) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let rank = task.get_rank();
+        let Some(old) = self.seen.insert((task.get_id(), task.get_job()), rank) else {
+            return;
+        };
+        let kind = match rank.cmp(&old) {
+            std::cmp::Ordering::Less => EventKind::Demoted,
+            std::cmp::Ordering::Greater => EventKind::Boosted,
+            std::cmp::Ordering::Equal => return,
+        };
+        self.notify(
+            kind,
+            &SchedEvent {
+                old_rank: old,
+                ..SchedEvent::of(task, at, reason)
+            },
+        );
+    }
+
+    // the job's deadline passed at `at`, at the rank it was last seen at or `rank`
+    pub fn deadline_missed(&mut self, task: i32, job: u32, rank: i32, at: u64) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let rank = self.seen.get(&(task, job)).copied().unwrap_or(rank);
+        self.notify(
+            EventKind::DeadlineMissed,
+            &SchedEvent {
+                task,
+                job,
+                old_rank: rank,
+                new_rank: rank,
+                at,
+                reason: "its deadline passed before it completed",
+            },
+        );
+    }
+
+    // the job is done, its rank is not watched any more
+    pub fn forget(&mut self, task: &Task) {
+        self.seen.remove(&(task.get_id(), task.get_job()));
+    }
+}
+
+// keeps every event it is told about, clones share the same log
+#[derive(Debug, Clone, Default)]
+pub struct EventLog {
+    events: Arc<Mutex<Vec<(EventKind, SchedEvent)>>>,
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<(EventKind, SchedEvent)> {
+        self.events.lock().unwrap().clone()
+    }
+
+    pub fn count(&self, kind: EventKind) -> usize {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(k, _)| *k == kind)
+            .count()
+    }
+}
+
+impl SchedulerObserver for EventLog {
+    fn on_event(&mut self, kind: EventKind, event: &SchedEvent) {
+        self.events.lock().unwrap().push((kind, event.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::by_name;
+    use crate::sim::workload::Workload;
+    use crate::sim::Simulator;
+
+    #[test]
+    fn every_observer_sees_the_whole_run() {
+        // two cpu hogs sink down the mlfq levels, the short job misses its
+        // deadline behind them
+        let workload = Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 80},
+                {"id": 2, "burst": 80},
+                {"id": 3, "burst": 5, "arrival": 1, "deadline": 3,
+                 "io": {"every": 2, "duration": 3}}
+            ]}"#,
+        )
+        .unwrap();
+        let (first, second) = (EventLog::new(), EventLog::new());
+        let report = Simulator::new(by_name("mlfq").unwrap())
+            .with_observer(Box::new(first.clone()))
+            .with_observer(Box::new(second.clone()))
+            .run(&workload);
+        assert_eq!(first.events(), second.events());
+        let counts: Vec<(&str, usize)> = [
+            EventKind::Admitted,
+            EventKind::Dispatched,
+            EventKind::Preempted,
+            EventKind::Demoted,
+            EventKind::Boosted,
+            EventKind::Blocked,
+            EventKind::Woken,
+            EventKind::Completed,
+            EventKind::DeadlineMissed,
+        ]
+        .iter()
+        .map(|kind| (kind.name(), first.count(*kind)))
+        .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("admitted", 3),
+                ("dispatched", 34),
+                ("preempted", 29),
+                ("demoted", 9),
+                ("boosted", 2),
+                ("blocked", 2),
+                ("woken", 2),
+                ("completed", 3),
+                ("deadline_missed", 1),
+            ]
+        );
+        let events = first.events();
+        // the boost at 100 lifts the hogs back to the top
+        let boost = events
+            .iter()
+            .find(|(kind, _)| *kind == EventKind::Boosted)
+            .unwrap();
+        assert_eq!(
+            (boost.1.old_rank, boost.1.new_rank, boost.1.at),
+            (0, 2, 100)
+        );
+        let missed = events
+            .iter()
+            .find(|(kind, _)| *kind == EventKind::DeadlineMissed)
+            .unwrap();
+        assert_eq!((missed.1.task, missed.1.at), (3, 4));
+        assert!(report.jobs[2].missed);
+
+        // without observers the run is the same
+        let quiet = Simulator::new(by_name("mlfq").unwrap()).run(&workload);
+        assert_eq!(quiet.jobs, report.jobs);
+    }
+
+    #[test]
+    fn a_boost_while_queued_is_reported_when_it_happens() {
+        let workload = Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 150},
+                {"id": 2, "burst": 150}
+            ]}"#,
+        )
+        .unwrap();
+        let log = EventLog::new();
+        Simulator::new(by_name("mlfq").unwrap())
+            .with_observer(Box::new(log.clone()))
+            .run(&workload);
+        // the waiting hog and the preempted one are both lifted when the boost happens
+        let boosts: Vec<(i32, u64, &str)> = log
+            .events()
+            .iter()
+            .filter(|(kind, _)| *kind == EventKind::Boosted)
+            .map(|(_, event)| (event.task, event.at, event.reason))
+            .collect();
+        let queued = "the policy changed its rank while it was queued";
+        assert_eq!(
+            boosts,
+            vec![
+                (1, 100, queued),
+                (2, 100, queued),
+                (1, 200, queued),
+                (2, 200, queued)
+            ]
+        );
+    }
+}
@@ -0,0 +1,675 @@
+use super::{Enqueue, Policy, PolicyRecords, Tick};
+use crate::datastructure::Task;
+use crate::sim::workload::Workload;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// what gives way when the released jobs cannot all meet their deadlines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    // firm tasks skip jobs their (m,k) constraint can spare
+    Skip,
+    // elastic tasks stretch their periods
+    Elastic,
+    // the tasks with the lowest value density are shed
+    Shed,
+}
+
+pub const STRATEGY_NAMES: &[&str] = &["skip", "elastic", "shed"];
+
+impl Strategy {
+    pub fn by_name(name: &str) -> Option<Strategy> {
+        match name {
+            "skip" => Some(Strategy::Skip),
+            "elastic" => Some(Strategy::Elastic),
+            "shed" => Some(Strategy::Shed),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::Skip => "skip",
+            Strategy::Elastic => "elastic",
+            Strategy::Shed => "shed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    // the job was dropped
+    Skip,
+    // the task runs at a longer period from now on
+    Stretch,
+    // the task is back at its own period
+    Restore,
+    // the task's jobs are dropped until the overload is over
+    Shed,
+    // a shed task releases jobs again
+    Readmit,
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Skip => "skip",
+            Action::Stretch => "stretch",
+            Action::Restore => "restore",
+            Action::Shed => "shed",
+            Action::Readmit => "readmit",
+        }
+    }
+}
+
+// a decision of the overload strategy and why it was taken
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OverloadEvent {
+    pub at: u64,
+    pub task: i32,
+    // the dropped job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub job: Option<u32>,
+    pub action: Action,
+    // the period the task runs at from now on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+    pub reason: String,
+}
+
+// a released job with a deadline that has not completed
+#[derive(Debug, Clone, Copy)]
+struct Active {
+    deadline: u64,
+    remaining: u64,
+    periodic: bool,
+}
+
+struct Firm {
+    m: u32,
+    k: u32,
+    // whether each of the last k jobs met its deadline, oldest first
+    history: VecDeque<bool>,
+}
+
+impl Firm {
+    fn record(&mut self, met: bool) {
+        self.history.push_back(met);
+        if self.history.len() > self.k as usize {
+            self.history.pop_front();
+        }
+    }
+
+    // deadlines met by the last k - 1 jobs, jobs before the first one count as met
+    fn met(&self) -> u32 {
+        let window = self.k as usize - 1;
+        let known = self.history.iter().rev().take(window);
+        let missed = known.filter(|met| !**met).count();
+        (window - missed) as u32
+    }
+
+    // the window of the last k - 1 jobs and a skipped one still holds m met deadlines
+    fn spare(&self) -> Option<u32> {
+        self.met().checked_sub(self.m)
+    }
+}
+
+struct Elastic {
+    id: i32,
+    wcet: u64,
+    period: u64,
+    max_period: u64,
+    elasticity: u32,
+    current: u64,
+    last_release: Option<u64>,
+}
+
+/*
+ * Overload management on top of a real-time policy. Whenever a job arrives
+ * the wrapper checks whether the released jobs can still meet their
+ * deadlines, in edf order: for every deadline d the work due by d has to fit
+ * into the ticks left until d. Jobs already past their deadline only add
+ * work. When it does not fit the strategy steps in:
+ * - skip drops ready jobs of firm tasks, the one whose (m,k) window has the
+ *   most met deadlines to spare first, until the work fits
+ * - elastic compresses the utilization of the elastic tasks into what the
+ *   other tasks leave (Buttazzo's elastic model), a stretched task only
+ *   releases a job once its stretched period has passed since the last one
+ * - shed drops every ready job of the task with the lowest value per tick of
+ *   burst until the work fits, shed tasks release no jobs
+ * The overload is over at the first instant with nothing left to run, the
+ * periods shrink back and the shed tasks are readmitted.
+ * Every decision is recorded with its reason, a dropped job is skipped in the
+ * simulation's records instead of missed.
+*/
+pub struct Overload {
+    inner: Box<dyn Policy>,
+    strategy: Strategy,
+    active: HashMap<(i32, u32), Active>,
+    // the active jobs the inner policy holds
+    ready: HashSet<(i32, u32)>,
+    firm: HashMap<i32, Firm>,
+    elastic: Vec<Elastic>,
+    // utilization of the periodic tasks that are not elastic
+    rigid: f64,
+    // value per tick of burst
+    density: HashMap<i32, f64>,
+    shed: HashSet<i32>,
+    events: Vec<OverloadEvent>,
+}
+
+impl Overload {
+    pub fn new(inner: Box<dyn Policy>, workload: &Workload, strategy: Strategy) -> Self {
+        let mut overload = Overload {
+            inner,
+            strategy,
+            active: HashMap::new(),
+            ready: HashSet::new(),
+            firm: HashMap::new(),
+            elastic: Vec::new(),
+            rigid: 0.0,
+            density: HashMap::new(),
+            shed: HashSet::new(),
+            events: Vec::new(),
+        };
+        for spec in &workload.tasks {
+            if let Some(firm) = spec.firm {
+                overload.firm.insert(
+                    spec.id,
+                    Firm {
+                        m: firm.m,
+                        k: firm.k,
+                        history: VecDeque::new(),
+                    },
+                );
+            }
+            if let Some(value) = spec.value {
+                overload
+                    .density
+                    .insert(spec.id, value as f64 / spec.burst as f64);
+            }
+            match (spec.period, spec.elastic) {
+                (Some(period), Some(elastic)) => overload.elastic.push(Elastic {
+                    id: spec.id,
+                    wcet: spec.burst,
+                    period,
+                    max_period: elastic.max_period,
+                    elasticity: elastic.elasticity,
+                    current: period,
+                    last_release: None,
+                }),
+                (Some(period), None) => overload.rigid += spec.burst as f64 / period as f64,
+                _ => {}
+            }
+        }
+        overload
+    }
+
+    pub fn events(&self) -> &[OverloadEvent] {
+        &self.events
+    }
+
+    // the period the task releases jobs at right now
+    pub fn period(&self, id: i32) -> Option<u64> {
+        self.elastic.iter().find(|e| e.id == id).map(|e| e.current)
+    }
+
+    // why the active jobs cannot all meet their deadlines, None when they can
+    fn overload(&self, now: u64) -> Option<String> {
+        let mut jobs: Vec<Active> = self.active.values().copied().collect();
+        jobs.sort_by_key(|job| job.deadline);
+        let mut due = 0;
+        for job in jobs {
+            due += job.remaining;
+            if job.deadline > now && now + due > job.deadline {
+                return Some(format!(
+                    "{due} ticks of work are due in the {} ticks up to {}",
+                    job.deadline - now,
+                    job.deadline
+                ));
+            }
+        }
+        None
+    }
+
+    fn event(&mut self, now: u64, task: i32, job: Option<u32>, action: Action, reason: String) {
+        let period = match action {
+            Action::Stretch | Action::Restore => self.period(task),
+            _ => None,
+        };
+        self.events.push(OverloadEvent {
+            at: now,
+            task,
+            job,
+            action,
+            period,
+            reason,
+        });
+    }
+
+    // takes the ready jobs of a task out of the inner policy, `keep` are handed back
+    fn take(&mut self, id: i32, now: u64, keep: impl Fn(&Task) -> bool) -> Vec<Task> {
+        let (kept, taken): (Vec<Task>, Vec<Task>) =
+            self.inner.remove(id).into_iter().partition(keep);
+        for job in kept {
+            self.inner.enqueue(job, now, Enqueue::Preempted);
+        }
+        for job in &taken {
+            let key = (job.get_id(), job.get_job());
+            self.active.remove(&key);
+            self.ready.remove(&key);
+        }
+        taken
+    }
+
+    fn handle(&mut self, now: u64) {
+        while let Some(reason) = self.overload(now) {
+            let handled = match self.strategy {
+                Strategy::Skip => self.skip(now, &reason),
+                Strategy::Shed => self.shed(now, &reason),
+                // a stretch only takes effect at the next releases
+                Strategy::Elastic => {
+                    self.stretch(now, &reason);
+                    false
+                }
+            };
+            if !handled {
+                break;
+            }
+        }
+    }
+
+    fn skip(&mut self, now: u64, reason: &str) -> bool {
+        let candidate = self
+            .ready
+            .iter()
+            .filter_map(|&(id, job)| {
+                let spare = self.firm.get(&id)?.spare()?;
+                Some((spare, self.active[&(id, job)].deadline, id, job))
+            })
+            .max();
+        let Some((_, _, id, job)) = candidate else {
+            return false;
+        };
+        self.take(id, now, |task| task.get_job() != job);
+        let firm = self.firm.get_mut(&id).expect("only firm tasks skip");
+        let (met, m, k) = (firm.met(), firm.m, firm.k);
+        firm.record(false);
+        self.event(
+            now,
+            id,
+            Some(job),
+            Action::Skip,
+            format!(
+                "{reason}, its last {} jobs met {met} deadlines and {m} of {k} are needed",
+                k - 1
+            ),
+        );
+        true
+    }
+
+    fn shed(&mut self, now: u64, reason: &str) -> bool {
+        let candidate = self
+            .ready
+            .iter()
+            .filter_map(|&(id, _)| Some((self.density.get(&id).copied()?, id)))
+            .min_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+        let Some((density, id)) = candidate else {
+            return false;
+        };
+        self.shed.insert(id);
+        let mut dropped = self.take(id, now, |_| false);
+        dropped.sort_by_key(Task::get_job);
+        for job in dropped {
+            self.event(
+                now,
+                id,
+                Some(job.get_job()),
+                Action::Shed,
+                format!("{reason}, its value density {density:.3} is the lowest"),
+            );
+        }
+        true
+    }
+
+    fn stretch(&mut self, now: u64, reason: &str) {
+        // aperiodic jobs need their remaining work before their deadlines
+        let aperiodic: f64 = self
+            .active
+            .values()
+            .filter(|job| !job.periodic && job.deadline > now)
+            .map(|job| job.remaining as f64 / (job.deadline - now) as f64)
+            .sum();
+        let left = 1.0 - self.rigid - aperiodic;
+        let utilizations = compress(&self.elastic, left);
+        let mut stretched = Vec::new();
+        for (elastic, utilization) in self.elastic.iter_mut().zip(utilizations) {
+            let period = ((elastic.wcet as f64 / utilization).ceil() as u64)
+                .clamp(elastic.period, elastic.max_period);
+            if period > elastic.current {
+                elastic.current = period;
+                stretched.push(elastic.id);
+            }
+        }
+        for id in stretched {
+            self.event(
+                now,
+                id,
+                None,
+                Action::Stretch,
+                format!("{reason}, the elastic tasks have {left:.3} of the cpu left"),
+            );
+        }
+    }
+
+    // the overload is over, every task gets its own period back
+    fn restore(&mut self, now: u64) {
+        let mut restored = Vec::new();
+        for elastic in &mut self.elastic {
+            if elastic.current != elastic.period {
+                elastic.current = elastic.period;
+                restored.push(elastic.id);
+            }
+        }
+        let mut shed: Vec<i32> = self.shed.drain().collect();
+        shed.sort_unstable();
+        for id in restored {
+            self.event(
+                now,
+                id,
+                None,
+                Action::Restore,
+                "nothing is left to run".into(),
+            );
+        }
+        for id in shed {
+            self.event(
+                now,
+                id,
+                None,
+                Action::Readmit,
+                "nothing is left to run".into(),
+            );
+        }
+    }
+
+    // a job released before the stretched period passed, or of a shed task, is dropped
+    fn drop_release(&mut self, task: &Task, now: u64) -> bool {
+        let id = task.get_id();
+        if self.shed.contains(&id) {
+            self.event(
+                now,
+                id,
+                Some(task.get_job()),
+                Action::Shed,
+                "the task is shed until nothing is left to run".into(),
+            );
+            return true;
+        }
+        let Some(elastic) = self.elastic.iter_mut().find(|e| e.id == id) else {
+            return false;
+        };
+        let release = task.get_arrival();
+        if let Some(last) = elastic
+            .last_release
+            .filter(|last| release < last + elastic.current)
+        {
+            let current = elastic.current;
+            self.event(
+                now,
+                id,
+                Some(task.get_job()),
+                Action::Skip,
+                format!(
+                    "released {} ticks after its last job, its period is stretched to {current}",
+                    release - last
+                ),
+            );
+            return true;
+        }
+        elastic.last_release = Some(release);
+        false
+    }
+}
+
+/*
+ * Buttazzo's elastic model: the elastic tasks give up utilization in
+ * proportion to their elasticity until they fit into `left`. A task that
+ * would go below its minimum, at its longest period, stays there and the
+ * others give up the rest.
+*/
+fn compress(tasks: &[Elastic], left: f64) -> Vec<f64> {
+    let nominal: Vec<f64> = tasks
+        .iter()
+        .map(|t| t.wcet as f64 / t.period as f64)
+        .collect();
+    let least: Vec<f64> = tasks
+        .iter()
+        .map(|t| t.wcet as f64 / t.max_period as f64)
+        .collect();
+    let mut utilization = nominal.clone();
+    let mut fixed = vec![false; tasks.len()];
+    loop {
+        let (mut free, mut taken, mut elasticity) = (0.0, 0.0, 0.0);
+        for i in 0..tasks.len() {
+            if fixed[i] {
+                taken += utilization[i];
+            } else {
+                free += nominal[i];
+                elasticity += tasks[i].elasticity as f64;
+            }
+        }
+        let excess = free + taken - left;
+        if excess <= 0.0 || elasticity == 0.0 {
+            return utilization;
+        }
+        let mut clamped = false;
+        for i in 0..tasks.len() {
+            if fixed[i] {
+                continue;
+            }
+            let u = nominal[i] - excess * tasks[i].elasticity as f64 / elasticity;
+            if u <= least[i] {
+                utilization[i] = least[i];
+                fixed[i] = true;
+                clamped = true;
+            } else {
+                utilization[i] = u;
+            }
+        }
+        if !clamped {
+            return utilization;
+        }
+    }
+}
+
+impl Policy for Overload {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn enqueue(&mut self, task: Task, now: u64, why: Enqueue) {
+        if why == Enqueue::Arrival && self.drop_release(&task, now) {
+            return;
+        }
+        let key = (task.get_id(), task.get_job());
+        if let Some(deadline) = task.get_abs_deadline() {
+            self.active.entry(key).or_insert(Active {
+                deadline,
+                remaining: task.get_remaining(),
+                periodic: task.get_period().is_some(),
+            });
+            self.ready.insert(key);
+        }
+        self.inner.enqueue(task, now, why);
+        if why == Enqueue::Arrival {
+            self.handle(now);
+        }
+    }
+
+    fn pick_next(&mut self, now: u64) -> Option<Task> {
+        let task = self.inner.pick_next(now);
+        match &task {
+            Some(task) => {
+                self.ready.remove(&(task.get_id(), task.get_job()));
+            }
+            None => self.restore(now),
+        }
+        task
+    }
+
+    fn tick(&mut self, running: &mut Task, now: u64) -> Tick {
+        if let Some(active) = self.active.get_mut(&(running.get_id(), running.get_job())) {
+            active.remaining = running.get_remaining();
+        }
+        self.inner.tick(running, now)
+    }
+
+    fn should_preempt(&self, running: &Task, now: u64) -> bool {
+        self.inner.should_preempt(running, now)
+    }
+
+    fn on_block(&mut self, task: &mut Task, now: u64) {
+        self.inner.on_block(task, now);
+    }
+
+    fn on_complete(&mut self, task: &Task, now: u64) {
+        let key = (task.get_id(), task.get_job());
+        if let Some(active) = self.active.remove(&key) {
+            if let Some(firm) = self.firm.get_mut(&key.0) {
+                firm.record(now <= active.deadline);
+            }
+        }
+        self.inner.on_complete(task, now);
+    }
+
+    fn on_clock(&mut self, now: u64) {
+        self.inner.on_clock(now);
+    }
+
+    fn rank_changes(&mut self) -> Vec<Task> {
+        self.inner.rank_changes()
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        let removed = self.inner.remove(id);
+        for job in &removed {
+            self.ready.remove(&(job.get_id(), job.get_job()));
+        }
+        removed
+    }
+
+    fn ready_count(&self) -> usize {
+        self.inner.ready_count()
+    }
+
+    fn records(&self) -> PolicyRecords {
+        PolicyRecords {
+            overloads: self.events.clone(),
+            ..self.inner.records()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::edf::Edf;
+    use crate::sim::{SimReport, Simulator};
+
+    // the aperiodic task 3 overloads the cpu at 10
+    fn workload() -> Workload {
+        Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 2, "period": 5, "firm": {"m": 1, "k": 2}, "value": 1},
+                {"id": 2, "burst": 3, "period": 10, "elastic": {"max_period": 30}, "value": 5},
+                {"id": 3, "burst": 6, "arrival": 10, "deadline": 8, "value": 100}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn run(strategy: Option<Strategy>) -> SimReport {
+        let workload = workload();
+        let policy: Box<dyn Policy> = match strategy {
+            Some(strategy) => Box::new(Overload::new(Box::new(Edf::new()), &workload, strategy)),
+            None => Box::new(Edf::new()),
+        };
+        Simulator::new(policy).with_horizon(Some(40)).run(&workload)
+    }
+
+    fn actions(report: &SimReport) -> Vec<(u64, i32, Action)> {
+        report
+            .overloads
+            .iter()
+            .map(|event| (event.at, event.task, event.action))
+            .collect()
+    }
+
+    #[test]
+    fn strategies_give_way_under_overload() {
+        assert_eq!(run(None).metrics.deadline_misses, 2);
+
+        // 1 can spare one job in two, once
+        let report = run(Some(Strategy::Skip));
+        assert_eq!(actions(&report), vec![(10, 1, Action::Skip)]);
+        assert_eq!(report.overloads[0].job, Some(2));
+        assert_eq!(
+            (report.metrics.skipped, report.metrics.deadline_misses),
+            (1, 1)
+        );
+
+        // 1 has the lowest value per tick and is shed until the cpu idles
+        let report = run(Some(Strategy::Shed));
+        assert_eq!(
+            actions(&report),
+            vec![
+                (10, 1, Action::Shed),
+                (15, 1, Action::Shed),
+                (19, 1, Action::Readmit)
+            ]
+        );
+        assert!(report.overloads[0].reason.starts_with(
+            "11 ticks of work are due in the 10 ticks up to 20, its value density 0.500"
+        ));
+        assert_eq!(report.metrics.deadline_misses, 0);
+
+        // 2 stretches as far as it can, skips its release at 20 and shrinks back
+        let report = run(Some(Strategy::Elastic));
+        assert_eq!(
+            actions(&report),
+            vec![
+                (10, 2, Action::Stretch),
+                (20, 2, Action::Skip),
+                (27, 2, Action::Restore)
+            ]
+        );
+        assert_eq!(report.overloads[0].period, Some(30));
+        assert_eq!(report.overloads[2].period, Some(10));
+    }
+
+    #[test]
+    fn elastic_tasks_give_up_utilization_by_elasticity() {
+        let elastic = |id, wcet, period, max_period, elasticity| Elastic {
+            id,
+            wcet,
+            period,
+            max_period,
+            elasticity,
+            current: period,
+            last_release: None,
+        };
+        // 0.5 + 0.5 into 0.7, 2 gives up twice as much as 1
+        let tasks = [elastic(1, 5, 10, 100, 1), elastic(2, 5, 10, 100, 2)];
+        let u = compress(&tasks, 0.7);
+        assert!((u[0] - 0.4).abs() < 1e-9 && (u[1] - 0.3).abs() < 1e-9);
+        // 2 cannot go below 5 / 12, 1 gives up the rest
+        let tasks = [elastic(1, 5, 10, 100, 1), elastic(2, 5, 10, 12, 2)];
+        let u = compress(&tasks, 0.7);
+        assert!((u[1] - 5.0 / 12.0).abs() < 1e-9);
+        assert!((u[0] + u[1] - 0.7).abs() < 1e-9);
+        // enough room, nothing changes
+        assert_eq!(compress(&tasks, 1.0), vec![0.5, 0.5]);
+    }
+}
@@ -0,0 +1,246 @@
+use super::{Enqueue, Policy, PolicyRecords, Tick};
+use crate::datastructure::Task;
+use crate::sim::workload::Workload;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/*
+ * Hierarchical scheduling over periodic resource reservations. Every
+ * partition holds a budget of Θ ticks that is refilled at every multiple of
+ * its period Π, and orders its own tasks with its own policy. The top level
+ * runs the partition with budget left and ready work whose period ends
+ * first, a partition that used up its budget waits for the next period even
+ * when the cpu would be idle, so a partition never gets more than its
+ * reservation and the others never get less.
+ * The running task is preempted when its partition runs dry or another
+ * partition with an earlier period end has work, the partition's own policy
+ * sees that as an ordinary preemption.
+*/
+pub struct Partitioned {
+    partitions: Vec<Partition>,
+    // partition of every task, unknown tasks go to the first one
+    member: HashMap<i32, usize>,
+}
+
+struct Partition {
+    name: String,
+    budget: u64,
+    period: u64,
+    left: u64,
+    used: u64,
+    policy: Box<dyn Policy>,
+}
+
+// what a partition got out of its reservation over a run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PartitionRecord {
+    pub name: String,
+    pub policy: String,
+    pub budget: u64,
+    pub period: u64,
+    // ticks its tasks ran
+    pub used: u64,
+}
+
+impl Partition {
+    fn period_end(&self, now: u64) -> u64 {
+        (now / self.period + 1) * self.period
+    }
+
+    fn can_run(&self) -> bool {
+        self.left > 0 && self.policy.ready_count() > 0
+    }
+}
+
+impl Partitioned {
+    // a single partition that owns the whole cpu
+    pub fn new(policy: Box<dyn Policy>) -> Self {
+        Partitioned {
+            partitions: vec![Partition {
+                name: "default".to_string(),
+                budget: 1,
+                period: 1,
+                left: 1,
+                used: 0,
+                policy,
+            }],
+            member: HashMap::new(),
+        }
+    }
+
+    // the partitions of a validated workload, `policy` builds each one's policy by name
+    pub fn from_workload(workload: &Workload, policy: impl Fn(&str) -> Box<dyn Policy>) -> Self {
+        if workload.partitions.is_empty() {
+            return Self::new(policy("mlfq"));
+        }
+        let partitions: Vec<Partition> = workload
+            .partitions
+            .iter()
+            .map(|spec| Partition {
+                name: spec.name.clone(),
+                budget: spec.budget,
+                period: spec.period,
+                left: spec.budget,
+                used: 0,
+                policy: policy(&spec.policy),
+            })
+            .collect();
+        let member = workload
+            .tasks
+            .iter()
+            .filter_map(|spec| {
+                let name = spec.partition.as_ref()?;
+                Some((spec.id, partitions.iter().position(|p| &p.name == name)?))
+            })
+            .collect();
+        Partitioned { partitions, member }
+    }
+
+    fn partition_of(&self, task: &Task) -> usize {
+        self.member.get(&task.get_id()).copied().unwrap_or(0)
+    }
+
+    // the partition the top level runs next, earliest period end first
+    fn choose(&self, now: u64) -> Option<usize> {
+        (0..self.partitions.len())
+            .filter(|&at| self.partitions[at].can_run())
+            .min_by_key(|&at| (self.partitions[at].period_end(now), at))
+    }
+}
+
+impl Policy for Partitioned {
+    fn name(&self) -> &str {
+        "partitioned"
+    }
+
+    fn enqueue(&mut self, task: Task, now: u64, why: Enqueue) {
+        let at = self.partition_of(&task);
+        self.partitions[at].policy.enqueue(task, now, why);
+    }
+
+    fn pick_next(&mut self, now: u64) -> Option<Task> {
+        let at = self.choose(now)?;
+        self.partitions[at].policy.pick_next(now)
+    }
+
+    fn tick(&mut self, running: &mut Task, now: u64) -> Tick {
+        let at = self.partition_of(running);
+        let partition = &mut self.partitions[at];
+        partition.left = partition.left.saturating_sub(1);
+        partition.used += 1;
+        partition.policy.tick(running, now)
+    }
+
+    fn should_preempt(&self, running: &Task, now: u64) -> bool {
+        let at = self.partition_of(running);
+        let partition = &self.partitions[at];
+        if partition.left == 0 {
+            return true;
+        }
+        let earlier = self.choose(now).is_some_and(|other| {
+            other != at && self.partitions[other].period_end(now) < partition.period_end(now)
+        });
+        earlier || partition.policy.should_preempt(running, now)
+    }
+
+    fn on_block(&mut self, task: &mut Task, now: u64) {
+        let at = self.partition_of(task);
+        self.partitions[at].policy.on_block(task, now);
+    }
+
+    fn on_complete(&mut self, task: &Task, now: u64) {
+        let at = self.partition_of(task);
+        self.partitions[at].policy.on_complete(task, now);
+    }
+
+    fn on_clock(&mut self, now: u64) {
+        for partition in &mut self.partitions {
+            if now.is_multiple_of(partition.period) {
+                partition.left = partition.budget;
+            }
+            partition.policy.on_clock(now);
+        }
+    }
+
+    fn rank_changes(&mut self) -> Vec<Task> {
+        self.partitions
+            .iter_mut()
+            .flat_map(|partition| partition.policy.rank_changes())
+            .collect()
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        let at = self.partition_of(&Task::new(id, 0, 0));
+        self.partitions[at].policy.remove(id)
+    }
+
+    fn ready_count(&self) -> usize {
+        self.partitions.iter().map(|p| p.policy.ready_count()).sum()
+    }
+
+    fn records(&self) -> PolicyRecords {
+        let partitions = self
+            .partitions
+            .iter()
+            .map(|p| PartitionRecord {
+                name: p.name.clone(),
+                policy: p.policy.name().to_string(),
+                budget: p.budget,
+                period: p.period,
+                used: p.used,
+            })
+            .collect();
+        PolicyRecords {
+            partitions,
+            ..PolicyRecords::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis;
+    use crate::scheduler::by_name;
+    use crate::sim::Simulator;
+
+    #[test]
+    fn partitions_get_their_reservation_and_no_more() {
+        // a greedy round robin partition next to a periodic edf one
+        let workload = Workload::parse(
+            r#"{"partitions": [
+                    {"name": "batch", "policy": "rr", "budget": 1, "period": 5},
+                    {"name": "control", "policy": "edf", "budget": 4, "period": 5}],
+                "tasks": [
+                {"id": 1, "burst": 2, "period": 10, "partition": "control"},
+                {"id": 2, "burst": 3, "period": 10, "partition": "control"},
+                {"id": 3, "burst": 200, "partition": "batch"},
+                {"id": 4, "burst": 200, "partition": "batch"}
+            ]}"#,
+        )
+        .unwrap();
+        let results = analysis::partitions(&workload);
+        assert_eq!(results[1].schedulable, Some(true));
+        assert_eq!(results[1].min_budget, Some(4));
+
+        let policy = Partitioned::from_workload(&workload, |name| by_name(name).unwrap());
+        let report = Simulator::new(Box::new(policy))
+            .with_horizon(Some(100))
+            .run(&workload);
+        assert!(report.jobs.iter().all(|job| !job.missed));
+        // the cpu idles for what control leaves over rather than handing
+        // it to batch
+        let used: Vec<u64> = report.partitions.iter().map(|p| p.used).collect();
+        assert_eq!(used, vec![20, 50]);
+        for window in (0..100).step_by(5) {
+            let batch: u64 = report
+                .trace
+                .slices
+                .iter()
+                .filter(|s| matches!(s.task, Some(3 | 4)))
+                .map(|s| s.end.min(window + 5).saturating_sub(s.start.max(window)))
+                .sum();
+            assert!(batch <= 1, "{window}");
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use super::{Enqueue, Policy};
+use crate::datastructure::avl::AvlTree;
+use crate::datastructure::Task;
+
+/*
+ * Preemptive fixed priority, the highest rank in the tree always runs and a
+ * higher rank arrival takes the cpu from the running task straight away.
+ * Rate monotonic is the same policy with the rank taken from the period,
+ * the shorter the period the higher the rank.
+*/
+#[derive(Debug)]
+pub struct FixedPriority {
+    ready: AvlTree,
+    rate_monotonic: bool,
+}
+
+impl Default for FixedPriority {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FixedPriority {
+    pub fn new() -> Self {
+        FixedPriority {
+            ready: AvlTree::new(),
+            rate_monotonic: false,
+        }
+    }
+
+    pub fn rate_monotonic() -> Self {
+        FixedPriority {
+            ready: AvlTree::new(),
+            rate_monotonic: true,
+        }
+    }
+}
+
+// aperiodic tasks go below every periodic one under rate monotonic
+pub fn rate_monotonic_rank(task: &Task) -> i32 {
+    match task.get_period() {
+        Some(period) => -(period.min(i32::MAX as u64) as i32),
+        None => i32::MIN,
+    }
+}
+
+impl Policy for FixedPriority {
+    fn name(&self) -> &str {
+        if self.rate_monotonic {
+            "rm"
+        } else {
+            "priority"
+        }
+    }
+
+    fn enqueue(&mut self, mut task: Task, _now: u64, why: Enqueue) {
+        if self.rate_monotonic && why == Enqueue::Arrival {
+            task.set_rank(rate_monotonic_rank(&task));
+        }
+        self.ready.insert(task);
+    }
+
+    fn pick_next(&mut self, _now: u64) -> Option<Task> {
+        self.ready.pop_highest()
+    }
+
+    fn should_preempt(&self, running: &Task, _now: u64) -> bool {
+        self.ready
+            .highest_rank()
+            .is_some_and(|rank| rank > running.get_rank())
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        self.ready.delete_by_id(id)
+    }
+
+    fn ready_count(&self) -> usize {
+        self.ready.task_count()
+    }
+}
@@ -0,0 +1,928 @@
+use super::burst::DEFAULT_ALPHA;
+use super::lottery::DEFAULT_SEED;
+use super::mlfq::config::MlfqConfig;
+use super::{Enqueue, Policy, PolicyRecords, Tick};
+use crate::datastructure::{Affinity, BurstEstimate, Criticality, Task};
+use crate::sim::workload::Workload;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/*
+ * Binary traces of everything a policy is told and everything it decides,
+ * and a replayer that feeds a trace back into a policy and checks that it
+ * decides the same.
+ *
+ * A trace starts with MAGIC, the format version, the policy's name, the
+ * settings it was built with and the workload, both as json, then one step
+ * per call into the policy until the end:
+ * a tag byte, the ticks since the previous step and the call's arguments
+ * followed by what the policy answered. Integers are LEB128 varints, signed
+ * ones zigzag encoded first. A job is handed over in full when it arrives,
+ * after that it is named by its task and job number together with its rank
+ * and the work it has left, so changes the simulator made outside the policy
+ * (ticks run, ranks raised by a resource protocol) are replayed too.
+*/
+pub const MAGIC: &[u8; 4] = b"MLFT";
+pub const VERSION: u8 = 2;
+
+const CLOCK: u8 = 0;
+const ARRIVE: u8 = 1;
+const WAKE: u8 = 2;
+const PREEMPTED: u8 = 3;
+const EXPIRED: u8 = 4;
+const PICK: u8 = 5;
+const TICK: u8 = 6;
+const PREEMPT: u8 = 7;
+const BLOCK: u8 = 8;
+const COMPLETE: u8 = 9;
+const REMOVE: u8 = 10;
+
+/*
+ * What the recorded policy was built with besides its name and the workload,
+ * so the same policy can be built again for the replay. The names are those
+ * of DEGRADATION_NAMES and STRATEGY_NAMES, the wrappers that take them are
+ * only built where they apply.
+*/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    // None for the built in mlfq levels
+    pub mlfq: Option<MlfqConfig>,
+    pub seed: u64,
+    pub alpha: f64,
+    pub hi_mode: String,
+    pub overload: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            mlfq: None,
+            seed: DEFAULT_SEED,
+            alpha: DEFAULT_ALPHA,
+            hi_mode: "drop".to_string(),
+            overload: None,
+        }
+    }
+}
+
+// a job as the simulator holds it when it hands it to the policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobState {
+    pub task: i32,
+    pub job: u32,
+    pub rank: i32,
+    pub remaining: u64,
+}
+
+impl JobState {
+    fn of(task: &Task) -> Self {
+        JobState {
+            task: task.get_id(),
+            job: task.get_job(),
+            rank: task.get_rank(),
+            remaining: task.get_remaining(),
+        }
+    }
+
+    fn key(&self) -> (i32, u32) {
+        (self.task, self.job)
+    }
+
+    fn name(&self) -> String {
+        format!("task {} job {}", self.task, self.job)
+    }
+}
+
+// one call into the policy, with the answer where it gave one
+#[derive(Debug, Clone, PartialEq)]
+pub enum Call {
+    Clock,
+    // a new job, handed over in full
+    Arrive(Task),
+    Enqueue(JobState, Enqueue),
+    // the job picked and its rank, None when the policy had nothing
+    Pick(Option<(i32, u32, i32)>),
+    // the verdict and the job's rank after the tick
+    Tick(JobState, Tick, i32),
+    Preempt(JobState, bool),
+    // the job blocked on io or yielded, with its rank after
+    Block(JobState, i32),
+    Complete(JobState),
+    // the jobs taken out of the ready set
+    Remove(i32, Vec<(i32, u32)>),
+}
+
+impl Call {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Call::Clock => "clock",
+            Call::Arrive(_) => "arrive",
+            Call::Enqueue(_, Enqueue::Wake) => "wake",
+            Call::Enqueue(_, _) => "enqueue",
+            Call::Pick(_) => "pick",
+            Call::Tick(..) => "tick",
+            Call::Preempt(..) => "preempt",
+            Call::Block(..) => "block",
+            Call::Complete(_) => "complete",
+            Call::Remove(..) => "remove",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub at: u64,
+    pub call: Call,
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    // not a trace at all
+    Magic,
+    Version(u8),
+    // the bytes end inside a step
+    Truncated,
+    Tag(u8),
+    // a value that no recorder writes, out of range or running past the end of time
+    Corrupt,
+    Workload(String),
+    Settings(String),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Magic => write!(f, "not a scheduler trace"),
+            TraceError::Version(version) => {
+                write!(
+                    f,
+                    "trace version {version} is not supported, only {VERSION}"
+                )
+            }
+            TraceError::Truncated => write!(f, "the trace is cut off"),
+            TraceError::Tag(tag) => write!(f, "unknown step {tag} in the trace"),
+            TraceError::Corrupt => write!(f, "the trace is corrupt"),
+            TraceError::Workload(msg) => write!(f, "the trace's workload is invalid: {msg}"),
+            TraceError::Settings(msg) => write!(f, "the trace's settings are invalid: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+// the encoding side, steps are appended as they happen
+#[derive(Debug, Default)]
+struct Writer {
+    bytes: Vec<u8>,
+    last: u64,
+}
+
+impl Writer {
+    fn u64(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    fn i64(&mut self, value: i64) {
+        self.u64(((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    fn str(&mut self, text: &str) {
+        self.u64(text.len() as u64);
+        self.bytes.extend_from_slice(text.as_bytes());
+    }
+
+    // the tag and the time, time never runs backwards but a step may be at
+    // the same tick as the one before
+    fn step(&mut self, tag: u8, at: u64) {
+        self.byte(tag);
+        self.i64(at as i64 - self.last as i64);
+        self.last = at;
+    }
+
+    fn job(&mut self, job: &JobState) {
+        self.i64(job.task as i64);
+        self.u64(job.job as u64);
+        self.i64(job.rank as i64);
+        self.u64(job.remaining);
+    }
+
+    fn task(&mut self, task: &Task) {
+        self.i64(task.get_id() as i64);
+        self.i64(task.get_rank() as i64);
+        self.i64(task.get_state() as i64);
+        self.u64(task.get_job() as u64);
+        self.u64(task.get_arrival());
+        self.u64(task.get_burst());
+        self.u64(task.get_remaining());
+        // an explicit deadline is only kept when it differs from the period
+        let deadline = task
+            .get_deadline()
+            .filter(|&d| Some(d) != task.get_period());
+        let lo = task.get_wcet(Criticality::Lo);
+        let hi = task.get_wcet(Criticality::Hi);
+        let budgets =
+            task.get_criticality() != Criticality::Lo || lo != task.get_burst() || hi != lo;
+        let flags = task.get_period().is_some() as u8
+            | (deadline.is_some() as u8) << 1
+            | (task.get_io().is_some() as u8) << 2
+            | (budgets as u8) << 3
+            | ((task.get_affinity() != Affinity::ALL) as u8) << 4;
+        self.byte(flags);
+        if let Some(period) = task.get_period() {
+            self.u64(period);
+        }
+        if let Some(deadline) = deadline {
+            self.u64(deadline);
+        }
+        if let Some(io) = task.get_io() {
+            self.u64(io.every);
+            self.u64(io.duration);
+        }
+        if budgets {
+            self.byte(task.get_criticality() as u8);
+            self.u64(lo);
+            self.u64(hi);
+        }
+        if task.get_affinity() != Affinity::ALL {
+            self.u64(task.get_affinity().bits());
+        }
+        self.i64(task.get_nice() as i64);
+        self.u64(task.get_vruntime());
+        let estimate = task.get_estimate();
+        self.bytes
+            .extend_from_slice(&estimate.predicted.to_le_bytes());
+        self.u64(estimate.ran);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+    last: u64,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, TraceError> {
+        let byte = *self.bytes.get(self.at).ok_or(TraceError::Truncated)?;
+        self.at += 1;
+        Ok(byte)
+    }
+
+    fn u64(&mut self) -> Result<u64, TraceError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(TraceError::Truncated)
+    }
+
+    fn i64(&mut self) -> Result<i64, TraceError> {
+        let value = self.u64()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn i32(&mut self) -> Result<i32, TraceError> {
+        i32::try_from(self.i64()?).map_err(|_| TraceError::Corrupt)
+    }
+
+    fn u32(&mut self) -> Result<u32, TraceError> {
+        u32::try_from(self.u64()?).map_err(|_| TraceError::Corrupt)
+    }
+
+    fn str(&mut self) -> Result<&str, TraceError> {
+        let len = self.u64()? as usize;
+        let end = self.at.checked_add(len).ok_or(TraceError::Truncated)?;
+        let bytes = self.bytes.get(self.at..end).ok_or(TraceError::Truncated)?;
+        self.at = end;
+        std::str::from_utf8(bytes).map_err(|err| TraceError::Workload(err.to_string()))
+    }
+
+    fn job(&mut self) -> Result<JobState, TraceError> {
+        Ok(JobState {
+            task: self.i32()?,
+            job: self.u32()?,
+            rank: self.i32()?,
+            remaining: self.u64()?,
+        })
+    }
+
+    fn task(&mut self) -> Result<Task, TraceError> {
+        let (id, rank, state) = (self.i32()?, self.i32()?, self.i32()?);
+        let (job, arrival, burst, remaining) = (self.u32()?, self.u64()?, self.u64()?, self.u64()?);
+        let flags = self.byte()?;
+        let mut task = Task::new(id, rank, state).with_burst(burst);
+        if flags & 1 != 0 {
+            task = task.with_period(self.u64()?);
+        }
+        if flags & 2 != 0 {
+            task = task.with_deadline(self.u64()?);
+        }
+        if flags & 4 != 0 {
+            task = task.with_io(self.u64()?, self.u64()?);
+        }
+        if flags & 8 != 0 {
+            let criticality = match self.byte()? {
+                0 => Criticality::Lo,
+                _ => Criticality::Hi,
+            };
+            task = task.with_criticality(criticality, self.u64()?, self.u64()?);
+        }
+        if flags & 16 != 0 {
+            task = task.with_affinity(Affinity::from_bits(self.u64()?));
+        }
+        // release adds the deadline to the arrival
+        if let Some(deadline) = task.get_deadline() {
+            arrival.checked_add(deadline).ok_or(TraceError::Corrupt)?;
+        }
+        let mut task = task.with_nice(self.i32()?).release(job, arrival);
+        task.set_remaining(remaining);
+        task.set_vruntime(self.u64()?);
+        let mut predicted = [0; 8];
+        for byte in &mut predicted {
+            *byte = self.byte()?;
+        }
+        task.set_estimate(BurstEstimate {
+            predicted: f64::from_le_bytes(predicted),
+            ran: self.u64()?,
+        });
+        Ok(task)
+    }
+
+    fn step(&mut self) -> Result<Step, TraceError> {
+        let tag = self.byte()?;
+        self.last = self
+            .last
+            .checked_add_signed(self.i64()?)
+            .ok_or(TraceError::Corrupt)?;
+        let call = match tag {
+            CLOCK => Call::Clock,
+            ARRIVE => Call::Arrive(self.task()?),
+            WAKE => Call::Enqueue(self.job()?, Enqueue::Wake),
+            PREEMPTED => Call::Enqueue(self.job()?, Enqueue::Preempted),
+            EXPIRED => Call::Enqueue(self.job()?, Enqueue::Expired),
+            PICK => Call::Pick(match self.byte()? {
+                0 => None,
+                _ => Some((self.i32()?, self.u32()?, self.i32()?)),
+            }),
+            TICK => {
+                let job = self.job()?;
+                let verdict = match self.byte()? {
+                    0 => Tick::Continue,
+                    _ => Tick::Expired,
+                };
+                Call::Tick(job, verdict, self.i32()?)
+            }
+            PREEMPT => Call::Preempt(self.job()?, self.byte()? != 0),
+            BLOCK => Call::Block(self.job()?, self.i32()?),
+            COMPLETE => Call::Complete(self.job()?),
+            REMOVE => {
+                let id = self.i32()?;
+                let count = self.u64()?;
+                let mut jobs = Vec::new();
+                for _ in 0..count {
+                    jobs.push((self.i32()?, self.u32()?));
+                }
+                Call::Remove(id, jobs)
+            }
+            tag => return Err(TraceError::Tag(tag)),
+        };
+        Ok(Step {
+            at: self.last,
+            call,
+        })
+    }
+}
+
+// the bytes a recorder wrote so far, clones share them
+#[derive(Debug, Clone, Default)]
+pub struct TraceBuffer {
+    writer: Rc<RefCell<Writer>>,
+}
+
+impl TraceBuffer {
+    pub fn bytes(&self) -> Vec<u8> {
+        self.writer.borrow().bytes.clone()
+    }
+}
+
+/*
+ * Records every call into the policy it wraps. It has to be the outermost
+ * policy to see the calls the simulator makes, the policies it wraps are
+ * replayed with it.
+*/
+pub struct Recorder {
+    inner: Box<dyn Policy>,
+    out: TraceBuffer,
+}
+
+impl Recorder {
+    pub fn new(inner: Box<dyn Policy>, workload: &Workload, settings: &Settings) -> Self {
+        let out = TraceBuffer::default();
+        {
+            let mut writer = out.writer.borrow_mut();
+            writer.bytes.extend_from_slice(MAGIC);
+            writer.byte(VERSION);
+            writer.str(inner.name());
+            writer.str(&serde_json::to_string(settings).expect("settings always serialize"));
+            writer.str(&serde_json::to_string(workload).expect("workloads always serialize"));
+        }
+        Recorder { inner, out }
+    }
+
+    pub fn buffer(&self) -> TraceBuffer {
+        self.out.clone()
+    }
+
+    fn write(&self, tag: u8, at: u64, write: impl FnOnce(&mut Writer)) {
+        let mut writer = self.out.writer.borrow_mut();
+        writer.step(tag, at);
+        write(&mut writer);
+    }
+}
+
+impl Policy for Recorder {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn enqueue(&mut self, task: Task, now: u64, why: Enqueue) {
+        match why {
+            Enqueue::Arrival => self.write(ARRIVE, now, |w| w.task(&task)),
+            Enqueue::Wake => self.write(WAKE, now, |w| w.job(&JobState::of(&task))),
+            Enqueue::Preempted => self.write(PREEMPTED, now, |w| w.job(&JobState::of(&task))),
+            Enqueue::Expired => self.write(EXPIRED, now, |w| w.job(&JobState::of(&task))),
+        }
+        self.inner.enqueue(task, now, why);
+    }
+
+    fn pick_next(&mut self, now: u64) -> Option<Task> {
+        let task = self.inner.pick_next(now);
+        self.write(PICK, now, |w| match &task {
+            Some(task) => {
+                w.byte(1);
+                w.i64(task.get_id() as i64);
+                w.u64(task.get_job() as u64);
+                w.i64(task.get_rank() as i64);
+            }
+            None => w.byte(0),
+        });
+        task
+    }
+
+    fn tick(&mut self, running: &mut Task, now: u64) -> Tick {
+        let before = JobState::of(running);
+        let verdict = self.inner.tick(running, now);
+        self.write(TICK, now, |w| {
+            w.job(&before);
+            w.byte((verdict == Tick::Expired) as u8);
+            w.i64(running.get_rank() as i64);
+        });
+        verdict
+    }
+
+    fn should_preempt(&self, running: &Task, now: u64) -> bool {
+        let preempt = self.inner.should_preempt(running, now);
+        self.write(PREEMPT, now, |w| {
+            w.job(&JobState::of(running));
+            w.byte(preempt as u8);
+        });
+        preempt
+    }
+
+    fn on_block(&mut self, task: &mut Task, now: u64) {
+        let before = JobState::of(task);
+        self.inner.on_block(task, now);
+        self.write(BLOCK, now, |w| {
+            w.job(&before);
+            w.i64(task.get_rank() as i64);
+        });
+    }
+
+    fn on_complete(&mut self, task: &Task, now: u64) {
+        self.write(COMPLETE, now, |w| w.job(&JobState::of(task)));
+        self.inner.on_complete(task, now);
+    }
+
+    fn on_clock(&mut self, now: u64) {
+        self.write(CLOCK, now, |_| {});
+        self.inner.on_clock(now);
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        let removed = self.inner.remove(id);
+        // remove is not told the time, it happens at the last step's
+        let at = self.out.writer.borrow().last;
+        self.write(REMOVE, at, |w| {
+            w.i64(id as i64);
+            w.u64(removed.len() as u64);
+            for task in &removed {
+                w.i64(task.get_id() as i64);
+                w.u64(task.get_job() as u64);
+            }
+        });
+        removed
+    }
+
+    fn ready_count(&self) -> usize {
+        self.inner.ready_count()
+    }
+
+    fn vruntime(&self, id: i32) -> Option<u64> {
+        self.inner.vruntime(id)
+    }
+
+    fn rank_changes(&mut self) -> Vec<Task> {
+        self.inner.rank_changes()
+    }
+
+    fn records(&self) -> PolicyRecords {
+        self.inner.records()
+    }
+}
+
+// a decoded trace
+#[derive(Debug, Clone)]
+pub struct Recording {
+    // name of the recorded policy
+    pub policy: String,
+    pub settings: Settings,
+    pub workload: Workload,
+    pub steps: Vec<Step>,
+}
+
+impl Recording {
+    pub fn decode(bytes: &[u8]) -> Result<Recording, TraceError> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(TraceError::Magic);
+        }
+        let mut reader = Reader {
+            bytes,
+            at: MAGIC.len(),
+            last: 0,
+        };
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(TraceError::Version(version));
+        }
+        let policy = reader.str()?.to_string();
+        let settings = serde_json::from_str(reader.str()?)
+            .map_err(|err| TraceError::Settings(err.to_string()))?;
+        let workload =
+            Workload::parse(reader.str()?).map_err(|err| TraceError::Workload(err.to_string()))?;
+        let mut steps = Vec::new();
+        while reader.at < bytes.len() {
+            steps.push(reader.step()?);
+        }
+        Ok(Recording {
+            policy,
+            settings,
+            workload,
+            steps,
+        })
+    }
+}
+
+// the first step the replayed policy decided differently
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    // index of the step, from 0
+    pub step: usize,
+    pub at: u64,
+    pub call: &'static str,
+    pub recorded: String,
+    pub replayed: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReplayReport {
+    pub policy: String,
+    pub steps: usize,
+    // steps replayed before the divergence, all of them without one
+    pub replayed: usize,
+    pub divergence: Option<Divergence>,
+}
+
+fn job_name(job: Option<(i32, u32, i32)>) -> String {
+    match job {
+        Some((task, job, rank)) => format!("task {task} job {job} at rank {rank}"),
+        None => "nothing".to_string(),
+    }
+}
+
+/*
+ * Feeds the recorded calls into `policy` in order and checks every answer
+ * against the recorded one, up to the first that differs. The jobs the
+ * policy hands out are kept here between the calls, like the simulator
+ * keeps them, and are given the recorded rank and work left whenever they
+ * go back in.
+*/
+pub fn replay(recording: &Recording, policy: &mut dyn Policy) -> ReplayReport {
+    let mut held: HashMap<(i32, u32), Task> = HashMap::new();
+    let mut report = ReplayReport {
+        policy: recording.policy.clone(),
+        steps: recording.steps.len(),
+        replayed: 0,
+        divergence: None,
+    };
+    for (index, step) in recording.steps.iter().enumerate() {
+        let at = step.at;
+        let diverged = |recorded: String, replayed: String| Divergence {
+            step: index,
+            at,
+            call: step.call.name(),
+            recorded,
+            replayed,
+        };
+        // a job the recorded run held that this one does not, only after
+        // a divergence that went unnoticed or a broken trace
+        let take = |held: &mut HashMap<(i32, u32), Task>, job: &JobState| {
+            let mut task = held
+                .remove(&job.key())
+                .ok_or_else(|| diverged(job.name(), "no such job held".to_string()))?;
+            task.set_rank(job.rank);
+            task.set_remaining(job.remaining);
+            Ok::<Task, Divergence>(task)
+        };
+        let outcome = match &step.call {
+            Call::Clock => {
+                policy.on_clock(at);
+                Ok(())
+            }
+            Call::Arrive(task) => {
+                policy.enqueue(task.clone(), at, Enqueue::Arrival);
+                Ok(())
+            }
+            Call::Enqueue(job, why) => {
+                take(&mut held, job).map(|task| policy.enqueue(task, at, *why))
+            }
+            Call::Pick(recorded) => {
+                let task = policy.pick_next(at);
+                let replayed = task
+                    .as_ref()
+                    .map(|t| (t.get_id(), t.get_job(), t.get_rank()));
+                if let Some(task) = task {
+                    held.insert((task.get_id(), task.get_job()), task);
+                }
+                if replayed == *recorded {
+                    Ok(())
+                } else {
+                    Err(diverged(job_name(*recorded), job_name(replayed)))
+                }
+            }
+            Call::Tick(job, verdict, rank) => take(&mut held, job).and_then(|mut task| {
+                let got = policy.tick(&mut task, at);
+                let replayed = (got, task.get_rank());
+                held.insert(job.key(), task);
+                if replayed == (*verdict, *rank) {
+                    Ok(())
+                } else {
+                    Err(diverged(
+                        format!("{} {verdict:?} at rank {rank}", job.name()),
+                        format!("{} {:?} at rank {}", job.name(), replayed.0, replayed.1),
+                    ))
+                }
+            }),
+            Call::Preempt(job, preempt) => take(&mut held, job).and_then(|task| {
+                let got = policy.should_preempt(&task, at);
+                held.insert(job.key(), task);
+                if got == *preempt {
+                    Ok(())
+                } else {
+                    Err(diverged(
+                        format!("{} preempt {preempt}", job.name()),
+                        format!("{} preempt {got}", job.name()),
+                    ))
+                }
+            }),
+            Call::Block(job, rank) => take(&mut held, job).and_then(|mut task| {
+                policy.on_block(&mut task, at);
+                let got = task.get_rank();
+                held.insert(job.key(), task);
+                if got == *rank {
+                    Ok(())
+                } else {
+                    Err(diverged(
+                        format!("{} at rank {rank}", job.name()),
+                        format!("{} at rank {got}", job.name()),
+                    ))
+                }
+            }),
+            Call::Complete(job) => take(&mut held, job).map(|task| policy.on_complete(&task, at)),
+            Call::Remove(id, recorded) => {
+                let removed = policy.remove(*id);
+                let replayed: Vec<(i32, u32)> =
+                    removed.iter().map(|t| (t.get_id(), t.get_job())).collect();
+                for task in removed {
+                    held.insert((task.get_id(), task.get_job()), task);
+                }
+                if replayed == *recorded {
+                    Ok(())
+                } else {
+                    Err(diverged(format!("{recorded:?}"), format!("{replayed:?}")))
+                }
+            }
+        };
+        if let Err(divergence) = outcome {
+            report.divergence = Some(divergence);
+            return report;
+        }
+        report.replayed += 1;
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::by_name;
+    use crate::scheduler::mlfq::config::MlfqConfig;
+    use crate::scheduler::mlfq::Mlfq;
+    use crate::sim::Simulator;
+
+    fn workload() -> Workload {
+        Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 30},
+                {"id": 2, "burst": 20, "arrival": 2, "io": {"every": 3, "duration": 2}},
+                {"id": 3, "rank": 1, "burst": 4, "period": 10, "deadline": 8}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    fn record(policy: Box<dyn Policy>, workload: &Workload) -> Vec<u8> {
+        let recorder = Recorder::new(policy, workload, &Settings::default());
+        let buffer = recorder.buffer();
+        Simulator::new(Box::new(recorder))
+            .with_horizon(Some(60))
+            .run(workload);
+        buffer.bytes()
+    }
+
+    #[test]
+    fn the_same_policy_replays_without_divergence() {
+        let w = workload();
+        for name in ["mlfq", "edf", "cfs", "lottery"] {
+            let bytes = record(by_name(name).unwrap(), &w);
+            let recording = Recording::decode(&bytes).unwrap();
+            assert_eq!(recording.policy, name);
+            assert_eq!(recording.workload, w);
+            // every released job comes back as it was handed over
+            let arrivals: Vec<&Task> = recording
+                .steps
+                .iter()
+                .filter_map(|step| match &step.call {
+                    Call::Arrive(task) => Some(task),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(arrivals.len(), 8);
+            assert!(arrivals.iter().any(|t| t.get_io().is_some()));
+            assert_eq!(arrivals.last().unwrap().get_abs_deadline(), Some(58));
+
+            let report = replay(&recording, by_name(name).unwrap().as_mut());
+            assert_eq!(report.divergence, None, "{name}");
+            assert_eq!(report.replayed, recording.steps.len());
+        }
+    }
+
+    #[test]
+    fn the_settings_come_back_from_the_header() {
+        let w = workload();
+        let settings = Settings {
+            mlfq: Some(MlfqConfig::preset("batch").unwrap()),
+            seed: 7,
+            alpha: 0.25,
+            hi_mode: "degrade".into(),
+            overload: Some("shed".into()),
+        };
+        let config = settings.mlfq.clone().unwrap();
+        let recorder = Recorder::new(Box::new(Mlfq::from_config(config.clone())), &w, &settings);
+        let buffer = recorder.buffer();
+        Simulator::new(Box::new(recorder))
+            .with_horizon(Some(60))
+            .run(&w);
+        let recording = Recording::decode(&buffer.bytes()).unwrap();
+        assert_eq!(recording.settings, settings);
+        // the policy built from them decides the same
+        let report = replay(&recording, &mut Mlfq::from_config(config));
+        assert_eq!(report.divergence, None);
+        let report = replay(&recording, &mut Mlfq::new());
+        assert!(report.divergence.is_some());
+    }
+
+    // a trace header for the workload, the steps are written by the test
+    fn header(workload: &Workload) -> Writer {
+        let mut writer = Writer::default();
+        writer.bytes.extend_from_slice(MAGIC);
+        writer.byte(VERSION);
+        writer.str("fifo");
+        writer.str(&serde_json::to_string(&Settings::default()).unwrap());
+        writer.str(&serde_json::to_string(workload).unwrap());
+        writer
+    }
+
+    #[test]
+    fn a_damaged_trace_is_rejected() {
+        let w = workload();
+        assert!(matches!(Recording::decode(b"MLFQ"), Err(TraceError::Magic)));
+        let bytes = record(by_name("fifo").unwrap(), &w);
+        assert!(matches!(
+            Recording::decode(&bytes[..bytes.len() - 1]),
+            Err(TraceError::Truncated)
+        ));
+        let mut version = bytes.clone();
+        version[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            Recording::decode(&version),
+            Err(TraceError::Version(_))
+        ));
+        let mut tag = header(&w);
+        tag.step(REMOVE + 1, 0);
+        assert!(matches!(
+            Recording::decode(&tag.bytes),
+            Err(TraceError::Tag(_))
+        ));
+
+        // a step before the start of time
+        let mut writer = header(&w);
+        writer.byte(CLOCK);
+        writer.i64(-1);
+        assert!(matches!(
+            Recording::decode(&writer.bytes),
+            Err(TraceError::Corrupt)
+        ));
+        // a step after the end of time
+        let mut writer = header(&w);
+        for delta in [i64::MAX, i64::MAX, 2] {
+            writer.byte(CLOCK);
+            writer.i64(delta);
+        }
+        assert!(matches!(
+            Recording::decode(&writer.bytes),
+            Err(TraceError::Corrupt)
+        ));
+        // a task id that does not fit
+        let mut writer = header(&w);
+        writer.step(REMOVE, 0);
+        writer.i64(i32::MAX as i64 + 1);
+        writer.u64(0);
+        assert!(matches!(
+            Recording::decode(&writer.bytes),
+            Err(TraceError::Corrupt)
+        ));
+        // a job whose deadline is past the end of time
+        let mut writer = header(&w);
+        writer.step(ARRIVE, 0);
+        for value in [1, 0, 0] {
+            writer.i64(value);
+        }
+        for value in [0, u64::MAX - 1, 4, 4] {
+            writer.u64(value);
+        }
+        writer.byte(2);
+        writer.u64(8);
+        assert!(matches!(
+            Recording::decode(&writer.bytes),
+            Err(TraceError::Corrupt)
+        ));
+    }
+
+    #[test]
+    fn a_changed_mlfq_diverges_at_its_first_other_decision() {
+        let w = workload();
+        let bytes = record(Box::new(Mlfq::new()), &w);
+        let recording = Recording::decode(&bytes).unwrap();
+        // a longer allotment at the top keeps task 1 there longer
+        let mut config = MlfqConfig::default();
+        config.levels[0].allotment *= 2;
+        let report = replay(&recording, &mut Mlfq::from_config(config));
+        let divergence = report.divergence.unwrap();
+        assert_eq!(
+            divergence,
+            Divergence {
+                step: 22,
+                at: 5,
+                call: "tick",
+                recorded: "task 1 job 0 Expired at rank 1".into(),
+                replayed: "task 1 job 0 Expired at rank 2".into(),
+            }
+        );
+        assert_eq!(report.replayed, 22);
+    }
+}
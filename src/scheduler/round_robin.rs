@@ -0,0 +1,78 @@
+use super::{Enqueue, Policy, Tick};
+use crate::datastructure::linklist::LinkList;
+use crate::datastructure::Task;
+
+pub const DEFAULT_QUANTUM: u64 = 4;
+
+// Every task runs for at most `quantum` ticks before going to the back of the list
+#[derive(Debug)]
+pub struct RoundRobin {
+    ready: LinkList,
+    quantum: u64,
+    slice: u64,
+}
+
+impl RoundRobin {
+    pub fn new(quantum: u64) -> Self {
+        RoundRobin {
+            ready: LinkList::new(),
+            quantum: quantum.max(1),
+            slice: 0,
+        }
+    }
+}
+
+impl Policy for RoundRobin {
+    fn name(&self) -> &str {
+        "rr"
+    }
+
+    fn enqueue(&mut self, task: Task, _now: u64, _why: Enqueue) {
+        self.ready.push_back(task);
+    }
+
+    fn pick_next(&mut self, _now: u64) -> Option<Task> {
+        self.slice = 0;
+        self.ready.pop()
+    }
+
+    fn tick(&mut self, _running: &mut Task, _now: u64) -> Tick {
+        self.slice += 1;
+        // nobody to hand the cpu to, keep going with a fresh slice
+        if self.slice >= self.quantum && !self.ready.is_empty() {
+            Tick::Expired
+        } else {
+            if self.slice >= self.quantum {
+                self.slice = 0;
+            }
+            Tick::Continue
+        }
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        self.ready.delete_by_id(id)
+    }
+
+    fn ready_count(&self) -> usize {
+        self.ready.len() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slice_expires_only_with_competition() {
+        let mut rr = RoundRobin::new(2);
+        let mut running = Task::new(1, 0, 0).with_burst(10);
+        rr.enqueue(running.clone(), 0, Enqueue::Arrival);
+        assert_eq!(rr.pick_next(0).unwrap().get_id(), 1);
+        assert_eq!(rr.tick(&mut running, 0), Tick::Continue);
+        assert_eq!(rr.tick(&mut running, 1), Tick::Continue);
+
+        rr.enqueue(Task::new(2, 0, 0), 2, Enqueue::Arrival);
+        assert_eq!(rr.tick(&mut running, 2), Tick::Continue);
+        assert_eq!(rr.tick(&mut running, 3), Tick::Expired);
+    }
+}
@@ -0,0 +1,474 @@
+use super::edf::rank_of_deadline;
+use super::{Enqueue, Policy, PolicyRecords, Tick};
+use crate::datastructure::Task;
+use crate::sim::workload::{ServerKind, Workload};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+
+/*
+ * Aperiodic servers. Every server owns a budget of Q ticks that is handed
+ * out again every period T, and serves the aperiodic tasks that name it one
+ * job at a time in arrival order. The job being served sits in the inner
+ * policy with the server's rank, the rest wait in the server. Tasks without
+ * a server go straight to the inner policy.
+ * A constant bandwidth server (Abeni and Buttazzo) runs under edf with a
+ * deadline d and budget c,
+ *  - a job arriving at an idle server at t keeps (c, d) when c < (d - t) Q / T,
+ *    otherwise the server starts over with c = Q and d = t + T
+ *  - when c runs out it is refilled to Q and d moves on to d + T, the job
+ *    goes back to the ready set under its later deadline
+ * so the server never asks for more than Q / T of the cpu and a job that
+ * runs past what it claimed only postpones itself.
+ * The fixed priority servers run at the server's rank and park their job
+ * while the budget is empty. Periods start at multiples of T,
+ *  - polling: the budget is set to Q at every period start and thrown away
+ *    when there is nothing to serve then or the queue runs dry
+ *  - deferrable: the budget is set to Q at every period start and kept while
+ *    idle, so it may serve 2Q back to back across a period start
+ *  - sporadic (Sprunt, Sha and Lehoczky): the server becomes active when it
+ *    starts serving with budget left, what it uses until it goes idle or
+ *    runs dry is given back T after it became active, so no window of T
+ *    ticks sees more than Q of service
+*/
+pub struct Served {
+    inner: Box<dyn Policy>,
+    servers: Vec<Server>,
+    // server of every served task
+    member: HashMap<i32, usize>,
+}
+
+struct Server {
+    name: String,
+    kind: ServerKind,
+    budget: u64,
+    period: u64,
+    // fixed priority servers only
+    rank: i32,
+    left: u64,
+    // cbs only
+    deadline: u64,
+    // the job handed to the inner policy or running
+    serving: Option<i32>,
+    // jobs waiting for service in arrival order
+    queue: VecDeque<Task>,
+    // sporadic only, when the server became active, what it used since and
+    // the budget coming back by time
+    active: Option<u64>,
+    used: u64,
+    refills: Vec<(u64, u64)>,
+    served: u64,
+    exhausted: u64,
+}
+
+// what a server handed out over a run
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ServerRecord {
+    pub name: String,
+    pub kind: ServerKind,
+    pub budget: u64,
+    pub period: u64,
+    // ticks its jobs ran
+    pub served: u64,
+    // times the budget ran out
+    pub exhausted: u64,
+}
+
+impl Server {
+    fn job_rank(&self) -> i32 {
+        match self.kind {
+            ServerKind::Cbs => rank_of_deadline(self.deadline),
+            _ => self.rank,
+        }
+    }
+
+    fn can_serve(&self) -> bool {
+        self.kind == ServerKind::Cbs || self.left > 0
+    }
+
+    // a job arrives while nothing is waiting or being served
+    fn wake(&mut self, now: u64) {
+        if self.kind == ServerKind::Cbs {
+            let window = self.deadline.saturating_sub(now);
+            if self.left * self.period >= window * self.budget {
+                self.left = self.budget;
+                self.deadline = now + self.period;
+            }
+        }
+    }
+
+    // the last job left, nothing is waiting
+    fn idle(&mut self) {
+        match self.kind {
+            ServerKind::Polling => self.left = 0,
+            ServerKind::Sporadic => self.deactivate(),
+            _ => {}
+        }
+    }
+
+    fn deactivate(&mut self) {
+        if let Some(active) = self.active.take() {
+            if self.used > 0 {
+                self.refills.push((active + self.period, self.used));
+            }
+        }
+        self.used = 0;
+    }
+
+    fn replenish(&mut self, now: u64) {
+        match self.kind {
+            ServerKind::Cbs => {}
+            ServerKind::Polling | ServerKind::Deferrable => {
+                if now.is_multiple_of(self.period) {
+                    self.left = self.budget;
+                    if self.kind == ServerKind::Polling
+                        && self.serving.is_none()
+                        && self.queue.is_empty()
+                    {
+                        self.left = 0;
+                    }
+                }
+            }
+            ServerKind::Sporadic => {
+                let (due, later): (Vec<_>, Vec<_>) =
+                    self.refills.iter().partition(|&&(at, _)| at <= now);
+                self.refills = later;
+                let amount: u64 = due.iter().map(|&(_, amount)| amount).sum();
+                self.left = (self.left + amount).min(self.budget);
+            }
+        }
+    }
+
+    fn record(&self) -> ServerRecord {
+        ServerRecord {
+            name: self.name.clone(),
+            kind: self.kind,
+            budget: self.budget,
+            period: self.period,
+            served: self.served,
+            exhausted: self.exhausted,
+        }
+    }
+}
+
+impl Served {
+    // the servers of a validated workload around `inner`, under rm a fixed
+    // priority server is ranked by its period like a periodic task
+    pub fn new(inner: Box<dyn Policy>, workload: &Workload) -> Self {
+        let rate_monotonic = inner.name() == "rm";
+        let servers: Vec<Server> = workload
+            .servers
+            .iter()
+            .map(|spec| Server {
+                name: spec.name.clone(),
+                kind: spec.kind,
+                budget: spec.budget,
+                period: spec.period,
+                rank: if rate_monotonic {
+                    -(spec.period.min(i32::MAX as u64) as i32)
+                } else {
+                    spec.rank
+                },
+                left: spec.budget,
+                deadline: 0,
+                serving: None,
+                queue: VecDeque::new(),
+                active: None,
+                used: 0,
+                refills: Vec::new(),
+                served: 0,
+                exhausted: 0,
+            })
+            .collect();
+        let member = workload
+            .tasks
+            .iter()
+            .filter_map(|spec| {
+                let name = spec.server.as_ref()?;
+                Some((spec.id, servers.iter().position(|s| &s.name == name)?))
+            })
+            .collect();
+        Served {
+            inner,
+            servers,
+            member,
+        }
+    }
+
+    // hands the next waiting job to the inner policy if the server may serve it
+    fn release(&mut self, at: usize, now: u64) {
+        let server = &mut self.servers[at];
+        if server.serving.is_some() || !server.can_serve() {
+            return;
+        }
+        let Some(mut job) = server.queue.pop_front() else {
+            return;
+        };
+        if server.kind == ServerKind::Sporadic {
+            server.active.get_or_insert(now);
+        }
+        job.set_rank(server.job_rank());
+        server.serving = Some(job.get_id());
+        self.inner.enqueue(job, now, Enqueue::Wake);
+    }
+
+    // the job being served blocked or completed
+    fn leave(&mut self, task: &Task, now: u64) {
+        let Some(&at) = self.member.get(&task.get_id()) else {
+            return;
+        };
+        let server = &mut self.servers[at];
+        server.serving = None;
+        if server.queue.is_empty() {
+            server.idle();
+        }
+        self.release(at, now);
+    }
+}
+
+impl Policy for Served {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn enqueue(&mut self, mut task: Task, now: u64, why: Enqueue) {
+        let id = task.get_id();
+        let Some(&at) = self.member.get(&id) else {
+            self.inner.enqueue(task, now, why);
+            return;
+        };
+        let server = &mut self.servers[at];
+        match why {
+            Enqueue::Arrival | Enqueue::Wake => {
+                if server.serving.is_none() && server.queue.is_empty() {
+                    server.wake(now);
+                }
+                server.queue.push_back(task);
+            }
+            // the job being served lost the cpu, it stays in service while
+            // there is budget for it
+            _ if server.serving == Some(id) && server.can_serve() => {
+                task.set_rank(server.job_rank());
+                self.inner.enqueue(task, now, why);
+                return;
+            }
+            _ => {
+                if server.serving == Some(id) {
+                    server.serving = None;
+                }
+                server.queue.push_front(task);
+            }
+        }
+        self.release(at, now);
+    }
+
+    fn pick_next(&mut self, now: u64) -> Option<Task> {
+        self.inner.pick_next(now)
+    }
+
+    fn tick(&mut self, running: &mut Task, now: u64) -> Tick {
+        let verdict = self.inner.tick(running, now);
+        let Some(&at) = self.member.get(&running.get_id()) else {
+            return verdict;
+        };
+        let server = &mut self.servers[at];
+        server.served += 1;
+        server.used += 1;
+        server.left = server.left.saturating_sub(1);
+        if server.left > 0 {
+            return verdict;
+        }
+        server.exhausted += 1;
+        match server.kind {
+            ServerKind::Cbs => {
+                server.left = server.budget;
+                server.deadline += server.period;
+                running.set_rank(server.job_rank());
+            }
+            ServerKind::Sporadic => server.deactivate(),
+            ServerKind::Polling | ServerKind::Deferrable => {}
+        }
+        Tick::Expired
+    }
+
+    fn should_preempt(&self, running: &Task, now: u64) -> bool {
+        self.inner.should_preempt(running, now)
+    }
+
+    fn on_block(&mut self, task: &mut Task, now: u64) {
+        self.inner.on_block(task, now);
+        self.leave(task, now);
+    }
+
+    fn on_complete(&mut self, task: &Task, now: u64) {
+        self.inner.on_complete(task, now);
+        self.leave(task, now);
+    }
+
+    fn on_clock(&mut self, now: u64) {
+        self.inner.on_clock(now);
+        for at in 0..self.servers.len() {
+            self.servers[at].replenish(now);
+            self.release(at, now);
+        }
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        let mut removed = self.inner.remove(id);
+        if let Some(&at) = self.member.get(&id) {
+            let server = &mut self.servers[at];
+            if !removed.is_empty() && server.serving == Some(id) {
+                server.serving = None;
+            }
+            let (taken, queue) = std::mem::take(&mut server.queue)
+                .into_iter()
+                .partition(|task| task.get_id() == id);
+            server.queue = queue;
+            removed.extend::<VecDeque<Task>>(taken);
+        }
+        removed
+    }
+
+    fn ready_count(&self) -> usize {
+        self.inner.ready_count() + self.servers.iter().map(|s| s.queue.len()).sum::<usize>()
+    }
+
+    fn vruntime(&self, id: i32) -> Option<u64> {
+        self.inner.vruntime(id)
+    }
+
+    fn records(&self) -> PolicyRecords {
+        PolicyRecords {
+            servers: self.servers.iter().map(Server::record).collect(),
+            ..self.inner.records()
+        }
+    }
+
+    fn rank_changes(&mut self) -> Vec<Task> {
+        self.inner.rank_changes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::edf::Edf;
+    use crate::scheduler::priority::FixedPriority;
+    use crate::sim::trace::Trace;
+    use crate::sim::Simulator;
+
+    // whether the task ran in each tick
+    fn ran(trace: &Trace, id: i32) -> Vec<bool> {
+        let mut ran = vec![false; trace.end() as usize];
+        for slice in trace.slices.iter().filter(|s| s.task == Some(id)) {
+            for tick in slice.start..slice.end {
+                ran[tick as usize] = true;
+            }
+        }
+        ran
+    }
+
+    // most service in any window of `length` ticks, aligned to multiples of it or not
+    fn most_in_window(ran: &[bool], length: usize, aligned: bool) -> usize {
+        let step = if aligned { length } else { 1 };
+        (0..ran.len())
+            .step_by(step)
+            .map(|start| {
+                ran[start..(start + length).min(ran.len())]
+                    .iter()
+                    .filter(|&&r| r)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn cbs_keeps_a_greedy_task_to_its_bandwidth() {
+        // task 3 claims a deadline of 2 and wants the cpu for 300 ticks
+        let workload = |server: &str| {
+            Workload::parse(&format!(
+                r#"{{"servers": [{{"name": "s", "kind": "cbs", "budget": 2, "period": 4}}],
+                    "tasks": [
+                    {{"id": 1, "burst": 3, "period": 10}},
+                    {{"id": 2, "burst": 4, "period": 20}},
+                    {{"id": 3, "burst": 300, "deadline": 2 {server}}}
+                ]}}"#
+            ))
+            .unwrap()
+        };
+        let plain = Simulator::new(Box::new(Edf::new()))
+            .with_horizon(Some(200))
+            .run(&workload(""));
+        assert!(plain.metrics.deadline_misses > 10);
+
+        let served = workload(r#", "server": "s""#);
+        let report = Simulator::new(Box::new(Served::new(Box::new(Edf::new()), &served)))
+            .with_horizon(Some(200))
+            .run(&served);
+        let periodic = report.jobs.iter().filter(|job| job.task != 3);
+        assert!(periodic.clone().all(|job| !job.missed));
+        assert_eq!(periodic.count(), 30);
+        // the periodic tasks need half the cpu, the server gets the other half
+        let record = &report.servers[0];
+        assert_eq!(record.served, 100);
+        assert_eq!(record.exhausted, 50);
+        assert_eq!(ran(&report.trace, 3).iter().filter(|&&r| r).count(), 100);
+    }
+
+    // first run and finish of a lone aperiodic job
+    fn fixed_priority(kind: &str, arrival: u64, burst: u64) -> (u64, u64) {
+        let workload = Workload::parse(&format!(
+            r#"{{"servers": [{{"name": "s", "kind": "{kind}", "budget": 2, "period": 4}}],
+                "tasks": [{{"id": 1, "burst": {burst}, "arrival": {arrival}, "server": "s"}}]}}"#
+        ))
+        .unwrap();
+        let policy = Served::new(Box::new(FixedPriority::rate_monotonic()), &workload);
+        let report = Simulator::new(Box::new(policy)).run(&workload);
+        let job = &report.jobs[0];
+        (job.first_run.unwrap(), job.finish.unwrap())
+    }
+
+    #[test]
+    fn fixed_priority_servers_replenish_by_their_rules() {
+        // the polling server found nothing to do at 0 and lost its budget
+        assert_eq!(fixed_priority("polling", 1, 3), (4, 9));
+        // the deferrable server kept it, and gets it back at 4
+        assert_eq!(fixed_priority("deferrable", 1, 3), (1, 5));
+        // the sporadic server became active at 1, the 2 ticks come back at 5
+        assert_eq!(fixed_priority("sporadic", 1, 3), (1, 6));
+        // a deferrable server serves 2 budgets back to back across a period
+        // start, the sporadic one only gives back at 2 + 4
+        assert_eq!(fixed_priority("deferrable", 2, 4), (2, 6));
+        assert_eq!(fixed_priority("sporadic", 2, 4), (2, 8));
+    }
+
+    #[test]
+    fn fixed_priority_servers_stay_within_their_budget() {
+        for kind in ["polling", "deferrable", "sporadic"] {
+            // task 1 runs at a lower rate than the server under rm
+            let workload = Workload::parse(&format!(
+                r#"{{"servers": [{{"name": "s", "kind": "{kind}", "budget": 2, "period": 4}}],
+                    "tasks": [
+                    {{"id": 1, "burst": 2, "period": 5}},
+                    {{"id": 2, "burst": 40, "arrival": 3, "server": "s"}},
+                    {{"id": 3, "burst": 20, "arrival": 50, "server": "s"}}
+                ]}}"#
+            ))
+            .unwrap();
+            let policy = Served::new(Box::new(FixedPriority::rate_monotonic()), &workload);
+            let report = Simulator::new(Box::new(policy))
+                .with_horizon(Some(120))
+                .run(&workload);
+            assert_eq!(report.metrics.deadline_misses, 0, "{kind}");
+            let served: Vec<bool> = ran(&report.trace, 2)
+                .iter()
+                .zip(ran(&report.trace, 3))
+                .map(|(a, b)| *a || b)
+                .collect();
+            assert_eq!(most_in_window(&served, 4, true), 2, "{kind}");
+            if kind == "sporadic" {
+                assert_eq!(most_in_window(&served, 4, false), 2);
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use super::burst::BurstPredictor;
+use super::{Enqueue, Policy, PolicyRecords, Tick};
+use crate::datastructure::avl::AvlTree;
+use crate::datastructure::Task;
+
+// shortest predicted remainder first, in sixteenths of a tick
+pub fn shortest_key(task: &Task) -> i64 {
+    -((task.get_estimate().remaining() * 16.0).round() as i64)
+}
+
+/*
+ * Shortest job first. The ready tree is keyed by what is left of each task's
+ * predicted burst, the real burst is never looked at. Without preemption a
+ * task keeps the cpu until it blocks or completes, the preemptive version
+ * (shortest remaining time first) hands the cpu to a ready task as soon as
+ * its predicted burst is shorter than what the running task is expected to
+ * have left. A task that runs past its prediction is expected to take as
+ * long again, so it does not keep the cpu for good.
+*/
+#[derive(Debug)]
+pub struct Sjf {
+    ready: AvlTree,
+    predictor: BurstPredictor,
+    preemptive: bool,
+}
+
+impl Default for Sjf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sjf {
+    pub fn new() -> Self {
+        Sjf {
+            ready: AvlTree::keyed_by(shortest_key),
+            predictor: BurstPredictor::default(),
+            preemptive: false,
+        }
+    }
+
+    // shortest remaining time first
+    pub fn srtf() -> Self {
+        Sjf {
+            preemptive: true,
+            ..Sjf::new()
+        }
+    }
+
+    pub fn with_predictor(mut self, predictor: BurstPredictor) -> Self {
+        self.predictor = predictor;
+        self
+    }
+}
+
+impl Policy for Sjf {
+    fn name(&self) -> &str {
+        if self.preemptive {
+            "srtf"
+        } else {
+            "sjf"
+        }
+    }
+
+    fn enqueue(&mut self, mut task: Task, _now: u64, why: Enqueue) {
+        if why == Enqueue::Arrival {
+            self.predictor.start(&mut task);
+        }
+        self.ready.insert(task);
+    }
+
+    fn pick_next(&mut self, _now: u64) -> Option<Task> {
+        self.ready.pop_highest()
+    }
+
+    fn tick(&mut self, running: &mut Task, _now: u64) -> Tick {
+        BurstPredictor::charge(running);
+        Tick::Continue
+    }
+
+    fn should_preempt(&self, running: &Task, _now: u64) -> bool {
+        self.preemptive
+            && self
+                .ready
+                .highest_key()
+                .is_some_and(|key| key > shortest_key(running))
+    }
+
+    fn on_block(&mut self, task: &mut Task, _now: u64) {
+        let estimate = self.predictor.observe(task);
+        task.set_estimate(estimate);
+    }
+
+    fn on_complete(&mut self, task: &Task, _now: u64) {
+        self.predictor.observe(task);
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        self.ready.delete_by_id(id)
+    }
+
+    fn ready_count(&self) -> usize {
+        self.ready.task_count()
+    }
+
+    fn records(&self) -> PolicyRecords {
+        PolicyRecords {
+            predictor: Some(self.predictor.clone()),
+            ..PolicyRecords::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::workload::Workload;
+    use crate::sim::Simulator;
+
+    // an interactive task with short bursts against a cpu bound one
+    fn workload() -> Workload {
+        Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 40},
+                {"id": 2, "burst": 12, "arrival": 1, "io": {"every": 2, "duration": 3}}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn srtf_lets_short_bursts_cut_in() {
+        let sjf = Simulator::new(Box::new(Sjf::new())).run(&workload());
+        let srtf = Simulator::new(Box::new(Sjf::srtf())).run(&workload());
+        // without preemption the short bursts wait behind the whole of task 1
+        assert_eq!(sjf.jobs[1].first_run, Some(40));
+        // task 1 is expected to be done after 4 ticks, once it runs past that
+        // its remainder grows and the 4 ticks task 2 is expected to need win
+        assert_eq!(srtf.jobs[1].first_run, Some(5));
+        assert!(srtf.jobs[1].finish < sjf.jobs[1].finish);
+        // task 2 always runs 2 ticks, the guess of 4 is soon forgotten, the
+        // single 40 tick burst of task 1 is off by 36
+        let task2 = srtf.predictions[1];
+        assert_eq!(task2.bursts, 6);
+        assert!(task2.mean_error < 1.0, "{}", task2.mean_error);
+        assert!(srtf.metrics.prediction_error.unwrap() > 5.0);
+    }
+}
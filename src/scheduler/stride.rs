@@ -0,0 +1,240 @@
+use super::tickets::{Tickets, DEFAULT_QUANTUM};
+use super::{Enqueue, Policy, PolicyRecords, Tick};
+use crate::datastructure::avl::AvlTree;
+use crate::datastructure::Task;
+use crate::sim::workload::Workload;
+use std::collections::{HashMap, HashSet};
+
+// pass a task holding a single base ticket advances by per tick
+pub const STRIDE1: u64 = 1 << 16;
+
+// smallest pass first
+pub fn pass_key(task: &Task) -> i64 {
+    -(task.get_pass().min(i64::MAX as u64) as i64)
+}
+
+/*
+ * Stride scheduling, the deterministic sibling of the lottery. A task's
+ * stride is STRIDE1 over the base value of its tickets and its pass grows by
+ * the stride for every tick it runs, the task with the smallest pass runs
+ * next. The ready tree is keyed by the task's pass.
+ * The global pass grows as if one task held all the active tickets. A task
+ * that arrives starts a stride past it, a task that leaves to block keeps
+ * how far it was ahead of the global pass and picks up there when it wakes.
+ * Passes only grow. The tree keys are i64, at STRIDE1 a tick a pass takes
+ * some 10^14 ticks to outrun them, so neither it nor the global pass is
+ * ever rebased.
+*/
+#[derive(Debug)]
+pub struct Stride {
+    ready: AvlTree,
+    tickets: Tickets,
+    global_pass: u64,
+    // pass left over beyond the global pass when a task stopped competing
+    remain: HashMap<i32, u64>,
+    // ready and running tasks
+    active: HashSet<i32>,
+    quantum: u64,
+    ran: u64,
+}
+
+impl Default for Stride {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stride {
+    // every task holds the default tickets
+    pub fn new() -> Self {
+        Self::from_workload(&Workload::default())
+    }
+
+    pub fn from_workload(workload: &Workload) -> Self {
+        Stride {
+            ready: AvlTree::keyed_by(pass_key),
+            tickets: Tickets::from_workload(workload),
+            global_pass: 0,
+            remain: HashMap::new(),
+            active: HashSet::new(),
+            quantum: DEFAULT_QUANTUM,
+            ran: 0,
+        }
+    }
+
+    pub fn with_quantum(mut self, quantum: u64) -> Self {
+        self.quantum = quantum.max(1);
+        self
+    }
+
+    pub fn tickets(&self) -> &Tickets {
+        &self.tickets
+    }
+
+    pub fn tickets_mut(&mut self) -> &mut Tickets {
+        &mut self.tickets
+    }
+
+    // see Tickets::transfer, the new strides count from the next tick on
+    pub fn transfer(&mut self, from: i32, to: i32, tickets: u64) -> bool {
+        self.tickets.transfer(from, to, tickets)
+    }
+
+    pub fn global_pass(&self) -> u64 {
+        self.global_pass
+    }
+
+    pub fn stride(&self, id: i32) -> u64 {
+        (STRIDE1 as f64 / self.tickets.value(id)).round().max(1.0) as u64
+    }
+
+    fn leave(&mut self, task: &Task) {
+        self.active.remove(&task.get_id());
+        self.remain.insert(
+            task.get_id(),
+            task.get_pass().saturating_sub(self.global_pass),
+        );
+    }
+}
+
+impl Policy for Stride {
+    fn name(&self) -> &str {
+        "stride"
+    }
+
+    fn enqueue(&mut self, mut task: Task, _now: u64, why: Enqueue) {
+        let id = task.get_id();
+        if matches!(why, Enqueue::Arrival | Enqueue::Wake) {
+            let remain = self.remain.remove(&id).unwrap_or_else(|| self.stride(id));
+            task.set_pass(self.global_pass + remain);
+        }
+        self.active.insert(id);
+        self.ready.insert(task);
+    }
+
+    fn pick_next(&mut self, _now: u64) -> Option<Task> {
+        self.ran = 0;
+        self.ready.pop_highest()
+    }
+
+    fn tick(&mut self, running: &mut Task, _now: u64) -> Tick {
+        running.set_pass(running.get_pass() + self.stride(running.get_id()));
+        let active: f64 = self.active.iter().map(|&id| self.tickets.value(id)).sum();
+        self.global_pass += (STRIDE1 as f64 / active.max(1.0)).round() as u64;
+        self.ran += 1;
+        if self.ran >= self.quantum && self.ready.task_count() > 0 {
+            return Tick::Expired;
+        }
+        Tick::Continue
+    }
+
+    fn on_block(&mut self, task: &mut Task, _now: u64) {
+        self.leave(task);
+    }
+
+    fn on_complete(&mut self, task: &Task, _now: u64) {
+        self.leave(task);
+    }
+
+    fn remove(&mut self, id: i32) -> Vec<Task> {
+        self.active.remove(&id);
+        self.ready.delete_by_id(id)
+    }
+
+    fn ready_count(&self) -> usize {
+        self.ready.task_count()
+    }
+
+    fn records(&self) -> PolicyRecords {
+        PolicyRecords {
+            tickets: Some(self.tickets.clone()),
+            ..PolicyRecords::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Simulator;
+
+    #[test]
+    fn runs_in_exact_proportion_to_tickets() {
+        let mut stride = Stride::new();
+        stride.tickets.set(1, 300);
+        stride.tickets.set(2, 200);
+        stride.tickets.set(3, 100);
+        for id in 1..=3 {
+            stride.enqueue(Task::new(id, 0, 0).with_burst(100), 0, Enqueue::Arrival);
+        }
+        let mut ran = [0; 3];
+        for now in 0..60 {
+            let mut task = stride.pick_next(now).unwrap();
+            stride.tick(&mut task, now);
+            ran[task.get_id() as usize - 1] += 1;
+            // the pass has its own field, the cfs vruntime is left alone
+            assert!(task.get_pass() > 0);
+            assert_eq!(task.get_vruntime(), 0);
+            stride.enqueue(task, now, Enqueue::Expired);
+        }
+        assert_eq!(ran, [30, 20, 10]);
+    }
+
+    #[test]
+    fn proportions_hold_once_passes_run_past_i32() {
+        let mut stride = Stride::new();
+        for (id, tickets) in [(1, 1), (2, 3), (3, 1)] {
+            stride.tickets.set(id, tickets);
+        }
+        stride.enqueue(Task::new(1, 0, 0).with_burst(50000), 0, Enqueue::Arrival);
+        // alone, one ticket moves the pass by STRIDE1 a tick
+        let mut task = stride.pick_next(0).unwrap();
+        for now in 0..40000 {
+            stride.tick(&mut task, now);
+        }
+        assert!(stride.global_pass() > i32::MAX as u64);
+        stride.enqueue(task, 40000, Enqueue::Expired);
+        for id in 2..=3 {
+            stride.enqueue(
+                Task::new(id, 0, 0).with_burst(50000),
+                40000,
+                Enqueue::Arrival,
+            );
+        }
+        let mut ran = [0; 3];
+        for now in 40000..45000 {
+            let mut task = stride.pick_next(now).unwrap();
+            stride.tick(&mut task, now);
+            ran[task.get_id() as usize - 1] += 1;
+            stride.enqueue(task, now, Enqueue::Expired);
+        }
+        assert_eq!(ran, [1000, 3000, 1000]);
+    }
+
+    #[test]
+    fn measured_share_follows_requested_share() {
+        let workload = Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 500, "tickets": 30},
+                {"id": 2, "burst": 500, "tickets": 10},
+                {"id": 3, "burst": 20, "arrival": 10, "tickets": 40, "io": {"every": 2, "duration": 2}}
+            ]}"#,
+        )
+        .unwrap();
+        let report = Simulator::new(Box::new(Stride::from_workload(&workload)))
+            .with_horizon(Some(400))
+            .run(&workload);
+        let shares: Vec<(f64, f64)> = report
+            .shares
+            .iter()
+            .map(|s| (s.requested, s.measured))
+            .collect();
+        assert_eq!(shares[0].0, 0.375);
+        // 1 and 2 split what 3 leaves three to one
+        let ratio = shares[0].1 / shares[1].1;
+        assert!((ratio - 3.0).abs() < 0.1, "{ratio}");
+        // 3 gets half the cpu while it is ready, the time it slept is not paid back
+        let job = &report.jobs[2];
+        assert!(job.finish.unwrap() - job.release - job.blocked <= 40);
+    }
+}
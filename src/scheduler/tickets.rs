@@ -0,0 +1,121 @@
+use crate::sim::workload::Workload;
+use std::collections::HashMap;
+
+// tickets of a task the workload says nothing about
+pub const DEFAULT_TICKETS: u64 = 100;
+// ticks a task runs before the lottery or stride policy decides again
+pub const DEFAULT_QUANTUM: u64 = 1;
+
+/*
+ * Tickets of the proportional share policies. A task holds tickets either
+ * in the base currency or in a named currency. A currency is funded with
+ * base tickets and its funding is split over all the tickets issued in it, so
+ * a currency caps what its tasks get together however many tickets they
+ * hand out. Tickets move between tasks of the same currency with `transfer`.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Tickets {
+    held: HashMap<i32, Holding>,
+    currencies: Vec<Currency>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Holding {
+    tickets: u64,
+    // None is the base currency
+    currency: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Currency {
+    name: String,
+    funding: u64,
+}
+
+impl Tickets {
+    // the workload has been validated, every currency a task names exists
+    pub fn from_workload(workload: &Workload) -> Self {
+        let currencies: Vec<Currency> = workload
+            .currencies
+            .iter()
+            .map(|spec| Currency {
+                name: spec.name.clone(),
+                funding: spec.funding,
+            })
+            .collect();
+        let held = workload
+            .tasks
+            .iter()
+            .map(|spec| {
+                let currency = spec
+                    .currency
+                    .as_ref()
+                    .and_then(|name| currencies.iter().position(|c| &c.name == name));
+                let tickets = spec.tickets.unwrap_or(DEFAULT_TICKETS);
+                (spec.id, Holding { tickets, currency })
+            })
+            .collect();
+        Tickets { held, currencies }
+    }
+
+    fn holding(&self, id: i32) -> Holding {
+        self.held.get(&id).copied().unwrap_or(Holding {
+            tickets: DEFAULT_TICKETS,
+            currency: None,
+        })
+    }
+
+    // tickets the task holds in its own currency
+    pub fn get(&self, id: i32) -> u64 {
+        self.holding(id).tickets
+    }
+
+    // a task keeps its currency, 0 counts as 1
+    pub fn set(&mut self, id: i32, tickets: u64) {
+        let holding = self.holding(id);
+        self.held.insert(
+            id,
+            Holding {
+                tickets: tickets.max(1),
+                ..holding
+            },
+        );
+    }
+
+    pub fn currency(&self, id: i32) -> Option<&str> {
+        let at = self.holding(id).currency?;
+        Some(&self.currencies[at].name)
+    }
+
+    /*
+     * Moves `tickets` from one task to another, e.g. from a client to the
+     * server working for it. Both have to hold tickets in the same currency
+     * and the giver keeps at least one, false when nothing moved.
+     */
+    pub fn transfer(&mut self, from: i32, to: i32, tickets: u64) -> bool {
+        let (giver, taker) = (self.holding(from), self.holding(to));
+        if from == to || giver.currency != taker.currency || tickets >= giver.tickets {
+            return false;
+        }
+        self.set(from, giver.tickets - tickets);
+        self.set(to, taker.tickets + tickets);
+        true
+    }
+
+    // the task's tickets in base tickets
+    pub fn value(&self, id: i32) -> f64 {
+        let holding = self.holding(id);
+        match holding.currency {
+            None => holding.tickets as f64,
+            Some(at) => {
+                let issued: u64 = self
+                    .held
+                    .values()
+                    .filter(|other| other.currency == Some(at))
+                    .map(|other| other.tickets)
+                    .sum();
+                holding.tickets as f64 * self.currencies[at].funding as f64 / issued as f64
+            }
+        }
+    }
+}
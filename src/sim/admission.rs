@@ -0,0 +1,382 @@
+use super::workload::{TaskSpec, Workload};
+use crate::analysis::{self, PeriodicTask, Test};
+use serde::Serialize;
+use std::collections::HashSet;
+
+// the online test a new periodic task has to pass together with the admitted ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    // Liu & Layland utilization bound, rate monotonic with implicit deadlines
+    Bound,
+    // response time analysis in the policy's priority order
+    Rta,
+    // sum of C / min(D, T) at most 1, for edf
+    Density,
+}
+
+pub const CHECK_NAMES: &[&str] = &["bound", "rta", "density"];
+
+impl Check {
+    pub fn by_name(name: &str) -> Option<Check> {
+        match name {
+            "bound" => Some(Check::Bound),
+            "rta" => Some(Check::Rta),
+            "density" => Some(Check::Density),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Check::Bound => "bound",
+            Check::Rta => "rta",
+            Check::Density => "density",
+        }
+    }
+
+    // the cheapest check for the policy, None for the policies without deadlines
+    pub fn by_policy(policy: &str) -> Option<Check> {
+        match policy {
+            "rm" => Some(Check::Bound),
+            "priority" => Some(Check::Rta),
+            "edf" => Some(Check::Density),
+            _ => None,
+        }
+    }
+
+    // passing the check only means something when it holds for the policy's order
+    pub fn holds_for(&self, policy: &str) -> bool {
+        matches!(
+            (self, policy),
+            (Check::Bound | Check::Rta, "rm") | (Check::Rta, "priority") | (Check::Density, "edf")
+        )
+    }
+}
+
+// what admission control made of a task
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "outcome", rename_all = "lowercase")]
+pub enum Admission {
+    Admitted,
+    // admitted with its fallback, `reason` is why it did not fit as asked
+    Degraded {
+        period: u64,
+        rank: i32,
+        reason: String,
+    },
+    Rejected {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AdmissionRecord {
+    pub task: i32,
+    // arrival of the task's first job
+    pub at: u64,
+    #[serde(flatten)]
+    pub admission: Admission,
+}
+
+/*
+ * Online admission control. A periodic task is only admitted when the set of
+ * admitted tasks still passes the check with it, otherwise it is rejected
+ * with the reason the check gave, unless its fallback (a longer period, a
+ * lower rank or both) passes. Aperiodic tasks are not checked.
+ * The checks are sufficient only, except rta, and they ignore blocking.
+*/
+#[derive(Debug, Clone)]
+pub struct AdmissionControl {
+    check: Check,
+    // priority order of rta
+    order: Test,
+    admitted: Vec<PeriodicTask>,
+}
+
+impl AdmissionControl {
+    // None for a policy without a check, see Check::by_policy
+    pub fn for_policy(policy: &str) -> Option<Self> {
+        Some(AdmissionControl {
+            check: Check::by_policy(policy)?,
+            order: Test::by_policy(policy)?,
+            admitted: Vec::new(),
+        })
+    }
+
+    pub fn with_check(mut self, check: Check) -> Self {
+        self.check = check;
+        self
+    }
+
+    pub fn get_check(&self) -> Check {
+        self.check
+    }
+
+    pub fn admitted(&self) -> &[PeriodicTask] {
+        &self.admitted
+    }
+
+    pub fn admit(&mut self, spec: &TaskSpec) -> Admission {
+        let Some(task) = analysis::periodic_task(spec) else {
+            return Admission::Admitted;
+        };
+        let reason = match self.fits(&task) {
+            Ok(()) => {
+                self.admitted.push(task);
+                return Admission::Admitted;
+            }
+            Err(reason) => reason,
+        };
+        let Some(fallback) = spec.fallback else {
+            return Admission::Rejected { reason };
+        };
+        let period = fallback.period.unwrap_or(task.period);
+        let degraded = PeriodicTask {
+            rank: fallback.rank.unwrap_or(task.rank),
+            period,
+            // an implicit deadline stretches with the period
+            deadline: spec.deadline.unwrap_or(period),
+            ..task
+        };
+        match self.fits(&degraded) {
+            Ok(()) => {
+                self.admitted.push(degraded);
+                Admission::Degraded {
+                    period,
+                    rank: degraded.rank,
+                    reason,
+                }
+            }
+            Err(again) => Admission::Rejected {
+                reason: format!("{reason} (with its fallback: {again})"),
+            },
+        }
+    }
+
+    // the task left, its share is free again
+    pub fn remove(&mut self, id: i32) {
+        self.admitted.retain(|task| task.id != id);
+    }
+
+    fn fits(&self, task: &PeriodicTask) -> Result<(), String> {
+        let mut tasks = self.admitted.clone();
+        tasks.push(*task);
+        match self.check {
+            Check::Bound => {
+                if task.deadline < task.period {
+                    return Err(format!(
+                        "deadline {} is shorter than the period {}, the bound needs implicit deadlines",
+                        task.deadline, task.period
+                    ));
+                }
+                let u = analysis::utilization(&tasks);
+                let bound = analysis::liu_layland_bound(tasks.len());
+                if u > bound + 1e-9 {
+                    return Err(format!(
+                        "utilization {u:.3} is above the bound {bound:.3} for {} tasks",
+                        tasks.len()
+                    ));
+                }
+            }
+            Check::Rta => {
+                let result = analysis::run(self.order, &tasks);
+                if let Some(late) = result.tasks.iter().find(|t| t.response.is_none()) {
+                    return Err(if late.id == task.id {
+                        format!("it would respond after its deadline {}", late.deadline)
+                    } else {
+                        format!(
+                            "task {} would respond after its deadline {}",
+                            late.id, late.deadline
+                        )
+                    });
+                }
+            }
+            Check::Density => {
+                let density = analysis::density(&tasks);
+                if density > 1.0 + 1e-9 {
+                    return Err(format!("density {density:.3} is above 1"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * Admits the tasks of the workload in the order their first jobs arrive
+     * and returns the workload that is left, rejected tasks are taken out and
+     * degraded ones run with their fallback. A task that depends on a
+     * rejected task is rejected as well.
+     */
+    pub fn admit_workload(&mut self, workload: &Workload) -> (Workload, Vec<AdmissionRecord>) {
+        let mut order: Vec<&TaskSpec> = workload.tasks.iter().collect();
+        order.sort_by_key(|spec| (spec.arrival, spec.id));
+        let mut records = Vec::new();
+        let mut rejected = HashSet::new();
+        let mut admitted = workload.clone();
+        for spec in order {
+            let admission = match spec.after.iter().find(|id| rejected.contains(*id)) {
+                Some(before) => Admission::Rejected {
+                    reason: format!("it depends on task {before}, which was rejected"),
+                },
+                None => self.admit(spec),
+            };
+            if let Admission::Degraded { period, rank, .. } = &admission {
+                let degraded = admitted.tasks.iter_mut().find(|s| s.id == spec.id).unwrap();
+                degraded.period = Some(*period);
+                degraded.rank = *rank;
+            }
+            if let Admission::Rejected { .. } = &admission {
+                rejected.insert(spec.id);
+            }
+            if spec.period.is_some() || rejected.contains(&spec.id) {
+                records.push(AdmissionRecord {
+                    task: spec.id,
+                    at: spec.arrival,
+                    admission,
+                });
+            }
+        }
+        // dependents of a rejected task could never release a job, also the
+        // ones that arrived before it
+        while let Some((spec, before)) = admitted.tasks.iter().find_map(|spec| {
+            let before = spec.after.iter().find(|id| rejected.contains(*id))?;
+            (!rejected.contains(&spec.id)).then_some((spec, *before))
+        }) {
+            self.remove(spec.id);
+            rejected.insert(spec.id);
+            let admission = Admission::Rejected {
+                reason: format!("it depends on task {before}, which was rejected"),
+            };
+            match records.iter_mut().find(|record| record.task == spec.id) {
+                Some(record) => record.admission = admission,
+                None => records.push(AdmissionRecord {
+                    task: spec.id,
+                    at: spec.arrival,
+                    admission,
+                }),
+            }
+        }
+        admitted.tasks.retain(|spec| !rejected.contains(&spec.id));
+        (admitted, records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::by_name;
+    use crate::sim::Simulator;
+
+    fn workload(text: &str) -> Workload {
+        Workload::parse(text).unwrap()
+    }
+
+    fn outcomes(records: &[AdmissionRecord]) -> Vec<(i32, &str)> {
+        records
+            .iter()
+            .map(|record| {
+                let outcome = match record.admission {
+                    Admission::Admitted => "admitted",
+                    Admission::Degraded { .. } => "degraded",
+                    Admission::Rejected { .. } => "rejected",
+                };
+                (record.task, outcome)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn each_policy_gets_its_own_check() {
+        // U = 0.5 + 0.3 + 0.1, above the rm bound for 3 tasks but rta passes
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "rank": 3, "burst": 1, "period": 2},
+                {"id": 2, "rank": 2, "burst": 3, "period": 10},
+                {"id": 3, "rank": 1, "burst": 1, "period": 10, "arrival": 5}
+            ]}"#,
+        );
+        let (left, records) = AdmissionControl::for_policy("rm")
+            .unwrap()
+            .admit_workload(&w);
+        assert_eq!(
+            outcomes(&records),
+            vec![(1, "admitted"), (2, "admitted"), (3, "rejected")]
+        );
+        assert_eq!(
+            records[2].admission,
+            Admission::Rejected {
+                reason: "utilization 0.900 is above the bound 0.780 for 3 tasks".into()
+            }
+        );
+        assert_eq!(left.tasks.len(), 2);
+
+        let (_, records) = AdmissionControl::for_policy("priority")
+            .unwrap()
+            .admit_workload(&w);
+        assert!(records.iter().all(|r| r.admission == Admission::Admitted));
+
+        // two jobs due 3 ticks after release need 4 ticks, too dense for edf
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "burst": 2, "period": 10, "deadline": 3},
+                {"id": 2, "burst": 2, "period": 10, "deadline": 3},
+                {"id": 3, "burst": 7}
+            ]}"#,
+        );
+        let mut control = AdmissionControl::for_policy("edf").unwrap();
+        let (left, records) = control.admit_workload(&w);
+        assert_eq!(outcomes(&records), vec![(1, "admitted"), (2, "rejected")]);
+        // the aperiodic task is not checked
+        assert_eq!(left.tasks.len(), 2);
+        control.remove(1);
+        assert_eq!(control.admit(&w.tasks[1]), Admission::Admitted);
+    }
+
+    #[test]
+    fn a_rejected_task_falls_back_to_a_longer_period() {
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "burst": 3, "period": 5},
+                {"id": 2, "burst": 5, "period": 10, "fallback": {"period": 20}},
+                {"id": 3, "burst": 4, "period": 10},
+                {"id": 4, "burst": 1, "period": 10, "after": [3]}
+            ]}"#,
+        );
+        let (left, records) = AdmissionControl::for_policy("edf")
+            .unwrap()
+            .admit_workload(&w);
+        // 2 fits at 0.25 instead of 0.5, 3 does not fit and takes 4 with it
+        assert_eq!(
+            outcomes(&records),
+            vec![
+                (1, "admitted"),
+                (2, "degraded"),
+                (3, "rejected"),
+                (4, "rejected")
+            ]
+        );
+        assert_eq!(
+            records[1].admission,
+            Admission::Degraded {
+                period: 20,
+                rank: 0,
+                reason: "density 1.100 is above 1".into(),
+            }
+        );
+        assert_eq!(
+            records[3].admission,
+            Admission::Rejected {
+                reason: "it depends on task 3, which was rejected".into()
+            }
+        );
+        assert_eq!(left.spec(2).unwrap().period, Some(20));
+        assert_eq!(left.tasks.len(), 2);
+
+        // the simulator releases no job of a rejected task
+        let report = Simulator::new(by_name("edf").unwrap())
+            .with_admission(AdmissionControl::for_policy("edf").unwrap())
+            .run(&w);
+        assert_eq!(report.admissions, records);
+        assert!(report.jobs.iter().all(|job| job.task <= 2 && !job.missed));
+    }
+}
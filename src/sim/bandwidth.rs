@@ -0,0 +1,202 @@
+use super::workload::{QuotaSpec, Workload};
+use crate::datastructure::Task;
+use crate::scheduler::Enqueue;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/*
+ * Cpu bandwidth control. A quota lets a task, or all the tasks in and below
+ * a group, run for `budget` ticks in every `period`. Every tick a job runs is
+ * charged to its own quota and to those of its groups. Once one of them is
+ * used up the simulator hands the running job back to the policy and takes
+ * it out again together with every ready job the quota covers, they are held
+ * here with the way they would have been queued. Jobs that become ready before the next period
+ * are held as well. At the start of the next period the budget is refilled
+ * and the held jobs that no other quota holds back go back.
+ * A quota counts as throttled from the first job it held until the refill, a
+ * budget that runs out with nothing else left to run is not a throttle.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Bandwidth {
+    quotas: Vec<Quota>,
+    // the quotas every task is charged to, its own first
+    covers: HashMap<i32, Vec<usize>>,
+    held: Vec<(Task, Enqueue)>,
+}
+
+#[derive(Debug, Clone)]
+struct Quota {
+    owner: String,
+    spec: QuotaSpec,
+    used: u64,
+    exhausted: bool,
+    throttled_at: Option<u64>,
+    throttles: u64,
+    throttled: u64,
+}
+
+// how a quota did over the simulation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuotaRecord {
+    // `task 3` or `group a`
+    pub owner: String,
+    pub budget: u64,
+    pub period: u64,
+    pub throttles: u64,
+    // ticks spent throttled
+    pub throttled: u64,
+}
+
+impl Bandwidth {
+    pub fn new(workload: &Workload) -> Self {
+        let mut bandwidth = Bandwidth::default();
+        let mut index: HashMap<&str, usize> = HashMap::new();
+        for group in &workload.groups {
+            if let Some(spec) = group.quota {
+                index.insert(&group.name, bandwidth.quotas.len());
+                bandwidth.add(format!("group {}", group.name), spec);
+            }
+        }
+        for spec in &workload.tasks {
+            let mut covers = Vec::new();
+            if let Some(quota) = spec.quota {
+                covers.push(bandwidth.quotas.len());
+                bandwidth.add(format!("task {}", spec.id), quota);
+            }
+            if let Some(group) = &spec.group {
+                covers.extend(
+                    workload
+                        .group_path(group)
+                        .iter()
+                        .filter_map(|group| index.get(group.name.as_str())),
+                );
+            }
+            if !covers.is_empty() {
+                bandwidth.covers.insert(spec.id, covers);
+            }
+        }
+        bandwidth
+    }
+
+    fn add(&mut self, owner: String, spec: QuotaSpec) {
+        self.quotas.push(Quota {
+            owner,
+            spec,
+            used: 0,
+            exhausted: false,
+            throttled_at: None,
+            throttles: 0,
+            throttled: 0,
+        });
+    }
+
+    fn quotas_of(&self, id: i32) -> &[usize] {
+        self.covers.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn is_throttled(&self, id: i32) -> bool {
+        self.quotas_of(id)
+            .iter()
+            .any(|&quota| self.quotas[quota].exhausted)
+    }
+
+    // the job when it may be queued now, otherwise it is held until a refill
+    pub fn admit(&mut self, job: Task, why: Enqueue, now: u64) -> Option<(Task, Enqueue)> {
+        if self.is_throttled(job.get_id()) {
+            self.hold(job, why, now);
+            return None;
+        }
+        Some((job, why))
+    }
+
+    /*
+     * Charges the tick that ended at `now` to the job's quotas, returns the
+     * ids of the tasks covered by a quota that ran out. A quota that runs out
+     * right as its period ends is refilled before it would be throttled.
+     */
+    pub fn charge(&mut self, job: &Task, now: u64) -> Vec<i32> {
+        let mut exhausted = Vec::new();
+        for &at in self
+            .covers
+            .get(&job.get_id())
+            .map_or(&[][..], Vec::as_slice)
+        {
+            let quota = &mut self.quotas[at];
+            quota.used += 1;
+            if quota.used >= quota.spec.budget
+                && !now.is_multiple_of(quota.spec.period)
+                && !quota.exhausted
+            {
+                quota.exhausted = true;
+                exhausted.push(at);
+            }
+        }
+        let mut ids: Vec<i32> = self
+            .covers
+            .iter()
+            .filter(|(_, covers)| covers.iter().any(|quota| exhausted.contains(quota)))
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    // takes a throttled job, the running one or one out of the ready set
+    pub fn hold(&mut self, job: Task, why: Enqueue, now: u64) {
+        for &at in self
+            .covers
+            .get(&job.get_id())
+            .map_or(&[][..], Vec::as_slice)
+        {
+            let quota = &mut self.quotas[at];
+            if quota.exhausted && quota.throttled_at.is_none() {
+                quota.throttled_at = Some(now);
+                quota.throttles += 1;
+            }
+        }
+        self.held.push((job, why));
+    }
+
+    // refills the quotas whose period starts at `now`, returns the jobs that may run again
+    pub fn refill(&mut self, now: u64) -> Vec<(Task, Enqueue)> {
+        let mut refilled = false;
+        for quota in &mut self.quotas {
+            if !now.is_multiple_of(quota.spec.period) {
+                continue;
+            }
+            quota.used = 0;
+            refilled |= quota.exhausted;
+            quota.exhausted = false;
+            if let Some(at) = quota.throttled_at.take() {
+                quota.throttled += now - at;
+            }
+        }
+        if !refilled {
+            return Vec::new();
+        }
+        let held = std::mem::take(&mut self.held);
+        let (free, held): (Vec<_>, Vec<_>) = held
+            .into_iter()
+            .partition(|(job, _)| !self.is_throttled(job.get_id()));
+        self.held = held;
+        free
+    }
+
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+
+    // one record per quota, task quotas after group quotas, closing open throttles at `now`
+    pub fn records(&self, now: u64) -> Vec<QuotaRecord> {
+        self.quotas
+            .iter()
+            .map(|quota| QuotaRecord {
+                owner: quota.owner.clone(),
+                budget: quota.spec.budget,
+                period: quota.spec.period,
+                throttles: quota.throttles,
+                throttled: quota.throttled + quota.throttled_at.map_or(0, |at| now - at),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,281 @@
+use super::rng::Rng;
+use super::workload::{IoSpec, SectionSpec, TaskSpec, Workload};
+use crate::datastructure::Criticality;
+
+// periods are picked from a harmonic friendly set to keep the hyperperiod at 200
+pub const PERIODS: &[u64] = &[10, 20, 25, 40, 50, 100, 200];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    // periodic real-time tasks with implicit deadlines
+    Periodic,
+    // one shot cpu bound and io bound tasks arriving over `span` ticks
+    Batch,
+    // a ring of tasks that each hold one resource and want the next one,
+    // deadlocks under a preemptive priority policy without a resource protocol
+    Deadlock,
+    // one shot tasks all arriving at 0, each after one or two earlier tasks
+    Dag,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    pub kind: Kind,
+    pub tasks: usize,
+    pub utilization: f64,
+    pub span: u64,
+    pub seed: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            kind: Kind::Periodic,
+            tasks: 5,
+            utilization: 0.7,
+            span: 100,
+            seed: 1,
+        }
+    }
+}
+
+pub fn generate(options: &Options) -> Workload {
+    let mut rng = Rng::new(options.seed);
+    match options.kind {
+        Kind::Periodic => periodic(options, &mut rng),
+        Kind::Batch => batch(options, &mut rng),
+        Kind::Deadlock => deadlock(options),
+        Kind::Dag => dag(options, &mut rng),
+    }
+}
+
+/*
+ * UUniFast (Bini & Buttazzo): splits the total utilization over n tasks
+ * so every split is equally likely.
+*/
+pub fn uunifast(n: usize, utilization: f64, rng: &mut Rng) -> Vec<f64> {
+    let mut shares = Vec::with_capacity(n);
+    let mut sum = utilization;
+    for i in 1..n {
+        let next = sum * rng.next_f64().powf(1.0 / (n - i) as f64);
+        shares.push(sum - next);
+        sum = next;
+    }
+    if n > 0 {
+        shares.push(sum);
+    }
+    shares
+}
+
+fn periodic(options: &Options, rng: &mut Rng) -> Workload {
+    let tasks = uunifast(options.tasks, options.utilization, rng)
+        .into_iter()
+        .enumerate()
+        .map(|(i, share)| {
+            let period = PERIODS[rng.range(0, PERIODS.len() as u64 - 1) as usize];
+            let burst = ((share * period as f64).round() as u64).clamp(1, period);
+            TaskSpec {
+                id: i as i32 + 1,
+                rank: 0,
+                arrival: 0,
+                burst,
+                period: Some(period),
+                deadline: None,
+                io: None,
+                affinity: None,
+                sections: Vec::new(),
+                after: Vec::new(),
+                group: None,
+                quota: None,
+                nice: 0,
+                tickets: None,
+                currency: None,
+                server: None,
+                criticality: Criticality::Lo,
+                wcet: None,
+                partition: None,
+                fallback: None,
+                firm: None,
+                elastic: None,
+                value: None,
+            }
+        })
+        .collect();
+    Workload {
+        tasks,
+        ..Workload::default()
+    }
+}
+
+// about a third of the batch tasks are interactive and block on io
+fn batch(options: &Options, rng: &mut Rng) -> Workload {
+    let tasks = (0..options.tasks)
+        .map(|i| {
+            let interactive = rng.range(0, 2) == 0;
+            TaskSpec {
+                id: i as i32 + 1,
+                rank: rng.range(0, 4) as i32,
+                arrival: rng.range(0, options.span),
+                burst: if interactive {
+                    rng.range(2, 10)
+                } else {
+                    rng.range(10, 40)
+                },
+                period: None,
+                deadline: None,
+                io: interactive.then(|| IoSpec {
+                    every: rng.range(1, 3),
+                    duration: rng.range(2, 8),
+                }),
+                affinity: None,
+                sections: Vec::new(),
+                after: Vec::new(),
+                group: None,
+                quota: None,
+                nice: 0,
+                tickets: None,
+                currency: None,
+                server: None,
+                criticality: Criticality::Lo,
+                wcet: None,
+                partition: None,
+                fallback: None,
+                firm: None,
+                elastic: None,
+                value: None,
+            }
+        })
+        .collect();
+    Workload {
+        tasks,
+        ..Workload::default()
+    }
+}
+
+/*
+ * Task i arrives at tick i - 1 with rank i and preempts the one before it
+ * after that took its first resource r(i-1). Every task then asks for the
+ * resource of the next one, the last one for r0.
+*/
+fn deadlock(options: &Options) -> Workload {
+    let n = options.tasks.max(2);
+    let tasks = (0..n)
+        .map(|i| {
+            let section = |resource: usize, start, length| SectionSpec {
+                resource: format!("r{resource}"),
+                start,
+                length,
+            };
+            TaskSpec {
+                id: i as i32 + 1,
+                rank: i as i32 + 1,
+                arrival: i as u64,
+                burst: 4,
+                period: None,
+                deadline: None,
+                io: None,
+                affinity: None,
+                sections: vec![section(i, 0, 3), section((i + 1) % n, 1, 2)],
+                after: Vec::new(),
+                group: None,
+                quota: None,
+                nice: 0,
+                tickets: None,
+                currency: None,
+                server: None,
+                criticality: Criticality::Lo,
+                wcet: None,
+                partition: None,
+                fallback: None,
+                firm: None,
+                elastic: None,
+                value: None,
+            }
+        })
+        .collect();
+    Workload {
+        tasks,
+        ..Workload::default()
+    }
+}
+
+// edges only point from lower to higher ids, so there is never a cycle
+fn dag(options: &Options, rng: &mut Rng) -> Workload {
+    let tasks = (0..options.tasks)
+        .map(|i| {
+            let mut after = Vec::new();
+            if i > 0 {
+                for _ in 0..rng.range(1, 2) {
+                    let before = rng.range(0, i as u64 - 1) as i32 + 1;
+                    if !after.contains(&before) {
+                        after.push(before);
+                    }
+                }
+                after.sort_unstable();
+            }
+            TaskSpec {
+                id: i as i32 + 1,
+                rank: 0,
+                arrival: 0,
+                burst: rng.range(1, 10),
+                period: None,
+                deadline: None,
+                io: None,
+                affinity: None,
+                sections: Vec::new(),
+                after,
+                group: None,
+                quota: None,
+                nice: 0,
+                tickets: None,
+                currency: None,
+                server: None,
+                criticality: Criticality::Lo,
+                wcet: None,
+                partition: None,
+                fallback: None,
+                firm: None,
+                elastic: None,
+                value: None,
+            }
+        })
+        .collect();
+    Workload {
+        tasks,
+        ..Workload::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uunifast_splits_total_utilization() {
+        let mut rng = Rng::new(7);
+        let shares = uunifast(8, 0.9, &mut rng);
+        assert_eq!(shares.len(), 8);
+        assert!(shares.iter().all(|u| *u >= 0.0));
+        assert!((shares.iter().sum::<f64>() - 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn same_seed_same_workload() {
+        let options = Options {
+            kind: Kind::Batch,
+            tasks: 10,
+            ..Options::default()
+        };
+        assert_eq!(generate(&options), generate(&options));
+        let periodic = generate(&Options::default());
+        assert!(periodic.validate().is_ok());
+        assert!(periodic.hyperperiod().unwrap() <= 200);
+        let dag = generate(&Options {
+            kind: Kind::Dag,
+            tasks: 12,
+            ..Options::default()
+        });
+        assert!(dag.validate().is_ok());
+        assert!(dag.tasks[1..].iter().all(|spec| !spec.after.is_empty()));
+    }
+}
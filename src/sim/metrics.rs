@@ -0,0 +1,242 @@
+use super::trace::Trace;
+use super::workload::Workload;
+use crate::scheduler::cfs::NICE_0_WEIGHT;
+use crate::scheduler::tickets::Tickets;
+use crate::scheduler::Policy;
+use serde::Serialize;
+use std::collections::HashMap;
+
+// what happened to a single job over the simulation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JobRecord {
+    pub task: i32,
+    pub job: u32,
+    pub release: u64,
+    pub burst: u64,
+    pub abs_deadline: Option<u64>,
+    pub first_run: Option<u64>,
+    pub finish: Option<u64>,
+    pub blocked: u64,
+    pub missed: bool,
+    // dropped to break a deadlock
+    pub aborted: bool,
+    // dropped by an overload strategy, see scheduler::overload, or as a lo
+    // job by mixed criticality
+    pub skipped: bool,
+}
+
+impl JobRecord {
+    pub fn turnaround(&self) -> Option<u64> {
+        self.finish.map(|finish| finish - self.release)
+    }
+
+    pub fn response(&self) -> Option<u64> {
+        self.first_run.map(|first| first - self.release)
+    }
+
+    // time spent ready but not running
+    pub fn waiting(&self) -> Option<u64> {
+        self.turnaround()
+            .map(|t| t.saturating_sub(self.burst + self.blocked))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metrics {
+    pub jobs: usize,
+    pub completed: usize,
+    pub avg_turnaround: f64,
+    pub avg_waiting: f64,
+    pub avg_response: f64,
+    pub max_response: u64,
+    pub throughput: f64,
+    pub utilization: f64,
+    pub context_switches: u64,
+    pub preemptions: u64,
+    // a job resumed on another cpu than it last ran on
+    pub migrations: u64,
+    // a job needed a resource that another job held
+    pub contended: u64,
+    pub deadlocks: u64,
+    pub aborted: usize,
+    pub skipped: usize,
+    // a quota ran out and its tasks were taken off the cpu
+    pub throttles: u64,
+    // ticks quotas spent throttled, summed over the quotas
+    pub throttled: u64,
+    pub deadline_misses: usize,
+    // mean absolute error of the predicted cpu bursts, for sjf, srtf and hrrn
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prediction_error: Option<f64>,
+}
+
+// counters the simulator keeps while running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub elapsed: u64,
+    pub busy: u64,
+    pub context_switches: u64,
+    pub preemptions: u64,
+    pub migrations: u64,
+    pub contended: u64,
+    pub deadlocks: u64,
+    pub throttles: u64,
+    pub throttled: u64,
+    // cpus the busy ticks are spread over, 0 counts as 1
+    pub cpus: u64,
+}
+
+impl Metrics {
+    pub fn from_records(records: &[JobRecord], counters: Counters) -> Metrics {
+        let mean = |values: Vec<u64>| {
+            if values.is_empty() {
+                0.0
+            } else {
+                values.iter().sum::<u64>() as f64 / values.len() as f64
+            }
+        };
+        let completed = records.iter().filter(|r| r.finish.is_some()).count();
+        let elapsed = counters.elapsed.max(1) as f64;
+        Metrics {
+            jobs: records.len(),
+            completed,
+            avg_turnaround: mean(records.iter().filter_map(JobRecord::turnaround).collect()),
+            avg_waiting: mean(records.iter().filter_map(JobRecord::waiting).collect()),
+            avg_response: mean(records.iter().filter_map(JobRecord::response).collect()),
+            max_response: records
+                .iter()
+                .filter_map(JobRecord::response)
+                .max()
+                .unwrap_or(0),
+            throughput: completed as f64 / elapsed,
+            utilization: counters.busy as f64 / (elapsed * counters.cpus.max(1) as f64),
+            context_switches: counters.context_switches,
+            preemptions: counters.preemptions,
+            migrations: counters.migrations,
+            contended: counters.contended,
+            deadlocks: counters.deadlocks,
+            aborted: records.iter().filter(|r| r.aborted).count(),
+            skipped: records.iter().filter(|r| r.skipped).count(),
+            throttles: counters.throttles,
+            throttled: counters.throttled,
+            deadline_misses: records.iter().filter(|r| r.missed).count(),
+            prediction_error: None,
+        }
+    }
+}
+
+// cpu time of a fair share group, counting the tasks in and below it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GroupUsage {
+    pub group: String,
+    pub parent: Option<String>,
+    // as declared, before any change
+    pub weight: u32,
+    pub busy: u64,
+    // of all busy ticks
+    pub share: f64,
+}
+
+// where the cfs policy left a task, in nice 0 ticks
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskVruntime {
+    pub task: i32,
+    pub nice: i32,
+    pub vruntime: f64,
+}
+
+// cpu a proportional share policy gave a task against what its tickets asked for
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TaskShare {
+    pub task: i32,
+    // base value of the task's tickets at the end of the run
+    pub tickets: f64,
+    pub requested: f64,
+    pub measured: f64,
+}
+
+// one entry per task when the policy hands out tickets, in workload order
+pub fn task_shares(
+    workload: &Workload,
+    trace: &Trace,
+    tickets: Option<&Tickets>,
+) -> Vec<TaskShare> {
+    let Some(tickets) = tickets else {
+        return Vec::new();
+    };
+    let tickets: Vec<(i32, f64)> = workload
+        .tasks
+        .iter()
+        .map(|spec| (spec.id, tickets.value(spec.id)))
+        .collect();
+    let ran = ran_per_task(trace);
+    let busy: u64 = ran.values().sum();
+    let issued: f64 = tickets.iter().map(|(_, value)| value).sum();
+    tickets
+        .into_iter()
+        .map(|(task, value)| TaskShare {
+            task,
+            tickets: value,
+            requested: value / issued,
+            measured: ran.get(&task).copied().unwrap_or(0) as f64 / busy.max(1) as f64,
+        })
+        .collect()
+}
+
+// ticks every task ran over the trace
+fn ran_per_task(trace: &Trace) -> HashMap<i32, u64> {
+    let mut ran: HashMap<i32, u64> = HashMap::new();
+    for slice in &trace.slices {
+        if let Some(task) = slice.task {
+            *ran.entry(task).or_default() += slice.end - slice.start;
+        }
+    }
+    ran
+}
+
+// one entry per task the policy keeps a vruntime for, in workload order
+pub fn task_vruntimes(workload: &Workload, policy: &dyn Policy) -> Vec<TaskVruntime> {
+    workload
+        .tasks
+        .iter()
+        .filter_map(|spec| {
+            Some(TaskVruntime {
+                task: spec.id,
+                nice: spec.nice,
+                vruntime: policy.vruntime(spec.id)? as f64 / NICE_0_WEIGHT as f64,
+            })
+        })
+        .collect()
+}
+
+// one entry per group of the workload in declaration order, empty without groups
+pub fn group_usage(workload: &Workload, trace: &Trace) -> Vec<GroupUsage> {
+    let ran = ran_per_task(trace);
+    let total: u64 = ran.values().sum();
+    let mut busy: HashMap<&str, u64> = HashMap::new();
+    for spec in &workload.tasks {
+        let path = spec
+            .group
+            .as_deref()
+            .map(|group| workload.group_path(group))
+            .unwrap_or_default();
+        for group in path {
+            *busy.entry(group.name.as_str()).or_default() +=
+                ran.get(&spec.id).copied().unwrap_or(0);
+        }
+    }
+    workload
+        .groups
+        .iter()
+        .map(|group| {
+            let busy = busy.get(group.name.as_str()).copied().unwrap_or(0);
+            GroupUsage {
+                group: group.name.clone(),
+                parent: group.parent.clone(),
+                weight: group.weight,
+                busy,
+                share: busy as f64 / total.max(1) as f64,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,716 @@
+pub mod admission;
+pub mod bandwidth;
+pub mod generate;
+pub mod metrics;
+pub mod multi;
+pub mod precedence;
+pub mod resource;
+pub mod rng;
+pub mod trace;
+pub mod workload;
+
+use crate::datastructure::Task;
+use crate::scheduler::burst::{BurstPredictor, Prediction};
+use crate::scheduler::mixed::ModeSwitch;
+use crate::scheduler::observer::{EventKind, Observers, SchedulerObserver};
+use crate::scheduler::overload::OverloadEvent;
+use crate::scheduler::partition::PartitionRecord;
+use crate::scheduler::server::ServerRecord;
+use crate::scheduler::{Enqueue, Policy, Tick};
+use admission::{AdmissionControl, AdmissionRecord};
+use bandwidth::{Bandwidth, QuotaRecord};
+use metrics::{
+    group_usage, task_shares, task_vruntimes, Counters, GroupUsage, JobRecord, Metrics, TaskShare,
+    TaskVruntime,
+};
+use precedence::Precedence;
+use resource::{DeadlockRecord, Detection, Level, Protocol, Recovery, Resources};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use trace::Trace;
+use workload::Workload;
+
+// everything a simulation run produced
+#[derive(Debug, Clone, Serialize)]
+pub struct SimReport {
+    pub policy: String,
+    pub horizon: u64,
+    pub metrics: Metrics,
+    pub jobs: Vec<JobRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deadlocks: Vec<DeadlockRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupUsage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub quotas: Vec<QuotaRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub vruntimes: Vec<TaskVruntime>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub shares: Vec<TaskShare>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub predictions: Vec<Prediction>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<ServerRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mode_switches: Vec<ModeSwitch>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub partitions: Vec<PartitionRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub admissions: Vec<AdmissionRecord>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overloads: Vec<OverloadEvent>,
+    #[serde(skip)]
+    pub trace: Trace,
+}
+
+/*
+ * Discrete time simulation of a single cpu. Every tick the simulator
+ * 1. releases the jobs that arrive and wakes the tasks whose io finished
+ * 2. lets the policy preempt the running task or dispatch a new one
+ * 3. charges the tick to the running task and handles completion, io and
+ *    an expired slice
+ * A job that is not done by its absolute deadline counts as a miss, it still
+ * runs to completion if the horizon allows it.
+ * Critical sections in the bursts take and release their resources around
+ * the ticks they cover, under the protocol set with `with_protocol`.
+ * Deadlocks between them are found and handled as set with `with_deadlock`,
+ * an aborted job counts as unfinished.
+ * A job that depends on other tasks is only released to the policy once
+ * their jobs completed. Tasks whose cpu quota ran out are kept away from the
+ * policy until their quota is refilled.
+ * With admission control the periodic tasks are checked as their first jobs
+ * arrive, a rejected task releases no jobs at all.
+ * Registered observers are told about every job the simulator admits,
+ * dispatches, preempts, blocks, wakes and completes, about the rank changes
+ * it sees and about every deadline that passes before its job completed.
+*/
+pub struct Simulator {
+    policy: Box<dyn Policy>,
+    horizon: Option<u64>,
+    protocol: Protocol,
+    level: Level,
+    detection: Detection,
+    recovery: Recovery,
+    admission: Option<AdmissionControl>,
+    observers: Observers,
+}
+
+impl Simulator {
+    pub fn new(policy: Box<dyn Policy>) -> Self {
+        Simulator {
+            policy,
+            horizon: None,
+            protocol: Protocol::None,
+            level: Level::Rank,
+            detection: Detection::Continuous,
+            recovery: Recovery::Report,
+            admission: None,
+            observers: Observers::default(),
+        }
+    }
+
+    pub fn with_horizon(mut self, horizon: Option<u64>) -> Self {
+        self.horizon = horizon;
+        self
+    }
+
+    // `level` has to match the policy's ranks, see Level::by_policy
+    pub fn with_protocol(mut self, protocol: Protocol, level: Level) -> Self {
+        self.protocol = protocol;
+        self.level = level;
+        self
+    }
+
+    pub fn with_deadlock(mut self, detection: Detection, recovery: Recovery) -> Self {
+        self.detection = detection;
+        self.recovery = recovery;
+        self
+    }
+
+    // every run starts from the tasks `admission` admitted already
+    pub fn with_admission(mut self, admission: AdmissionControl) -> Self {
+        self.admission = Some(admission);
+        self
+    }
+
+    // may be called more than once, every observer sees every event
+    pub fn with_observer(mut self, observer: Box<dyn SchedulerObserver>) -> Self {
+        self.observers.register(observer);
+        self
+    }
+
+    pub fn run(&mut self, workload: &Workload) -> SimReport {
+        let admitted;
+        let (workload, admissions) = match &self.admission {
+            Some(admission) => {
+                let (left, records) = admission.clone().admit_workload(workload);
+                admitted = left;
+                (&admitted, records)
+            }
+            None => (workload, Vec::new()),
+        };
+        let horizon = self.horizon.unwrap_or_else(|| workload.default_horizon());
+        let mut pending: VecDeque<Task> = workload.jobs(horizon).into();
+        let mut records = job_records(&pending);
+        let index = record_index(&records);
+        let key = |task: &Task| index[&(task.get_id(), task.get_job())];
+
+        let detection = self.detection;
+        let mut resources = Resources::new(workload, self.protocol, self.level)
+            .with_deadlock(detection, self.recovery);
+        let mut precedence = Precedence::new(workload);
+        let mut bandwidth = Bandwidth::new(workload);
+        let policy = self.policy.as_mut();
+        let observers = &mut self.observers;
+        // deadlines still to pass, only watched for the observers
+        let mut deadlines: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::new();
+        if !observers.is_empty() {
+            deadlines.extend(
+                records
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, record)| Some(Reverse((record.abs_deadline?, i)))),
+            );
+        }
+        let ranks: Vec<i32> = pending.iter().map(Task::get_rank).collect();
+        let mut trace = Trace::new(policy.name());
+        let mut counters = Counters::default();
+        // tasks waiting on io with the tick they wake up at
+        let mut blocked: Vec<(u64, Task)> = Vec::new();
+        // ticks each job ran since it last blocked
+        let mut since_io: Vec<u64> = vec![0; records.len()];
+        let mut running: Option<Task> = None;
+        let mut last_dispatched: Option<usize> = None;
+        let mut done = 0;
+        let mut now = 0;
+
+        while now < horizon && done < records.len() {
+            pass_deadlines(&mut deadlines, &records, &ranks, observers, now);
+            if let Detection::Every(every) = detection {
+                if every > 0 && now % every == 0 {
+                    resources.detect(now);
+                }
+            }
+            for job in resources.aborted() {
+                records[key(&job)].aborted = true;
+                done += 1;
+            }
+            for (job, why) in bandwidth.refill(now) {
+                // a job held on arrival still has to pass the resource protocol
+                let job = match why {
+                    Enqueue::Arrival => resources.admit(job),
+                    _ => Some(job),
+                };
+                if let Some(job) = job {
+                    let (kind, reason) = match why {
+                        Enqueue::Arrival => {
+                            (EventKind::Admitted, "released once its quota refilled")
+                        }
+                        _ => (EventKind::Woken, "its quota refilled"),
+                    };
+                    observers.emit(kind, &job, now, reason);
+                    policy.enqueue(job, now, why);
+                }
+            }
+            for job in resources.admitted() {
+                observers.emit(
+                    EventKind::Admitted,
+                    &job,
+                    now,
+                    "released once its resources were free",
+                );
+                policy.enqueue(job, now, Enqueue::Arrival);
+            }
+            while pending.front().is_some_and(|job| job.get_arrival() <= now) {
+                let job = pending.pop_front().unwrap();
+                let Some(job) = precedence.admit(job) else {
+                    continue;
+                };
+                if let Some(job) = bandwidth
+                    .admit(job, Enqueue::Arrival, now)
+                    .and_then(|(job, _)| resources.admit(job))
+                {
+                    observers.emit(EventKind::Admitted, &job, now, "released");
+                    policy.enqueue(job, now, Enqueue::Arrival);
+                }
+            }
+            let (woken, still_blocked): (Vec<_>, Vec<_>) =
+                blocked.into_iter().partition(|(wake, _)| *wake <= now);
+            blocked = still_blocked;
+            for (_, task) in woken {
+                if let Some((task, why)) = bandwidth.admit(task, Enqueue::Wake, now) {
+                    observers.emit(EventKind::Woken, &task, now, "its io completed");
+                    policy.enqueue(task, now, why);
+                }
+            }
+            policy.on_clock(now);
+
+            if let Some(task) = running.take() {
+                if policy.should_preempt(&task, now) {
+                    counters.preemptions += 1;
+                    observers.emit(EventKind::Preempted, &task, now, "the policy preempted it");
+                    policy.enqueue(task, now, Enqueue::Preempted);
+                } else {
+                    running = enter(&mut resources, observers, task, now);
+                }
+            }
+            for task in policy.rank_changes() {
+                observers.watch_rank(
+                    &task,
+                    now,
+                    "the policy changed its rank while it was queued",
+                );
+            }
+            if running.is_none() {
+                // a job that finds its resource taken waits for it, try the next one
+                running = loop {
+                    for task in resources.woken() {
+                        if let Some((task, why)) = bandwidth.admit(task, Enqueue::Wake, now) {
+                            observers.emit(
+                                EventKind::Woken,
+                                &task,
+                                now,
+                                "its resource was released",
+                            );
+                            policy.enqueue(task, now, why);
+                        }
+                    }
+                    let Some(task) = policy.pick_next(now) else {
+                        break None;
+                    };
+                    if let Some(task) = enter(&mut resources, observers, task, now) {
+                        break Some(task);
+                    }
+                };
+                if let Some(task) = &running {
+                    observers.watch_rank(task, now, "the policy changed its rank while it waited");
+                    observers.emit(EventKind::Dispatched, task, now, "the policy picked it");
+                    let job = key(task);
+                    if last_dispatched.is_some_and(|last| last != job) {
+                        counters.context_switches += 1;
+                    }
+                    last_dispatched = Some(job);
+                    records[job].first_run.get_or_insert(now);
+                }
+            }
+
+            let Some(mut task) = running.take() else {
+                trace.record(0, now, None);
+                now += 1;
+                continue;
+            };
+            trace.record(0, now, Some(task.get_id()));
+            let job = key(&task);
+            let finished = task.run_for(1);
+            let verdict = policy.tick(&mut task, now);
+            observers.watch_rank(&task, now, "the policy changed its rank after a tick");
+            counters.busy += 1;
+            since_io[job] += 1;
+            now += 1;
+            resources.leave(&mut task);
+            for woken in resources.woken() {
+                if let Some((woken, why)) = bandwidth.admit(woken, Enqueue::Wake, now) {
+                    observers.emit(EventKind::Woken, &woken, now, "its resource was released");
+                    policy.enqueue(woken, now, why);
+                }
+            }
+            // a quota ran out, everything it covers leaves the ready set
+            for id in bandwidth.charge(&task, now) {
+                for job in policy.remove(id) {
+                    observers.emit(EventKind::Blocked, &job, now, "its quota ran out");
+                    bandwidth.hold(job, Enqueue::Preempted, now);
+                }
+            }
+
+            if finished {
+                records[job].finish = Some(now);
+                done += 1;
+                policy.on_complete(&task, now);
+                observers.emit(EventKind::Completed, &task, now, "it ran its whole burst");
+                observers.forget(&task);
+                for job in precedence.complete(&task) {
+                    if let Some(job) = bandwidth
+                        .admit(job, Enqueue::Arrival, now)
+                        .and_then(|(job, _)| resources.admit(job))
+                    {
+                        observers.emit(
+                            EventKind::Admitted,
+                            &job,
+                            now,
+                            "the jobs it depends on completed",
+                        );
+                        policy.enqueue(job, now, Enqueue::Arrival);
+                    }
+                }
+                continue;
+            }
+            if let Some(io) = task.get_io() {
+                if since_io[job] >= io.every {
+                    since_io[job] = 0;
+                    records[job].blocked += io.duration;
+                    policy.on_block(&mut task, now);
+                    observers.watch_rank(&task, now, "the policy changed its rank as it blocked");
+                    observers.emit(EventKind::Blocked, &task, now, "it waits for io");
+                    blocked.push((now + io.duration, task));
+                    continue;
+                }
+            }
+            if bandwidth.is_throttled(task.get_id()) {
+                let why = match verdict {
+                    Tick::Expired => Enqueue::Expired,
+                    Tick::Continue => Enqueue::Preempted,
+                };
+                // handed back and taken out again so the policy sees it leave the cpu
+                let id = task.get_id();
+                policy.enqueue(task, now, why);
+                for job in policy.remove(id) {
+                    observers.emit(EventKind::Blocked, &job, now, "its quota ran out");
+                    bandwidth.hold(job, why, now);
+                }
+                continue;
+            }
+            match verdict {
+                Tick::Expired => {
+                    observers.emit(EventKind::Preempted, &task, now, "its slice expired");
+                    policy.enqueue(task, now, Enqueue::Expired)
+                }
+                Tick::Continue => running = Some(task),
+            }
+        }
+        pass_deadlines(&mut deadlines, &records, &ranks, observers, now);
+        for job in resources.aborted() {
+            records[key(&job)].aborted = true;
+        }
+        counters.elapsed = now;
+        counters.contended = resources.contended;
+        counters.deadlocks = resources.deadlocks.len() as u64;
+        let quotas = bandwidth.records(now);
+        counters.throttles = quotas.iter().map(|quota| quota.throttles).sum();
+        counters.throttled = quotas.iter().map(|quota| quota.throttled).sum();
+        let policy_records = policy.records();
+        for event in &policy_records.overloads {
+            if let Some(job) = event.job {
+                records[index[&(event.task, job)]].skipped = true;
+            }
+        }
+        for job in &policy_records.dropped_jobs {
+            records[index[job]].skipped = true;
+        }
+        mark_misses(&mut records, now);
+        let mut metrics = Metrics::from_records(&records, counters);
+        let predictor = policy_records.predictor.as_ref();
+        metrics.prediction_error = predictor.and_then(BurstPredictor::mean_error);
+        SimReport {
+            policy: policy.name().to_string(),
+            horizon: now,
+            metrics,
+            jobs: records,
+            deadlocks: resources.deadlocks,
+            groups: group_usage(workload, &trace),
+            quotas,
+            vruntimes: task_vruntimes(workload, &*policy),
+            shares: task_shares(workload, &trace, policy_records.tickets.as_ref()),
+            predictions: predictor
+                .map(BurstPredictor::predictions)
+                .unwrap_or_default(),
+            servers: policy_records.servers,
+            mode_switches: policy_records.mode_switches,
+            partitions: policy_records.partitions,
+            admissions,
+            overloads: policy_records.overloads,
+            trace,
+        }
+    }
+}
+
+// the job is running unless its resource is taken, then it waits for the resource
+fn enter(
+    resources: &mut Resources,
+    observers: &mut Observers,
+    task: Task,
+    now: u64,
+) -> Option<Task> {
+    if observers.is_empty() {
+        return resources.enter(task, now);
+    }
+    let blocked = task.clone();
+    let entered = resources.enter(task, now);
+    if entered.is_none() {
+        observers.emit(EventKind::Blocked, &blocked, now, "its resource is taken");
+    }
+    entered
+}
+
+// tells the observers about the deadlines up to `now` whose jobs are not done
+fn pass_deadlines(
+    deadlines: &mut BinaryHeap<Reverse<(u64, usize)>>,
+    records: &[JobRecord],
+    ranks: &[i32],
+    observers: &mut Observers,
+    now: u64,
+) {
+    while let Some(&Reverse((deadline, i))) = deadlines.peek() {
+        if deadline > now {
+            break;
+        }
+        deadlines.pop();
+        let record = &records[i];
+        if record.finish.is_none() {
+            observers.deadline_missed(record.task, record.job, ranks[i], deadline);
+        }
+    }
+}
+
+// one empty record per released job, in release order
+pub(crate) fn job_records<'a>(jobs: impl IntoIterator<Item = &'a Task>) -> Vec<JobRecord> {
+    jobs.into_iter()
+        .map(|job| JobRecord {
+            task: job.get_id(),
+            job: job.get_job(),
+            release: job.get_arrival(),
+            burst: job.get_burst(),
+            abs_deadline: job.get_abs_deadline(),
+            first_run: None,
+            finish: None,
+            blocked: 0,
+            missed: false,
+            aborted: false,
+            skipped: false,
+        })
+        .collect()
+}
+
+// (task id, job number) -> position in the records
+pub(crate) fn record_index(records: &[JobRecord]) -> HashMap<(i32, u32), usize> {
+    records
+        .iter()
+        .enumerate()
+        .map(|(i, r)| ((r.task, r.job), i))
+        .collect()
+}
+
+// a job misses when it finished late or its deadline passed unfinished by `now`
+pub(crate) fn mark_misses(records: &mut [JobRecord], now: u64) {
+    for record in records.iter_mut().filter(|record| !record.skipped) {
+        if let Some(deadline) = record.abs_deadline {
+            record.missed = match record.finish {
+                Some(finish) => finish > deadline,
+                None => deadline <= now,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::by_name;
+
+    fn workload(text: &str) -> Workload {
+        Workload::parse(text).unwrap()
+    }
+
+    #[test]
+    fn fifo_runs_to_completion_in_arrival_order() {
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "burst": 3},
+                {"id": 2, "burst": 2, "arrival": 1},
+                {"id": 3, "burst": 1, "arrival": 1}
+            ]}"#,
+        );
+        let report = Simulator::new(by_name("fifo").unwrap()).run(&w);
+        let finish: Vec<Option<u64>> = report.jobs.iter().map(|j| j.finish).collect();
+        assert_eq!(finish, vec![Some(3), Some(5), Some(6)]);
+        assert_eq!(report.metrics.completed, 3);
+        assert_eq!(report.metrics.context_switches, 2);
+        assert_eq!(report.metrics.utilization, 1.0);
+        assert_eq!(report.horizon, 6);
+    }
+
+    #[test]
+    fn priority_preempts_lower_rank() {
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "rank": 1, "burst": 4},
+                {"id": 2, "rank": 5, "burst": 2, "arrival": 1}
+            ]}"#,
+        );
+        let report = Simulator::new(by_name("priority").unwrap()).run(&w);
+        assert_eq!(report.jobs[1].finish, Some(3));
+        assert_eq!(report.jobs[0].finish, Some(6));
+        assert_eq!(report.metrics.preemptions, 1);
+        let order: Vec<Option<i32>> = report.trace.slices.iter().map(|s| s.task).collect();
+        assert_eq!(order, vec![Some(1), Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn edf_meets_deadlines_rm_cannot() {
+        // U = 2/5 + 4/7 = 0.97, schedulable under EDF but not under RM
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "burst": 2, "period": 5},
+                {"id": 2, "burst": 4, "period": 7}
+            ]}"#,
+        );
+        let edf = Simulator::new(by_name("edf").unwrap()).run(&w);
+        assert_eq!(edf.metrics.deadline_misses, 0);
+        let rm = Simulator::new(by_name("rm").unwrap()).run(&w);
+        assert!(rm.metrics.deadline_misses > 0);
+    }
+
+    #[test]
+    fn io_bound_task_is_blocked_off_cpu() {
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "burst": 4, "io": {"every": 2, "duration": 3}},
+                {"id": 2, "burst": 6, "arrival": 1}
+            ]}"#,
+        );
+        let report = Simulator::new(by_name("mlfq").unwrap()).run(&w);
+        assert_eq!(report.jobs[0].blocked, 3);
+        assert_eq!(report.metrics.completed, 2);
+        assert_eq!(report.metrics.utilization, 1.0);
+    }
+
+    #[test]
+    fn ceiling_protocols_bound_priority_inversion() {
+        // low holds the bus, high needs it, medium keeps low off the cpu
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "rank": 1, "burst": 4,
+                 "sections": [{"resource": "bus", "start": 0, "length": 3}]},
+                {"id": 2, "rank": 9, "burst": 2, "arrival": 1,
+                 "sections": [{"resource": "bus", "start": 0, "length": 1}]},
+                {"id": 3, "rank": 5, "burst": 6, "arrival": 2}
+            ]}"#,
+        );
+        let run = |protocol| {
+            let report = Simulator::new(by_name("priority").unwrap())
+                .with_protocol(protocol, Level::Rank)
+                .run(&w);
+            let finish: Vec<Option<u64>> = report.jobs.iter().map(|j| j.finish).collect();
+            (finish, report.metrics.contended)
+        };
+        assert_eq!(run(Protocol::None), (vec![Some(12), Some(11), Some(8)], 1));
+        // high waits for the rest of the bus section only
+        assert_eq!(run(Protocol::Ipcp), (vec![Some(12), Some(5), Some(11)], 0));
+        assert_eq!(run(Protocol::Srp), (vec![Some(12), Some(5), Some(11)], 0));
+    }
+
+    #[test]
+    fn injected_deadlock_is_found_and_broken() {
+        let w = generate::generate(&generate::Options {
+            kind: generate::Kind::Deadlock,
+            tasks: 3,
+            ..generate::Options::default()
+        });
+        let run = |detection, recovery| {
+            Simulator::new(by_name("priority").unwrap())
+                .with_horizon(Some(40))
+                .with_deadlock(detection, recovery)
+                .run(&w)
+        };
+        let report = run(Detection::Continuous, Recovery::Report);
+        assert_eq!(report.deadlocks.len(), 1);
+        assert_eq!(report.deadlocks[0].at, 3);
+        assert_eq!(report.deadlocks[0].tasks, vec![1, 2, 3]);
+        assert_eq!(report.deadlocks[0].resources, vec!["r1", "r2", "r0"]);
+        assert_eq!(report.metrics.completed, 0);
+
+        // checked every 5 ticks the same cycle is found later
+        let report = run(Detection::Every(5), Recovery::Abort);
+        assert_eq!(report.deadlocks[0].at, 5);
+        assert_eq!(report.deadlocks[0].victim, Some(1));
+        assert!(report.jobs[0].aborted);
+        assert_eq!(report.metrics.completed, 2);
+        assert_eq!(report.metrics.aborted, 1);
+
+        let report = run(Detection::Continuous, Recovery::Preempt);
+        assert_eq!(report.deadlocks.len(), 1);
+        assert_eq!(report.metrics.completed, 3);
+        // the victim redid its section, one tick more than the bursts
+        assert_eq!(report.metrics.utilization * report.horizon as f64, 13.0);
+    }
+
+    #[test]
+    fn dependents_wait_for_their_predecessors() {
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "burst": 2},
+                {"id": 2, "burst": 3, "after": [1]},
+                {"id": 3, "burst": 1, "after": [1, 2]},
+                {"id": 4, "burst": 4}
+            ]}"#,
+        );
+        let report = Simulator::new(by_name("fifo").unwrap()).run(&w);
+        let finish: Vec<Option<u64>> = report.jobs.iter().map(|j| j.finish).collect();
+        // 2 is only queued behind 4 once 1 completed
+        assert_eq!(finish, vec![Some(2), Some(9), Some(10), Some(6)]);
+    }
+
+    #[test]
+    fn quota_throttles_a_group_until_its_next_period() {
+        // a may use 4 ticks in every 10, b soaks up the rest
+        let w = workload(
+            r#"{"groups": [{"name": "a", "quota": {"budget": 4, "period": 10}}, {"name": "b"}],
+                "tasks": [
+                {"id": 1, "rank": 5, "burst": 6, "group": "a"},
+                {"id": 2, "rank": 5, "burst": 2, "group": "a"},
+                {"id": 3, "rank": 1, "burst": 8, "group": "b"}
+            ]}"#,
+        );
+        let report = Simulator::new(by_name("priority").unwrap()).run(&w);
+        let finish: Vec<Option<u64>> = report.jobs.iter().map(|j| j.finish).collect();
+        // 1 runs to 4, both a tasks sit out to 10 while 3 runs, then 2 and 1
+        // use up the next budget as they finish, which holds nothing back
+        assert_eq!(finish, vec![Some(14), Some(12), Some(16)]);
+        assert_eq!(report.quotas[0].throttles, 1);
+        assert_eq!(report.quotas[0].throttled, 6);
+        assert_eq!(report.metrics.throttled, 6);
+        assert_eq!(report.groups[0].busy, 8);
+    }
+
+    #[test]
+    fn quota_throttles_a_single_task() {
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "rank": 5, "burst": 5, "quota": {"budget": 3, "period": 10}},
+                {"id": 2, "rank": 1, "burst": 4}
+            ]}"#,
+        );
+        let report = Simulator::new(by_name("priority").unwrap()).run(&w);
+        let finish: Vec<Option<u64>> = report.jobs.iter().map(|j| j.finish).collect();
+        // 1 is held from 3 to 10, 2 runs meanwhile and the cpu idles from 7
+        assert_eq!(finish, vec![Some(12), Some(7)]);
+        assert_eq!(report.quotas[0].owner, "task 1");
+        assert_eq!(report.quotas[0].throttles, 1);
+        assert_eq!(report.quotas[0].throttled, 7);
+    }
+
+    #[test]
+    fn task_quota_inside_a_group_quota() {
+        // 1 may use 2 in every 5 and, together with 2, a may use 6 in every 10
+        let w = workload(
+            r#"{"groups": [{"name": "a", "quota": {"budget": 6, "period": 10}}, {"name": "b"}],
+                "tasks": [
+                {"id": 1, "rank": 5, "burst": 6, "group": "a", "quota": {"budget": 2, "period": 5}},
+                {"id": 2, "rank": 3, "burst": 6, "group": "a"},
+                {"id": 3, "rank": 1, "burst": 10, "group": "b"}
+            ]}"#,
+        );
+        let report = Simulator::new(by_name("priority").unwrap()).run(&w);
+        let finish: Vec<Option<u64>> = report.jobs.iter().map(|j| j.finish).collect();
+        assert_eq!(finish, vec![Some(16), Some(15), Some(22)]);
+        let quotas: Vec<(&str, u64, u64)> = report
+            .quotas
+            .iter()
+            .map(|quota| (quota.owner.as_str(), quota.throttles, quota.throttled))
+            .collect();
+        // the task quota holds 1 from 2 to 5 and from 12 to 15, the group
+        // holds both tasks from 6 to 10 while 3 runs
+        assert_eq!(quotas, vec![("group a", 1, 4), ("task 1", 2, 6)]);
+        assert_eq!(report.metrics.throttled, 10);
+    }
+}
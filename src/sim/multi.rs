@@ -0,0 +1,681 @@
+use super::metrics::{group_usage, Counters, Metrics};
+use super::precedence::Precedence;
+use super::trace::Trace;
+use super::workload::{TaskSpec, Workload};
+use super::{job_records, mark_misses, pass_deadlines, record_index, SimReport};
+use crate::datastructure::avl::AvlTree;
+use crate::datastructure::{Affinity, Task};
+use crate::scheduler::edf::deadline_rank;
+use crate::scheduler::observer::{EventKind, Observers, SchedulerObserver};
+use crate::scheduler::priority::rate_monotonic_rank;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+/*
+ * Discrete time simulation of M identical cpus for real-time task sets.
+ *
+ * Global: one ready tree feeds every cpu, at any tick the M highest ranked
+ * ready jobs run. A job that is preempted may resume on any cpu, that is a
+ * migration. An idle cpu prefers the job that last ran on it.
+ * Partitioned: tasks are packed onto the cpus by utilization before the run
+ * (first, best or worst fit decreasing), every cpu has its own ready tree and
+ * schedules its tasks on its own, jobs never migrate.
+ *
+ * Jobs only ever run on a cpu of their affinity, in the packing as well as
+ * when a global cpu picks its next job.
+ *
+ * The rank of a job comes from the priority: EDF derives it from the absolute
+ * deadline at release, fixed priority keeps the rank of the task and rate
+ * monotonic ranks by period.
+ *
+ * Registered observers are told the same events as by the single cpu
+ * Simulator, a job's rank is fixed at release so there are no demotions or
+ * boosts.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Priority {
+    Edf,
+    FixedPriority,
+    RateMonotonic,
+}
+
+impl Priority {
+    // the single cpu policy with the same ordering
+    pub fn by_policy(name: &str) -> Option<Priority> {
+        match name {
+            "edf" => Some(Priority::Edf),
+            "priority" => Some(Priority::FixedPriority),
+            "rm" => Some(Priority::RateMonotonic),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Edf => "edf",
+            Priority::FixedPriority => "fp",
+            Priority::RateMonotonic => "rm",
+        }
+    }
+
+    fn rank(self, task: &Task) -> i32 {
+        match self {
+            Priority::Edf => deadline_rank(task),
+            Priority::FixedPriority => task.get_rank(),
+            Priority::RateMonotonic => rate_monotonic_rank(task),
+        }
+    }
+}
+
+// bin packing heuristic, tasks are always taken by decreasing utilization
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fit {
+    // the first cpu the task fits on
+    First,
+    // the fullest cpu the task still fits on
+    Best,
+    // the emptiest cpu
+    Worst,
+}
+
+impl Fit {
+    pub fn name(self) -> &'static str {
+        match self {
+            Fit::First => "ffd",
+            Fit::Best => "bfd",
+            Fit::Worst => "wfd",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placement {
+    Global,
+    Partitioned(Fit),
+}
+
+// tasks assigned to one cpu
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Bin {
+    pub tasks: Vec<i32>,
+    pub utilization: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Partition {
+    pub fit: Fit,
+    pub cpus: Vec<Bin>,
+    // tasks that fit nowhere, the simulator puts them on the emptiest cpu
+    // their affinity allows
+    pub unplaced: Vec<i32>,
+}
+
+impl Partition {
+    pub fn is_feasible(&self) -> bool {
+        self.unplaced.is_empty()
+    }
+
+    fn cpu_of(&self) -> HashMap<i32, usize> {
+        self.cpus
+            .iter()
+            .enumerate()
+            .flat_map(|(cpu, bin)| bin.tasks.iter().map(move |&id| (id, cpu)))
+            .collect()
+    }
+}
+
+// burst over period, a task without a period spreads its burst over the horizon
+pub fn task_utilization(spec: &TaskSpec, horizon: u64) -> f64 {
+    let span = spec.period.unwrap_or(horizon).max(1);
+    spec.burst as f64 / span as f64
+}
+
+fn affinities(workload: &Workload) -> HashMap<i32, Affinity> {
+    workload
+        .tasks
+        .iter()
+        .map(|spec| (spec.id, spec.to_task().get_affinity()))
+        .collect()
+}
+
+pub fn partition(workload: &Workload, cpus: usize, fit: Fit, horizon: u64) -> Partition {
+    let mut order: Vec<(i32, f64)> = workload
+        .tasks
+        .iter()
+        .map(|spec| (spec.id, task_utilization(spec, horizon)))
+        .collect();
+    // decreasing utilization, ties by id so the packing is reproducible
+    order.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+
+    let mut bins = vec![Bin::default(); cpus.max(1)];
+    let mut unplaced = Vec::new();
+    // a little slack so 0.1 + 0.2 + 0.7 still fits
+    let fits = |bin: &Bin, u: f64| bin.utilization + u <= 1.0 + 1e-9;
+    let affinity = affinities(workload);
+    for (id, u) in order {
+        let candidates = bins
+            .iter()
+            .enumerate()
+            .filter(|(cpu, bin)| affinity[&id].allows(*cpu) && fits(bin, u));
+        let chosen = match fit {
+            Fit::First => candidates.map(|(cpu, _)| cpu).next(),
+            Fit::Best => candidates
+                .max_by(|a, b| {
+                    a.1.utilization
+                        .total_cmp(&b.1.utilization)
+                        .then(b.0.cmp(&a.0))
+                })
+                .map(|(cpu, _)| cpu),
+            Fit::Worst => candidates
+                .min_by(|a, b| {
+                    a.1.utilization
+                        .total_cmp(&b.1.utilization)
+                        .then(a.0.cmp(&b.0))
+                })
+                .map(|(cpu, _)| cpu),
+        };
+        match chosen {
+            Some(cpu) => {
+                bins[cpu].tasks.push(id);
+                bins[cpu].utilization += u;
+            }
+            None => unplaced.push(id),
+        }
+    }
+    Partition {
+        fit,
+        cpus: bins,
+        unplaced,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CpuReport {
+    pub cpu: usize,
+    pub busy: u64,
+    pub utilization: f64,
+    // partitioned only, the tasks that run on this cpu and the utilization
+    // the packing assigned to it (without tasks that did not fit)
+    pub tasks: Vec<i32>,
+    pub assigned_utilization: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MultiReport {
+    #[serde(flatten)]
+    pub report: SimReport,
+    pub placement: Placement,
+    pub cpus: Vec<CpuReport>,
+    pub partition: Option<Partition>,
+}
+
+pub struct MultiSimulator {
+    cpus: usize,
+    priority: Priority,
+    placement: Placement,
+    horizon: Option<u64>,
+    observers: Observers,
+}
+
+impl MultiSimulator {
+    pub fn new(cpus: usize, priority: Priority, placement: Placement) -> Self {
+        MultiSimulator {
+            cpus: cpus.max(1),
+            priority,
+            placement,
+            horizon: None,
+            observers: Observers::default(),
+        }
+    }
+
+    pub fn with_horizon(mut self, horizon: Option<u64>) -> Self {
+        self.horizon = horizon;
+        self
+    }
+
+    // may be called more than once, every observer sees every event
+    pub fn with_observer(mut self, observer: Box<dyn SchedulerObserver>) -> Self {
+        self.observers.register(observer);
+        self
+    }
+
+    // e.g. global-edf or partitioned-ffd-rm
+    pub fn name(&self) -> String {
+        match self.placement {
+            Placement::Global => format!("global-{}", self.priority.name()),
+            Placement::Partitioned(fit) => {
+                format!("partitioned-{}-{}", fit.name(), self.priority.name())
+            }
+        }
+    }
+
+    pub fn run(&mut self, workload: &Workload) -> MultiReport {
+        let cpus = self.cpus;
+        let horizon = self.horizon.unwrap_or_else(|| workload.default_horizon());
+        let name = self.name();
+
+        // ready tree of every queue and the queue each cpu takes work from
+        let (partition, cpu_queue): (Option<Partition>, Vec<usize>) = match self.placement {
+            Placement::Global => (None, vec![0; cpus]),
+            Placement::Partitioned(fit) => (
+                Some(partition(workload, cpus, fit, horizon)),
+                (0..cpus).collect(),
+            ),
+        };
+        let mut queues: Vec<AvlTree> = (0..=cpu_queue.iter().copied().max().unwrap_or(0))
+            .map(|_| AvlTree::new())
+            .collect();
+        let task_queue: HashMap<i32, usize> = match &partition {
+            Some(partition) => {
+                let mut cpu_of = partition.cpu_of();
+                let affinity = affinities(workload);
+                for &id in &partition.unplaced {
+                    // a task pinned past the last cpu never runs, it is parked on cpu 0
+                    let emptiest = (0..cpus)
+                        .filter(|&cpu| affinity[&id].allows(cpu))
+                        .min_by(|&a, &b| {
+                            partition.cpus[a]
+                                .utilization
+                                .total_cmp(&partition.cpus[b].utilization)
+                        })
+                        .unwrap_or(0);
+                    cpu_of.insert(id, emptiest);
+                }
+                cpu_of
+            }
+            None => HashMap::new(),
+        };
+        let queue_of = |task: &Task| task_queue.get(&task.get_id()).copied().unwrap_or(0);
+
+        let mut pending: VecDeque<Task> = workload.jobs(horizon).into();
+        let mut records = job_records(&pending);
+        let index = record_index(&records);
+        let key = |task: &Task| index[&(task.get_id(), task.get_job())];
+
+        let observers = &mut self.observers;
+        // deadlines still to pass, only watched for the observers
+        let mut deadlines: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::new();
+        if !observers.is_empty() {
+            deadlines.extend(
+                records
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(i, record)| Some(Reverse((record.abs_deadline?, i)))),
+            );
+        }
+        let ranks: Vec<i32> = pending.iter().map(|job| self.priority.rank(job)).collect();
+        let mut trace = Trace::new(&name);
+        let mut counters = Counters {
+            cpus: cpus as u64,
+            ..Counters::default()
+        };
+        let mut busy = vec![0u64; cpus];
+        let mut blocked: Vec<(u64, Task)> = Vec::new();
+        let mut since_io: Vec<u64> = vec![0; records.len()];
+        // cpu each job last ran on
+        let mut last_cpu: Vec<Option<usize>> = vec![None; records.len()];
+        let mut last_dispatched: Vec<Option<usize>> = vec![None; cpus];
+        let mut running: Vec<Option<Task>> = vec![None; cpus];
+        let mut precedence = Precedence::new(workload);
+        // jobs whose dependencies completed in the last tick
+        let mut released: Vec<Task> = Vec::new();
+        let mut done = 0;
+        let mut now = 0;
+
+        while now < horizon && done < records.len() {
+            pass_deadlines(&mut deadlines, &records, &ranks, observers, now);
+            while pending.front().is_some_and(|job| job.get_arrival() <= now) {
+                let job = pending.pop_front().unwrap();
+                released.extend(precedence.admit(job));
+            }
+            for mut job in released.drain(..) {
+                job.set_rank(self.priority.rank(&job));
+                observers.emit(EventKind::Admitted, &job, now, "released");
+                queues[queue_of(&job)].insert(job);
+            }
+            let (woken, still_blocked): (Vec<_>, Vec<_>) =
+                blocked.into_iter().partition(|(wake, _)| *wake <= now);
+            blocked = still_blocked;
+            for (_, task) in woken {
+                observers.emit(EventKind::Woken, &task, now, "its io completed");
+                queues[queue_of(&task)].insert(task);
+            }
+
+            for (queue, ready) in queues.iter_mut().enumerate() {
+                let served: Vec<usize> = (0..cpus).filter(|&cpu| cpu_queue[cpu] == queue).collect();
+                loop {
+                    let idle: Vec<usize> = served
+                        .iter()
+                        .copied()
+                        .filter(|&cpu| running[cpu].is_none())
+                        .collect();
+                    // an idle cpu takes the best job it may run, the top job
+                    // goes back to the cpu it last ran on when that one is idle
+                    let home = ready.peek_highest().and_then(|task| {
+                        last_cpu[key(&task)]
+                            .filter(|cpu| idle.contains(cpu) && task.can_run_on(*cpu))
+                    });
+                    let idle_cpu = home.or_else(|| {
+                        idle.iter()
+                            .copied()
+                            .find(|&cpu| ready.highest_rank_for_cpu(cpu).is_some())
+                    });
+                    let cpu = match idle_cpu {
+                        Some(cpu) => cpu,
+                        None => {
+                            // the lowest ranked running job that a waiting job
+                            // may replace gives way
+                            let victim = served
+                                .iter()
+                                .filter_map(|&cpu| {
+                                    let rank = running[cpu].as_ref()?.get_rank();
+                                    let top = ready.highest_rank_for_cpu(cpu)?;
+                                    (top > rank).then_some((cpu, rank))
+                                })
+                                .min_by_key(|&(cpu, rank)| (rank, std::cmp::Reverse(cpu)));
+                            let Some((cpu, _)) = victim else {
+                                break;
+                            };
+                            let preempted = running[cpu].take().unwrap();
+                            counters.preemptions += 1;
+                            observers.emit(
+                                EventKind::Preempted,
+                                &preempted,
+                                now,
+                                "a higher ranked job needs its cpu",
+                            );
+                            ready.insert(preempted);
+                            cpu
+                        }
+                    };
+                    let task = ready
+                        .pick_next_for_cpu(cpu)
+                        .expect("the cpu was chosen for a waiting job");
+                    let job = key(&task);
+                    if last_cpu[job].is_some_and(|last| last != cpu) {
+                        counters.migrations += 1;
+                    }
+                    if last_dispatched[cpu].is_some_and(|last| last != job) {
+                        counters.context_switches += 1;
+                    }
+                    last_cpu[job] = Some(cpu);
+                    last_dispatched[cpu] = Some(job);
+                    records[job].first_run.get_or_insert(now);
+                    observers.emit(EventKind::Dispatched, &task, now, "a cpu picked it");
+                    running[cpu] = Some(task);
+                }
+            }
+
+            for cpu in 0..cpus {
+                let Some(mut task) = running[cpu].take() else {
+                    trace.record(cpu, now, None);
+                    continue;
+                };
+                trace.record(cpu, now, Some(task.get_id()));
+                let job = key(&task);
+                busy[cpu] += 1;
+                since_io[job] += 1;
+                if task.run_for(1) {
+                    records[job].finish = Some(now + 1);
+                    done += 1;
+                    observers.emit(EventKind::Completed, &task, now, "it ran its whole burst");
+                    released.extend(precedence.complete(&task));
+                    continue;
+                }
+                if let Some(io) = task.get_io() {
+                    if since_io[job] >= io.every {
+                        since_io[job] = 0;
+                        records[job].blocked += io.duration;
+                        observers.emit(EventKind::Blocked, &task, now, "it waits for io");
+                        blocked.push((now + 1 + io.duration, task));
+                        continue;
+                    }
+                }
+                running[cpu] = Some(task);
+            }
+            now += 1;
+        }
+        counters.elapsed = now;
+        counters.busy = busy.iter().sum();
+        mark_misses(&mut records, now);
+
+        let cpu_reports = (0..cpus)
+            .map(|cpu| {
+                let bin = partition.as_ref().map(|p| &p.cpus[cpu]);
+                // the packed tasks and any that did not fit but were put here
+                let mut tasks = bin.map(|bin| bin.tasks.clone()).unwrap_or_default();
+                if let Some(partition) = &partition {
+                    tasks.extend(
+                        partition
+                            .unplaced
+                            .iter()
+                            .filter(|id| task_queue[*id] == cpu),
+                    );
+                }
+                CpuReport {
+                    cpu,
+                    busy: busy[cpu],
+                    utilization: busy[cpu] as f64 / now.max(1) as f64,
+                    tasks,
+                    assigned_utilization: bin.map(|bin| bin.utilization),
+                }
+            })
+            .collect();
+        MultiReport {
+            report: SimReport {
+                policy: name,
+                horizon: now,
+                metrics: Metrics::from_records(&records, counters),
+                jobs: records,
+                deadlocks: Vec::new(),
+                groups: group_usage(workload, &trace),
+                quotas: Vec::new(),
+                vruntimes: Vec::new(),
+                shares: Vec::new(),
+                predictions: Vec::new(),
+                servers: Vec::new(),
+                mode_switches: Vec::new(),
+                partitions: Vec::new(),
+                admissions: Vec::new(),
+                overloads: Vec::new(),
+                trace,
+            },
+            placement: self.placement,
+            cpus: cpu_reports,
+            partition,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::observer::EventLog;
+
+    fn workload(text: &str) -> Workload {
+        Workload::parse(text).unwrap()
+    }
+
+    #[test]
+    fn bin_packing_heuristics() {
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "burst": 5, "period": 10},
+                {"id": 2, "burst": 7, "period": 10},
+                {"id": 3, "burst": 3, "period": 10},
+                {"id": 4, "burst": 2, "period": 10},
+                {"id": 5, "burst": 2, "period": 10}
+            ]}"#,
+        );
+        let tasks = |p: &Partition| p.cpus.iter().map(|b| b.tasks.clone()).collect::<Vec<_>>();
+
+        // decreasing order is 2 (0.7), 1 (0.5), 3 (0.3), 4 (0.2), 5 (0.2)
+        let first = partition(&w, 3, Fit::First, 10);
+        assert_eq!(tasks(&first), vec![vec![2, 3], vec![1, 4, 5], vec![]]);
+        let best = partition(&w, 3, Fit::Best, 10);
+        assert_eq!(tasks(&best), vec![vec![2, 3], vec![1, 4, 5], vec![]]);
+        let worst = partition(&w, 3, Fit::Worst, 10);
+        assert_eq!(tasks(&worst), vec![vec![2], vec![1, 5], vec![3, 4]]);
+        assert!(worst.is_feasible());
+
+        let tight = partition(&w, 1, Fit::First, 10);
+        assert_eq!(tight.unplaced, vec![1, 4, 5]);
+    }
+
+    #[test]
+    fn global_edf_migrates_partitioned_does_not() {
+        // three tasks of utilization above 1/2 on two cpus, no partition fits them
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "burst": 3, "period": 5},
+                {"id": 2, "burst": 4, "period": 7},
+                {"id": 3, "burst": 5, "period": 9}
+            ]}"#,
+        );
+        let global = MultiSimulator::new(2, Priority::Edf, Placement::Global).run(&w);
+        assert_eq!(global.report.policy, "global-edf");
+        assert_eq!(global.report.metrics.deadline_misses, 0);
+        assert!(global.report.metrics.migrations > 0);
+        let busy: u64 = global.cpus.iter().map(|cpu| cpu.busy).sum();
+        let mean = global.cpus.iter().map(|cpu| cpu.utilization).sum::<f64>() / 2.0;
+        assert_eq!(
+            busy,
+            global.report.jobs.iter().map(|j| j.burst).sum::<u64>()
+        );
+        assert!((global.report.metrics.utilization - mean).abs() < 1e-9);
+
+        let partitioned =
+            MultiSimulator::new(2, Priority::Edf, Placement::Partitioned(Fit::First)).run(&w);
+        assert_eq!(partitioned.report.metrics.migrations, 0);
+        assert_eq!(partitioned.partition.as_ref().unwrap().unplaced, vec![3]);
+        assert!(partitioned.report.metrics.deadline_misses > 0);
+        assert_eq!(partitioned.cpus[0].tasks, vec![1]);
+        assert_eq!(partitioned.cpus[1].tasks, vec![2, 3]);
+        assert_eq!(partitioned.report.policy, "partitioned-ffd-edf");
+    }
+
+    #[test]
+    fn jobs_stay_on_their_affinity() {
+        // the highest rank is pinned to cpu 1 and must wait for it
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "rank": 1, "burst": 6},
+                {"id": 2, "rank": 5, "burst": 3, "arrival": 1, "affinity": [1]},
+                {"id": 3, "rank": 9, "burst": 4, "affinity": [1]}
+            ]}"#,
+        );
+        let global = MultiSimulator::new(2, Priority::FixedPriority, Placement::Global).run(&w);
+        let pinned = global
+            .report
+            .trace
+            .slices
+            .iter()
+            .filter(|s| matches!(s.task, Some(2) | Some(3)));
+        assert!(pinned.into_iter().all(|s| s.cpu == 1));
+        let finish: Vec<Option<u64>> = global.report.jobs.iter().map(|j| j.finish).collect();
+        // jobs are listed by release, task 1 keeps cpu 0 although task 2 outranks it
+        assert_eq!(finish, vec![Some(6), Some(4), Some(7)]);
+        assert_eq!(global.report.metrics.preemptions, 0);
+
+        let partitioned = MultiSimulator::new(
+            2,
+            Priority::FixedPriority,
+            Placement::Partitioned(Fit::Worst),
+        )
+        .run(&w);
+        assert_eq!(partitioned.cpus[0].tasks, vec![1]);
+        assert_eq!(partitioned.cpus[1].tasks, vec![3, 2]);
+    }
+
+    #[test]
+    fn global_fp_runs_the_top_ranks() {
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "rank": 1, "burst": 4},
+                {"id": 2, "rank": 2, "burst": 4},
+                {"id": 3, "rank": 3, "burst": 2, "arrival": 1}
+            ]}"#,
+        );
+        let report = MultiSimulator::new(2, Priority::FixedPriority, Placement::Global).run(&w);
+        let finish: Vec<Option<u64>> = report.report.jobs.iter().map(|j| j.finish).collect();
+        // task 3 takes the cpu of task 1, which finishes on the cpu it gets back
+        assert_eq!(finish, vec![Some(6), Some(4), Some(3)]);
+        assert_eq!(report.report.metrics.preemptions, 1);
+        assert_eq!(report.report.metrics.migrations, 0);
+    }
+
+    #[test]
+    fn observers_see_every_cpu() {
+        let w = workload(
+            r#"{"tasks": [
+                {"id": 1, "rank": 1, "burst": 4, "deadline": 4},
+                {"id": 2, "rank": 2, "burst": 4, "io": {"every": 2, "duration": 1}},
+                {"id": 3, "rank": 3, "burst": 2, "arrival": 1}
+            ]}"#,
+        );
+        let log = EventLog::new();
+        let report = MultiSimulator::new(2, Priority::FixedPriority, Placement::Global)
+            .with_observer(Box::new(log.clone()))
+            .run(&w);
+        let kinds: Vec<(&str, i32, u64)> = log
+            .events()
+            .iter()
+            .map(|(kind, event)| (kind.name(), event.task, event.at))
+            .collect();
+        // task 3 takes the cpu of task 1, which gets back the one task 2 left for io
+        assert_eq!(
+            kinds,
+            vec![
+                ("admitted", 1, 0),
+                ("admitted", 2, 0),
+                ("dispatched", 2, 0),
+                ("dispatched", 1, 0),
+                ("admitted", 3, 1),
+                ("preempted", 1, 1),
+                ("dispatched", 3, 1),
+                ("blocked", 2, 1),
+                ("dispatched", 1, 2),
+                ("completed", 3, 2),
+                ("woken", 2, 3),
+                ("dispatched", 2, 3),
+                ("deadline_missed", 1, 4),
+                ("completed", 1, 4),
+                ("completed", 2, 4),
+            ]
+        );
+        assert!(report.report.jobs[0].missed);
+    }
+
+    #[test]
+    fn upward_rank_shortens_the_critical_path() {
+        // 1 -> {2, 3} -> 4 is the long chain, 5 and 6 are ranked above it
+        let mut w = workload(
+            r#"{"tasks": [
+                {"id": 1, "rank": 1, "burst": 2},
+                {"id": 2, "rank": 1, "burst": 5, "after": [1]},
+                {"id": 3, "rank": 1, "burst": 1, "after": [1]},
+                {"id": 4, "rank": 1, "burst": 3, "after": [2, 3]},
+                {"id": 5, "rank": 5, "burst": 4},
+                {"id": 6, "rank": 5, "burst": 4}
+            ]}"#,
+        );
+        let run = |w: &Workload| {
+            MultiSimulator::new(2, Priority::FixedPriority, Placement::Global)
+                .run(w)
+                .report
+        };
+        let report = run(&w);
+        assert_eq!(report.jobs[0].first_run, Some(4));
+        assert_eq!(report.horizon, 14);
+
+        w.rank_by_upward_rank().unwrap();
+        let report = run(&w);
+        assert_eq!(report.jobs[0].first_run, Some(0));
+        assert_eq!(report.jobs[1].first_run, Some(2));
+        assert_eq!(report.horizon, 11);
+    }
+}
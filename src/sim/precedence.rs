@@ -0,0 +1,59 @@
+use super::workload::Workload;
+use crate::datastructure::dag::Dag;
+use crate::datastructure::Task;
+use std::collections::HashSet;
+
+/*
+ * Holds back released jobs until the jobs they depend on completed. Job k of
+ * a task waits for job k of every task in its `after` list, the held job is
+ * handed back from `complete` once the last of them finished.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct Precedence {
+    dag: Dag,
+    completed: HashSet<(i32, u32)>,
+    held: Vec<Task>,
+}
+
+impl Precedence {
+    // the workload has been validated, its edges form no cycle
+    pub fn new(workload: &Workload) -> Self {
+        Precedence {
+            dag: workload.dag().unwrap_or_default(),
+            ..Precedence::default()
+        }
+    }
+
+    fn is_ready(&self, job: &Task) -> bool {
+        self.dag
+            .predecessors(job.get_id())
+            .iter()
+            .all(|before| self.completed.contains(&(*before, job.get_job())))
+    }
+
+    // the job when it may run now, otherwise it is held
+    pub fn admit(&mut self, job: Task) -> Option<Task> {
+        if self.is_ready(&job) {
+            return Some(job);
+        }
+        self.held.push(job);
+        None
+    }
+
+    // the held jobs the completed job was the last one missing for
+    pub fn complete(&mut self, job: &Task) -> Vec<Task> {
+        if self.dag.successors(job.get_id()).is_empty() {
+            return Vec::new();
+        }
+        self.completed.insert((job.get_id(), job.get_job()));
+        let held = std::mem::take(&mut self.held);
+        let (ready, held): (Vec<Task>, Vec<Task>) =
+            held.into_iter().partition(|job| self.is_ready(job));
+        self.held = held;
+        ready
+    }
+
+    pub fn held(&self) -> usize {
+        self.held.len()
+    }
+}
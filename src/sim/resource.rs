@@ -0,0 +1,599 @@
+use super::workload::{SectionSpec, TaskSpec, Workload};
+use crate::datastructure::waitfor::{Deadlock, WaitForGraph};
+use crate::datastructure::Task;
+use crate::scheduler::priority::rate_monotonic_rank;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/*
+ * Shared resources held in critical sections of task bursts.
+ * Every resource has a ceiling, the highest level of the tasks that use it.
+ * Levels are ranks in the order the policy runs the tasks, the workload rank
+ * for fixed priority, shorter periods first for rate monotonic and shorter
+ * relative deadlines first for EDF (the preemption levels of SRP).
+ *
+ * none  a job that finds its resource taken blocks until it is released, a
+ *       medium rank job can keep the holder off the cpu (priority inversion)
+ * ipcp  immediate priority ceiling: a job runs at the ceiling of what it
+ *       holds, nothing that shares a resource with it can preempt it
+ * srp   stack resource policy (Baker): a job only starts once its level is
+ *       above the ceiling of every resource held, it never blocks after that
+ *       and works with EDF too
+ *
+ * With ipcp and srp a job is blocked at most once, by one critical section of
+ * a lower level task, `blocking_times` gives that bound for the analysis.
+ * Without a protocol jobs that take resources in different orders can
+ * deadlock, the wait-for graph finds the cycles and `Recovery` breaks them.
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    None,
+    Ipcp,
+    Srp,
+}
+
+pub const PROTOCOL_NAMES: &[&str] = &["none", "ipcp", "srp"];
+
+impl Protocol {
+    pub fn by_name(name: &str) -> Option<Protocol> {
+        match name {
+            "none" => Some(Protocol::None),
+            "ipcp" => Some(Protocol::Ipcp),
+            "srp" => Some(Protocol::Srp),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Protocol::None => "none",
+            Protocol::Ipcp => "ipcp",
+            Protocol::Srp => "srp",
+        }
+    }
+}
+
+// the order ceilings are computed in, higher runs first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Level {
+    #[default]
+    Rank,
+    RateMonotonic,
+    DeadlineMonotonic,
+}
+
+impl Level {
+    // ipcp raises ranks so it needs the level to be the policy's rank
+    pub fn by_policy(policy: &str, protocol: Protocol) -> Option<Level> {
+        match (policy, protocol) {
+            (_, Protocol::None) => Some(Level::Rank),
+            ("priority", _) => Some(Level::Rank),
+            ("rm", _) => Some(Level::RateMonotonic),
+            ("edf", Protocol::Srp) => Some(Level::DeadlineMonotonic),
+            _ => None,
+        }
+    }
+
+    pub fn of(&self, spec: &TaskSpec) -> i32 {
+        match self {
+            Level::Rank => spec.rank,
+            Level::RateMonotonic => rate_monotonic_rank(&spec.to_task()),
+            Level::DeadlineMonotonic => match spec.deadline.or(spec.period) {
+                Some(deadline) => -(deadline.min(i32::MAX as u64) as i32),
+                None => i32::MIN,
+            },
+        }
+    }
+}
+
+pub fn ceilings(workload: &Workload, level: Level) -> HashMap<String, i32> {
+    let mut ceilings: HashMap<String, i32> = HashMap::new();
+    for spec in &workload.tasks {
+        for section in &spec.sections {
+            let ceiling = ceilings.entry(section.resource.clone()).or_insert(i32::MIN);
+            *ceiling = (*ceiling).max(level.of(spec));
+        }
+    }
+    ceilings
+}
+
+/*
+ * Longest critical section of a lower level task on a resource whose ceiling
+ * is at least the task's level, by task id. Under ipcp and srp that is the
+ * longest a job can wait for lower level work.
+*/
+pub fn blocking_times(workload: &Workload, level: Level) -> HashMap<i32, u64> {
+    let ceilings = ceilings(workload, level);
+    workload
+        .tasks
+        .iter()
+        .map(|spec| {
+            let own = level.of(spec);
+            let blocking = workload
+                .tasks
+                .iter()
+                .filter(|other| level.of(other) < own)
+                .flat_map(|other| &other.sections)
+                .filter(|section| ceilings[&section.resource] >= own)
+                .map(|section| section.length)
+                .max()
+                .unwrap_or(0);
+            (spec.id, blocking)
+        })
+        .collect()
+}
+
+type JobKey = (i32, u32);
+
+fn key(task: &Task) -> JobKey {
+    (task.get_id(), task.get_job())
+}
+
+// ticks of its burst the job already ran
+fn progress(task: &Task) -> u64 {
+    task.get_burst() - task.get_remaining()
+}
+
+// when the wait-for graph is searched for cycles
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Detection {
+    // every time a job blocks on a resource
+    #[default]
+    Continuous,
+    // the whole graph every n ticks
+    Every(u64),
+}
+
+/*
+ * What happens to a deadlock once it is found. The victim is the lowest rank
+ * job of the cycle.
+ * report   only record it, the jobs stay blocked
+ * abort    the victim is dropped, everything it holds is released
+ * preempt  the victim loses the resource of the cycle it holds and is rolled
+ *          back to the start of that section, it runs the section again
+*/
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Recovery {
+    #[default]
+    Report,
+    Abort,
+    Preempt,
+}
+
+pub const RECOVERY_NAMES: &[&str] = &["report", "abort", "preempt"];
+
+impl Recovery {
+    pub fn by_name(name: &str) -> Option<Recovery> {
+        match name {
+            "report" => Some(Recovery::Report),
+            "abort" => Some(Recovery::Abort),
+            "preempt" => Some(Recovery::Preempt),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Recovery::Report => "report",
+            Recovery::Abort => "abort",
+            Recovery::Preempt => "preempt",
+        }
+    }
+}
+
+// a cycle of jobs, tasks[i] waits for resources[i] which tasks[i + 1] holds
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeadlockRecord {
+    pub at: u64,
+    pub tasks: Vec<i32>,
+    pub jobs: Vec<u32>,
+    pub resources: Vec<String>,
+    pub recovery: String,
+    pub victim: Option<i32>,
+}
+
+// resource state of one simulation run
+#[derive(Debug, Default)]
+pub struct Resources {
+    protocol: Protocol,
+    ceilings: HashMap<String, i32>,
+    levels: HashMap<i32, i32>,
+    sections: HashMap<i32, Vec<SectionSpec>>,
+    graph: WaitForGraph<JobKey, String>,
+    // jobs that found the resource taken
+    waiting: HashMap<String, Vec<Task>>,
+    // rank of a job under ipcp before it was raised to a ceiling
+    own_ranks: HashMap<JobKey, i32>,
+    // srp jobs that may not start yet
+    deferred: Vec<Task>,
+    // jobs that may run again, for the simulator to pick up
+    woken: Vec<Task>,
+    aborted: Vec<Task>,
+    detection: Detection,
+    recovery: Recovery,
+    // cycles already reported, so a check on demand does not count them twice
+    reported: HashSet<Vec<JobKey>>,
+    pub deadlocks: Vec<DeadlockRecord>,
+    // times a job found its resource taken
+    pub contended: u64,
+}
+
+impl Resources {
+    pub fn new(workload: &Workload, protocol: Protocol, level: Level) -> Self {
+        Resources {
+            protocol,
+            ceilings: ceilings(workload, level),
+            levels: workload
+                .tasks
+                .iter()
+                .map(|spec| (spec.id, level.of(spec)))
+                .collect(),
+            sections: workload
+                .tasks
+                .iter()
+                .filter(|spec| !spec.sections.is_empty())
+                .map(|spec| (spec.id, spec.sections.clone()))
+                .collect(),
+            ..Resources::default()
+        }
+    }
+
+    pub fn with_deadlock(mut self, detection: Detection, recovery: Recovery) -> Self {
+        self.detection = detection;
+        self.recovery = recovery;
+        self
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn graph(&self) -> &WaitForGraph<JobKey, String> {
+        &self.graph
+    }
+
+    // highest ceiling of the resources held right now
+    pub fn system_ceiling(&self) -> Option<i32> {
+        self.ceilings
+            .iter()
+            .filter(|(resource, _)| self.graph.holder(resource).is_some())
+            .map(|(_, ceiling)| *ceiling)
+            .max()
+    }
+
+    fn level(&self, task: &Task) -> i32 {
+        self.levels.get(&task.get_id()).copied().unwrap_or(i32::MIN)
+    }
+
+    fn may_start(&self, task: &Task) -> bool {
+        self.protocol != Protocol::Srp
+            || self
+                .system_ceiling()
+                .is_none_or(|ceiling| self.level(task) > ceiling)
+    }
+
+    // a released job, None when srp holds it back
+    pub fn admit(&mut self, task: Task) -> Option<Task> {
+        if self.may_start(&task) {
+            return Some(task);
+        }
+        self.deferred.push(task);
+        None
+    }
+
+    // jobs held back by srp that may start now
+    pub fn admitted(&mut self) -> Vec<Task> {
+        let deferred = std::mem::take(&mut self.deferred);
+        let (ready, held): (Vec<Task>, Vec<Task>) =
+            deferred.into_iter().partition(|task| self.may_start(task));
+        self.deferred = held;
+        ready
+    }
+
+    // jobs that got their resource back or were rolled back, ready to run
+    pub fn woken(&mut self) -> Vec<Task> {
+        std::mem::take(&mut self.woken)
+    }
+
+    // jobs dropped to break a deadlock
+    pub fn aborted(&mut self) -> Vec<Task> {
+        std::mem::take(&mut self.aborted)
+    }
+
+    /*
+     * Called before the job runs a tick, takes the resources whose sections
+     * start here. None when one of them is held by another job, the job then
+     * waits for it off the cpu.
+     */
+    pub fn enter(&mut self, mut task: Task, now: u64) -> Option<Task> {
+        let Some(sections) = self.sections.get(&task.get_id()) else {
+            return Some(task);
+        };
+        let at = progress(&task);
+        let job = key(&task);
+        let starting: Vec<String> = sections
+            .iter()
+            .filter(|section| section.start == at)
+            .map(|section| section.resource.clone())
+            .filter(|resource| self.graph.holder(resource) != Some(job))
+            .collect();
+        if let Some(taken) = starting
+            .iter()
+            .find(|resource| self.graph.holder(resource).is_some())
+        {
+            self.contended += 1;
+            self.waiting.entry(taken.clone()).or_default().push(task);
+            let cycle = self.graph.wait(job, taken.clone());
+            if let (Some(cycle), Detection::Continuous) = (cycle, self.detection) {
+                self.resolve(cycle, now);
+            }
+            return None;
+        }
+        for resource in starting {
+            if self.protocol == Protocol::Ipcp {
+                let own = *self.own_ranks.entry(job).or_insert(task.get_rank());
+                let ceiling = self.ceilings[&resource];
+                task.set_rank(task.get_rank().max(ceiling).max(own));
+            }
+            self.graph.acquire(resource, job);
+        }
+        Some(task)
+    }
+
+    // called after the job ran a tick, releases the sections that ended
+    pub fn leave(&mut self, task: &mut Task) {
+        let Some(sections) = self.sections.get(&task.get_id()) else {
+            return;
+        };
+        let at = progress(task);
+        let job = key(task);
+        let ending: Vec<String> = sections
+            .iter()
+            .filter(|section| section.end() == at)
+            .filter(|section| self.graph.holder(&section.resource) == Some(job))
+            .map(|section| section.resource.clone())
+            .collect();
+        if ending.is_empty() {
+            return;
+        }
+        for resource in ending {
+            self.release(&resource);
+        }
+        self.restore_rank(task);
+    }
+
+    // frees the resource and wakes the jobs waiting for it, they retry when they run
+    fn release(&mut self, resource: &String) {
+        self.graph.release(resource);
+        for task in self.waiting.remove(resource).unwrap_or_default() {
+            self.graph.stop_waiting(key(&task));
+            self.woken.push(task);
+        }
+    }
+
+    // ipcp: back to the ceiling of what the job still holds, or its own rank
+    fn restore_rank(&mut self, task: &mut Task) {
+        let job = key(task);
+        let Some(&own) = self.own_ranks.get(&job) else {
+            return;
+        };
+        let held = self
+            .graph
+            .held_by(job)
+            .iter()
+            .map(|resource| self.ceilings[resource])
+            .max();
+        task.set_rank(held.map_or(own, |ceiling| ceiling.max(own)));
+        if held.is_none() {
+            self.own_ranks.remove(&job);
+        }
+    }
+
+    // searches the whole graph, for Detection::Every
+    pub fn detect(&mut self, now: u64) {
+        for cycle in self.graph.cycles() {
+            self.resolve(cycle, now);
+        }
+    }
+
+    fn resolve(&mut self, cycle: Deadlock<JobKey, String>, now: u64) {
+        let mut members = cycle.tasks.clone();
+        members.sort_unstable();
+        if !self.reported.insert(members) {
+            return;
+        }
+        // every job of a cycle is blocked, so all of them are in a waiting list
+        let victim = (self.recovery != Recovery::Report)
+            .then(|| {
+                cycle
+                    .tasks
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(at, job)| Some((at, self.waiting_task(*job)?.get_rank())))
+                    .min_by_key(|(at, rank)| (*rank, cycle.tasks[*at]))
+                    .map(|(at, _)| at)
+            })
+            .flatten();
+        self.deadlocks.push(DeadlockRecord {
+            at: now,
+            tasks: cycle.tasks.iter().map(|job| job.0).collect(),
+            jobs: cycle.tasks.iter().map(|job| job.1).collect(),
+            resources: cycle.resources.clone(),
+            recovery: self.recovery.name().to_string(),
+            victim: victim.map(|at| cycle.tasks[at].0),
+        });
+        let Some(at) = victim else {
+            return;
+        };
+        let job = cycle.tasks[at];
+        // the resource the victim holds in the cycle is the one its predecessor waits for
+        let held = cycle.resources[(at + cycle.tasks.len() - 1) % cycle.tasks.len()].clone();
+        let mut task = self.take_waiting(job).expect("victims are blocked");
+        self.reported.retain(|members| !members.contains(&job));
+        match self.recovery {
+            Recovery::Report => {}
+            Recovery::Abort => {
+                for resource in self.graph.remove(job) {
+                    self.release(&resource);
+                }
+                self.own_ranks.remove(&job);
+                self.aborted.push(task);
+            }
+            Recovery::Preempt => {
+                self.graph.stop_waiting(job);
+                let start = self.sections[&job.0]
+                    .iter()
+                    .filter(|section| section.resource == held && section.start <= progress(&task))
+                    .map(|section| section.start)
+                    .max()
+                    .unwrap_or(0);
+                // sections entered from there on are run again, so they are given up too
+                let redo: Vec<String> = self.sections[&job.0]
+                    .iter()
+                    .filter(|section| section.start >= start)
+                    .filter(|section| self.graph.holder(&section.resource) == Some(job))
+                    .map(|section| section.resource.clone())
+                    .collect();
+                for resource in redo {
+                    self.release(&resource);
+                }
+                task.set_remaining(task.get_burst() - start);
+                self.restore_rank(&mut task);
+                self.woken.push(task);
+            }
+        }
+    }
+
+    fn waiting_task(&self, job: JobKey) -> Option<&Task> {
+        self.waiting
+            .values()
+            .flatten()
+            .find(|task| key(task) == job)
+    }
+
+    fn take_waiting(&mut self, job: JobKey) -> Option<Task> {
+        for queue in self.waiting.values_mut() {
+            if let Some(at) = queue.iter().position(|task| key(task) == job) {
+                return Some(queue.remove(at));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workload() -> Workload {
+        Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "rank": 1, "burst": 6, "period": 30,
+                 "sections": [{"resource": "bus", "start": 1, "length": 4}]},
+                {"id": 2, "rank": 5, "burst": 3, "period": 20,
+                 "sections": [{"resource": "log", "start": 0, "length": 2}]},
+                {"id": 3, "rank": 9, "burst": 2, "period": 10,
+                 "sections": [{"resource": "bus", "start": 0, "length": 1}]},
+                {"id": 4, "rank": 7, "burst": 1, "period": 10,
+                 "sections": [{"resource": "log", "start": 0, "length": 1}]}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn ceilings_and_blocking() {
+        let w = workload();
+        let ceilings = ceilings(&w, Level::Rank);
+        assert_eq!(ceilings["bus"], 9);
+        assert_eq!(ceilings["log"], 7);
+        let blocking = blocking_times(&w, Level::Rank);
+        // 3 waits for the bus section of 1, 4 for the longer of 1 (bus) and 2 (log)
+        assert_eq!(blocking[&3], 4);
+        assert_eq!(blocking[&4], 4);
+        assert_eq!(blocking[&2], 4);
+        assert_eq!(blocking[&1], 0);
+    }
+
+    #[test]
+    fn ipcp_raises_to_the_ceiling_and_back() {
+        let w = workload();
+        let mut resources = Resources::new(&w, Protocol::Ipcp, Level::Rank);
+        let mut low = w.tasks[0].to_task().release(0, 0);
+        low.run_for(1);
+        let mut low = resources.enter(low, 0).unwrap();
+        assert_eq!(low.get_rank(), 9);
+        // the high task cannot get the bus while low holds it
+        let high = w.tasks[2].to_task().release(0, 0);
+        assert!(resources.enter(high, 0).is_none());
+        low.run_for(4);
+        resources.leave(&mut low);
+        assert_eq!(low.get_rank(), 1);
+        assert_eq!(resources.woken().len(), 1);
+        assert_eq!(resources.system_ceiling(), None);
+    }
+
+    // 1 takes a then b, 2 takes b then a, both got their first resource
+    fn crossed(recovery: Recovery) -> (Resources, Task, Task) {
+        let w = Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "rank": 1, "burst": 4, "sections": [
+                    {"resource": "a", "start": 0, "length": 3},
+                    {"resource": "b", "start": 1, "length": 2}]},
+                {"id": 2, "rank": 5, "burst": 4, "sections": [
+                    {"resource": "b", "start": 0, "length": 3},
+                    {"resource": "a", "start": 1, "length": 2}]}
+            ]}"#,
+        )
+        .unwrap();
+        let mut resources = Resources::new(&w, Protocol::None, Level::Rank)
+            .with_deadlock(Detection::Continuous, recovery);
+        let mut jobs = w.jobs(1).into_iter();
+        let mut first = resources.enter(jobs.next().unwrap(), 0).unwrap();
+        let mut second = resources.enter(jobs.next().unwrap(), 0).unwrap();
+        first.run_for(1);
+        second.run_for(1);
+        (resources, first, second)
+    }
+
+    #[test]
+    fn deadlock_is_reported_with_its_cycle() {
+        let (mut resources, first, second) = crossed(Recovery::Report);
+        assert!(resources.enter(first, 2).is_none());
+        assert!(resources.deadlocks.is_empty());
+        assert!(resources.enter(second, 3).is_none());
+        let deadlock = &resources.deadlocks[0];
+        assert_eq!((deadlock.at, deadlock.victim), (3, None));
+        assert_eq!(deadlock.tasks, vec![2, 1]);
+        assert_eq!(deadlock.resources, vec!["a", "b"]);
+        // a check on demand finds the same cycle and does not count it again
+        resources.detect(4);
+        assert_eq!(resources.deadlocks.len(), 1);
+    }
+
+    #[test]
+    fn recovery_breaks_the_cycle_at_the_lowest_rank() {
+        let (mut resources, first, second) = crossed(Recovery::Abort);
+        resources.enter(first, 2);
+        resources.enter(second, 3);
+        assert_eq!(resources.deadlocks[0].victim, Some(1));
+        assert_eq!(resources.aborted()[0].get_id(), 1);
+        let woken = resources.woken();
+        assert_eq!(woken[0].get_id(), 2);
+        assert!(resources.enter(woken[0].clone(), 4).is_some());
+
+        let (mut resources, first, second) = crossed(Recovery::Preempt);
+        resources.enter(first, 2);
+        resources.enter(second, 3);
+        assert!(resources.aborted().is_empty());
+        // 1 gives a up and starts over, 2 gets a when it runs next
+        let woken = resources.woken();
+        let ids: Vec<i32> = woken.iter().map(Task::get_id).collect();
+        assert_eq!(ids, vec![2, 1]);
+        assert_eq!(woken[1].get_remaining(), 4);
+        assert!(resources.enter(woken[0].clone(), 4).is_some());
+        assert!(resources.enter(woken[1].clone(), 4).is_none());
+        assert!(resources.deadlocks.len() == 1);
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+// `task` ran on `cpu` from `start` up to (not including) `end`, None is idle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slice {
+    pub cpu: usize,
+    pub start: u64,
+    pub end: u64,
+    pub task: Option<i32>,
+}
+
+// what ran where over a simulation, consecutive ticks of a task are merged
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Trace {
+    pub policy: String,
+    pub slices: Vec<Slice>,
+}
+
+impl Trace {
+    pub fn new(policy: &str) -> Self {
+        Trace {
+            policy: policy.to_string(),
+            slices: Vec::new(),
+        }
+    }
+
+    // records one tick starting at `now`
+    pub fn record(&mut self, cpu: usize, now: u64, task: Option<i32>) {
+        if let Some(last) = self.slices.iter_mut().rev().find(|slice| slice.cpu == cpu) {
+            if last.task == task && last.end == now {
+                last.end = now + 1;
+                return;
+            }
+        }
+        self.slices.push(Slice {
+            cpu,
+            start: now,
+            end: now + 1,
+            task,
+        });
+    }
+
+    pub fn end(&self) -> u64 {
+        self.slices.iter().map(|s| s.end).max().unwrap_or(0)
+    }
+
+    /*
+     * Renders the trace as a text gantt chart with one row per task, each
+     * column covers `end / width` ticks (rounded up) and is marked when the
+     * task ran at any point in it. Multi cpu traces mark the column with the
+     * cpu number instead of '#'.
+     */
+    pub fn gantt(&self, width: usize) -> String {
+        let end = self.end();
+        let width = width.max(1);
+        let scale = end.div_ceil(width as u64).max(1);
+        let columns = end.div_ceil(scale) as usize;
+        let multi_cpu = self.slices.iter().any(|s| s.cpu > 0);
+        let tasks: BTreeSet<i32> = self.slices.iter().filter_map(|s| s.task).collect();
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} (1 column = {} tick{})",
+            self.policy,
+            scale,
+            if scale == 1 { "" } else { "s" }
+        );
+        let mut axis = vec![' '; columns];
+        for col in (0..columns).step_by(10) {
+            let label = (col as u64 * scale).to_string();
+            for (i, ch) in label.chars().enumerate() {
+                if col + i < columns {
+                    axis[col + i] = ch;
+                }
+            }
+        }
+        let _ = writeln!(out, "{:>6} {}", "", axis.iter().collect::<String>());
+
+        let row = |task: Option<i32>| {
+            let mut cells = vec!['.'; columns];
+            for slice in self.slices.iter().filter(|s| s.task == task) {
+                let first = (slice.start / scale) as usize;
+                let last = ((slice.end - 1) / scale) as usize;
+                for cell in &mut cells[first..=last] {
+                    *cell = if multi_cpu {
+                        char::from_digit(slice.cpu as u32 % 36, 36).unwrap()
+                    } else {
+                        '#'
+                    };
+                }
+            }
+            cells.into_iter().collect::<String>()
+        };
+        for task in &tasks {
+            let _ = writeln!(out, "{:>6} |{}|", format!("T{task}"), row(Some(*task)));
+        }
+        if self.slices.iter().any(|s| s.task.is_none()) {
+            let _ = writeln!(out, "{:>6} |{}|", "idle", row(None));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_ticks_and_renders() {
+        let mut trace = Trace::new("fifo");
+        for (now, task) in [(0, Some(1)), (1, Some(1)), (2, None), (3, Some(2))] {
+            trace.record(0, now, task);
+        }
+        assert_eq!(trace.slices.len(), 3);
+        assert_eq!(trace.slices[0].end, 2);
+
+        let chart = trace.gantt(80);
+        assert!(chart.contains("    T1 |##..|"));
+        assert!(chart.contains("    T2 |...#|"));
+        assert!(chart.contains("  idle |..#.|"));
+    }
+}
@@ -0,0 +1,889 @@
+use crate::datastructure::dag::{Dag, DagError};
+use crate::datastructure::{Affinity, Criticality, Task};
+use crate::scheduler::POLICY_NAMES;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+// longest a periodic workload is simulated for when no horizon is given
+pub const MAX_HORIZON: u64 = 100_000;
+
+/*
+ * A workload as stored on disk
+ * {"tasks": [{"id": 1, "rank": 2, "arrival": 0, "burst": 5, "period": 20}]}
+ * Only `id` and `burst` are required, a task with a period is periodic.
+ * `"affinity": [0, 2]` limits a task to the listed cpus.
+ * `"sections": [{"resource": "bus", "start": 1, "length": 2}]` holds the
+ * resource `bus` from the second tick of every job's burst for two ticks,
+ * sections of different resources may nest.
+ * `"after": [1, 2]` only releases a job once the jobs with the same number
+ * of tasks 1 and 2 completed, tasks joined like this share their period.
+ * `"groups": [{"name": "a", "weight": 2}, {"name": "a/web", "parent": "a"}]`
+ * declares a hierarchy of fair share groups, every task then names the leaf
+ * group it belongs to with `"group": "a/web"`.
+ * `"quota": {"budget": 30, "period": 100}` on a task or a group caps the
+ * cpu it and everything below it may use in every period.
+ * `"tickets": 50` gives a task 50 tickets for the lottery and stride
+ * policies, in the base currency or in the currency it names with
+ * `"currency": "alice"`. `"currencies": [{"name": "alice", "funding": 100}]`
+ * funds a currency with base tickets.
+ * `"servers": [{"name": "s", "kind": "cbs", "budget": 2, "period": 10}]`
+ * declares aperiodic servers, an aperiodic task is served by the one it
+ * names with `"server": "s"`.
+ * `"criticality": "hi"` with `"wcet": {"lo": 2, "hi": 5}` makes a task hi
+ * criticality with a budget of 2 ticks in lo mode and 5 in hi mode, the
+ * burst is what its jobs really take. Tasks are lo criticality by default.
+ * `"partitions": [{"name": "rt", "policy": "rm", "budget": 3, "period": 5}]`
+ * reserves 3 ticks in every 5 for a partition that schedules its tasks with
+ * its own policy, every task then names its partition with `"partition": "rt"`.
+ * `"fallback": {"period": 40, "rank": 1}` lets a periodic task that admission
+ * control rejects run with a longer period or a lower rank instead.
+ * Under overload `"firm": {"m": 2, "k": 3}` lets a periodic task skip jobs as
+ * long as 2 of any 3 meet their deadlines, `"elastic": {"max_period": 40}`
+ * lets its period stretch up to 40 and `"value": 10` is what its jobs are
+ * worth when tasks are shed.
+*/
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Workload {
+    pub tasks: Vec<TaskSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub currencies: Vec<CurrencySpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<ServerSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub partitions: Vec<PartitionSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TaskSpec {
+    pub id: i32,
+    #[serde(default)]
+    pub rank: i32,
+    #[serde(default)]
+    pub arrival: u64,
+    pub burst: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io: Option<IoSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub affinity: Option<Vec<usize>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<SectionSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<QuotaSpec>,
+    // -20 (heaviest) to 19 (lightest), only the cfs policy looks at it
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub nice: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tickets: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(default, skip_serializing_if = "is_lo")]
+    pub criticality: Criticality,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wcet: Option<WcetSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub partition: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<FallbackSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firm: Option<FirmSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elastic: Option<ElasticSpec>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u64>,
+}
+
+// what a periodic task settles for when admission control turns it away
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FallbackSpec {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<i32>,
+}
+
+// budget of a task at each criticality level, hi defaults to lo
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WcetSpec {
+    pub lo: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hi: Option<u64>,
+}
+
+impl WcetSpec {
+    pub fn hi(&self) -> u64 {
+        self.hi.unwrap_or(self.lo)
+    }
+}
+
+// at least `m` of any `k` consecutive jobs have to meet their deadlines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FirmSpec {
+    pub m: u32,
+    pub k: u32,
+}
+
+// the period may stretch up to `max_period`, tasks with a larger elasticity stretch more
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ElasticSpec {
+    pub max_period: u64,
+    #[serde(default = "default_elasticity")]
+    pub elasticity: u32,
+}
+
+// a ticket currency backed by `funding` base tickets
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurrencySpec {
+    pub name: String,
+    pub funding: u64,
+}
+
+/*
+ * A server for aperiodic tasks, `budget` ticks of service every `period`.
+ * A cbs runs under edf, the other kinds under the fixed priority policies
+ * where the server competes with `rank`, or with its period under rm.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerSpec {
+    pub name: String,
+    pub kind: ServerKind,
+    pub budget: u64,
+    pub period: u64,
+    #[serde(default)]
+    pub rank: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerKind {
+    Cbs,
+    Polling,
+    Deferrable,
+    Sporadic,
+}
+
+impl ServerKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ServerKind::Cbs => "cbs",
+            ServerKind::Polling => "polling",
+            ServerKind::Deferrable => "deferrable",
+            ServerKind::Sporadic => "sporadic",
+        }
+    }
+}
+
+// a periodic resource reservation of `budget` ticks every `period` for the tasks of a partition
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionSpec {
+    pub name: String,
+    pub policy: String,
+    pub budget: u64,
+    pub period: u64,
+}
+
+/*
+ * A fair share group, the cpu is split between sibling groups by weight.
+ * A group without a parent hangs off the root, parents are declared before
+ * their children. `changes` sets a new weight at the given ticks.
+*/
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupSpec {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<WeightChange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quota: Option<QuotaSpec>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WeightChange {
+    pub at: u64,
+    pub weight: u32,
+}
+
+// at most `budget` ticks of cpu in every `period`, periods start at multiples of it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaSpec {
+    pub budget: u64,
+    pub period: u64,
+}
+
+impl QuotaSpec {
+    fn check(&self) -> Result<(), String> {
+        if self.budget == 0 || self.period == 0 {
+            return Err("quota budget and period must be at least 1".into());
+        }
+        if self.budget > self.period {
+            return Err(format!(
+                "quota budget {} is longer than its period {}",
+                self.budget, self.period
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_elasticity() -> u32 {
+    1
+}
+
+fn is_zero(nice: &i32) -> bool {
+    *nice == 0
+}
+
+fn is_lo(criticality: &Criticality) -> bool {
+    *criticality == Criticality::Lo
+}
+
+// a critical section, `start` ticks into the burst the job holds `resource` for `length` ticks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionSpec {
+    pub resource: String,
+    pub start: u64,
+    pub length: u64,
+}
+
+impl SectionSpec {
+    pub fn end(&self) -> u64 {
+        self.start + self.length
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IoSpec {
+    pub every: u64,
+    pub duration: u64,
+}
+
+#[derive(Debug)]
+pub enum WorkloadError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for WorkloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkloadError::Io(err) => write!(f, "cannot read workload: {err}"),
+            WorkloadError::Parse(err) => write!(f, "cannot parse workload: {err}"),
+            WorkloadError::Invalid(msg) => write!(f, "invalid workload: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for WorkloadError {}
+
+impl TaskSpec {
+    pub fn to_task(&self) -> Task {
+        let mut task = Task::new(self.id, self.rank, 0)
+            .with_arrival(self.arrival)
+            .with_burst(self.burst)
+            .with_nice(self.nice);
+        if let Some(period) = self.period {
+            task = task.with_period(period);
+        }
+        if let Some(deadline) = self.deadline {
+            task = task.with_deadline(deadline);
+        }
+        if let Some(io) = self.io {
+            task = task.with_io(io.every, io.duration);
+        }
+        if let Some(cpus) = &self.affinity {
+            task = task.with_affinity(Affinity::cpus(cpus));
+        }
+        if let Some(wcet) = self.wcet {
+            task = task.with_criticality(self.criticality, wcet.lo, wcet.hi());
+        } else if self.criticality == Criticality::Hi {
+            task = task.with_criticality(self.criticality, self.burst, self.burst);
+        }
+        task
+    }
+}
+
+impl Workload {
+    pub fn load(path: &Path) -> Result<Workload, WorkloadError> {
+        let text = std::fs::read_to_string(path).map_err(WorkloadError::Io)?;
+        Workload::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Workload, WorkloadError> {
+        let workload: Workload = serde_json::from_str(text).map_err(WorkloadError::Parse)?;
+        workload.validate()?;
+        Ok(workload)
+    }
+
+    pub fn validate(&self) -> Result<(), WorkloadError> {
+        let mut ids = std::collections::HashSet::new();
+        for spec in &self.tasks {
+            let invalid = |msg: &str| WorkloadError::Invalid(format!("task {}: {msg}", spec.id));
+            if !ids.insert(spec.id) {
+                return Err(invalid("duplicate id"));
+            }
+            if spec.burst == 0 {
+                return Err(invalid("burst must be at least 1"));
+            }
+            if spec.period == Some(0) {
+                return Err(invalid("period must be at least 1"));
+            }
+            if spec.deadline == Some(0) {
+                return Err(invalid("deadline must be at least 1"));
+            }
+            if let Some(io) = spec.io {
+                if io.every == 0 {
+                    return Err(invalid("io every must be at least 1"));
+                }
+            }
+            if let Some(cpus) = &spec.affinity {
+                if cpus.is_empty() {
+                    return Err(invalid("affinity must name at least one cpu"));
+                }
+                if let Some(cpu) = cpus.iter().find(|&&cpu| cpu >= Affinity::MAX_CPUS) {
+                    return Err(invalid(&format!(
+                        "affinity cpu {cpu} is past the last cpu {}",
+                        Affinity::MAX_CPUS - 1
+                    )));
+                }
+            }
+            if !(-20..=19).contains(&spec.nice) {
+                return Err(invalid(&format!("nice {} is outside -20 to 19", spec.nice)));
+            }
+            if spec.tickets == Some(0) {
+                return Err(invalid("tickets must be at least 1"));
+            }
+            if let Some(currency) = &spec.currency {
+                if !self.currencies.iter().any(|c| &c.name == currency) {
+                    return Err(invalid(&format!("no currency {currency}")));
+                }
+            }
+            if let Some(quota) = &spec.quota {
+                quota.check().map_err(|msg| invalid(&msg))?;
+            }
+            if let Some(wcet) = spec.wcet {
+                if wcet.lo == 0 {
+                    return Err(invalid("wcet must be at least 1"));
+                }
+                if wcet.hi() < wcet.lo {
+                    return Err(invalid(&format!(
+                        "hi wcet {} is below the lo wcet {}",
+                        wcet.hi(),
+                        wcet.lo
+                    )));
+                }
+                if spec.criticality == Criticality::Lo && wcet.hi() != wcet.lo {
+                    return Err(invalid("a lo task only has a lo wcet"));
+                }
+            }
+            if let Some(server) = &spec.server {
+                if !self.servers.iter().any(|s| &s.name == server) {
+                    return Err(invalid(&format!("no server {server}")));
+                }
+                if spec.period.is_some() {
+                    return Err(invalid("only aperiodic tasks go through a server"));
+                }
+            }
+            if let Some(fallback) = spec.fallback {
+                let Some(period) = spec.period else {
+                    return Err(invalid("only periodic tasks have a fallback"));
+                };
+                if fallback.period.is_none() && fallback.rank.is_none() {
+                    return Err(invalid("fallback needs a period or a rank"));
+                }
+                if let Some(shorter) = fallback.period.filter(|&longer| longer < period) {
+                    return Err(invalid(&format!(
+                        "fallback period {shorter} is shorter than the period {period}"
+                    )));
+                }
+                if let Some(higher) = fallback.rank.filter(|&lower| lower > spec.rank) {
+                    return Err(invalid(&format!(
+                        "fallback rank {higher} is above the rank {}",
+                        spec.rank
+                    )));
+                }
+            }
+            if let Some(firm) = spec.firm {
+                if spec.period.is_none() {
+                    return Err(invalid("only periodic tasks are firm"));
+                }
+                if firm.m == 0 || firm.m > firm.k {
+                    return Err(invalid(&format!(
+                        "firm m {} is not between 1 and k {}",
+                        firm.m, firm.k
+                    )));
+                }
+            }
+            if let Some(elastic) = spec.elastic {
+                let Some(period) = spec.period else {
+                    return Err(invalid("only periodic tasks are elastic"));
+                };
+                if elastic.max_period < period {
+                    return Err(invalid(&format!(
+                        "elastic max period {} is shorter than the period {period}",
+                        elastic.max_period
+                    )));
+                }
+                if elastic.elasticity == 0 {
+                    return Err(invalid("elasticity must be at least 1"));
+                }
+            }
+            for (i, section) in spec.sections.iter().enumerate() {
+                if section.resource.is_empty() {
+                    return Err(invalid("section needs a resource name"));
+                }
+                if section.length == 0 {
+                    return Err(invalid("section length must be at least 1"));
+                }
+                if section.end() > spec.burst {
+                    return Err(invalid(&format!(
+                        "section on {} ends at {}, past the burst",
+                        section.resource,
+                        section.end()
+                    )));
+                }
+                let overlaps = spec.sections[..i].iter().any(|other| {
+                    other.resource == section.resource
+                        && other.start < section.end()
+                        && section.start < other.end()
+                });
+                if overlaps {
+                    return Err(invalid(&format!(
+                        "sections on {} overlap",
+                        section.resource
+                    )));
+                }
+            }
+        }
+        for spec in &self.tasks {
+            for before in spec.after.iter().filter_map(|id| self.spec(*id)) {
+                let falls_back =
+                    |spec: &TaskSpec| spec.fallback.is_some_and(|f| f.period.is_some());
+                if falls_back(before) || falls_back(spec) {
+                    return Err(WorkloadError::Invalid(format!(
+                        "task {}: tasks joined by after cannot fall back to another period",
+                        spec.id
+                    )));
+                }
+                if before.period != spec.period {
+                    return Err(WorkloadError::Invalid(format!(
+                        "task {}: depends on task {} with a different period",
+                        spec.id, before.id
+                    )));
+                }
+            }
+        }
+        self.dag()
+            .map_err(|err| WorkloadError::Invalid(err.to_string()))?;
+        self.validate_currencies()?;
+        self.validate_servers()?;
+        self.validate_partitions()?;
+        self.validate_groups()
+    }
+
+    fn validate_partitions(&self) -> Result<(), WorkloadError> {
+        let mut names = std::collections::HashSet::new();
+        for partition in &self.partitions {
+            let invalid =
+                |msg: &str| WorkloadError::Invalid(format!("partition {}: {msg}", partition.name));
+            if partition.name.is_empty() {
+                return Err(WorkloadError::Invalid("partition needs a name".into()));
+            }
+            if !names.insert(partition.name.as_str()) {
+                return Err(invalid("duplicate name"));
+            }
+            if partition.policy == "partitioned"
+                || !POLICY_NAMES.contains(&partition.policy.as_str())
+            {
+                return Err(invalid(&format!("no policy {}", partition.policy)));
+            }
+            if partition.budget == 0 || partition.period == 0 {
+                return Err(invalid("budget and period must be at least 1"));
+            }
+            if partition.budget > partition.period {
+                return Err(invalid(&format!(
+                    "budget {} is longer than its period {}",
+                    partition.budget, partition.period
+                )));
+            }
+        }
+        let reserved: f64 = self
+            .partitions
+            .iter()
+            .map(|p| p.budget as f64 / p.period as f64)
+            .sum();
+        if reserved > 1.0 + 1e-9 {
+            return Err(WorkloadError::Invalid(format!(
+                "partitions reserve {reserved:.3} of the cpu"
+            )));
+        }
+        for spec in &self.tasks {
+            let invalid = |msg: &str| WorkloadError::Invalid(format!("task {}: {msg}", spec.id));
+            match &spec.partition {
+                None if !self.partitions.is_empty() => return Err(invalid("needs a partition")),
+                Some(name) if !names.contains(name.as_str()) => {
+                    return Err(invalid(&format!("no partition {name}")))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_servers(&self) -> Result<(), WorkloadError> {
+        let mut names = std::collections::HashSet::new();
+        for server in &self.servers {
+            let invalid =
+                |msg: &str| WorkloadError::Invalid(format!("server {}: {msg}", server.name));
+            if server.name.is_empty() {
+                return Err(WorkloadError::Invalid("server needs a name".into()));
+            }
+            if !names.insert(server.name.as_str()) {
+                return Err(invalid("duplicate name"));
+            }
+            if server.budget == 0 || server.period == 0 {
+                return Err(invalid("budget and period must be at least 1"));
+            }
+            if server.budget > server.period {
+                return Err(invalid(&format!(
+                    "budget {} is longer than its period {}",
+                    server.budget, server.period
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_currencies(&self) -> Result<(), WorkloadError> {
+        let mut names = std::collections::HashSet::new();
+        for currency in &self.currencies {
+            let invalid =
+                |msg: &str| WorkloadError::Invalid(format!("currency {}: {msg}", currency.name));
+            if currency.name.is_empty() {
+                return Err(WorkloadError::Invalid("currency needs a name".into()));
+            }
+            if !names.insert(currency.name.as_str()) {
+                return Err(invalid("duplicate name"));
+            }
+            if currency.funding == 0 {
+                return Err(invalid("funding must be at least 1"));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_groups(&self) -> Result<(), WorkloadError> {
+        let mut names = std::collections::HashSet::new();
+        for group in &self.groups {
+            let invalid =
+                |msg: &str| WorkloadError::Invalid(format!("group {}: {msg}", group.name));
+            if group.name.is_empty() {
+                return Err(WorkloadError::Invalid("group needs a name".into()));
+            }
+            if let Some(parent) = &group.parent {
+                if !names.contains(parent.as_str()) {
+                    return Err(invalid(&format!(
+                        "parent {parent} has to be declared before it"
+                    )));
+                }
+            }
+            if !names.insert(group.name.as_str()) {
+                return Err(invalid("duplicate name"));
+            }
+            if group.weight == 0 || group.changes.iter().any(|change| change.weight == 0) {
+                return Err(invalid("weight must be at least 1"));
+            }
+            if let Some(quota) = &group.quota {
+                quota.check().map_err(|msg| invalid(&msg))?;
+            }
+        }
+        for spec in &self.tasks {
+            let invalid = |msg: &str| WorkloadError::Invalid(format!("task {}: {msg}", spec.id));
+            match &spec.group {
+                None if !self.groups.is_empty() => return Err(invalid("needs a group")),
+                None => {}
+                Some(name) if !names.contains(name.as_str()) => {
+                    return Err(invalid(&format!("no group {name}")))
+                }
+                Some(name) => {
+                    let inner = self
+                        .groups
+                        .iter()
+                        .any(|group| group.parent.as_ref() == Some(name));
+                    if inner {
+                        return Err(invalid(&format!(
+                            "group {name} has groups below it, tasks go in leaf groups"
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn spec(&self, id: i32) -> Option<&TaskSpec> {
+        self.tasks.iter().find(|spec| spec.id == id)
+    }
+
+    // the `after` edges, every task costs its burst
+    pub fn dag(&self) -> Result<Dag, DagError> {
+        let mut dag = Dag::new();
+        for spec in &self.tasks {
+            dag.add_task(spec.id, spec.burst);
+        }
+        for spec in &self.tasks {
+            for &before in &spec.after {
+                dag.add_edge(before, spec.id)?;
+            }
+        }
+        Ok(dag)
+    }
+
+    // every task ranked by the longest chain of work it starts, see Dag::upward_ranks
+    pub fn rank_by_upward_rank(&mut self) -> Result<(), DagError> {
+        let ranks = self.dag()?.upward_ranks();
+        for spec in &mut self.tasks {
+            spec.rank = i32::try_from(ranks[&spec.id]).unwrap_or(i32::MAX);
+        }
+        Ok(())
+    }
+
+    pub fn tasks(&self) -> Vec<Task> {
+        self.tasks.iter().map(TaskSpec::to_task).collect()
+    }
+
+    pub fn is_mixed_criticality(&self) -> bool {
+        self.tasks
+            .iter()
+            .any(|spec| spec.criticality == Criticality::Hi)
+    }
+
+    pub fn has_quotas(&self) -> bool {
+        self.tasks.iter().any(|spec| spec.quota.is_some())
+            || self.groups.iter().any(|group| group.quota.is_some())
+    }
+
+    // the group and the groups above it, innermost first
+    pub fn group_path(&self, group: &str) -> Vec<&GroupSpec> {
+        let mut path = Vec::new();
+        let mut at = Some(group);
+        while let Some(name) = at {
+            let Some(group) = self.groups.iter().find(|g| g.name == name) else {
+                break;
+            };
+            path.push(group);
+            at = group.parent.as_deref();
+        }
+        path
+    }
+
+    pub fn is_periodic(&self) -> bool {
+        self.tasks.iter().any(|spec| spec.period.is_some())
+    }
+
+    // least common multiple of the periods, capped at MAX_HORIZON
+    pub fn hyperperiod(&self) -> Option<u64> {
+        let mut lcm: u64 = 1;
+        for period in self.tasks.iter().filter_map(|spec| spec.period) {
+            lcm = lcm / gcd(lcm, period) * period;
+            if lcm >= MAX_HORIZON {
+                return Some(MAX_HORIZON);
+            }
+        }
+        self.is_periodic().then_some(lcm)
+    }
+
+    /*
+     * How long to simulate when no horizon is given. A periodic workload runs
+     * past its last arrival for one hyperperiod, anything else gets an upper
+     * bound on the time every job needs, the simulator stops early once all
+     * jobs are done.
+     */
+    pub fn default_horizon(&self) -> u64 {
+        let last_arrival = self.tasks.iter().map(|s| s.arrival).max().unwrap_or(0);
+        match self.hyperperiod() {
+            Some(hyper) => (last_arrival + hyper).min(MAX_HORIZON),
+            None => {
+                let work: u64 = self
+                    .tasks
+                    .iter()
+                    .map(|spec| {
+                        let blocked = spec
+                            .io
+                            .map_or(0, |io| (spec.burst / io.every + 1) * io.duration);
+                        spec.burst + blocked
+                    })
+                    .sum();
+                // a quota of budget b every p ticks stretches the work by up to p / b
+                // and so does a server or a partition
+                let quotas = self
+                    .tasks
+                    .iter()
+                    .filter_map(|spec| spec.quota)
+                    .chain(self.groups.iter().filter_map(|group| group.quota))
+                    .chain(self.servers.iter().map(|server| QuotaSpec {
+                        budget: server.budget,
+                        period: server.period,
+                    }))
+                    .chain(self.partitions.iter().map(|partition| QuotaSpec {
+                        budget: partition.budget,
+                        period: partition.period,
+                    }));
+                let (stretch, period) = quotas.fold((1, 0), |(stretch, period), quota| {
+                    (
+                        stretch.max(quota.period.div_ceil(quota.budget)),
+                        period.max(quota.period),
+                    )
+                });
+                last_arrival + work * stretch + period + 1
+            }
+        }
+    }
+
+    // every job released before `horizon`, ordered by release time then id
+    pub fn jobs(&self, horizon: u64) -> Vec<Task> {
+        let mut jobs = Vec::new();
+        for task in self.tasks() {
+            match task.get_period() {
+                Some(period) => {
+                    let mut release = task.get_arrival();
+                    let mut job = 0;
+                    while release < horizon {
+                        jobs.push(task.release(job, release));
+                        release += period;
+                        job += 1;
+                    }
+                }
+                None => jobs.push(task.release(0, task.get_arrival())),
+            }
+        }
+        jobs.sort_by_key(|job| (job.get_arrival(), job.get_id()));
+        jobs
+    }
+}
+
+pub fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_expand_jobs() {
+        let workload = Workload::parse(
+            r#"{"tasks": [
+                {"id": 1, "burst": 2, "period": 4},
+                {"id": 2, "burst": 1, "period": 6, "deadline": 3},
+                {"id": 3, "burst": 5, "arrival": 1, "io": {"every": 2, "duration": 3}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(workload.hyperperiod(), Some(12));
+        assert_eq!(workload.default_horizon(), 13);
+
+        let jobs = workload.jobs(12);
+        let releases: Vec<(i32, u64)> =
+            jobs.iter().map(|j| (j.get_id(), j.get_arrival())).collect();
+        assert_eq!(
+            releases,
+            vec![(1, 0), (2, 0), (3, 1), (1, 4), (2, 6), (1, 8)]
+        );
+        assert_eq!(jobs[4].get_abs_deadline(), Some(9));
+        assert_eq!(jobs[4].get_job(), 1);
+    }
+
+    #[test]
+    fn rejects_invalid_tasks() {
+        let dup = r#"{"tasks": [{"id": 1, "burst": 2}, {"id": 1, "burst": 3}]}"#;
+        assert!(matches!(
+            Workload::parse(dup),
+            Err(WorkloadError::Invalid(_))
+        ));
+        let zero = r#"{"tasks": [{"id": 1, "burst": 0}]}"#;
+        assert!(matches!(
+            Workload::parse(zero),
+            Err(WorkloadError::Invalid(_))
+        ));
+        let section = r#"{"tasks": [{"id": 1, "burst": 2,
+            "sections": [{"resource": "bus", "start": 1, "length": 2}]}]}"#;
+        assert!(matches!(
+            Workload::parse(section),
+            Err(WorkloadError::Invalid(_))
+        ));
+        let cycle = r#"{"tasks": [{"id": 1, "burst": 2, "after": [2]},
+            {"id": 2, "burst": 1, "after": [1]}]}"#;
+        assert_eq!(
+            Workload::parse(cycle).unwrap_err().to_string(),
+            "invalid workload: dependency cycle 1 -> 2 -> 1"
+        );
+        let inner = r#"{"groups": [{"name": "a"}, {"name": "a/web", "parent": "a"}],
+            "tasks": [{"id": 1, "burst": 2, "group": "a"}]}"#;
+        assert_eq!(
+            Workload::parse(inner).unwrap_err().to_string(),
+            "invalid workload: task 1: group a has groups below it, tasks go in leaf groups"
+        );
+        let currency = r#"{"tasks": [{"id": 1, "burst": 2, "currency": "alice"}]}"#;
+        assert_eq!(
+            Workload::parse(currency).unwrap_err().to_string(),
+            "invalid workload: task 1: no currency alice"
+        );
+        let nice = r#"{"tasks": [{"id": 1, "burst": 2, "nice": 20}]}"#;
+        assert_eq!(
+            Workload::parse(nice).unwrap_err().to_string(),
+            "invalid workload: task 1: nice 20 is outside -20 to 19"
+        );
+        let quota = r#"{"tasks": [{"id": 1, "burst": 2, "quota": {"budget": 5, "period": 4}}]}"#;
+        assert_eq!(
+            Workload::parse(quota).unwrap_err().to_string(),
+            "invalid workload: task 1: quota budget 5 is longer than its period 4"
+        );
+        let served = r#"{"servers": [{"name": "s", "kind": "cbs", "budget": 2, "period": 4}],
+            "tasks": [{"id": 1, "burst": 2, "period": 10, "server": "s"}]}"#;
+        assert_eq!(
+            Workload::parse(served).unwrap_err().to_string(),
+            "invalid workload: task 1: only aperiodic tasks go through a server"
+        );
+        let wcet = r#"{"tasks": [{"id": 1, "burst": 2, "criticality": "hi", "wcet": {"lo": 3, "hi": 2}}]}"#;
+        assert_eq!(
+            Workload::parse(wcet).unwrap_err().to_string(),
+            "invalid workload: task 1: hi wcet 2 is below the lo wcet 3"
+        );
+        let fallback =
+            r#"{"tasks": [{"id": 1, "burst": 2, "period": 10, "fallback": {"period": 5}}]}"#;
+        assert_eq!(
+            Workload::parse(fallback).unwrap_err().to_string(),
+            "invalid workload: task 1: fallback period 5 is shorter than the period 10"
+        );
+        let firm = r#"{"tasks": [{"id": 1, "burst": 2, "period": 10, "firm": {"m": 3, "k": 2}}]}"#;
+        assert_eq!(
+            Workload::parse(firm).unwrap_err().to_string(),
+            "invalid workload: task 1: firm m 3 is not between 1 and k 2"
+        );
+        assert!(matches!(Workload::parse("{"), Err(WorkloadError::Parse(_))));
+    }
+}